@@ -95,6 +95,18 @@ impl AccountAccessCache {
         self.cache.remove(&key);
     }
 
+    pub fn remove_account(&mut self, account_id: &str) {
+        println!(
+            "[ACCOUNT MIDDLEWARE] Removing cache entries for account_id: {}",
+            account_id
+        );
+        self.cache.retain(|key, _| key.account_id != account_id);
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
     // Cleanup expired entries
     pub fn cleanup(&mut self) {
         println!("[ACCOUNT MIDDLEWARE] Starting cache cleanup");
@@ -1,52 +1,436 @@
 use axum::{
-    extract::Request,
+    extract::{ConnectInfo, Request, State},
     http::{HeaderMap, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
-use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use chrono::{DateTime, Duration, Utc};
+use dotenv::dotenv;
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    env, fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use uuid::Uuid;
+
+use crate::{
+    secrets::{get_secret_by_secret_value, SecretByValueResponse},
+    supabase_jwt_middleware::User,
+    system_plugins::webhook_trigger::rate_limit::{get_client_ip, IpCidr},
+    AppState, CachedApiKey,
+};
+
+//Don't write last_used_at more than once a minute per key
+const API_KEY_LAST_USED_INTERVAL_SECS: i64 = 60;
+
+//Lifetime of the Supabase JWT minted for each API key request
+const API_KEY_JWT_TTL_SECS: i64 = 300;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeyScope {
+    All,               // "*"
+    RunWorkflows,      // "workflows:run" for every workflow in the account
+    RunWorkflow(Uuid), // "workflows:run:<workflow_id>"
+    ReadTasks,         // "tasks:read"
+    ManageWorkflows,   // "workflows:manage"
+}
+
+impl ApiKeyScope {
+    pub fn parse(scope: &str) -> Result<Self, String> {
+        match scope.trim() {
+            "*" => Ok(ApiKeyScope::All),
+            "workflows:run" => Ok(ApiKeyScope::RunWorkflows),
+            "tasks:read" => Ok(ApiKeyScope::ReadTasks),
+            "workflows:manage" => Ok(ApiKeyScope::ManageWorkflows),
+            other => match other.strip_prefix("workflows:run:") {
+                Some(workflow_id) => Uuid::parse_str(workflow_id)
+                    .map(ApiKeyScope::RunWorkflow)
+                    .map_err(|_| format!("Invalid workflow id in scope: {}", scope)),
+                None => Err(format!("Unknown API key scope: {}", scope)),
+            },
+        }
+    }
+
+    //Keys created before scopes existed have none stored and keep full access
+    pub fn parse_all(scopes: Option<&[String]>) -> Result<Vec<Self>, String> {
+        match scopes {
+            None => Ok(vec![ApiKeyScope::All]),
+            Some(scopes) => scopes.iter().map(|scope| Self::parse(scope)).collect(),
+        }
+    }
+
+    pub fn grants(&self, required: &ApiKeyScope) -> bool {
+        match (self, required) {
+            (ApiKeyScope::All, _) => true,
+            (ApiKeyScope::RunWorkflows, ApiKeyScope::RunWorkflow(_)) => true,
+            (granted, required) => granted == required,
+        }
+    }
+}
+
+impl fmt::Display for ApiKeyScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeyScope::All => write!(f, "*"),
+            ApiKeyScope::RunWorkflows => write!(f, "workflows:run"),
+            ApiKeyScope::RunWorkflow(workflow_id) => write!(f, "workflows:run:{}", workflow_id),
+            ApiKeyScope::ReadTasks => write!(f, "tasks:read"),
+            ApiKeyScope::ManageWorkflows => write!(f, "workflows:manage"),
+        }
+    }
+}
+
+pub fn parse_allowed_ips(allowed_ips: Option<&[String]>) -> Result<Vec<IpCidr>, String> {
+    allowed_ips
+        .unwrap_or_default()
+        .iter()
+        .map(|ip| IpCidr::parse(ip))
+        .collect()
+}
+
+fn parse_timestamp(value: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    value
+        .map(|value| {
+            DateTime::parse_from_rfc3339(value)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .map_err(|e| format!("Invalid timestamp {}: {}", value, e))
+        })
+        .transpose()
+}
+
+impl CachedApiKey {
+    pub fn from_secret(secret: &SecretByValueResponse) -> Result<Self, String> {
+        Ok(CachedApiKey {
+            account_id: secret.account_id.clone(),
+            secret_id: Uuid::parse_str(&secret.secret_id).map_err(|e| e.to_string())?,
+            secret_name: secret.secret_name.clone(),
+            created_by: secret.created_by.clone(),
+            scopes: ApiKeyScope::parse_all(secret.api_key_scopes.as_deref())?,
+            expires_at: parse_timestamp(secret.api_key_expires_at.as_deref())?,
+            allowed_ips: parse_allowed_ips(secret.api_key_allowed_ips.as_deref())?,
+            last_used_at: parse_timestamp(secret.api_key_last_used_at.as_deref())?,
+        })
+    }
+
+    pub fn has_scope(&self, required: &ApiKeyScope) -> bool {
+        self.scopes.iter().any(|scope| scope.grants(required))
+    }
+
+    //Expiry and IP restrictions, checked on every request including cache hits
+    pub fn check_access(
+        &self,
+        client_ip: &IpAddr,
+        now: DateTime<Utc>,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        if matches!(self.expires_at, Some(expires_at) if expires_at <= now) {
+            return Err((StatusCode::UNAUTHORIZED, "API key expired"));
+        }
+        if !self.allowed_ips.is_empty()
+            && !self.allowed_ips.iter().any(|cidr| cidr.contains(client_ip))
+        {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "API key not allowed from this address",
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    match headers.get("Authorization").and_then(|h| h.to_str().ok()) {
+        Some(header) if header.starts_with("Bearer ") => Some(header[7..].to_string()),
+        _ => None,
+    }
+}
+
+//Looks the key up in the cache or database and checks expiry and IP restrictions.
+//Scopes are left to the caller since each route needs a different one.
+pub async fn authenticate_api_key(
+    state: Arc<AppState>,
+    api_key: &str,
+    client_ip: IpAddr,
+) -> Result<CachedApiKey, (StatusCode, &'static str)> {
+    let cached = state.api_key_cache.read().await.get(api_key).cloned();
+
+    let key = match cached {
+        Some(key) => key,
+        None => {
+            println!("[API KEY] API key not found in cache, checking database");
+            let secret = get_secret_by_secret_value(state.clone(), api_key.to_string())
+                .await
+                .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid API key"))?;
+
+            // Verify this is an API key secret
+            if !secret.anything_api_key {
+                return Err((StatusCode::UNAUTHORIZED, "Invalid API key"));
+            }
+
+            let key = CachedApiKey::from_secret(&secret).map_err(|e| {
+                println!(
+                    "[API KEY] Failed to read API key {}: {}",
+                    secret.secret_id, e
+                );
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Invalid API key configuration",
+                )
+            })?;
+
+            state
+                .api_key_cache
+                .write()
+                .await
+                .insert(api_key.to_string(), key.clone());
+            key
+        }
+    };
 
-use crate::secrets;
+    if let Err(rejection) = key.check_access(&client_ip, Utc::now()) {
+        println!(
+            "[API KEY] Rejected API key {} from {}: {}",
+            key.secret_id, client_ip, rejection.1
+        );
+        return Err(rejection);
+    }
+
+    record_api_key_use(state, api_key, &key).await;
+
+    Ok(key)
+}
+
+async fn record_api_key_use(state: Arc<AppState>, api_key: &str, key: &CachedApiKey) {
+    let now = Utc::now();
+    {
+        let mut cache = state.api_key_cache.write().await;
+        match cache.get_mut(api_key) {
+            Some(cached)
+                if cached.last_used_at.map_or(true, |last_used_at| {
+                    now - last_used_at >= Duration::seconds(API_KEY_LAST_USED_INTERVAL_SECS)
+                }) =>
+            {
+                cached.last_used_at = Some(now);
+            }
+            _ => return,
+        }
+    }
+
+    let usage = json!({
+        "secret_id": key.secret_id,
+        "account_id": key.account_id,
+        "last_used_at": now,
+    });
+
+    tokio::spawn(async move {
+        dotenv().ok();
+        let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+            .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+        match state
+            .anything_client
+            .from("api_key_usage")
+            .auth(supabase_service_role_api_key)
+            .upsert(usage.to_string())
+            .on_conflict("secret_id")
+            .execute()
+            .await
+        {
+            Ok(response) if !response.status().is_success() => println!(
+                "[API KEY] Failed to record API key use: {}",
+                response.text().await.unwrap_or_default()
+            ),
+            Ok(_) => {}
+            Err(e) => println!("[API KEY] Failed to record API key use: {:?}", e),
+        }
+    });
+}
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug)]
 pub struct ApiKeyUser {
     pub account_id: String,
+    pub secret_id: Uuid,
+    pub scopes: Vec<ApiKeyScope>,
 }
 
+#[derive(Debug, Serialize)]
+struct ApiKeyClaims {
+    sub: String,
+    aud: String,
+    role: String,
+    iat: i64,
+    exp: i64,
+}
+
+//Short lived Supabase JWT for the key's creator so RLS still applies to API key requests
+fn mint_user_jwt(user_id: &str) -> Result<String, jsonwebtoken::errors::Error> {
+    let secret = env::var("SUPABASE_JWT_SECRET").expect("SUPABASE_JWT_SECRET must be set");
+    let now = Utc::now().timestamp();
+    let claims = ApiKeyClaims {
+        sub: user_id.to_string(),
+        aud: "authenticated".to_string(),
+        role: "authenticated".to_string(),
+        iat: now,
+        exp: now + API_KEY_JWT_TTL_SECS,
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_ref()),
+    )
+}
+
+//For /api/v1/account/:account_id routes. Handlers get the same User extension as the
+//dashboard routes, acting as whoever created the key.
 pub async fn api_key_middleware(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
     mut request: Request,
     next: Next,
-) -> Result<Response, StatusCode> {
+) -> Response {
     // Get the API key from the Authorization header
-    let api_key = match headers.get("Authorization").and_then(|h| h.to_str().ok()) {
-        Some(header) if header.starts_with("Bearer ") => header[7..].to_string(),
-        _ => return Err(StatusCode::UNAUTHORIZED),
+    let api_key = match bearer_token(&headers) {
+        Some(api_key) => api_key,
+        None => return (StatusCode::UNAUTHORIZED, "Missing or invalid API key").into_response(),
     };
 
-    // Get the state from the request extensions
-    let state = request
-        .extensions()
-        .get::<Arc<crate::AppState>>()
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    // Check if the API key exists and is valid in the database
-    let secret = match secrets::get_secret_by_secret_value(state.clone(), api_key).await {
-        Ok(secret) => secret,
-        Err(_) => return Err(StatusCode::UNAUTHORIZED),
+    let client_ip = get_client_ip(&headers, &peer_addr);
+    let key = match authenticate_api_key(state, &api_key, client_ip).await {
+        Ok(key) => key,
+        Err(rejection) => return rejection.into_response(),
     };
 
-    // Verify this is an API key secret
-    if !secret.anything_api_key {
-        return Err(StatusCode::UNAUTHORIZED);
+    // Keys only work against the account that owns them
+    let path_account_id = request.uri().path().split('/').nth(4).unwrap_or_default();
+    if path_account_id != key.account_id {
+        println!(
+            "[API KEY] API key {} used for account {}",
+            key.secret_id, path_account_id
+        );
+        return (StatusCode::FORBIDDEN, "API key not valid for this account").into_response();
     }
 
-    // Add the user info to request extensions
-    let api_key_user = ApiKeyUser {
-        account_id: secret.account_id,
+    let jwt = match mint_user_jwt(&key.created_by) {
+        Ok(jwt) => jwt,
+        Err(e) => {
+            println!("[API KEY] Failed to create JWT for API key: {:?}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to authorize API key",
+            )
+                .into_response();
+        }
     };
-    request.extensions_mut().insert(api_key_user);
 
-    Ok(next.run(request).await)
+    request.extensions_mut().insert(User {
+        jwt,
+        account_id: key.created_by.clone(),
+    });
+    request.extensions_mut().insert(ApiKeyUser {
+        account_id: key.account_id,
+        secret_id: key.secret_id,
+        scopes: key.scopes,
+    });
+
+    next.run(request).await
+}
+
+async fn require_scope(scope: ApiKeyScope, request: Request, next: Next) -> Response {
+    let allowed = request
+        .extensions()
+        .get::<ApiKeyUser>()
+        .map_or(false, |user| user.scopes.iter().any(|s| s.grants(&scope)));
+
+    if !allowed {
+        return (
+            StatusCode::FORBIDDEN,
+            format!("API key is missing the {} scope", scope),
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+pub async fn require_manage_workflows(request: Request, next: Next) -> Response {
+    require_scope(ApiKeyScope::ManageWorkflows, request, next).await
+}
+
+pub async fn require_read_tasks(request: Request, next: Next) -> Response {
+    require_scope(ApiKeyScope::ReadTasks, request, next).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(
+        scopes: &[&str],
+        expires_at: Option<DateTime<Utc>>,
+        allowed_ips: &[&str],
+    ) -> CachedApiKey {
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        let allowed_ips: Vec<String> = allowed_ips.iter().map(|s| s.to_string()).collect();
+        CachedApiKey {
+            account_id: Uuid::new_v4().to_string(),
+            secret_id: Uuid::new_v4(),
+            secret_name: "ci".to_string(),
+            created_by: Uuid::new_v4().to_string(),
+            scopes: ApiKeyScope::parse_all(Some(scopes.as_slice())).unwrap(),
+            expires_at,
+            allowed_ips: parse_allowed_ips(Some(allowed_ips.as_slice())).unwrap(),
+            last_used_at: None,
+        }
+    }
+
+    #[test]
+    fn scopes_expiry_and_ip_restrictions() {
+        let workflow_id = Uuid::new_v4();
+        let other_workflow_id = Uuid::new_v4();
+        let scoped = key(
+            &[&format!("workflows:run:{}", workflow_id), "tasks:read"],
+            None,
+            &[],
+        );
+        assert!(scoped.has_scope(&ApiKeyScope::RunWorkflow(workflow_id)));
+        assert!(!scoped.has_scope(&ApiKeyScope::RunWorkflow(other_workflow_id)));
+        assert!(scoped.has_scope(&ApiKeyScope::ReadTasks));
+        assert!(!scoped.has_scope(&ApiKeyScope::ManageWorkflows));
+
+        let runner = key(&["workflows:run"], None, &[]);
+        assert!(runner.has_scope(&ApiKeyScope::RunWorkflow(other_workflow_id)));
+        assert!(!runner.has_scope(&ApiKeyScope::ReadTasks));
+
+        //Legacy keys have no scopes stored
+        assert_eq!(
+            ApiKeyScope::parse_all(None).unwrap(),
+            vec![ApiKeyScope::All]
+        );
+        assert!(ApiKeyScope::parse("workflows:delete").is_err());
+        assert!(ApiKeyScope::parse("workflows:run:not-a-uuid").is_err());
+        assert_eq!(
+            ApiKeyScope::parse(&ApiKeyScope::RunWorkflow(workflow_id).to_string()).unwrap(),
+            ApiKeyScope::RunWorkflow(workflow_id)
+        );
+
+        let now = Utc::now();
+        let office: IpAddr = "10.1.2.3".parse().unwrap();
+        let elsewhere: IpAddr = "203.0.113.9".parse().unwrap();
+        let restricted = key(&["*"], Some(now + Duration::hours(1)), &["10.0.0.0/8"]);
+        assert!(restricted.check_access(&office, now).is_ok());
+        assert_eq!(
+            restricted.check_access(&elsewhere, now).unwrap_err().0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            restricted
+                .check_access(&office, now + Duration::hours(2))
+                .unwrap_err()
+                .0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
 }
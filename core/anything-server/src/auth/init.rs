@@ -1,3 +1,4 @@
+use crate::cache_invalidation::{invalidate_caches, CacheInvalidation};
 use crate::vault::insert_secret_to_vault;
 use crate::AppState;
 use axum::{
@@ -401,15 +402,18 @@ pub async fn handle_provider_callback(
         create_account_response
     );
 
-    // Invalidate the bundler secrets cache for this account after creating a new secret
+    // Invalidate the bundler accounts cache for this account on every instance
     println!(
-        "[OAUTH] Invalidating bundler secrets cache for account: {}",
+        "[OAUTH] Invalidating bundler accounts cache for account: {}",
         auth_state.account_id
     );
-    {
-        let mut cache = state.bundler_accounts_cache.write().await;
-        cache.invalidate(&auth_state.account_id);
-    }
+    invalidate_caches(
+        &state,
+        CacheInvalidation::Accounts {
+            account_id: auth_state.account_id.clone(),
+        },
+    )
+    .await;
     println!("[OAUTH] Cache invalidated successfully");
 
     // Return success response
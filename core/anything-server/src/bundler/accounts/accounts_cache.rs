@@ -61,6 +61,10 @@ impl AccountsCache {
         self.cache.remove(account_id);
     }
 
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
     pub fn cleanup(&mut self) {
        println!("[BUNDLER] Starting accounts cache cleanup");
         let now = SystemTime::now();
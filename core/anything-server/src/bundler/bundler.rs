@@ -10,8 +10,14 @@ use std::error::Error;
 use std::sync::Arc;
 
 use crate::bundler::accounts::fetch_cached_auth_accounts;
-use crate::bundler::secrets::get_decrypted_secrets;
-use crate::templater::Templater;
+use crate::bundler::redaction::SecretRedactor;
+use crate::bundler::secrets::{get_decrypted_secrets, SecretScope};
+use crate::processor::flow_session_cache::{compile_workflow_templates, CompiledActionTemplates};
+use crate::secret_access_log::{
+    record_secret_access, template_secret_references, SecretAccessContext, SecretAccessEvent,
+    SecretResourceType,
+};
+use crate::templater::CompiledTemplate;
 use crate::types::task_types::TaskStatus;
 
 use uuid::Uuid;
@@ -23,11 +29,27 @@ pub async fn bundle_tasks_cached_context(
     client: &Postgrest,
     task: &Task,
     refresh_auth: bool,
-) -> Result<(Value, Value), Box<dyn Error + Send + Sync>> {
+) -> Result<(Value, Value, SecretRedactor), Box<dyn Error + Send + Sync>> {
     println!("[BUNDLER] Starting to bundle context from parts");
 
-    let rendered_inputs_definition =
-        bundle_tasks_cached_inputs(state, client, task, refresh_auth).await?;
+    //Templates are compiled once per workflow version and shared by its sessions
+    let compiled = {
+        let cache = state.flow_session_cache.read().await;
+        cache.get_compiled_templates(&task.flow_version_id, &task.action_id)
+    };
+    let compiled = match compiled {
+        Some(compiled) => Some(compiled),
+        None => compile_session_templates(&state, task).await,
+    };
+
+    let (rendered_inputs_definition, redactor) = bundle_tasks_cached_inputs(
+        state,
+        client,
+        task,
+        refresh_auth,
+        compiled.as_ref().and_then(|c| c.inputs.as_ref()),
+    )
+    .await?;
 
     let plugin_config = task.config.plugin_config.as_ref();
     let plugin_config_schema = task.config.plugin_config_schema.as_ref();
@@ -36,39 +58,74 @@ pub async fn bundle_tasks_cached_context(
         rendered_inputs_definition.clone(),
         plugin_config,
         plugin_config_schema,
+        compiled.as_ref().and_then(|c| c.plugin_config.as_ref()),
+        &redactor,
     )?;
 
     Ok((
         rendered_inputs_definition,
         rendered_plugin_config_definition,
+        redactor,
     ))
 }
 
+//Compiles the version's templates without holding the cache lock, then stores them
+async fn compile_session_templates(
+    state: &AppState,
+    task: &Task,
+) -> Option<Arc<CompiledActionTemplates>> {
+    let flow_session_id = Uuid::parse_str(&task.flow_session_id).ok()?;
+    let workflow = {
+        let cache = state.flow_session_cache.read().await;
+        if !cache.compiled_templates_missing(&task.flow_version_id) {
+            //Compiled already, the action just has nothing that compiled
+            return None;
+        }
+        cache.get_workflow(&flow_session_id)?
+    };
+    if workflow.flow_version_id != task.flow_version_id {
+        return None;
+    }
+
+    let templates = compile_workflow_templates(&workflow);
+    let compiled = templates.get(&task.action_id).cloned();
+    state
+        .flow_session_cache
+        .write()
+        .await
+        .insert_compiled_templates(task.flow_version_id, templates);
+    compiled
+}
+
 pub async fn bundle_tasks_cached_inputs(
     state: Arc<AppState>,
     client: &Postgrest,
     task: &Task,
     refresh_auth: bool,
-) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    compiled_inputs: Option<&CompiledTemplate>,
+) -> Result<(Value, SecretRedactor), Box<dyn Error + Send + Sync>> {
     println!("[BUNDLER] Starting to bundle context from parts");
 
     let account_id = task.account_id.to_string();
     let flow_session_id = task.flow_session_id.to_string();
     let inputs = task.config.inputs.as_ref();
     let inputs_schema = task.config.inputs_schema.as_ref();
+    //Test runs get sandbox secret values
+    let scope = SecretScope::new(Some(task.flow_id), task.stage.clone());
 
-    let rendered_inputs_definition = bundle_cached_inputs(
+    bundle_cached_inputs(
         state,
         client,
         &account_id,
         &flow_session_id,
+        &scope,
         inputs,
         inputs_schema,
         refresh_auth,
+        compiled_inputs,
+        Some(SecretAccessContext::for_task(task)),
     )
-    .await?;
-
-    Ok(rendered_inputs_definition)
+    .await
 }
 
 pub async fn bundle_context_from_parts(
@@ -76,6 +133,7 @@ pub async fn bundle_context_from_parts(
     client: &Postgrest,
     account_id: &str,
     flow_session_id: &str,
+    scope: &SecretScope,
     inputs: Option<&Value>,
     inputs_schema: Option<&JsonSchema>,
     plugin_config: Option<&Value>,
@@ -84,14 +142,21 @@ pub async fn bundle_context_from_parts(
 ) -> Result<Value, Box<dyn Error + Send + Sync>> {
     println!("[BUNDLER] Starting to bundle context from parts");
 
-    let rendered_inputs_definition = bundle_cached_inputs(
+    let (rendered_inputs_definition, redactor) = bundle_cached_inputs(
         state,
         client,
         account_id,
         flow_session_id,
+        scope,
         inputs,
         inputs_schema,
         refresh_auth,
+        None,
+        Some(SecretAccessContext {
+            flow_id: scope.flow_id,
+            flow_session_id: Some(flow_session_id.to_string()),
+            ..Default::default()
+        }),
     )
     .await?;
 
@@ -99,6 +164,8 @@ pub async fn bundle_context_from_parts(
         rendered_inputs_definition,
         plugin_config,
         plugin_config_schema,
+        None,
+        &redactor,
     )
 }
 
@@ -107,14 +174,18 @@ pub async fn bundle_cached_inputs(
     client: &Postgrest,
     account_id: &str,
     flow_session_id: &str,
+    scope: &SecretScope,
     inputs: Option<&Value>,
     inputs_schema: Option<&JsonSchema>,
     refresh_auth: bool,
-) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    compiled_inputs: Option<&CompiledTemplate>,
+    audit: Option<SecretAccessContext>,
+) -> Result<(Value, SecretRedactor), Box<dyn Error + Send + Sync>> {
     println!("[BUNDLER] Starting to bundle inputs");
 
     // Pre-allocate with known capacity
     let mut render_inputs_context = HashMap::with_capacity(4);
+    let mut redactor = SecretRedactor::new();
 
     // Parallel fetch of secrets, accounts, and cached task results
     let (secrets_result, accounts_result, tasks_result) = tokio::join!(
@@ -125,18 +196,31 @@ pub async fn bundle_cached_inputs(
 
     // Process accounts
     let mut accounts = HashMap::new();
+    let mut available = Vec::new(); // (type, id, name) of everything the templates could read
     for account in accounts_result? {
         let slug = account.account_auth_provider_account_slug.clone();
         println!("[BUNDLER] Inserting account with slug: {}", slug);
+        redactor.add_account(&account);
+        available.push((
+            SecretResourceType::Account,
+            account.account_auth_provider_account_id.to_string(),
+            slug.clone(),
+        ));
         accounts.insert(slug, serde_json::to_value(account)?);
     }
     render_inputs_context.insert("accounts".to_string(), serde_json::to_value(accounts)?);
 
-    // Process secrets
+    // Process secrets the workflow is allowed to use, with the value for the task's stage
     let mut secrets = HashMap::new();
-    for secret in secrets_result? {
+    for secret in scope.apply(secrets_result?) {
         let secret_name = secret.secret_name.clone();
         println!("[BUNDLER] Inserting secret with name: {}", secret_name);
+        redactor.add_secret(&secret_name, &secret.secret_value);
+        available.push((
+            SecretResourceType::Secret,
+            secret.secret_id.to_string(),
+            secret_name.clone(),
+        ));
         secrets.insert(secret_name, serde_json::to_value(secret.secret_value)?);
     }
     render_inputs_context.insert("secrets".to_string(), serde_json::to_value(secrets)?);
@@ -155,25 +239,42 @@ pub async fn bundle_cached_inputs(
         serde_json::to_value(get_system_variables())?,
     );
 
-    // Extract and set validations from schemas
-    let mut templater = Templater::new();
-
     if let Some(inputs) = inputs {
-        templater.add_template("task_inputs_definition", inputs.clone());
+        //Audit the secrets and accounts the templates actually read
+        if let Some(audit) = audit {
+            let references = template_secret_references(inputs);
+            let events = available
+                .into_iter()
+                .filter(|(resource_type, _, name)| match resource_type {
+                    SecretResourceType::Account => references.accounts.contains(name),
+                    _ => references.secrets.contains(name),
+                })
+                .map(|(resource_type, id, name)| {
+                    SecretAccessEvent::by_workflow(account_id, &audit, resource_type, id, name)
+                })
+                .collect();
+            record_secret_access(state.clone(), events);
+        }
 
+        // Extract and set validations from schemas
         let input_validations = extract_template_key_validations_from_schema(inputs_schema);
         let context_value = serde_json::to_value(&render_inputs_context)?;
-        let rendered = templater.render(
-            "task_inputs_definition",
+        let rendered = render_template(
+            inputs,
+            compiled_inputs,
             &context_value,
-            input_validations,
+            &input_validations,
+            &redactor,
         )?;
 
-        println!("[BUNDLER] Rendered inputs output: {}", rendered);
-        Ok(rendered)
+        println!(
+            "[BUNDLER] Rendered inputs output: {}",
+            redactor.redact_value(&rendered)
+        );
+        Ok((rendered, redactor))
     } else {
         println!("[BUNDLER] No inputs found in task config");
-        Ok(json!({}))
+        Ok((json!({}), redactor))
     }
 }
 
@@ -200,13 +301,12 @@ pub fn bundle_plugin_config(
     rendered_inputs: Value,
     plugin_config: Option<&Value>,
     plugin_config_schema: Option<&JsonSchema>,
+    compiled_plugin_config: Option<&CompiledTemplate>,
+    redactor: &SecretRedactor,
 ) -> Result<Value, Box<dyn Error + Send + Sync>> {
     let mut render_input_context: HashMap<String, Value> = HashMap::new();
     render_input_context.insert("inputs".to_string(), rendered_inputs);
 
-    // Create a new Templater instance for rendering inputs
-    let mut templater = Templater::new();
-
     // Convert context HashMap to Value
     let inputs_context_value = serde_json::to_value(render_input_context.clone())?;
 
@@ -216,19 +316,19 @@ pub fn bundle_plugin_config(
             "[BUNDLER] Task plugin config definition: {}",
             plugin_config.clone()
         );
-        templater.add_template("task_plugin_config_definition", plugin_config.clone());
-
         let plugin_config_validations =
             extract_template_key_validations_from_schema(plugin_config_schema);
         // Render the task definition with the context
-        let rendered_plugin_config_definition = templater.render(
-            "task_plugin_config_definition",
+        let rendered_plugin_config_definition = render_template(
+            plugin_config,
+            compiled_plugin_config,
             &inputs_context_value,
-            plugin_config_validations,
+            &plugin_config_validations,
+            redactor,
         )?;
         println!(
             "[BUNDLER] Rendered plugin config output: {}",
-            rendered_plugin_config_definition
+            redactor.redact_value(&rendered_plugin_config_definition)
         );
         Ok(rendered_plugin_config_definition)
     } else {
@@ -237,6 +337,23 @@ pub fn bundle_plugin_config(
     }
 }
 
+//Uses the template compiled with the workflow version when there is one.
+//Template errors can quote rendered values so they are redacted too.
+fn render_template(
+    template: &Value,
+    compiled: Option<&CompiledTemplate>,
+    context: &Value,
+    validations: &HashMap<String, ValidationFieldType>,
+    redactor: &SecretRedactor,
+) -> Result<Value, Box<dyn Error + Send + Sync>> {
+    let rendered = match compiled {
+        Some(compiled) => compiled.render(context, validations),
+        None => CompiledTemplate::compile(template)
+            .and_then(|compiled| compiled.render(context, validations)),
+    };
+    rendered.map_err(|e| redactor.redact_str(&e.to_string()).into())
+}
+
 fn extract_template_key_validations_from_schema(
     schema: Option<&JsonSchema>,
 ) -> HashMap<String, ValidationFieldType> {
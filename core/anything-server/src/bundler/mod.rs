@@ -1,5 +1,6 @@
 pub mod accounts;
 pub mod bundler;
+pub mod redaction;
 pub mod secrets;
 
 #[cfg(test)]
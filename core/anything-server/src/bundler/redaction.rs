@@ -0,0 +1,168 @@
+use base64::Engine;
+use serde_json::Value;
+
+use crate::auth::init::AccountAuthProviderAccount;
+
+//Shorter values would blank out unrelated text like ids and numbers
+const MIN_REDACTED_LENGTH: usize = 4;
+
+//Tracks every secret and account token that went into a bundled context so they can be
+//swapped for placeholders like "***secret:NAME***" before anything is persisted or logged.
+//Execution always gets the real values.
+#[derive(Debug, Clone, Default)]
+pub struct SecretRedactor {
+    values: Vec<(String, String)>, // (value, placeholder) longest value first
+}
+
+impl SecretRedactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_secret(&mut self, name: &str, value: &str) {
+        self.add(value, format!("***secret:{}***", name));
+    }
+
+    pub fn add_account(&mut self, account: &AccountAuthProviderAccount) {
+        let slug = &account.account_auth_provider_account_slug;
+        self.add(
+            &account.access_token,
+            format!("***account:{}.access_token***", slug),
+        );
+        if let Some(refresh_token) = &account.refresh_token {
+            self.add(
+                refresh_token,
+                format!("***account:{}.refresh_token***", slug),
+            );
+        }
+    }
+
+    //Also covers the forms the base64_encode, url_encode and json filters turn a value into
+    fn add(&mut self, value: &str, placeholder: String) {
+        if value.len() < MIN_REDACTED_LENGTH {
+            return;
+        }
+        let json_escaped = serde_json::to_string(value).unwrap_or_default();
+        let encoded = [
+            value.to_string(),
+            base64::engine::general_purpose::STANDARD.encode(value),
+            urlencoding::encode(value).into_owned(),
+            json_escaped
+                .strip_prefix('"')
+                .and_then(|escaped| escaped.strip_suffix('"'))
+                .unwrap_or(value)
+                .to_string(),
+        ];
+        for form in encoded {
+            if !self.values.iter().any(|(v, _)| *v == form) {
+                self.values.push((form, placeholder.clone()));
+            }
+        }
+        //Longest first so a secret containing another one is replaced whole
+        self.values.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn redact_str(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+        for (value, placeholder) in &self.values {
+            if redacted.contains(value.as_str()) {
+                redacted = redacted.replace(value.as_str(), placeholder);
+            }
+        }
+        redacted
+    }
+
+    //Redacts every string in the value, keys included
+    pub fn redact_value(&self, value: &Value) -> Value {
+        if self.is_empty() {
+            return value.clone();
+        }
+        match value {
+            Value::String(s) => Value::String(self.redact_str(s)),
+            Value::Array(items) => {
+                Value::Array(items.iter().map(|item| self.redact_value(item)).collect())
+            }
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .map(|(k, v)| (self.redact_str(k), self.redact_value(v)))
+                    .collect(),
+            ),
+            _ => value.clone(),
+        }
+    }
+}
+
+//For logging rendered inputs where no redactor is available. Object keys and sizes only,
+//the values can hold secrets the bundler already substituted in.
+pub fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Object(map) => format!(
+            "object with keys {:?}",
+            map.keys().map(|k| k.as_str()).collect::<Vec<_>>()
+        ),
+        Value::Array(items) => format!("array of {} items", items.len()),
+        Value::String(s) => format!("string of {} bytes", s.len()),
+        Value::Null => "null".to_string(),
+        _ => "scalar".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn replaces_secrets_with_placeholders() {
+        let mut redactor = SecretRedactor::new();
+        redactor.add_secret("API_KEY", "sk_live_123");
+        redactor.add_secret("API_KEY_PREFIX", "sk_live");
+        redactor.add_secret("PIN", "42");
+
+        let context = json!({
+            "headers": {"Authorization": "Bearer sk_live_123"},
+            "body": {"prefix": "sk_live", "pin": "42", "ids": [42, "sk_live_123"]}
+        });
+
+        assert_eq!(
+            redactor.redact_value(&context),
+            json!({
+                "headers": {"Authorization": "Bearer ***secret:API_KEY***"},
+                "body": {
+                    "prefix": "***secret:API_KEY_PREFIX***",
+                    "pin": "42",
+                    "ids": [42, "***secret:API_KEY***"]
+                }
+            })
+        );
+        assert_eq!(
+            redactor.redact_str("Cannot convert value to number: sk_live_123"),
+            "Cannot convert value to number: ***secret:API_KEY***"
+        );
+
+        //Filters can hand a transformed secret to the action
+        let mut redactor = SecretRedactor::new();
+        redactor.add_secret("CREDS", "user:pa ss\"word");
+        assert_eq!(
+            redactor.redact_value(&json!({
+                "auth": "Basic dXNlcjpwYSBzcyJ3b3Jk",
+                "query": "creds=user%3Apa%20ss%22word",
+                "body": "{\"creds\":\"user:pa ss\\\"word\"}"
+            })),
+            json!({
+                "auth": "Basic ***secret:CREDS***",
+                "query": "creds=***secret:CREDS***",
+                "body": "{\"creds\":\"***secret:CREDS***\"}"
+            })
+        );
+
+        assert_eq!(
+            describe_value(&context),
+            "object with keys [\"body\", \"headers\"]"
+        );
+    }
+}
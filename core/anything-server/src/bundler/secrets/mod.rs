@@ -7,6 +7,9 @@ use uuid::Uuid;
 
 use serde::{Deserialize, Serialize};
 
+use crate::types::task_types::Stage;
+use crate::types::workflow_types::DatabaseFlowVersion;
+use crate::vault::read_secrets_from_vault;
 use crate::AppState;
 
 pub mod secrets_cache;
@@ -17,6 +20,77 @@ pub struct DecryptedSecret {
     pub secret_name: String,
     pub secret_value: String,
     pub secret_description: Option<String>,
+    //Sandbox value for test runs, production value is used when there isn't one
+    #[serde(default)]
+    pub testing_secret_value: Option<String>,
+    //Workflows allowed to use this secret, None or empty means every workflow
+    #[serde(default)]
+    pub flow_ids: Option<Vec<Uuid>>,
+}
+
+impl DecryptedSecret {
+    pub fn available_to(&self, flow_id: &Uuid) -> bool {
+        match &self.flow_ids {
+            Some(flow_ids) if !flow_ids.is_empty() => flow_ids.contains(flow_id),
+            _ => true,
+        }
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.flow_ids.as_ref().map_or(false, |ids| !ids.is_empty())
+    }
+
+    pub fn value_for_stage(&self, stage: &Stage) -> &str {
+        match (stage, &self.testing_secret_value) {
+            (Stage::Testing, Some(testing_value)) => testing_value,
+            _ => &self.secret_value,
+        }
+    }
+}
+
+//The workflow and stage a context is bundled for. Decides which secrets are visible
+//and which of their values gets used.
+#[derive(Debug, Clone)]
+pub struct SecretScope {
+    pub flow_id: Option<Uuid>,
+    pub stage: Stage,
+}
+
+impl SecretScope {
+    pub fn new(flow_id: Option<Uuid>, stage: Stage) -> Self {
+        Self { flow_id, stage }
+    }
+
+    //Unpublished versions only run as tests
+    pub fn for_flow_version(flow_version: &DatabaseFlowVersion) -> Self {
+        let stage = if flow_version.published {
+            Stage::Production
+        } else {
+            Stage::Testing
+        };
+        Self::new(Some(flow_version.flow_id), stage)
+    }
+
+    //Without a workflow only unrestricted secrets are visible
+    pub fn allows(&self, secret: &DecryptedSecret) -> bool {
+        match &self.flow_id {
+            Some(flow_id) => secret.available_to(flow_id),
+            None => !secret.is_restricted(),
+        }
+    }
+
+    //Drops secrets outside the scope and puts the stage's value in secret_value
+    pub fn apply(&self, secrets: Vec<DecryptedSecret>) -> Vec<DecryptedSecret> {
+        secrets
+            .into_iter()
+            .filter(|secret| self.allows(secret))
+            .map(|mut secret| {
+                secret.secret_value = secret.value_for_stage(&self.stage).to_string();
+                secret.testing_secret_value = None;
+                secret
+            })
+            .collect()
+    }
 }
 
 pub async fn get_decrypted_secrets(
@@ -57,6 +131,17 @@ pub async fn get_decrypted_secrets(
     Ok(secrets)
 }
 
+//Row in anything.secrets, values are read from the vault by id
+#[derive(Debug, Deserialize)]
+struct SecretRow {
+    secret_id: Uuid,
+    secret_name: String,
+    secret_description: Option<String>,
+    vault_secret_id: String,
+    testing_vault_secret_id: Option<String>,
+    flow_ids: Option<Vec<Uuid>>,
+}
+
 // Secrets for building context with API KEYS
 pub async fn fetch_secrets_from_vault(
     client: &Postgrest,
@@ -70,32 +155,52 @@ pub async fn fetch_secrets_from_vault(
         account_id
     );
 
-    let input = serde_json::json!({
-        "team_account_id": account_id.to_string()
-    })
-    .to_string();
-
     let response = client
-        .rpc("get_decrypted_secrets", &input)
-        .auth(supabase_service_role_api_key.clone())
+        .from("secrets")
+        .auth(supabase_service_role_api_key)
+        .eq("account_id", account_id)
+        .eq("anything_api_key", "false")
+        .select("secret_id,secret_name,secret_description,vault_secret_id,testing_vault_secret_id,flow_ids")
         .execute()
         .await?;
 
-    println!(
-        "[BUNDLER] Response for get_decryped_secrets: {:?}",
-        response
-    );
-
     let body = response.text().await?;
-    let items: Vec<DecryptedSecret> = match serde_json::from_str(&body) {
+    let rows: Vec<SecretRow> = match serde_json::from_str(&body) {
         Ok(parsed) => parsed,
         Err(e) => {
-            println!("[BUNDLER] Error parsing decrypted secrets: {}", e);
+            println!("[BUNDLER] Error parsing secrets: {}", e);
             println!("[BUNDLER] Response body: {}", body);
             return Err(Box::new(e));
         }
     };
 
+    let vault_ids: Vec<String> = rows
+        .iter()
+        .flat_map(|row| {
+            std::iter::once(row.vault_secret_id.clone()).chain(row.testing_vault_secret_id.clone())
+        })
+        .collect();
+    let mut values = read_secrets_from_vault(client, &vault_ids).await?;
+
+    //Secrets whose value is gone from the vault are skipped
+    let items: Vec<DecryptedSecret> = rows
+        .into_iter()
+        .filter_map(|row| {
+            let secret_value = values.remove(&row.vault_secret_id)?;
+            let testing_secret_value = row
+                .testing_vault_secret_id
+                .and_then(|id| values.remove(&id));
+            Some(DecryptedSecret {
+                secret_id: row.secret_id,
+                secret_name: row.secret_name,
+                secret_value,
+                secret_description: row.secret_description,
+                testing_secret_value,
+                flow_ids: row.flow_ids,
+            })
+        })
+        .collect();
+
     println!(
         "[BUNDLER] Successfully retrieved {} decrypted secrets",
         items.len()
@@ -103,3 +208,53 @@ pub async fn fetch_secrets_from_vault(
 
     Ok(items)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(
+        name: &str,
+        testing_value: Option<&str>,
+        flow_ids: Option<Vec<Uuid>>,
+    ) -> DecryptedSecret {
+        DecryptedSecret {
+            secret_id: Uuid::new_v4(),
+            secret_name: name.to_string(),
+            secret_value: format!("{}_live", name),
+            secret_description: None,
+            testing_secret_value: testing_value.map(str::to_string),
+            flow_ids,
+        }
+    }
+
+    #[test]
+    fn scopes_secrets_by_workflow_and_stage() {
+        let flow_id = Uuid::new_v4();
+        let secrets = vec![
+            secret("shared", Some("shared_sandbox"), None),
+            secret("scoped", None, Some(vec![flow_id])),
+            secret("other", None, Some(vec![Uuid::new_v4()])),
+            secret("open", None, Some(vec![])),
+        ];
+
+        let testing = SecretScope::new(Some(flow_id), Stage::Testing).apply(secrets.clone());
+        let values: Vec<(&str, &str)> = testing
+            .iter()
+            .map(|s| (s.secret_name.as_str(), s.secret_value.as_str()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("shared", "shared_sandbox"),
+                ("scoped", "scoped_live"),
+                ("open", "open_live")
+            ]
+        );
+
+        let unscoped = SecretScope::new(None, Stage::Production).apply(secrets);
+        let names: Vec<&str> = unscoped.iter().map(|s| s.secret_name.as_str()).collect();
+        assert_eq!(names, vec!["shared", "open"]);
+        assert_eq!(unscoped[0].secret_value, "shared_live");
+    }
+}
@@ -57,6 +57,10 @@ impl SecretsCache {
         self.cache.remove(account_id);
     }
 
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
     pub fn cleanup(&mut self) {
         println!("[BUNDLER] Starting secrets cache cleanup");
         let now = SystemTime::now();
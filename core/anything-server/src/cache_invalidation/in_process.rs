@@ -0,0 +1,41 @@
+use futures::future::BoxFuture;
+use tokio::sync::broadcast;
+
+use super::{CacheBusResult, CacheInvalidation, CacheInvalidationBus, CACHE_INVALIDATION_BUFFER};
+
+//Only reaches this process. Fine for a single instance or local development.
+pub struct InProcessCacheBus {
+    sender: broadcast::Sender<CacheInvalidation>,
+}
+
+impl InProcessCacheBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CACHE_INVALIDATION_BUFFER);
+        Self { sender }
+    }
+}
+
+impl Default for InProcessCacheBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheInvalidationBus for InProcessCacheBus {
+    fn name(&self) -> &'static str {
+        "in_process"
+    }
+
+    fn publish<'a>(
+        &'a self,
+        invalidation: &'a CacheInvalidation,
+    ) -> BoxFuture<'a, CacheBusResult<()>> {
+        //No subscribers just means nobody has anything cached yet
+        let _ = self.sender.send(invalidation.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<CacheInvalidation> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,207 @@
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use dotenv::dotenv;
+
+use std::env;
+use std::sync::Arc;
+
+use crate::AppState;
+
+pub mod in_process;
+pub mod postgres;
+
+pub use in_process::InProcessCacheBus;
+pub use postgres::PostgresCacheBus;
+
+pub type CacheBusResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+//Channel the database triggers and PostgresCacheBus notify on
+pub const CACHE_INVALIDATION_CHANNEL: &str = "anything_cache_invalidation";
+
+//Subscribers that fall further behind than this drop every cache
+pub const CACHE_INVALIDATION_BUFFER: usize = 1024;
+
+//Payloads are also built by anything.notify_cache_invalidation() in the migrations,
+//keep the two in sync
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cache", rename_all = "snake_case")]
+pub enum CacheInvalidation {
+    //Bundler secrets of an account
+    Secrets {
+        account_id: String,
+    },
+    //Bundler OAuth accounts of an account
+    Accounts {
+        account_id: String,
+    },
+    //api_key_cache is keyed by the key itself so revocations go out by secret id
+    ApiKey {
+        secret_id: Uuid,
+    },
+    //Membership changed, no user_id clears every member of the account
+    AccountAccess {
+        #[serde(default)]
+        user_id: Option<String>,
+        account_id: String,
+    },
+    //Templates compiled from a workflow version's definition, dropped when a draft is edited
+    CompiledTemplates {
+        flow_version_id: Uuid,
+    },
+    //Sent locally when messages may have been missed
+    All,
+}
+
+//Fans invalidations out to every server instance, the sender included
+pub trait CacheInvalidationBus: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn publish<'a>(
+        &'a self,
+        invalidation: &'a CacheInvalidation,
+    ) -> BoxFuture<'a, CacheBusResult<()>>;
+
+    fn subscribe(&self) -> broadcast::Receiver<CacheInvalidation>;
+}
+
+//Backend is picked from ANYTHING_CACHE_BUS, "in_process" (default) or "postgres".
+//Run postgres whenever more than one instance serves traffic.
+pub fn cache_invalidation_bus_from_env() -> Arc<dyn CacheInvalidationBus> {
+    dotenv().ok();
+    let bus: Arc<dyn CacheInvalidationBus> =
+        match env::var("ANYTHING_CACHE_BUS").unwrap_or_default().as_str() {
+            "postgres" => {
+                let database_url = env::var("ANYTHING_CACHE_BUS_DATABASE_URL")
+                    .expect("ANYTHING_CACHE_BUS_DATABASE_URL must be set");
+                Arc::new(PostgresCacheBus::new(database_url))
+            }
+            _ => Arc::new(InProcessCacheBus::new()),
+        };
+    println!("[CACHE BUS] Using {} cache invalidation bus", bus.name());
+    bus
+}
+
+//Clears our own caches right away so the caller sees the change, then tells everyone else
+pub async fn invalidate_caches(state: &AppState, invalidation: CacheInvalidation) {
+    apply_cache_invalidation(state, &invalidation).await;
+    if let Err(e) = state.cache_bus.publish(&invalidation).await {
+        println!("[CACHE BUS] Failed to publish {:?}: {}", invalidation, e);
+    }
+}
+
+pub async fn apply_cache_invalidation(state: &AppState, invalidation: &CacheInvalidation) {
+    match invalidation {
+        CacheInvalidation::Secrets { account_id } => {
+            state
+                .bundler_secrets_cache
+                .write()
+                .await
+                .invalidate(account_id);
+        }
+        CacheInvalidation::Accounts { account_id } => {
+            state
+                .bundler_accounts_cache
+                .write()
+                .await
+                .invalidate(account_id);
+        }
+        CacheInvalidation::ApiKey { secret_id } => {
+            state
+                .api_key_cache
+                .write()
+                .await
+                .retain(|_, key| key.secret_id != *secret_id);
+        }
+        CacheInvalidation::AccountAccess {
+            user_id,
+            account_id,
+        } => {
+            let mut cache = state.account_access_cache.write().await;
+            match user_id {
+                Some(user_id) => cache.remove(user_id, account_id),
+                None => cache.remove_account(account_id),
+            }
+        }
+        CacheInvalidation::CompiledTemplates { flow_version_id } => {
+            state
+                .flow_session_cache
+                .write()
+                .await
+                .invalidate_compiled_templates(flow_version_id);
+        }
+        CacheInvalidation::All => {
+            println!("[CACHE BUS] Clearing every cache");
+            state.bundler_secrets_cache.write().await.clear();
+            state.bundler_accounts_cache.write().await.clear();
+            state.api_key_cache.write().await.clear();
+            state.account_access_cache.write().await.clear();
+            state
+                .flow_session_cache
+                .write()
+                .await
+                .clear_compiled_templates();
+        }
+    }
+}
+
+//Applies invalidations from other instances for as long as the server runs
+pub async fn cache_invalidation_listener(state: Arc<AppState>) {
+    let mut receiver = state.cache_bus.subscribe();
+    loop {
+        match receiver.recv().await {
+            Ok(invalidation) => apply_cache_invalidation(&state, &invalidation).await,
+            Err(RecvError::Lagged(skipped)) => {
+                println!(
+                    "[CACHE BUS] Listener fell behind by {} invalidations",
+                    skipped
+                );
+                apply_cache_invalidation(&state, &CacheInvalidation::All).await;
+            }
+            Err(RecvError::Closed) => {
+                println!("[CACHE BUS] Bus closed, stopping listener");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn delivers_invalidations_in_the_trigger_format() {
+        let bus = InProcessCacheBus::new();
+        let mut receiver = bus.subscribe();
+
+        //Same shape json_build_object produces in the database trigger
+        let from_trigger: CacheInvalidation = serde_json::from_value(json!({
+            "cache": "account_access",
+            "user_id": "5b4c7f8e-0000-4000-8000-000000000001",
+            "account_id": "5b4c7f8e-0000-4000-8000-000000000002"
+        }))
+        .unwrap();
+        bus.publish(&from_trigger).await.unwrap();
+
+        let secret_id = Uuid::new_v4();
+        let revoked = CacheInvalidation::ApiKey { secret_id };
+        assert_eq!(
+            serde_json::to_value(&revoked).unwrap(),
+            json!({ "cache": "api_key", "secret_id": secret_id })
+        );
+        bus.publish(&revoked).await.unwrap();
+
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            CacheInvalidation::AccountAccess {
+                user_id: Some("5b4c7f8e-0000-4000-8000-000000000001".to_string()),
+                account_id: "5b4c7f8e-0000-4000-8000-000000000002".to_string(),
+            }
+        );
+        assert_eq!(receiver.recv().await.unwrap(), revoked);
+    }
+}
@@ -0,0 +1,141 @@
+use futures::{future::BoxFuture, stream, StreamExt};
+use openssl::ssl::{SslConnector, SslMethod};
+use postgres_openssl::MakeTlsConnector;
+use tokio::sync::{broadcast, RwLock};
+use tokio_postgres::{AsyncMessage, Client};
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{
+    CacheBusResult, CacheInvalidation, CacheInvalidationBus, CACHE_INVALIDATION_BUFFER,
+    CACHE_INVALIDATION_CHANNEL,
+};
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+//LISTEN/NOTIFY on a direct database connection. Transaction poolers like pgbouncer
+//drop LISTEN, so point ANYTHING_CACHE_BUS_DATABASE_URL at the database or a session pooler.
+pub struct PostgresCacheBus {
+    client: Arc<RwLock<Option<Client>>>,
+    sender: broadcast::Sender<CacheInvalidation>,
+}
+
+impl PostgresCacheBus {
+    pub fn new(database_url: String) -> Self {
+        let (sender, _) = broadcast::channel(CACHE_INVALIDATION_BUFFER);
+        let client = Arc::new(RwLock::new(None));
+        tokio::spawn(run_listener(database_url, client.clone(), sender.clone()));
+        Self { client, sender }
+    }
+}
+
+impl CacheInvalidationBus for PostgresCacheBus {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn publish<'a>(
+        &'a self,
+        invalidation: &'a CacheInvalidation,
+    ) -> BoxFuture<'a, CacheBusResult<()>> {
+        Box::pin(async move {
+            let payload = serde_json::to_string(invalidation)?;
+            let client = self.client.read().await;
+            let client = client
+                .as_ref()
+                .ok_or("Postgres cache bus is not connected")?;
+            client
+                .execute(
+                    "SELECT pg_notify($1, $2)",
+                    &[&CACHE_INVALIDATION_CHANNEL, &payload],
+                )
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<CacheInvalidation> {
+        self.sender.subscribe()
+    }
+}
+
+async fn run_listener(
+    database_url: String,
+    client: Arc<RwLock<Option<Client>>>,
+    sender: broadcast::Sender<CacheInvalidation>,
+) {
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    loop {
+        match listen(&database_url, &client, &sender).await {
+            Ok(()) => reconnect_delay = MIN_RECONNECT_DELAY,
+            Err(e) => println!("[CACHE BUS] Postgres listener failed: {}", e),
+        }
+        *client.write().await = None;
+
+        println!(
+            "[CACHE BUS] Reconnecting to Postgres in {:?}",
+            reconnect_delay
+        );
+        tokio::time::sleep(reconnect_delay).await;
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+//Returns once an established connection drops
+async fn listen(
+    database_url: &str,
+    client_slot: &RwLock<Option<Client>>,
+    sender: &broadcast::Sender<CacheInvalidation>,
+) -> CacheBusResult<()> {
+    let connector = MakeTlsConnector::new(SslConnector::builder(SslMethod::tls())?.build());
+    let (client, mut connection) = tokio_postgres::connect(database_url, connector).await?;
+
+    //The connection has to be polled for the LISTEN below to complete
+    let notification_sender = sender.clone();
+    let driver = tokio::spawn(async move {
+        let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+        while let Some(message) = messages.next().await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    match serde_json::from_str::<CacheInvalidation>(notification.payload()) {
+                        Ok(invalidation) => {
+                            let _ = notification_sender.send(invalidation);
+                        }
+                        Err(e) => println!(
+                            "[CACHE BUS] Ignoring invalid notification {}: {}",
+                            notification.payload(),
+                            e
+                        ),
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    });
+
+    if let Err(e) = client
+        .batch_execute(&format!("LISTEN {}", CACHE_INVALIDATION_CHANNEL))
+        .await
+    {
+        driver.abort();
+        return Err(e.into());
+    }
+
+    println!(
+        "[CACHE BUS] Listening for cache invalidations on {}",
+        CACHE_INVALIDATION_CHANNEL
+    );
+    *client_slot.write().await = Some(client);
+
+    //Anything sent while we were disconnected never reached us
+    let _ = sender.send(CacheInvalidation::All);
+
+    if let Err(e) = driver.await? {
+        println!("[CACHE BUS] Lost Postgres connection: {}", e);
+    }
+    Ok(())
+}
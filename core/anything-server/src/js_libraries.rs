@@ -0,0 +1,257 @@
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::{
+    supabase_jwt_middleware::User, system_plugins::javascript::libraries::is_valid_library_name,
+    AppState,
+};
+
+//Library source is stored as text, keep it to something an editor can open
+const MAX_JS_LIBRARY_CODE_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateJsLibraryVersionPayload {
+    name: String,
+    code: String,
+    description: Option<String>,
+}
+
+async fn json_or_error(
+    response: Result<reqwest::Response, reqwest::Error>,
+    error_status: StatusCode,
+    error_message: &'static str,
+) -> Result<Value, Response> {
+    let response = match response {
+        Ok(response) => response,
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response())
+        }
+    };
+
+    let status = response.status();
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(_) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response())
+        }
+    };
+
+    if !status.is_success() {
+        println!("[JS LIBRARIES] Request failed with {}: {}", status, body);
+        return Err((error_status, error_message).into_response());
+    }
+
+    serde_json::from_str(&body)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON").into_response())
+}
+
+pub async fn get_js_libraries(
+    Path(account_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    println!(
+        "[JS LIBRARIES] Handling get_js_libraries for account {}",
+        account_id
+    );
+
+    let response = state
+        .anything_client
+        .from("js_libraries")
+        .auth(&user.jwt)
+        .select("*")
+        .eq("account_id", &account_id)
+        .eq("archived", "false")
+        .order("name.asc")
+        .execute()
+        .await;
+
+    match json_or_error(
+        response,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to load JavaScript libraries",
+    )
+    .await
+    {
+        Ok(items) => Json(items).into_response(),
+        Err(response) => response,
+    }
+}
+
+//The library with its version history, code is fetched per version
+pub async fn get_js_library(
+    Path((account_id, name)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    println!(
+        "[JS LIBRARIES] Handling get_js_library {} for account {}",
+        name, account_id
+    );
+
+    let response = state
+        .anything_client
+        .from("js_libraries")
+        .auth(&user.jwt)
+        .select("*,versions:js_library_versions(version,created_at,created_by)")
+        .eq("account_id", &account_id)
+        .eq("name", &name)
+        .execute()
+        .await;
+
+    match json_or_error(
+        response,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to load JavaScript library",
+    )
+    .await
+    {
+        Ok(Value::Array(mut items)) if !items.is_empty() => {
+            let mut library = items.remove(0);
+            //Newest first
+            if let Some(versions) = library["versions"].as_array_mut() {
+                versions.sort_by_key(|version| -version["version"].as_i64().unwrap_or(0));
+            }
+            Json(library).into_response()
+        }
+        Ok(_) => (StatusCode::NOT_FOUND, "JavaScript library not found").into_response(),
+        Err(response) => response,
+    }
+}
+
+pub async fn get_js_library_version(
+    Path((account_id, name, version)): Path<(String, String, i64)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    println!(
+        "[JS LIBRARIES] Handling get_js_library_version {}@{} for account {}",
+        name, version, account_id
+    );
+
+    let response = state
+        .anything_client
+        .from("js_library_versions")
+        .auth(&user.jwt)
+        .select("*")
+        .eq("account_id", &account_id)
+        .eq("name", &name)
+        .eq("version", version.to_string())
+        .execute()
+        .await;
+
+    match json_or_error(
+        response,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to load JavaScript library version",
+    )
+    .await
+    {
+        Ok(Value::Array(mut items)) if !items.is_empty() => Json(items.remove(0)).into_response(),
+        Ok(_) => (
+            StatusCode::NOT_FOUND,
+            "JavaScript library version not found",
+        )
+            .into_response(),
+        Err(response) => response,
+    }
+}
+
+//Saving never changes an existing version, workflows published against it keep running the same code
+pub async fn create_js_library_version(
+    Path(account_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(payload): Json<CreateJsLibraryVersionPayload>,
+) -> impl IntoResponse {
+    println!(
+        "[JS LIBRARIES] Handling create_js_library_version {} for account {}",
+        payload.name, account_id
+    );
+
+    if !is_valid_library_name(&payload.name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "Library names are up to 64 lowercase letters, numbers, - and _",
+        )
+            .into_response();
+    }
+
+    if payload.code.len() > MAX_JS_LIBRARY_CODE_BYTES {
+        return (StatusCode::BAD_REQUEST, "Library code is too large").into_response();
+    }
+
+    let input = json!({
+        "p_account_id": account_id,
+        "p_name": payload.name,
+        "p_code": payload.code,
+        "p_description": payload.description,
+    });
+
+    let response = state
+        .anything_client
+        .rpc("create_js_library_version", input.to_string())
+        .auth(&user.jwt)
+        .execute()
+        .await;
+
+    match json_or_error(
+        response,
+        StatusCode::BAD_REQUEST,
+        "Failed to save JavaScript library",
+    )
+    .await
+    {
+        Ok(Value::Array(mut items)) if !items.is_empty() => Json(items.remove(0)).into_response(),
+        Ok(items) => Json(items).into_response(),
+        Err(response) => response,
+    }
+}
+
+//Archived libraries can't be imported by drafts anymore, published versions keep their pinned copy
+pub async fn archive_js_library(
+    Path((account_id, name)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    println!(
+        "[JS LIBRARIES] Handling archive_js_library {} for account {}",
+        name, account_id
+    );
+
+    let response = state
+        .anything_client
+        .from("js_libraries")
+        .auth(&user.jwt)
+        .eq("account_id", &account_id)
+        .eq("name", &name)
+        .update(json!({ "archived": true }).to_string())
+        .execute()
+        .await;
+
+    match json_or_error(
+        response,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to archive JavaScript library",
+    )
+    .await
+    {
+        Ok(items) => Json(items).into_response(),
+        Err(response) => response,
+    }
+}
@@ -15,8 +15,9 @@ use serde_json::Value;
 use std::{collections::HashMap, time::Duration};
 use std::env;
 use std::sync::Arc;
+use std::net::SocketAddr;
 use tokio::sync::RwLock;
-use tokio::sync::{watch, Semaphore};
+use tokio::sync::Semaphore;
 use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::set_header::SetResponseHeaderLayer;
 use tokio::sync::mpsc; 
@@ -47,6 +48,9 @@ mod variables;
 mod charts;
 mod marketplace;
 mod secrets;
+mod secret_access_log;
+mod js_libraries;
+mod cache_invalidation;
 mod supabase_jwt_middleware;
 mod api_key_middleware;
 mod account_auth_middleware;
@@ -54,6 +58,10 @@ mod types;
 mod templater;
 mod testing; 
 mod trigger_engine;
+mod scheduled_runs;
+mod polling_triggers;
+mod webhook_requests;
+mod workflow_analysis;
 mod agents; 
 
 use tokio::sync::oneshot;
@@ -66,10 +74,16 @@ pub struct FlowCompletion {
     pub needs_response: bool,
 }
 
+#[derive(Clone, Debug)]
 pub struct CachedApiKey {
     pub account_id: String,
     pub secret_id: uuid::Uuid,
     pub secret_name: String,
+    pub created_by: String,
+    pub scopes: Vec<api_key_middleware::ApiKeyScope>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub allowed_ips: Vec<system_plugins::webhook_trigger::rate_limit::IpCidr>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 pub struct AppState {
@@ -79,7 +93,8 @@ pub struct AppState {
     http_client: Arc<Client>,
     workflow_processor_semaphore: Arc<Semaphore>,
     auth_states: RwLock<HashMap<String, AuthState>>,
-    trigger_engine_signal: watch::Sender<String>,
+    trigger_engine_signal: mpsc::UnboundedSender<String>,
+    trigger_engine_receiver: Mutex<mpsc::UnboundedReceiver<String>>,
     processor_sender: mpsc::Sender<ProcessorMessage>,
     processor_receiver: Mutex<mpsc::Receiver<ProcessorMessage>>, 
     flow_completions: Arc<Mutex<HashMap<String, FlowCompletion>>>,
@@ -87,7 +102,13 @@ pub struct AppState {
     account_access_cache: Arc<RwLock<account_auth_middleware::AccountAccessCache>>,
     bundler_secrets_cache: RwLock<SecretsCache>,
     bundler_accounts_cache: RwLock<AccountsCache>,
+    cache_bus: Arc<dyn cache_invalidation::CacheInvalidationBus>,
+    js_limits_cache: RwLock<system_plugins::javascript::limits::JsLimitsCache>,
+    js_fetcher: Arc<system_plugins::javascript::fetch::JsFetcher>,
+    webhook_callback_client: Client,
+    js_library_cache: RwLock<system_plugins::javascript::libraries::JsLibraryCache>,
     flow_session_cache: Arc<RwLock<processor::flow_session_cache::FlowSessionCache>>,
+    webhook_rate_limiter: RwLock<system_plugins::webhook_trigger::rate_limit::WebhookRateLimiter>,
     shutdown_signal: Arc<AtomicBool>,
 }
 
@@ -120,6 +141,21 @@ async fn main() {
             .insert_header("apikey", supabase_api_key.clone()),
     );
 
+    //`anything-server rotate-vault-key` re-wraps local vault data keys under the current master key and exits
+    if env::args().nth(1).as_deref() == Some("rotate-vault-key") {
+        match vault::local::rotate_master_key(&anything_client).await {
+            Ok(rotated) => println!("[VAULT] Rotated {} secrets to the current master key", rotated),
+            Err(e) => {
+                println!("[VAULT] Failed to rotate vault master key: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    //Fail on startup rather than on the first secret if the vault is misconfigured
+    vault::secret_vault();
+
     let cors_origin = Arc::new(cors_origin);
     println!("[CORS] CORS origin: {:?}", cors_origin);
 
@@ -174,7 +210,7 @@ async fn main() {
         HeaderValue::from_static("*"),
     );
 
-    let (trigger_engine_signal, _) = watch::channel("".to_string());
+    let (trigger_engine_signal, trigger_engine_rx) = mpsc::unbounded_channel::<String>();
     let (processor_tx, processor_rx) = mpsc::channel::<ProcessorMessage>(1000); // Create both sender and receiver
 
 
@@ -188,6 +224,7 @@ async fn main() {
         workflow_processor_semaphore: Arc::new(Semaphore::new(100)), //How many workflows we can run at once
 
         trigger_engine_signal,
+        trigger_engine_receiver: Mutex::new(trigger_engine_rx),
         processor_sender: processor_tx,
         processor_receiver: Mutex::new(processor_rx),
         flow_completions: Arc::new(Mutex::new(HashMap::new())),
@@ -197,7 +234,13 @@ async fn main() {
         )),
         bundler_secrets_cache: RwLock::new(SecretsCache::new(Duration::from_secs(86400))), // 1 day TTL
         bundler_accounts_cache: RwLock::new(AccountsCache::new(Duration::from_secs(86400))), // 1 day TTL
+        cache_bus: cache_invalidation::cache_invalidation_bus_from_env(), // Keeps the caches above in sync across instances
+        js_limits_cache: RwLock::new(system_plugins::javascript::limits::JsLimitsCache::new()),
+        js_fetcher: Arc::new(system_plugins::javascript::fetch::JsFetcher::from_env()), // fetch() for the JavaScript action
+        webhook_callback_client: system_plugins::javascript::fetch::public_only_client(Arc::new(system_plugins::javascript::fetch::JsFetchPolicy::default())), // X-Callback-Url deliveries, public addresses only
+        js_library_cache: RwLock::new(system_plugins::javascript::libraries::JsLibraryCache::new()),
         flow_session_cache: Arc::new(RwLock::new(processor::flow_session_cache::FlowSessionCache::new(Duration::from_secs(3600)))),
+        webhook_rate_limiter: RwLock::new(system_plugins::webhook_trigger::rate_limit::WebhookRateLimiter::new()),
         shutdown_signal: Arc::new(AtomicBool::new(false)),
     });
 
@@ -205,6 +248,19 @@ pub async fn root() -> impl IntoResponse {
     Html(r#"Check out <a href="https://www.tryanything.xyz">tryanything.xyz</a> to start"#)
 }
 
+    // API Routes for running workflows - every request is recorded in the webhook request log
+    let webhook_routes = Router::new()
+        .route("/api/v1/workflow/:workflow_id/start", any(system_plugins::webhook_trigger::run_workflow))
+        .route("/api/v1/workflow/:workflow_id/start/respond", any(system_plugins::webhook_trigger::run_workflow_and_respond))
+        .route("/api/v1/workflow/:workflow_id/version/:workflow_version_id/start", any(system_plugins::webhook_trigger::run_workflow_version))
+        .route("/api/v1/workflow/:workflow_id/version/:workflow_version_id/start/respond", any(system_plugins::webhook_trigger::run_workflow_version_and_respond))
+        .route("/api/v1/workflow/:workflow_id/start/async", any(system_plugins::webhook_trigger::async_webhook::run_workflow_and_respond_async))
+        .route("/api/v1/workflow/:workflow_id/version/:workflow_version_id/start/async", any(system_plugins::webhook_trigger::async_webhook::run_workflow_version_and_respond_async))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            system_plugins::webhook_trigger::request_log::webhook_request_log_middleware,
+        ));
+
     // Define routes that are public
     let public_routes = Router::new()
     .route("/", get(root))
@@ -235,10 +291,10 @@ pub async fn root() -> impl IntoResponse {
     .route("/marketplace/profile/:username", get(marketplace::profiles::get_marketplace_profile_by_username))
 
     // API Routes for running workflows - some protection done at api.rs vs route level
-    .route("/api/v1/workflow/:workflow_id/start", any(system_plugins::webhook_trigger::run_workflow))
-    .route("/api/v1/workflow/:workflow_id/start/respond", any(system_plugins::webhook_trigger::run_workflow_and_respond))
-    .route("/api/v1/workflow/:workflow_id/version/:workflow_version_id/start", any(system_plugins::webhook_trigger::run_workflow_version))
-    .route("/api/v1/workflow/:workflow_id/version/:workflow_version_id/start/respond", any(system_plugins::webhook_trigger::run_workflow_version_and_respond))
+    .route("/api/v1/flow_session/:flow_session_id", get(system_plugins::webhook_trigger::async_webhook::get_flow_session_status))
+    .route("/api/v1/workflow/:workflow_id/schedule", post(scheduled_runs::schedule_workflow_run))
+    .route("/api/v1/workflow/:workflow_id/schedule", get(scheduled_runs::get_scheduled_runs))
+    .route("/api/v1/workflow/:workflow_id/schedule/:scheduled_run_id", delete(scheduled_runs::cancel_scheduled_run))
 
     // API routes for running agent tools - very simliar to webhooks just shapped differnt to capture relationshipe between agent and workflow
     .route("/api/v1/agent/:agent_id/tool/:tool_id/start/respond", post(system_plugins::agent_tool_trigger::run_workflow_as_tool_call_and_respond));
@@ -261,6 +317,7 @@ pub async fn root() -> impl IntoResponse {
         )
         .route("/account/:account_id/workflow", post(workflows::create_workflow))
         .route("/account/:account_id/workflow/json", post(workflows::create_workflow_from_json))
+        .route("/account/:account_id/workflow/analyze", post(workflow_analysis::analyze_workflow))
         .route("/account/:account_id/workflow/:id", delete(workflows::delete_workflow))
         .route("/account/:account_id/workflow/:id", put(workflows::update_workflow))
         .route("/account/:account_id/actions", get(actions::get_actions))
@@ -284,6 +341,11 @@ pub async fn root() -> impl IntoResponse {
         .route("/account/:account_id/tasks", get(tasks::get_tasks))
         .route("/account/:account_id/tasks/:workflow_id", get(tasks::get_task_by_workflow_id))
 
+        //Webhook request log
+        .route("/account/:account_id/workflow/:workflow_id/webhook_requests", get(webhook_requests::get_webhook_requests))
+        .route("/account/:account_id/webhook_request/:webhook_request_id", get(webhook_requests::get_webhook_request))
+        .route("/account/:account_id/webhook_request/:webhook_request_id/replay", post(webhook_requests::replay_webhook_request))
+
         //Charts
         .route(
             "/account/:account_id/charts/:workflow_id/tasks/:start_date/:end_date/:time_unit/:timezone",
@@ -295,6 +357,14 @@ pub async fn root() -> impl IntoResponse {
         .route("/account/:account_id/secrets", get(secrets::get_decrypted_secrets))
         .route("/account/:account_id/secret", post(secrets::create_secret))
         .route("/account/:account_id/secret/:id", delete(secrets::delete_secret))
+        .route("/account/:account_id/secret/:id/scope", put(secrets::update_secret_scope))
+        .route("/account/:account_id/secret_access_log", get(secret_access_log::get_secret_access_log))
+
+        // JavaScript libraries
+        .route("/account/:account_id/javascript/libraries", get(js_libraries::get_js_libraries))
+        .route("/account/:account_id/javascript/library", post(js_libraries::create_js_library_version)) //creates the library or saves a new version
+        .route("/account/:account_id/javascript/library/:name", get(js_libraries::get_js_library).delete(js_libraries::archive_js_library))
+        .route("/account/:account_id/javascript/library/:name/version/:version", get(js_libraries::get_js_library_version))
         
         // User Facing API
         .route("/account/:account_id/keys", get(secrets::get_decrypted_anything_api_keys)) //read
@@ -377,9 +447,47 @@ pub async fn root() -> impl IntoResponse {
         .layer(middleware::from_fn(supabase_jwt_middleware::middleware));
    
 
+    // Management API for scripts and CI, authenticated with a scoped Anything API key instead of a session
+    let api_key_workflow_routes = Router::new()
+        .route("/api/v1/account/:account_id/workflows", get(workflows::get_workflows))
+        .route("/api/v1/account/:account_id/workflow/:id", get(workflows::get_workflow))
+        .route("/api/v1/account/:account_id/workflow/:id/versions", get(workflows::get_flow_versions))
+        .route(
+            "/api/v1/account/:account_id/workflow/:workflow_id/version/:workflow_version_id",
+            get(workflows::get_flow_version),
+        )
+        .route(
+            "/api/v1/account/:account_id/workflow/:workflow_id/version/:workflow_version_id",
+            put(workflows::update_workflow_version),
+        )
+        .route(
+            "/api/v1/account/:account_id/workflow/:workflow_id/version/:workflow_version_id/publish",
+            put(workflows::publish_workflow_version),
+        )
+        .route("/api/v1/account/:account_id/workflow", post(workflows::create_workflow))
+        .route("/api/v1/account/:account_id/workflow/json", post(workflows::create_workflow_from_json))
+        .route("/api/v1/account/:account_id/workflow/:id", delete(workflows::delete_workflow))
+        .route("/api/v1/account/:account_id/workflow/:id", put(workflows::update_workflow))
+        .route_layer(middleware::from_fn(api_key_middleware::require_manage_workflows));
+
+    let api_key_task_routes = Router::new()
+        .route("/api/v1/account/:account_id/tasks", get(tasks::get_tasks))
+        .route("/api/v1/account/:account_id/tasks/:workflow_id", get(tasks::get_task_by_workflow_id))
+        .route_layer(middleware::from_fn(api_key_middleware::require_read_tasks));
+
+    let api_key_routes = Router::new()
+        .merge(api_key_workflow_routes)
+        .merge(api_key_task_routes)
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            api_key_middleware::api_key_middleware,
+        ));
+
     let app = Router::new()
         .merge(public_routes) // Public routes
+        .merge(webhook_routes) // Webhook routes
         .merge(protected_routes) // Protected routes
+        .merge(api_key_routes) // API key routes
         .layer(cors)
         .layer(preflightlayer)
         .with_state(state.clone());
@@ -399,6 +507,10 @@ pub async fn root() -> impl IntoResponse {
     // Add the cache cleanup task here
     tokio::spawn(account_auth_middleware::cleanup_account_access_cache(state.clone()));
     tokio::spawn(bundler::cleanup_bundler_caches(state.clone()));
+    tokio::spawn(cache_invalidation::cache_invalidation_listener(state.clone()));
+    tokio::spawn(system_plugins::webhook_trigger::hmac_signature::cleanup_webhook_signatures(state.clone()));
+    tokio::spawn(system_plugins::webhook_trigger::rate_limit::cleanup_webhook_rate_limiter(state.clone()));
+    tokio::spawn(system_plugins::webhook_trigger::request_log::cleanup_webhook_requests(state.clone()));
 
     // Spawn the hydrate processor
     tokio::spawn(processor::hydrate_processor::hydrate_processor(state.clone()));
@@ -417,5 +529,11 @@ pub async fn root() -> impl IntoResponse {
 
     // Run the API server
     let listener = tokio::net::TcpListener::bind(&bind_address).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    //Connect info gives webhooks the client address for IP allowlists and rate limits
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 }
@@ -0,0 +1,455 @@
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{cmp::Ordering, collections::HashMap, env, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    bundler::{bundle_context_from_parts, secrets::SecretScope},
+    system_plugins::http::http_plugin::process_http_task,
+    templater::{TemplateError, Templater},
+    trigger_engine::{create_trigger_task_with_result, InMemoryTrigger},
+    types::{json_schema::ValidationFieldType, task_types::Stage},
+    AppState,
+};
+
+pub const POLLING_PLUGIN_NAME: &str = "@anything/polling";
+
+//How many of the most recent item ids we remember per trigger.
+//Needs to be larger than a page of results or old items will look new again.
+const MAX_SEEN_IDS: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DedupeMode {
+    SeenIds,       // Start items whose id we have not seen before
+    HighWaterMark, // Start items whose cursor is past the largest cursor we have seen
+}
+
+impl DedupeMode {
+    pub fn from_config(value: Option<&str>) -> Self {
+        match value {
+            Some("high_water_mark") => DedupeMode::HighWaterMark,
+            _ => DedupeMode::SeenIds,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PollingTriggerState {
+    pub account_id: String,
+    pub flow_id: String,
+    pub action_id: String,
+    pub seen_ids: Vec<String>,
+    pub high_water_mark: Option<String>,
+    pub last_polled_at: Option<DateTime<Utc>>,
+}
+
+//Calls the configured endpoint and starts one flow session per item we have not seen before.
+//The first poll only records what is already there so enabling a trigger doesn't replay history.
+pub async fn poll_trigger(
+    state: &Arc<AppState>,
+    trigger: &InMemoryTrigger,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!(
+        "[POLLING TRIGGER] Polling for workflow: {}, action: {}",
+        trigger.flow_id, trigger.action_id
+    );
+
+    //Render config when we poll so secrets and accounts are always current
+    let config = bundle_context_from_parts(
+        state.clone(),
+        &state.anything_client,
+        &trigger.account_id,
+        &Uuid::new_v4().to_string(),
+        &SecretScope::new(Uuid::parse_str(&trigger.flow_id).ok(), Stage::Production),
+        trigger.config.inputs.as_ref(),
+        trigger.config.inputs_schema.as_ref(),
+        trigger.config.plugin_config.as_ref(),
+        trigger.config.plugin_config_schema.as_ref(),
+        false,
+    )
+    .await?;
+
+    let response = process_http_task(&state.http_client, &config)
+        .await?
+        .ok_or("Polling request returned no result")?;
+
+    let status_code = response["status_code"].as_u64().unwrap_or(0);
+    if !(200..300).contains(&status_code) {
+        return Err(format!("Polling request failed with status code {}", status_code).into());
+    }
+
+    let items = extract_items(&response, config["items_path"].as_str().unwrap_or(""))?;
+    let mode = DedupeMode::from_config(config["dedupe_mode"].as_str());
+    let id_field = config["id_field"].as_str().unwrap_or("id");
+    let cursor_field = config["cursor_field"].as_str().unwrap_or("");
+
+    if mode == DedupeMode::HighWaterMark && cursor_field.is_empty() {
+        return Err("Polling trigger with high_water_mark dedupe needs a cursor_field".into());
+    }
+
+    let stored = fetch_polling_state(state, &trigger.flow_id, &trigger.action_id).await?;
+    let is_first_poll = stored.is_none();
+
+    let mut polling_state = stored.unwrap_or(PollingTriggerState {
+        account_id: trigger.account_id.clone(),
+        flow_id: trigger.flow_id.clone(),
+        action_id: trigger.action_id.clone(),
+        seen_ids: Vec::new(),
+        high_water_mark: None,
+        last_polled_at: None,
+    });
+
+    let new_items = select_new_items(&items, &mode, id_field, cursor_field, &polling_state)?;
+
+    println!(
+        "[POLLING TRIGGER] Found {} items, {} new",
+        items.len(),
+        new_items.len()
+    );
+
+    if is_first_poll {
+        println!(
+            "[POLLING TRIGGER] First poll, recording existing items without starting workflows"
+        );
+    } else {
+        for item in &new_items {
+            let result = json!({
+                "item": item.value,
+                "polled_at": Utc::now(),
+                "created_at": Utc::now()
+            });
+            if let Err(e) = create_trigger_task_with_result(state, trigger, result).await {
+                println!("[POLLING TRIGGER] Error creating trigger task: {:?}", e);
+            }
+        }
+    }
+
+    record_new_items(&mut polling_state, &new_items);
+    polling_state.last_polled_at = Some(Utc::now());
+    save_polling_state(state, &polling_state).await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct PolledItem {
+    pub id: Option<String>,
+    pub cursor: Option<String>,
+    pub value: Value,
+}
+
+//Pulls the list of items out of the http result using a templated path like "body.data"
+pub fn extract_items(response: &Value, items_path: &str) -> Result<Vec<Value>, TemplateError> {
+    let path = match items_path.trim() {
+        "" => "response".to_string(),
+        path => format!("response.{}", path),
+    };
+
+    match render_path(
+        &json!({ "response": response }),
+        &path,
+        ValidationFieldType::Array,
+    )? {
+        Value::Array(items) => Ok(items),
+        _ => Err(TemplateError {
+            message: "Polling items path did not resolve to an array".to_string(),
+            variable: path,
+        }),
+    }
+}
+
+pub fn select_new_items(
+    items: &[Value],
+    mode: &DedupeMode,
+    id_field: &str,
+    cursor_field: &str,
+    polling_state: &PollingTriggerState,
+) -> Result<Vec<PolledItem>, TemplateError> {
+    let mut new_items = Vec::new();
+
+    for item in items {
+        let context = json!({ "item": item });
+        match mode {
+            DedupeMode::SeenIds => {
+                //One malformed item shouldn't hold back every other new item
+                let id = match item_field_as_string(&context, id_field) {
+                    Ok(id) if !id.is_empty() => id,
+                    _ => {
+                        println!(
+                            "[POLLING TRIGGER] Skipping item without a value at {}",
+                            id_field
+                        );
+                        continue;
+                    }
+                };
+                let already_seen = polling_state.seen_ids.contains(&id)
+                    || new_items
+                        .iter()
+                        .any(|i: &PolledItem| i.id.as_ref() == Some(&id));
+                if !already_seen {
+                    new_items.push(PolledItem {
+                        id: Some(id),
+                        cursor: None,
+                        value: item.clone(),
+                    });
+                }
+            }
+            DedupeMode::HighWaterMark => {
+                let cursor = match item_field_as_string(&context, cursor_field) {
+                    Ok(cursor) if !cursor.is_empty() => cursor,
+                    _ => {
+                        println!(
+                            "[POLLING TRIGGER] Skipping item without a value at {}",
+                            cursor_field
+                        );
+                        continue;
+                    }
+                };
+                let is_new = match &polling_state.high_water_mark {
+                    Some(mark) => compare_cursors(&cursor, mark) == Ordering::Greater,
+                    None => true,
+                };
+                if is_new {
+                    new_items.push(PolledItem {
+                        id: None,
+                        cursor: Some(cursor),
+                        value: item.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    //Start high-water mark items oldest first so workflows see them in order
+    if *mode == DedupeMode::HighWaterMark {
+        new_items.sort_by(|a, b| {
+            compare_cursors(
+                a.cursor.as_deref().unwrap_or(""),
+                b.cursor.as_deref().unwrap_or(""),
+            )
+        });
+    }
+
+    Ok(new_items)
+}
+
+fn record_new_items(polling_state: &mut PollingTriggerState, new_items: &[PolledItem]) {
+    let mut seen_ids: Vec<String> = new_items.iter().filter_map(|i| i.id.clone()).collect();
+    seen_ids.extend(polling_state.seen_ids.drain(..));
+    seen_ids.truncate(MAX_SEEN_IDS);
+    polling_state.seen_ids = seen_ids;
+
+    for cursor in new_items.iter().filter_map(|i| i.cursor.as_ref()) {
+        let is_higher = match &polling_state.high_water_mark {
+            Some(mark) => compare_cursors(cursor, mark) == Ordering::Greater,
+            None => true,
+        };
+        if is_higher {
+            polling_state.high_water_mark = Some(cursor.clone());
+        }
+    }
+}
+
+//Numbers compare numerically, everything else (ISO timestamps, sortable ids) lexically
+fn compare_cursors(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+fn item_field_as_string(context: &Value, field: &str) -> Result<String, TemplateError> {
+    let value = render_path(
+        context,
+        &format!("item.{}", field),
+        ValidationFieldType::String,
+    )?;
+    Ok(value.as_str().unwrap_or_default().to_string())
+}
+
+fn render_path(
+    context: &Value,
+    path: &str,
+    expected_type: ValidationFieldType,
+) -> Result<Value, TemplateError> {
+    let mut templater = Templater::new();
+    templater.add_template("path", json!({ "value": format!("{{{{{}}}}}", path) }));
+
+    let mut validations = HashMap::new();
+    validations.insert("value".to_string(), expected_type);
+
+    let rendered = templater.render("path", context, validations)?;
+    Ok(rendered["value"].clone())
+}
+
+async fn fetch_polling_state(
+    state: &AppState,
+    flow_id: &str,
+    action_id: &str,
+) -> Result<Option<PollingTriggerState>, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let response = state
+        .anything_client
+        .from("polling_trigger_state")
+        .auth(supabase_service_role_api_key)
+        .select("account_id, flow_id, action_id, seen_ids, high_water_mark, last_polled_at")
+        .eq("flow_id", flow_id)
+        .eq("action_id", action_id)
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+    let mut rows: Vec<PollingTriggerState> = serde_json::from_str(&body)?;
+    Ok(rows.pop())
+}
+
+async fn save_polling_state(
+    state: &AppState,
+    polling_state: &PollingTriggerState,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let response = state
+        .anything_client
+        .from("polling_trigger_state")
+        .auth(supabase_service_role_api_key)
+        .upsert(serde_json::to_string(polling_state)?)
+        .on_conflict("flow_id,action_id")
+        .execute()
+        .await?;
+
+    if !response.status().is_success() {
+        let body = response.text().await?;
+        return Err(format!("Failed to save polling trigger state: {}", body).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_state() -> PollingTriggerState {
+        PollingTriggerState {
+            account_id: "account".to_string(),
+            flow_id: "flow".to_string(),
+            action_id: "action".to_string(),
+            seen_ids: Vec::new(),
+            high_water_mark: None,
+            last_polled_at: None,
+        }
+    }
+
+    fn ids(items: &[PolledItem]) -> Vec<String> {
+        items.iter().filter_map(|item| item.id.clone()).collect()
+    }
+
+    #[test]
+    fn extracts_items_from_the_configured_path() {
+        let response =
+            json!({ "status_code": 200, "body": { "data": [{ "id": "a" }, { "id": "b" }] } });
+
+        assert_eq!(
+            extract_items(&response, "body.data").unwrap(),
+            vec![json!({ "id": "a" }), json!({ "id": "b" })]
+        );
+        assert!(extract_items(&response, "body").is_err());
+    }
+
+    #[test]
+    fn first_poll_seeds_seen_ids_and_later_polls_only_return_new_items() {
+        let mut state = empty_state();
+        let first_page = vec![json!({ "id": "a" }), json!({ "id": "b" })];
+
+        //Everything is new on the first poll, recording it is what keeps it from running later
+        let seeded = select_new_items(&first_page, &DedupeMode::SeenIds, "id", "", &state).unwrap();
+        assert_eq!(ids(&seeded), vec!["a", "b"]);
+        record_new_items(&mut state, &seeded);
+
+        //Duplicates inside a page only count once
+        let second_page = vec![
+            json!({ "id": "c" }),
+            json!({ "id": "a" }),
+            json!({ "id": "c" }),
+            json!({ "id": "b" }),
+        ];
+        let new_items =
+            select_new_items(&second_page, &DedupeMode::SeenIds, "id", "", &state).unwrap();
+        assert_eq!(ids(&new_items), vec!["c"]);
+
+        record_new_items(&mut state, &new_items);
+        assert_eq!(state.seen_ids, vec!["c", "a", "b"]);
+
+        //Items without an id are skipped instead of failing the poll
+        let with_missing_id = vec![json!({ "name": "no id" }), json!({ "id": "d" })];
+        let new_items =
+            select_new_items(&with_missing_id, &DedupeMode::SeenIds, "id", "", &state).unwrap();
+        assert_eq!(ids(&new_items), vec!["d"]);
+        assert!(
+            select_new_items(&second_page, &DedupeMode::SeenIds, "id", "", &state)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn seen_ids_keep_the_most_recent_entries() {
+        let mut state = empty_state();
+        state.seen_ids = (0..MAX_SEEN_IDS).map(|i| format!("old-{}", i)).collect();
+
+        let new_items = select_new_items(
+            &[json!({ "id": "new-1" }), json!({ "id": "new-2" })],
+            &DedupeMode::SeenIds,
+            "id",
+            "",
+            &state,
+        )
+        .unwrap();
+        record_new_items(&mut state, &new_items);
+
+        assert_eq!(state.seen_ids.len(), MAX_SEEN_IDS);
+        assert_eq!(&state.seen_ids[..3], &["new-1", "new-2", "old-0"]);
+        assert!(!state
+            .seen_ids
+            .contains(&format!("old-{}", MAX_SEEN_IDS - 1)));
+    }
+
+    #[test]
+    fn high_water_mark_starts_newer_items_in_cursor_order() {
+        assert_eq!(compare_cursors("9", "10"), Ordering::Less);
+        assert_eq!(compare_cursors("10", "9.5"), Ordering::Greater);
+        assert_eq!(
+            compare_cursors("2024-01-09T00:00:00Z", "2024-01-10T00:00:00Z"),
+            Ordering::Less
+        );
+
+        let mut state = empty_state();
+        state.high_water_mark = Some("10".to_string());
+        let items = vec![
+            json!({ "updated": "12" }),
+            json!({ "updated": "9" }),
+            json!({ "updated": "11" }),
+            json!({ "updated": "10" }),
+        ];
+
+        let new_items =
+            select_new_items(&items, &DedupeMode::HighWaterMark, "id", "updated", &state).unwrap();
+        let cursors: Vec<_> = new_items
+            .iter()
+            .filter_map(|item| item.cursor.clone())
+            .collect();
+        assert_eq!(cursors, vec!["11", "12"]);
+
+        record_new_items(&mut state, &new_items);
+        assert_eq!(state.high_water_mark.as_deref(), Some("12"));
+        assert!(state.seen_ids.is_empty());
+    }
+}
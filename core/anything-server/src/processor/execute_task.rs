@@ -3,6 +3,7 @@ use std::sync::Arc;
 use postgrest::Postgrest;
 
 use crate::bundler::bundle_tasks_cached_context;
+use crate::bundler::redaction::SecretRedactor;
 use crate::processor::process_trigger_utils::process_trigger_task;
 use crate::system_plugins::formatter_actions::{
     date_formatter::process_date_task, text_formatter::process_text_task,
@@ -10,7 +11,9 @@ use crate::system_plugins::formatter_actions::{
 use crate::system_plugins::webhook_response::process_webhook_response_task;
 
 use crate::system_plugins::http::http_plugin::process_http_task;
-use crate::system_plugins::javascript::process_js_task;
+use crate::system_plugins::javascript::libraries::js_library_loader;
+use crate::system_plugins::javascript::limits::js_limits_for_account;
+use crate::system_plugins::javascript::{js_error_details, process_js_task};
 use crate::types::task_types::Task;
 use crate::AppState;
 use crate::system_plugins::agent_tool_trigger_response::process_tool_call_result_task;
@@ -18,13 +21,15 @@ use serde_json::{json, Value};
 
 use crate::types::action_types::ActionType;
 
+//context and error are redacted, result keeps real values for the tasks that follow.
+//Use the redactor before persisting or logging the result.
 #[derive(Debug, Clone)]
 pub struct TaskError {
     pub error: Value,
     pub context: Value,
 }
 
-pub type TaskResult = Result<(Option<Value>, Value), TaskError>;
+pub type TaskResult = Result<(Option<Value>, Value, SecretRedactor), TaskError>;
 
 pub async fn execute_task(state: Arc<AppState>, client: &Postgrest, task: &Task) -> TaskResult {
     println!("[PROCESS TASK] Processing task {}", task.task_id);
@@ -33,13 +38,15 @@ pub async fn execute_task(state: Arc<AppState>, client: &Postgrest, task: &Task)
     let state_clone = Arc::clone(&state);
 
     // Bundle context with results from cache
-    let bundled_context_result: Result<(Value, Value), Box<dyn std::error::Error + Send + Sync>> =
-        bundle_tasks_cached_context(state, client, task, true).await;
+    let bundled_context_result: Result<
+        (Value, Value, SecretRedactor),
+        Box<dyn std::error::Error + Send + Sync>,
+    > = bundle_tasks_cached_context(state, client, task, true).await;
 
     let http_client = state_clone.http_client.clone();
 
     match bundled_context_result {
-        Ok((bundled_inputs, bundled_plugin_cofig)) => {
+        Ok((bundled_inputs, bundled_plugin_cofig, redactor)) => {
             let task_result = if task.r#type == ActionType::Trigger.as_str().to_string() {
                 println!("[PROCESS TASK] Processing trigger task {}", task.task_id);
                 process_trigger_task(task)
@@ -53,7 +60,34 @@ pub async fn execute_task(state: Arc<AppState>, client: &Postgrest, task: &Task)
                         //JS need bundled variables because variables are injected into the JS runtime vs tempalted into the string like we do other places.
                         //Honestly not sure this is required vs templating the text but it feels safer even if this adds a anit pattern to task processing for JS.
                         "@anything/javascript" => {
-                            process_js_task(&bundled_inputs, &bundled_plugin_cofig).await
+                            let limits = js_limits_for_account(
+                                &state_clone,
+                                &task.account_id.to_string(),
+                            )
+                            .await;
+                            match js_library_loader(
+                                state_clone.clone(),
+                                task.account_id.to_string(),
+                                task.flow_version_id.to_string(),
+                            )
+                            .await
+                            {
+                                Ok(libraries) => {
+                                    process_js_task(
+                                        &bundled_inputs,
+                                        &bundled_plugin_cofig,
+                                        limits,
+                                        state_clone.js_fetcher.clone(),
+                                        libraries,
+                                    )
+                                    .await
+                                }
+                                Err(e) => Err(format!(
+                                    "Failed to load the JavaScript library versions for this workflow version: {}",
+                                    e
+                                )
+                                .into()),
+                            }
                         }
                         "@anything/webhook_response" => {
                             process_webhook_response_task(
@@ -81,12 +115,23 @@ pub async fn execute_task(state: Arc<AppState>, client: &Postgrest, task: &Task)
                 }
             };
 
+            //Plugins ran with the real values, everything kept after this point is redacted
+            let context = redactor.redact_value(&bundled_plugin_cofig);
             match task_result {
-                Ok(result) => Ok((result, bundled_plugin_cofig)),
-                Err(e) => Err(TaskError {
-                    error: json!({ "message": e.to_string() }),
-                    context: bundled_plugin_cofig,
-                }),
+                Ok(result) => Ok((result, context, redactor)),
+                Err(e) => {
+                    let mut error = json!({ "message": redactor.redact_str(&e.to_string()) });
+                    //Limit errors carry a code and the limit that was hit, script errors their logs
+                    if let Some(details) = js_error_details(e.as_ref()) {
+                        let details = redactor.redact_value(&details);
+                        if let (Some(error), Some(details)) =
+                            (error.as_object_mut(), details.as_object())
+                        {
+                            error.extend(details.clone());
+                        }
+                    }
+                    Err(TaskError { error, context })
+                }
             }
         }
         Err(e) => {
@@ -1,9 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tracing::debug;
 use uuid::Uuid;
 
+use crate::templater::CompiledTemplate;
 use crate::types::task_types::Task;
 use crate::types::workflow_types::DatabaseFlowVersion;
 
@@ -22,8 +24,23 @@ struct CachedSession {
     expires_at: SystemTime,
 }
 
+//Templates of one action, parsed when its workflow version is loaded.
+//None when the action has nothing to render or the template did not compile,
+//the bundler then compiles it at render time so the error reaches the task.
+#[derive(Debug, Default)]
+pub struct CompiledActionTemplates {
+    pub inputs: Option<CompiledTemplate>,
+    pub plugin_config: Option<CompiledTemplate>,
+}
+
+struct CachedTemplates {
+    templates: HashMap<String, Arc<CompiledActionTemplates>>, // action_id -> templates
+    expires_at: SystemTime,
+}
+
 pub struct FlowSessionCache {
     cache: HashMap<Uuid, CachedSession>, // flow_session_id -> session data
+    compiled_templates: HashMap<Uuid, CachedTemplates>, // flow_version_id -> compiled templates
     ttl: Duration,
 }
 
@@ -35,6 +52,7 @@ impl FlowSessionCache {
         );
         Self {
             cache: HashMap::new(),
+            compiled_templates: HashMap::new(),
             ttl,
         }
     }
@@ -56,10 +74,73 @@ impl FlowSessionCache {
             flow_session_id
         );
         let expires_at = SystemTime::now() + self.ttl;
+        //Sessions of the same version share one set of compiled templates, the bundler
+        //compiles them outside the lock the first time a task of the version renders
+        if let Some(workflow) = &data.workflow {
+            if let Some(cached) = self.compiled_templates.get_mut(&workflow.flow_version_id) {
+                cached.expires_at = expires_at;
+            }
+        }
         let cached_session = CachedSession { data, expires_at };
         self.cache.insert(*flow_session_id, cached_session);
     }
 
+    //Just the workflow of a session, so compiling templates doesn't clone every task
+    pub fn get_workflow(&self, flow_session_id: &Uuid) -> Option<DatabaseFlowVersion> {
+        self.cache
+            .get(flow_session_id)
+            .filter(|entry| entry.expires_at > SystemTime::now())
+            .and_then(|entry| entry.data.workflow.clone())
+    }
+
+    pub fn compiled_templates_missing(&self, flow_version_id: &Uuid) -> bool {
+        !matches!(
+            self.compiled_templates.get(flow_version_id),
+            Some(cached) if cached.expires_at > SystemTime::now()
+        )
+    }
+
+    //Keeps templates another task compiled first, both came from the same version
+    pub fn insert_compiled_templates(
+        &mut self,
+        flow_version_id: Uuid,
+        templates: HashMap<String, Arc<CompiledActionTemplates>>,
+    ) {
+        if !self.compiled_templates_missing(&flow_version_id) {
+            return;
+        }
+        self.compiled_templates.insert(
+            flow_version_id,
+            CachedTemplates {
+                templates,
+                expires_at: SystemTime::now() + self.ttl,
+            },
+        );
+    }
+
+    pub fn get_compiled_templates(
+        &self,
+        flow_version_id: &Uuid,
+        action_id: &str,
+    ) -> Option<Arc<CompiledActionTemplates>> {
+        self.compiled_templates
+            .get(flow_version_id)
+            .filter(|cached| cached.expires_at > SystemTime::now())
+            .and_then(|cached| cached.templates.get(action_id).cloned())
+    }
+
+    pub fn invalidate_compiled_templates(&mut self, flow_version_id: &Uuid) {
+        println!(
+            "[PROCESSOR] Invalidating compiled templates for workflow version: {}",
+            flow_version_id
+        );
+        self.compiled_templates.remove(flow_version_id);
+    }
+
+    pub fn clear_compiled_templates(&mut self) {
+        self.compiled_templates.clear();
+    }
+
     pub fn add_task(&mut self, flow_session_id: &Uuid, task: Task) -> bool {
         if let Some(cached_session) = self.cache.get_mut(flow_session_id) {
             if SystemTime::now() > cached_session.expires_at {
@@ -107,5 +188,43 @@ impl FlowSessionCache {
         println!("[PROCESSOR] Starting flow session cache cleanup");
         let now = SystemTime::now();
         self.cache.retain(|_, session| session.expires_at > now);
+        self.compiled_templates
+            .retain(|_, templates| templates.expires_at > now);
     }
 }
+
+//Parses every action's templates of a workflow version. Runs without the cache lock held,
+//a large workflow shouldn't stall every other session while it compiles.
+pub fn compile_workflow_templates(
+    workflow: &DatabaseFlowVersion,
+) -> HashMap<String, Arc<CompiledActionTemplates>> {
+    println!(
+        "[PROCESSOR] Compiling templates for workflow version: {}",
+        workflow.flow_version_id
+    );
+    let compile = |template: Option<&serde_json::Value>, action_id: &str| {
+        template.and_then(|template| match CompiledTemplate::compile(template) {
+            Ok(compiled) => Some(compiled),
+            Err(e) => {
+                println!(
+                    "[PROCESSOR] Failed to compile template for action {}: {}",
+                    action_id, e
+                );
+                None
+            }
+        })
+    };
+
+    workflow
+        .flow_definition
+        .actions
+        .iter()
+        .map(|action| {
+            let compiled = CompiledActionTemplates {
+                inputs: compile(action.inputs.as_ref(), &action.action_id),
+                plugin_config: compile(Some(&action.plugin_config), &action.action_id),
+            };
+            (action.action_id.clone(), Arc::new(compiled))
+        })
+        .collect()
+}
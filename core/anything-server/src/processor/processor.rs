@@ -305,11 +305,7 @@ pub async fn processor(
                                         r#type: action.r#type.clone(),
                                         plugin_name: action.plugin_name.clone(),
                                         plugin_version: action.plugin_version.clone(),
-                                        stage: if workflow.published {
-                                            Stage::Production.as_str().to_string()
-                                        } else {
-                                            Stage::Testing.as_str().to_string()
-                                        },
+                                        stage: task.stage.as_str().to_string(),
                                         config: TaskConfig {
                                             inputs: Some(action.inputs.clone().unwrap()),
                                             inputs_schema: Some(
@@ -376,7 +372,7 @@ pub async fn processor(
 
                 let processing_order = task.processing_order;
 
-                let (task_result, bundled_context) =
+                let (task_result, bundled_context, redactor) =
                     match execute_task(state.clone(), &client, &task).await {
                         Ok(success_value) => {
                             println!("[PROCESSOR] Task {} completed successfully", task.task_id);
@@ -455,7 +451,10 @@ pub async fn processor(
                 // Spawn task status update to DB asynchronously
                 let state_clone = state.clone();
                 let task_id = task.task_id.clone();
-                let task_result_clone = task_result.clone();
+                //Persist results without secrets, the cache keeps real values for the next tasks
+                let task_result_clone = task_result
+                    .as_ref()
+                    .map(|result| redactor.redact_value(result));
                 let bundled_context_clone = bundled_context.clone();
                 tokio::spawn(async move {
                     if let Err(e) = update_task_status(
@@ -531,11 +530,8 @@ pub async fn processor(
                         r#type: next_action.r#type,
                         plugin_name: next_action.plugin_name.clone(),
                         plugin_version: next_action.plugin_version.clone(),
-                        stage: if workflow.published {
-                            Stage::Production.as_str().to_string()
-                        } else {
-                            Stage::Testing.as_str().to_string()
-                        },
+                        //Test runs stay in testing so every task gets sandbox secrets
+                        stage: task.stage.as_str().to_string(),
                         config: TaskConfig {
                             inputs: Some(next_action.inputs.clone().unwrap()),
                             inputs_schema: Some(next_action.inputs_schema.clone().unwrap()),
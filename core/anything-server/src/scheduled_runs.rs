@@ -0,0 +1,619 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, env, net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    processor::{
+        db_calls::get_workflow_definition, parsing_utils::get_trigger_node,
+        processor::ProcessorMessage,
+    },
+    system_plugins::webhook_trigger::{
+        rate_limit::get_client_ip, webhook_trigger_utils::validate_api_key,
+    },
+    types::{
+        action_types::ActionType,
+        task_types::{
+            CreateTaskInput, FlowSessionStatus, Stage, TaskConfig, TaskStatus, TriggerSessionStatus,
+        },
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduledRunStatus {
+    Pending,  // Waiting for run_at
+    Fired,    // Handed to the processor
+    Canceled, // Canceled before it fired
+    Failed,   // Could not be started
+}
+
+impl ScheduledRunStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ScheduledRunStatus::Pending => "pending",
+            ScheduledRunStatus::Fired => "fired",
+            ScheduledRunStatus::Canceled => "canceled",
+            ScheduledRunStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScheduledRun {
+    pub scheduled_run_id: Uuid,
+    pub account_id: Uuid,
+    pub flow_id: Uuid,
+    pub flow_version_id: Option<Uuid>,
+    pub run_at: DateTime<Utc>,
+    pub payload: Option<Value>,
+    pub status: ScheduledRunStatus,
+    pub flow_session_id: Option<Uuid>,
+    pub fired_at: Option<DateTime<Utc>>,
+    pub error: Option<Value>,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduleWorkflowRunPayload {
+    pub run_at: Option<DateTime<Utc>>,
+    pub delay_seconds: Option<u64>,
+    pub payload: Option<Value>,
+    pub workflow_version_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreateScheduledRunInput {
+    account_id: String,
+    flow_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flow_version_id: Option<String>,
+    run_at: DateTime<Utc>,
+    payload: Value,
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScheduledRunsQuery {
+    pub status: Option<String>,
+}
+
+//Scheduled runs are driven by the API so we authenticate with an Anything API key
+//and make sure the key belongs to the account that owns the workflow
+async fn authorize_workflow_api_request(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    peer_addr: &SocketAddr,
+    workflow_id: &str,
+) -> Result<String, (StatusCode, &'static str)> {
+    let api_key = match headers.get("Authorization").and_then(|h| h.to_str().ok()) {
+        Some(header) if header.starts_with("Bearer ") => header[7..].to_string(),
+        _ => return Err((StatusCode::UNAUTHORIZED, "Missing or invalid API key")),
+    };
+
+    let client_ip = get_client_ip(headers, peer_addr);
+    let account_id = validate_api_key(state.clone(), api_key, workflow_id, client_ip).await?;
+
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let response = state
+        .anything_client
+        .from("flows")
+        .auth(supabase_service_role_api_key)
+        .select("flow_id")
+        .eq("flow_id", workflow_id)
+        .eq("account_id", &account_id)
+        .eq("archived", "false")
+        .execute()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to execute request"))?;
+
+    let body = response
+        .text()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response body"))?;
+
+    let flows: Vec<Value> = serde_json::from_str(&body).unwrap_or_default();
+    if flows.is_empty() {
+        println!(
+            "[SCHEDULED RUNS] Workflow {} not found for account {}",
+            workflow_id, account_id
+        );
+        return Err((StatusCode::NOT_FOUND, "Workflow not found"));
+    }
+
+    Ok(account_id)
+}
+
+pub async fn schedule_workflow_run(
+    Path(workflow_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<ScheduleWorkflowRunPayload>,
+) -> impl IntoResponse {
+    println!("[SCHEDULED RUNS] Handling schedule_workflow_run for {}", workflow_id);
+
+    let account_id = match authorize_workflow_api_request(state.clone(), &headers, &peer_addr, &workflow_id).await
+    {
+        Ok(account_id) => account_id,
+        Err(response) => return response.into_response(),
+    };
+
+    let run_at = match (payload.run_at, payload.delay_seconds) {
+        (Some(run_at), None) => run_at,
+        (None, Some(delay_seconds)) => {
+            //Anything past chrono's range would panic, reject it instead
+            match i64::try_from(delay_seconds)
+                .ok()
+                .and_then(chrono::TimeDelta::try_seconds)
+                .and_then(|delay| Utc::now().checked_add_signed(delay))
+            {
+                Some(run_at) => run_at,
+                None => {
+                    return (StatusCode::BAD_REQUEST, "delay_seconds is too large").into_response()
+                }
+            }
+        }
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Provide exactly one of run_at or delay_seconds",
+            )
+                .into_response()
+        }
+    };
+
+    let input = CreateScheduledRunInput {
+        account_id: account_id.clone(),
+        flow_id: workflow_id.clone(),
+        flow_version_id: payload.workflow_version_id.map(|id| id.to_string()),
+        run_at,
+        payload: payload.payload.unwrap_or(json!({})),
+        status: ScheduledRunStatus::Pending.as_str().to_string(),
+    };
+
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let response = match state
+        .anything_client
+        .from("scheduled_runs")
+        .auth(supabase_service_role_api_key)
+        .insert(serde_json::to_string(&input).unwrap())
+        .execute()
+        .await
+    {
+        Ok(response) => response,
+        Err(err) => {
+            println!("[SCHEDULED RUNS] Failed to create scheduled run: {:?}", err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response();
+        }
+    };
+
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response()
+        }
+    };
+
+    let created: Vec<ScheduledRun> = match serde_json::from_str(&body) {
+        Ok(created) => created,
+        Err(err) => {
+            println!("[SCHEDULED RUNS] Failed to parse scheduled run: {:?} {}", err, body);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON").into_response();
+        }
+    };
+
+    let scheduled_run = match created.into_iter().next() {
+        Some(run) => run,
+        None => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "No scheduled run was created",
+            )
+                .into_response()
+        }
+    };
+
+    // Let the trigger engine pick up the new run
+    if let Err(err) = state.trigger_engine_signal.send(workflow_id) {
+        println!("[SCHEDULED RUNS] Failed to send trigger signal: {:?}", err);
+    }
+
+    (StatusCode::CREATED, Json(scheduled_run)).into_response()
+}
+
+pub async fn get_scheduled_runs(
+    Path(workflow_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<ScheduledRunsQuery>,
+) -> impl IntoResponse {
+    println!("[SCHEDULED RUNS] Handling get_scheduled_runs for {}", workflow_id);
+
+    let account_id = match authorize_workflow_api_request(state.clone(), &headers, &peer_addr, &workflow_id).await
+    {
+        Ok(account_id) => account_id,
+        Err(response) => return response.into_response(),
+    };
+
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let mut request = state
+        .anything_client
+        .from("scheduled_runs")
+        .auth(supabase_service_role_api_key)
+        .select("*")
+        .eq("account_id", &account_id)
+        .eq("flow_id", &workflow_id);
+
+    if let Some(status) = &query.status {
+        request = request.eq("status", status);
+    }
+
+    let response = match request.order("run_at.asc").limit(100).execute().await {
+        Ok(response) => response,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response()
+        }
+    };
+
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response()
+        }
+    };
+
+    let items: Value = match serde_json::from_str(&body) {
+        Ok(items) => items,
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON").into_response()
+        }
+    };
+
+    Json(items).into_response()
+}
+
+pub async fn cancel_scheduled_run(
+    Path((workflow_id, scheduled_run_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    println!(
+        "[SCHEDULED RUNS] Canceling scheduled run {} for {}",
+        scheduled_run_id, workflow_id
+    );
+
+    let account_id = match authorize_workflow_api_request(state.clone(), &headers, &peer_addr, &workflow_id).await
+    {
+        Ok(account_id) => account_id,
+        Err(response) => return response.into_response(),
+    };
+
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    //Only pending runs can be canceled. Fired runs are already flow sessions.
+    let response = match state
+        .anything_client
+        .from("scheduled_runs")
+        .auth(supabase_service_role_api_key)
+        .eq("scheduled_run_id", &scheduled_run_id)
+        .eq("account_id", &account_id)
+        .eq("flow_id", &workflow_id)
+        .eq("status", ScheduledRunStatus::Pending.as_str())
+        .update(json!({ "status": ScheduledRunStatus::Canceled.as_str() }).to_string())
+        .execute()
+        .await
+    {
+        Ok(response) => response,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response()
+        }
+    };
+
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response()
+        }
+    };
+
+    let canceled: Vec<ScheduledRun> = serde_json::from_str(&body).unwrap_or_default();
+    let scheduled_run = match canceled.into_iter().next() {
+        Some(run) => run,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                "No pending scheduled run found to cancel",
+            )
+                .into_response()
+        }
+    };
+
+    // Let the trigger engine drop the canceled run from memory
+    if let Err(err) = state.trigger_engine_signal.send(workflow_id) {
+        println!("[SCHEDULED RUNS] Failed to send trigger signal: {:?}", err);
+    }
+
+    Json(scheduled_run).into_response()
+}
+
+//Rows fetched per request when loading pending runs, kept under PostgREST's max rows
+const SCHEDULED_RUN_PAGE_SIZE: usize = 500;
+
+//Used by the trigger engine to load pending runs. Pass a workflow_id to only load one workflow.
+//Pages by scheduled_run_id, runs that fire while we page don't shift the later pages.
+pub async fn fetch_pending_scheduled_runs(
+    state: &AppState,
+    workflow_id: Option<&str>,
+) -> Result<Vec<ScheduledRun>, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let mut runs: Vec<ScheduledRun> = Vec::new();
+    loop {
+        let mut request = state
+            .anything_client
+            .from("scheduled_runs")
+            .auth(supabase_service_role_api_key.clone())
+            .select("*")
+            .eq("status", ScheduledRunStatus::Pending.as_str());
+
+        if let Some(workflow_id) = workflow_id {
+            request = request.eq("flow_id", workflow_id);
+        }
+        if let Some(last) = runs.last() {
+            request = request.gt("scheduled_run_id", last.scheduled_run_id.to_string());
+        }
+
+        let response = request
+            .order("scheduled_run_id.asc")
+            .limit(SCHEDULED_RUN_PAGE_SIZE)
+            .execute()
+            .await?;
+        let body = response.text().await?;
+        let page: Vec<ScheduledRun> = serde_json::from_str(&body)?;
+
+        let page_len = page.len();
+        runs.extend(page);
+        if page_len < SCHEDULED_RUN_PAGE_SIZE {
+            break;
+        }
+    }
+
+    Ok(runs)
+}
+
+//Marks a pending run as fired. Returns false if another instance or a cancel got there first.
+async fn claim_scheduled_run(
+    state: &AppState,
+    scheduled_run_id: &Uuid,
+    flow_session_id: &Uuid,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let response = state
+        .anything_client
+        .from("scheduled_runs")
+        .auth(supabase_service_role_api_key)
+        .eq("scheduled_run_id", scheduled_run_id.to_string())
+        .eq("status", ScheduledRunStatus::Pending.as_str())
+        .update(
+            json!({
+                "status": ScheduledRunStatus::Fired.as_str(),
+                "flow_session_id": flow_session_id,
+                "fired_at": Utc::now(),
+            })
+            .to_string(),
+        )
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+    let claimed: Vec<Value> = serde_json::from_str(&body).unwrap_or_default();
+    Ok(!claimed.is_empty())
+}
+
+//Only moves the run out of the status the caller saw, so a run another instance fired or
+//a user canceled in the meantime keeps its status
+async fn mark_scheduled_run_failed(
+    state: &AppState,
+    scheduled_run_id: &Uuid,
+    expected_status: ScheduledRunStatus,
+    error: &str,
+) {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    if let Err(e) = state
+        .anything_client
+        .from("scheduled_runs")
+        .auth(supabase_service_role_api_key)
+        .eq("scheduled_run_id", scheduled_run_id.to_string())
+        .eq("status", expected_status.as_str())
+        .update(
+            json!({
+                "status": ScheduledRunStatus::Failed.as_str(),
+                "error": { "message": error },
+            })
+            .to_string(),
+        )
+        .execute()
+        .await
+    {
+        println!("[SCHEDULED RUNS] Failed to mark run as failed: {:?}", e);
+    }
+}
+
+//Starts a flow session for a scheduled run using the workflow's trigger with the payload as its result
+pub async fn fire_scheduled_run(
+    state: &Arc<AppState>,
+    run: &ScheduledRun,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!(
+        "[SCHEDULED RUNS] Firing scheduled run {} for workflow {}",
+        run.scheduled_run_id, run.flow_id
+    );
+
+    let workflow =
+        match get_workflow_definition(state.clone(), &run.flow_id, run.flow_version_id.as_ref())
+            .await
+        {
+            Ok(workflow) => workflow,
+            Err(e) => {
+                mark_scheduled_run_failed(
+                    state,
+                    &run.scheduled_run_id,
+                    ScheduledRunStatus::Pending,
+                    &e,
+                )
+                .await;
+                return Err(e.into());
+            }
+        };
+
+    let trigger_node = match get_trigger_node(&workflow.flow_definition) {
+        Some(trigger_node) => trigger_node,
+        None => {
+            let message = "No trigger found in workflow";
+            mark_scheduled_run_failed(
+                state,
+                &run.scheduled_run_id,
+                ScheduledRunStatus::Pending,
+                message,
+            )
+            .await;
+            return Err(message.into());
+        }
+    };
+
+    let flow_session_id = Uuid::new_v4();
+    let trigger_session_id = Uuid::new_v4();
+
+    if !claim_scheduled_run(state, &run.scheduled_run_id, &flow_session_id).await? {
+        println!(
+            "[SCHEDULED RUNS] Scheduled run {} is no longer pending, skipping",
+            run.scheduled_run_id
+        );
+        return Ok(());
+    }
+
+    let input = CreateTaskInput {
+        account_id: run.account_id.to_string(),
+        task_status: TaskStatus::Running.as_str().to_string(),
+        flow_id: run.flow_id.to_string(),
+        flow_version_id: workflow.flow_version_id.to_string(),
+        action_label: trigger_node.label.clone(),
+        trigger_id: trigger_node.action_id.clone(),
+        trigger_session_id: trigger_session_id.to_string(),
+        trigger_session_status: TriggerSessionStatus::Running.as_str().to_string(),
+        flow_session_id: flow_session_id.to_string(),
+        flow_session_status: FlowSessionStatus::Running.as_str().to_string(),
+        action_id: trigger_node.action_id.clone(),
+        r#type: ActionType::Trigger,
+        plugin_name: trigger_node.plugin_name.clone(),
+        plugin_version: trigger_node.plugin_version.clone(),
+        stage: Stage::Production.as_str().to_string(),
+        config: TaskConfig {
+            inputs: trigger_node.inputs.clone(),
+            inputs_schema: trigger_node.inputs_schema.clone(),
+            plugin_config: Some(trigger_node.plugin_config.clone()),
+            plugin_config_schema: Some(trigger_node.plugin_config_schema.clone()),
+        },
+        result: Some(json!({
+            "body": run.payload.clone().unwrap_or(json!({})),
+            "scheduled_run_id": run.scheduled_run_id,
+            "run_at": run.run_at,
+            "created_at": Utc::now(),
+        })),
+        error: None,
+        test_config: None,
+        processing_order: 0,
+        started_at: Some(Utc::now()),
+    };
+
+    let processor_message = ProcessorMessage {
+        workflow_id: run.flow_id,
+        version_id: Some(workflow.flow_version_id),
+        flow_session_id,
+        trigger_session_id,
+        trigger_task: Some(input),
+    };
+
+    if let Err(e) = state.processor_sender.send(processor_message).await {
+        println!("[SCHEDULED RUNS] Failed to send message to processor: {}", e);
+        let message = format!("Failed to send message to processor: {}", e);
+        //Already claimed, nothing else will pick it up
+        mark_scheduled_run_failed(
+            state,
+            &run.scheduled_run_id,
+            ScheduledRunStatus::Fired,
+            &message,
+        )
+        .await;
+        return Err(message.into());
+    }
+
+    println!(
+        "[SCHEDULED RUNS] Scheduled run {} started flow session {}",
+        run.scheduled_run_id, flow_session_id
+    );
+
+    Ok(())
+}
+
+//Group pending runs by id for the trigger engine's in memory store
+pub fn index_scheduled_runs(runs: Vec<ScheduledRun>) -> HashMap<String, ScheduledRun> {
+    runs.into_iter()
+        .map(|run| (run.scheduled_run_id.to_string(), run))
+        .collect()
+}
@@ -0,0 +1,351 @@
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashSet, env, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    supabase_jwt_middleware::User, templater::Templater, types::task_types::Task,
+    workflow_analysis::root_and_key, AppState,
+};
+
+const SECRET_ACCESS_LOG_PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretAccessAction {
+    //A workflow rendered the value into a task
+    Read,
+    Create,
+    Update,
+    //The decrypted value was returned by the API
+    Reveal,
+    Delete,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretResourceType {
+    Secret,
+    ApiKey,
+    Account,
+}
+
+//Where a workflow read happened
+#[derive(Debug, Clone, Default)]
+pub struct SecretAccessContext {
+    pub flow_id: Option<Uuid>,
+    pub flow_version_id: Option<Uuid>,
+    pub flow_session_id: Option<String>,
+    pub task_id: Option<Uuid>,
+}
+
+impl SecretAccessContext {
+    pub fn for_task(task: &Task) -> Self {
+        Self {
+            flow_id: Some(task.flow_id),
+            flow_version_id: Some(task.flow_version_id),
+            flow_session_id: Some(task.flow_session_id.to_string()),
+            task_id: Some(task.task_id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct SecretAccessEvent {
+    pub account_id: String,
+    pub action: SecretAccessAction,
+    pub resource_type: SecretResourceType,
+    pub resource_id: Option<String>,
+    pub resource_name: Option<String>,
+    pub flow_id: Option<Uuid>,
+    pub flow_version_id: Option<Uuid>,
+    pub flow_session_id: Option<String>,
+    pub task_id: Option<Uuid>,
+    pub actor_user_id: Option<String>,
+}
+
+impl SecretAccessEvent {
+    //A change or reveal made by a person through the API
+    pub fn by_user(
+        user: &User,
+        account_id: &str,
+        action: SecretAccessAction,
+        resource_type: SecretResourceType,
+        resource_id: Option<String>,
+        resource_name: Option<String>,
+    ) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            action,
+            resource_type,
+            resource_id,
+            resource_name,
+            flow_id: None,
+            flow_version_id: None,
+            flow_session_id: None,
+            task_id: None,
+            actor_user_id: Some(user.account_id.clone()),
+        }
+    }
+
+    //A value read while the bundler rendered a workflow's templates
+    pub fn by_workflow(
+        account_id: &str,
+        context: &SecretAccessContext,
+        resource_type: SecretResourceType,
+        resource_id: String,
+        resource_name: String,
+    ) -> Self {
+        Self {
+            account_id: account_id.to_string(),
+            action: SecretAccessAction::Read,
+            resource_type,
+            resource_id: Some(resource_id),
+            resource_name: Some(resource_name),
+            flow_id: context.flow_id,
+            flow_version_id: context.flow_version_id,
+            flow_session_id: context.flow_session_id.clone(),
+            task_id: context.task_id,
+            actor_user_id: None,
+        }
+    }
+}
+
+//Names a template reads under one root. `All` when it reads the whole object or uses a
+//query we can't narrow down, so everything that was available counts as read.
+#[derive(Debug, PartialEq)]
+pub enum ReferencedNames {
+    All,
+    Names(HashSet<String>),
+}
+
+impl ReferencedNames {
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            ReferencedNames::All => true,
+            ReferencedNames::Names(names) => names.contains(name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TemplateSecretReferences {
+    pub secrets: ReferencedNames,
+    pub accounts: ReferencedNames,
+}
+
+//Secrets and accounts the inputs template refers to
+pub fn template_secret_references(template: &Value) -> TemplateSecretReferences {
+    let mut templater = Templater::new();
+    templater.add_template("inputs", template.clone());
+
+    let variables = match templater.get_template_variables("inputs") {
+        Ok(variables) => variables,
+        Err(_) => {
+            return TemplateSecretReferences {
+                secrets: ReferencedNames::All,
+                accounts: ReferencedNames::All,
+            }
+        }
+    };
+
+    let mut secrets = ReferencedNames::Names(HashSet::new());
+    let mut accounts = ReferencedNames::Names(HashSet::new());
+    for variable in variables {
+        let (names, key) = match root_and_key(&variable) {
+            Some((root, key)) if root == "secrets" => (&mut secrets, key),
+            Some((root, key)) if root == "accounts" => (&mut accounts, key),
+            Some(_) => continue,
+            //Recursive queries like $..token can reach anything
+            None => {
+                secrets = ReferencedNames::All;
+                accounts = ReferencedNames::All;
+                continue;
+            }
+        };
+        match (names, key) {
+            (ReferencedNames::Names(names), Some(key)) => {
+                names.insert(key);
+            }
+            (names, None) => *names = ReferencedNames::All,
+            _ => {}
+        }
+    }
+
+    TemplateSecretReferences { secrets, accounts }
+}
+
+//Appends to the log in the background so callers never wait on it
+pub fn record_secret_access(state: Arc<AppState>, events: Vec<SecretAccessEvent>) {
+    if events.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        if let Err(e) = insert_secret_access_events(&state, &events).await {
+            println!("[SECRET ACCESS LOG] Failed to store access events: {}", e);
+        }
+    });
+}
+
+async fn insert_secret_access_events(
+    state: &AppState,
+    events: &[SecretAccessEvent],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let response = state
+        .anything_client
+        .from("secret_access_log")
+        .auth(supabase_service_role_api_key)
+        .insert(serde_json::to_string(events)?)
+        .execute()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await?;
+        return Err(format!("Failed to insert secret access events: {}", body).into());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SecretAccessLogQuery {
+    pub action: Option<String>,
+    pub resource_type: Option<String>,
+    pub resource_id: Option<String>,
+    pub flow_id: Option<String>,
+    pub flow_session_id: Option<String>,
+    pub task_id: Option<String>,
+    pub actor_user_id: Option<String>,
+    //RFC 3339 timestamps
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub page: Option<usize>,
+}
+
+pub async fn get_secret_access_log(
+    Path(account_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Query(query): Query<SecretAccessLogQuery>,
+) -> impl IntoResponse {
+    println!(
+        "[SECRET ACCESS LOG] Handling get_secret_access_log for account {}",
+        account_id
+    );
+
+    let page = query.page.unwrap_or(0);
+    let start = page * SECRET_ACCESS_LOG_PAGE_SIZE;
+
+    let mut request = state
+        .anything_client
+        .from("secret_access_log")
+        .auth(&user.jwt)
+        .select("*")
+        .eq("account_id", &account_id);
+
+    let filters = [
+        ("action", &query.action),
+        ("resource_type", &query.resource_type),
+        ("resource_id", &query.resource_id),
+        ("flow_id", &query.flow_id),
+        ("flow_session_id", &query.flow_session_id),
+        ("task_id", &query.task_id),
+        ("actor_user_id", &query.actor_user_id),
+    ];
+    for (column, value) in filters {
+        if let Some(value) = value {
+            request = request.eq(column, value);
+        }
+    }
+    if let Some(since) = &query.since {
+        request = request.gte("created_at", since);
+    }
+    if let Some(until) = &query.until {
+        request = request.lt("created_at", until);
+    }
+
+    let response = match request
+        .order("created_at.desc")
+        .range(start, start + SECRET_ACCESS_LOG_PAGE_SIZE - 1)
+        .execute()
+        .await
+    {
+        Ok(response) => response,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response()
+        }
+    };
+
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response()
+        }
+    };
+
+    let items: Value = match serde_json::from_str(&body) {
+        Ok(items) => items,
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON").into_response()
+        }
+    };
+
+    Json(items).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn names(names: &[&str]) -> ReferencedNames {
+        ReferencedNames::Names(names.iter().map(|name| name.to_string()).collect())
+    }
+
+    #[test]
+    fn finds_secrets_and_accounts_read_by_templates() {
+        let references = template_secret_references(&json!({
+            "url": "https://api.example.com/{{actions.fetch.result.id}}",
+            "headers": {
+                "Authorization": "Bearer {{secrets.API_KEY}}",
+                "X-Token": "{{$.accounts['github'].access_token}}"
+            },
+            "body": "{{#if secrets.DEBUG_TOKEN}}debug{{/if}}"
+        }));
+        assert_eq!(
+            references,
+            TemplateSecretReferences {
+                secrets: names(&["API_KEY", "DEBUG_TOKEN"]),
+                accounts: names(&["github"]),
+            }
+        );
+
+        let references = template_secret_references(&json!({
+            "all": "{{secrets}}",
+            "deep": "{{$..refresh_token}}"
+        }));
+        assert_eq!(references.secrets, ReferencedNames::All);
+        assert_eq!(references.accounts, ReferencedNames::All);
+        assert!(references.secrets.contains("ANYTHING"));
+    }
+}
@@ -5,22 +5,38 @@ use axum::{
     Json,
 };
 
+use postgrest::Postgrest;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
 use std::sync::Arc;
 
+use crate::api_key_middleware::{parse_allowed_ips, ApiKeyScope};
+use crate::bundler::secrets::fetch_secrets_from_vault;
+use crate::cache_invalidation::{invalidate_caches, CacheInvalidation};
+use crate::secret_access_log::{
+    record_secret_access, SecretAccessAction, SecretAccessEvent, SecretResourceType,
+};
 use crate::supabase_jwt_middleware::User;
+use crate::vault::read_secrets_from_vault;
 use crate::AppState;
 
+use chrono::{DateTime, Utc};
 use dotenv::dotenv;
 use slugify::slugify;
+use uuid::Uuid;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateSecretPayload {
     secret_name: String,
     secret_value: String,
     secret_description: String,
+    #[serde(default)]
+    testing_secret_value: Option<String>, // used instead of secret_value by test runs
+    #[serde(default)]
+    flow_ids: Option<Vec<Uuid>>, // workflows allowed to use the secret, none means all
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -37,6 +53,8 @@ pub struct AnythingCreateSecretInput {
     vault_secret_id: String,
     secret_description: String,
     account_id: String,
+    testing_vault_secret_id: Option<String>,
+    flow_ids: Option<Vec<Uuid>>,
 }
 
 pub async fn create_secret(
@@ -82,12 +100,36 @@ pub async fn create_secret(
         }
     };
 
+    // Sandbox value lives in its own vault secret
+    let testing_vault_secret_id = match &payload.testing_secret_value {
+        Some(testing_value) => match crate::vault::insert_secret_to_vault(
+            client,
+            &format!("{}_testing", input.name),
+            testing_value,
+            &input.description,
+        )
+        .await
+        {
+            Ok(id) => Some(id),
+            Err(_) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to create testing secret in vault",
+                )
+                    .into_response()
+            }
+        },
+        None => None,
+    };
+
     let anything_secret_input = AnythingCreateSecretInput {
         secret_id: secret_vault_id.clone(),
         secret_name: payload.secret_name.clone(),
         vault_secret_id: secret_vault_id,
         secret_description: payload.secret_description.clone(),
         account_id: account_id.clone(),
+        testing_vault_secret_id,
+        flow_ids: payload.flow_ids.clone(),
     };
 
     //Create Flow Version
@@ -121,20 +163,232 @@ pub async fn create_secret(
 
     println!("DB Secret Body: {:?}", db_secret_body);
 
+    record_secret_access(
+        state.clone(),
+        vec![SecretAccessEvent::by_user(
+            &user,
+            &account_id,
+            SecretAccessAction::Create,
+            SecretResourceType::Secret,
+            Some(anything_secret_input.secret_id.clone()),
+            Some(payload.secret_name.clone()),
+        )],
+    );
+
     // Invalidate the bundler secrets cache for this account after creating a new secret
-    // Only lock for the minimum time needed
+    invalidate_caches(
+        &state,
+        CacheInvalidation::Secrets {
+            account_id: account_id.clone(),
+        },
+    )
+    .await;
+
+    Json(db_secret_body).into_response()
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UpdateSecretScopePayload {
+    //Left out keeps the current workflows, null or empty opens the secret to every workflow
+    #[serde(default, deserialize_with = "deserialize_present")]
+    flow_ids: Option<Option<Vec<Uuid>>>,
+    #[serde(default)]
+    testing_secret_value: Option<String>,
+}
+
+//Some(None) for an explicit null, fields that are left out stay None through serde(default)
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+//Only the fields the request set, a request that just sets the test value keeps the workflows
+fn secret_scope_update(payload: &UpdateSecretScopePayload) -> Value {
+    let mut update = json!({});
+    if let Some(flow_ids) = &payload.flow_ids {
+        update["flow_ids"] = json!(flow_ids);
+    }
+    update
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretScopeRow {
+    secret_name: String,
+    secret_description: Option<String>,
+    testing_vault_secret_id: Option<String>,
+}
+
+//Limits a secret to some workflows and sets or replaces its test run value
+pub async fn update_secret_scope(
+    Path((account_id, secret_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(payload): Json<UpdateSecretScopePayload>,
+) -> impl IntoResponse {
+    println!(
+        "[SECRETS] Updating scope of secret {} for account {}",
+        secret_id, account_id
+    );
+
+    let client = &state.anything_client;
+
+    // RLS makes sure the user can see this secret
+    let response = match client
+        .from("secrets")
+        .auth(user.jwt.clone())
+        .eq("secret_id", &secret_id)
+        .eq("account_id", &account_id)
+        .eq("anything_api_key", "false")
+        .select("secret_name,secret_description,testing_vault_secret_id")
+        .single()
+        .execute()
+        .await
     {
-        let mut cache = state.bundler_secrets_cache.write().await;
-        cache.invalidate(&account_id);
+        Ok(response) => response,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response()
+        }
+    };
+
+    let row: SecretScopeRow = match response.text().await {
+        Ok(body) => match serde_json::from_str(&body) {
+            Ok(row) => row,
+            Err(_) => return (StatusCode::NOT_FOUND, "Secret not found").into_response(),
+        },
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response()
+        }
+    };
+
+    if payload.flow_ids.is_none() && payload.testing_secret_value.is_none() {
+        return (StatusCode::BAD_REQUEST, "Nothing to update").into_response();
     }
 
-    Json(db_secret_body).into_response()
+    let mut update = secret_scope_update(&payload);
+
+    if let Some(testing_value) = &payload.testing_secret_value {
+        match &row.testing_vault_secret_id {
+            Some(testing_vault_secret_id) => {
+                if crate::vault::update_secret_in_vault(
+                    client,
+                    testing_vault_secret_id,
+                    testing_value,
+                )
+                .await
+                .is_err()
+                {
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Failed to update testing secret in vault",
+                    )
+                        .into_response();
+                }
+            }
+            None => {
+                let vault_secret_name = slugify!(
+                    format!("{}_{}_testing", account_id, row.secret_name).as_str(),
+                    separator = "_"
+                );
+                match crate::vault::insert_secret_to_vault(
+                    client,
+                    &vault_secret_name,
+                    testing_value,
+                    row.secret_description.as_deref().unwrap_or(""),
+                )
+                .await
+                {
+                    Ok(id) => update["testing_vault_secret_id"] = Value::String(id),
+                    Err(_) => {
+                        return (
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Failed to create testing secret in vault",
+                        )
+                            .into_response()
+                    }
+                }
+            }
+        }
+    }
+
+    //A new value for an existing test secret only changes the vault, the write still returns the row
+    if update.as_object().is_some_and(|fields| fields.is_empty()) {
+        update["secret_name"] = Value::String(row.secret_name.clone());
+    }
+
+    let response = match client
+        .from("secrets")
+        .auth(user.jwt.clone())
+        .eq("secret_id", &secret_id)
+        .eq("account_id", &account_id)
+        .update(update.to_string())
+        .execute()
+        .await
+    {
+        Ok(response) => response,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response()
+        }
+    };
+
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response()
+        }
+    };
+
+    record_secret_access(
+        state.clone(),
+        vec![SecretAccessEvent::by_user(
+            &user,
+            &account_id,
+            SecretAccessAction::Update,
+            SecretResourceType::Secret,
+            Some(secret_id.clone()),
+            Some(row.secret_name.clone()),
+        )],
+    );
+
+    invalidate_caches(
+        &state,
+        CacheInvalidation::Secrets {
+            account_id: account_id.clone(),
+        },
+    )
+    .await;
+
+    Json(body).into_response()
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CreateAnythingApiKeyPayload {
     secret_name: String,
     secret_description: String,
+    #[serde(default)]
+    scopes: Option<Vec<String>>, // none means every scope
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    allowed_ips: Option<Vec<String>>, // CIDRs or addresses, none allows any address
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -145,6 +399,10 @@ pub struct CreateAnythingApiKeySecretInput {
     secret_description: String,
     account_id: String,
     anything_api_key: bool,
+    api_key_scopes: Vec<String>,
+    api_key_expires_at: Option<DateTime<Utc>>,
+    api_key_allowed_ips: Option<Vec<String>>,
+    api_key_hash: String,
 }
 
 pub async fn create_anything_api_key(
@@ -170,6 +428,26 @@ pub async fn create_anything_api_key(
 
     println!("New Name: {}", vault_secret_name);
 
+    let scopes = match payload.scopes.as_deref() {
+        Some([]) => {
+            return (StatusCode::BAD_REQUEST, "API key needs at least one scope").into_response()
+        }
+        Some(scopes) => ApiKeyScope::parse_all(Some(scopes)),
+        None => Ok(vec![ApiKeyScope::All]),
+    };
+    let scopes = match scopes {
+        Ok(scopes) => scopes,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    if let Err(e) = parse_allowed_ips(payload.allowed_ips.as_deref()) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    if matches!(payload.expires_at, Some(expires_at) if expires_at <= Utc::now()) {
+        return (StatusCode::BAD_REQUEST, "expires_at must be in the future").into_response();
+    }
+
     // Generate a unique API key with a prefix for easy identification
     let api_key = format!("any_{}", uuid::Uuid::new_v4());
 
@@ -199,6 +477,10 @@ pub async fn create_anything_api_key(
         secret_description: payload.secret_description.clone(),
         account_id: account_id.clone(),
         anything_api_key: true,
+        api_key_scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+        api_key_expires_at: payload.expires_at,
+        api_key_allowed_ips: payload.allowed_ips.clone(),
+        api_key_hash: api_key_hash(&api_key),
     };
 
     //Create Flow Version
@@ -232,12 +514,19 @@ pub async fn create_anything_api_key(
 
     println!("DB Secret Body: {:?}", db_secret_body);
 
-    Json(db_secret_body).into_response()
-}
+    record_secret_access(
+        state.clone(),
+        vec![SecretAccessEvent::by_user(
+            &user,
+            &account_id,
+            SecretAccessAction::Create,
+            SecretResourceType::ApiKey,
+            Some(anything_secret_input.secret_id.clone()),
+            Some(payload.secret_name.clone()),
+        )],
+    );
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct GetDecryptedSecretsInput {
-    team_account_id: String,
+    Json(db_secret_body).into_response()
 }
 
 // Secrets
@@ -248,118 +537,158 @@ pub async fn get_decrypted_secrets(
 ) -> impl IntoResponse {
     println!("Handling a get_decrypted_secrets");
 
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-
-    let input = GetDecryptedSecretsInput {
-        team_account_id: account_id,
-    };
-
-    println!("get_decrypted_secrets rpc Input?: {:?}", input);
-
     let client = &state.anything_client;
 
-    let response = match client
-        .rpc(
-            "get_decrypted_secrets",
-            serde_json::to_string(&input).unwrap(),
-        )
-        .auth(supabase_service_role_api_key.clone())
-        .execute()
-        .await
-    {
-        Ok(response) => response,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to execute request",
-            )
-                .into_response()
+    //Same read the bundler does, values come from whichever vault backend is configured
+    let secrets = match fetch_secrets_from_vault(client, &account_id).await {
+        Ok(secrets) => secrets,
+        Err(e) => {
+            println!("Failed to read secrets for account {}: {:?}", account_id, e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read secrets").into_response();
         }
     };
 
-    let body = match response.text().await {
-        Ok(body) => body,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read response body",
-            )
-                .into_response()
-        }
-    };
+    let items = serde_json::to_value(&secrets).unwrap_or_else(|_| Value::Array(vec![]));
 
-    let items: Value = match serde_json::from_str(&body) {
-        Ok(items) => items,
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON").into_response()
-        }
-    };
+    record_secret_access(
+        state.clone(),
+        revealed_events(&user, &account_id, SecretResourceType::Secret, &items),
+    );
 
     Json(items).into_response()
 }
 
+//Row in anything.secrets for an API key, the value is read from the vault by id
+#[derive(Debug, Deserialize)]
+struct ApiKeyRow {
+    secret_id: String,
+    secret_name: String,
+    secret_description: Option<String>,
+    vault_secret_id: String,
+    api_key_scopes: Option<Vec<String>>,
+    api_key_expires_at: Option<String>,
+    api_key_allowed_ips: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiKeyUsageRow {
+    secret_id: String,
+    last_used_at: Option<String>,
+}
+
+async fn service_role_rows<T: serde::de::DeserializeOwned>(
+    request: postgrest::Builder,
+) -> Result<Vec<T>, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")?;
+    let response = request
+        .auth(supabase_service_role_api_key)
+        .execute()
+        .await?;
+    let body = response.text().await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+async fn fetch_api_keys_from_vault(
+    client: &Postgrest,
+    account_id: &str,
+) -> Result<Vec<Value>, Box<dyn std::error::Error + Send + Sync>> {
+    let rows: Vec<ApiKeyRow> = service_role_rows(
+        client
+            .from("secrets")
+            .eq("account_id", account_id)
+            .eq("anything_api_key", "true")
+            .select("secret_id,secret_name,secret_description,vault_secret_id,api_key_scopes,api_key_expires_at,api_key_allowed_ips"),
+    )
+    .await?;
+
+    let usage: Vec<ApiKeyUsageRow> = service_role_rows(
+        client
+            .from("api_key_usage")
+            .eq("account_id", account_id)
+            .select("secret_id,last_used_at"),
+    )
+    .await?;
+    let mut last_used: HashMap<String, Option<String>> = usage
+        .into_iter()
+        .map(|row| (row.secret_id, row.last_used_at))
+        .collect();
+
+    let vault_ids: Vec<String> = rows.iter().map(|row| row.vault_secret_id.clone()).collect();
+    let mut values = read_secrets_from_vault(client, &vault_ids).await?;
+
+    //Keys whose value is gone from the vault can't be used anymore, leave them out
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            let secret_value = values.remove(&row.vault_secret_id)?;
+            Some(json!({
+                "secret_id": row.secret_id,
+                "secret_name": row.secret_name,
+                "secret_value": secret_value,
+                "secret_description": row.secret_description,
+                "api_key_scopes": row.api_key_scopes,
+                "api_key_expires_at": row.api_key_expires_at,
+                "api_key_allowed_ips": row.api_key_allowed_ips,
+                "api_key_last_used_at": last_used.remove(&row.secret_id).flatten(),
+            }))
+        })
+        .collect())
+}
+
 // Secrets
 pub async fn get_decrypted_anything_api_keys(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
     Path(account_id): Path<String>,
 ) -> impl IntoResponse {
-    println!("Handling a get_decrypted_secrets");
-
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-
-    let input = GetDecryptedSecretsInput {
-        team_account_id: account_id,
-    };
-
-    println!("get_decrypted_anything_api_keys rpc Input?: {:?}", input);
+    println!("Handling a get_decrypted_anything_api_keys");
 
     let client = &state.anything_client;
 
-    let response = match client
-        .rpc(
-            "get_decrypted_anything_api_keys",
-            serde_json::to_string(&input).unwrap(),
-        )
-        .auth(supabase_service_role_api_key.clone())
-        .execute()
-        .await
-    {
-        Ok(response) => response,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to execute request",
-            )
-                .into_response()
-        }
-    };
-
-    let body = match response.text().await {
-        Ok(body) => body,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read response body",
-            )
-                .into_response()
+    let items = match fetch_api_keys_from_vault(client, &account_id).await {
+        Ok(items) => Value::Array(items),
+        Err(e) => {
+            println!(
+                "Failed to read API keys for account {}: {:?}",
+                account_id, e
+            );
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read API keys").into_response();
         }
     };
 
-    let items: Value = match serde_json::from_str(&body) {
-        Ok(items) => items,
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON").into_response()
-        }
-    };
+    record_secret_access(
+        state.clone(),
+        revealed_events(&user, &account_id, SecretResourceType::ApiKey, &items),
+    );
 
     Json(items).into_response()
 }
 
+//One reveal per row the API handed back decrypted
+fn revealed_events(
+    user: &User,
+    account_id: &str,
+    resource_type: SecretResourceType,
+    items: &Value,
+) -> Vec<SecretAccessEvent> {
+    items
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|item| {
+            SecretAccessEvent::by_user(
+                user,
+                account_id,
+                SecretAccessAction::Reveal,
+                resource_type,
+                item["secret_id"].as_str().map(str::to_string),
+                item["secret_name"].as_str().map(str::to_string),
+            )
+        })
+        .collect()
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct UpdateSecretPayload {
     secret_id: String,
@@ -390,16 +719,6 @@ pub struct ReadVaultDecryptedSecretInput {
     secret_uuid: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct DeleteVaultSecretInput {
-    secret_id: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct GetSecretBySecretValueInput {
-    secret_value: String,
-}
-
 pub async fn delete_secret(
     Path((account_id, secret_id)): Path<(String, String)>,
     State(state): State<Arc<AppState>>,
@@ -416,7 +735,7 @@ pub async fn delete_secret(
     // Delete in DB
     let response = match client
         .from("secrets")
-        .auth(user.jwt)
+        .auth(user.jwt.clone())
         .eq("secret_id", &secret_id)
         .eq("account_id", &account_id)
         .delete()
@@ -447,57 +766,59 @@ pub async fn delete_secret(
     println!("Delete DB Secret Body: {:?}", body);
 
     //Delete in Vault
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-
     //If the user is allowed to delete the secret from the anything.secrets table the RLS policy means they are allowed to delete from vault.
     // It should fail if the user is not allowed to delete from the anything.secrets table
     // So this should be safe ( but i wish it was safer )
     //TODO: protect this more. right now its a little open.
     //TODO: protect this more. right now its a little open.
-    let input = DeleteVaultSecretInput {
-        secret_id: secret_id.clone(),
-    };
-
-    println!("delete secret rpc Input?: {:?}", input);
+    if let Err(e) = crate::vault::delete_secret_from_vault(client, &secret_id).await {
+        println!("Failed to delete secret from vault: {:?}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to execute request",
+        )
+            .into_response();
+    }
 
-    let rpc_response = match client
-        .rpc("delete_secret", serde_json::to_string(&input).unwrap())
-        .auth(supabase_service_role_api_key.clone())
-        .execute()
-        .await
-    {
-        Ok(response) => response,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to execute request",
-            )
-                .into_response()
-        }
-    };
+    //The deleted row comes back in the body, drop its sandbox value too
+    let deleted_row = serde_json::from_str::<Vec<Value>>(&body)
+        .ok()
+        .and_then(|rows| rows.into_iter().next());
+    let testing_vault_secret_id = deleted_row
+        .as_ref()
+        .and_then(|row| row["testing_vault_secret_id"].as_str().map(str::to_string));
+
+    record_secret_access(
+        state.clone(),
+        vec![SecretAccessEvent::by_user(
+            &user,
+            &account_id,
+            SecretAccessAction::Delete,
+            SecretResourceType::Secret,
+            Some(secret_id.clone()),
+            deleted_row
+                .as_ref()
+                .and_then(|row| row["secret_name"].as_str().map(str::to_string)),
+        )],
+    );
 
-    let rpc_body = match rpc_response.text().await {
-        Ok(body) => body,
-        Err(_) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read response body",
-            )
-                .into_response()
+    if let Some(testing_vault_secret_id) = testing_vault_secret_id {
+        if let Err(e) =
+            crate::vault::delete_secret_from_vault(client, &testing_vault_secret_id).await
+        {
+            println!("Failed to delete testing secret from vault: {:?}", e);
         }
-    };
-
-    println!("Delete Vault Secret Body: {:?}", rpc_body);
-
-    // Invalidate the bundler secrets cache for this account after creating a new secret
-    // Only lock for the minimum time needed
-    {
-        let mut cache = state.bundler_secrets_cache.write().await;
-        cache.invalidate(&account_id);
     }
 
+    // Invalidate the bundler secrets cache for this account on every instance
+    invalidate_caches(
+        &state,
+        CacheInvalidation::Secrets {
+            account_id: account_id.clone(),
+        },
+    )
+    .await;
+
     Json(body).into_response()
 }
 
@@ -514,96 +835,11 @@ pub async fn delete_api_key(
 
     let client = &state.anything_client;
 
-    // Get the API key value from vault before deleting
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-    let get_secret_input = ReadVaultDecryptedSecretInput {
-        secret_uuid: secret_id.clone(),
-    };
-    println!("[DELETE API KEY] Getting secret value from vault");
-    let get_secret_response = match client
-        .rpc(
-            "get_decrypted_secret",
-            serde_json::to_string(&get_secret_input).unwrap(),
-        )
-        .auth(supabase_service_role_api_key.clone())
-        .execute()
-        .await
-    {
-        Ok(response) => {
-            println!("[DELETE API KEY] Got response from vault: {:?}", response);
-            response
-        }
-        Err(e) => {
-            println!("[DELETE API KEY] Failed to get secret from vault: {:?}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to execute request",
-            )
-                .into_response();
-        }
-    };
-
-    let secret_body = match get_secret_response.text().await {
-        Ok(body) => {
-            println!("[DELETE API KEY] Got secret body from response: {:?}", body);
-            body
-        }
-        Err(e) => {
-            println!(
-                "[DELETE API KEY] Failed to read secret value from response: {:?}",
-                e
-            );
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read response body",
-            )
-                .into_response();
-        }
-    };
-
-    let secret_json: Value = match serde_json::from_str(&secret_body) {
-        Ok(json) => json,
-        Err(_) => {
-            println!("[DELETE API KEY] Failed to parse secret JSON");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to parse secret response",
-            )
-                .into_response();
-        }
-    };
-
-    // Extract secret value from the returned JSON array
-    let secret_value = match secret_json[0]["secret_value"].as_str() {
-        Some(value) => value.to_string(),
-        None => {
-            println!("[DELETE API KEY] Failed to get secret value from JSON");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to get secret value",
-            )
-                .into_response();
-        }
-    };
-
-    println!("[DELETE API KEY] Removing API key from cache");
-    // Delete from API key cache
-    {
-        let mut cache = state.api_key_cache.write().await;
-        let removed = cache.remove(&secret_value);
-        println!(
-            "[DELETE API KEY] Successfully removed from cache: {}",
-            removed.is_some()
-        );
-    }
-
     println!("[DELETE API KEY] Deleting secret from database");
     // Delete in DB
     let response = match client
         .from("secrets")
-        .auth(user.jwt)
+        .auth(user.jwt.clone())
         .eq("secret_id", &secret_id)
         .eq("account_id", &account_id)
         .delete()
@@ -635,44 +871,52 @@ pub async fn delete_api_key(
 
     println!("[DELETE API KEY] Database deletion response: {:?}", body);
 
-    println!("[DELETE API KEY] Deleting secret from vault");
-    //Delete in Vault
-    let input = DeleteVaultSecretInput {
-        secret_id: secret_id.clone(),
-    };
+    // Revoke the key on every instance, not just this one
+    if let Ok(secret_uuid) = Uuid::parse_str(&secret_id) {
+        invalidate_caches(
+            &state,
+            CacheInvalidation::ApiKey {
+                secret_id: secret_uuid,
+            },
+        )
+        .await;
+    }
 
-    println!("[DELETE API KEY] Vault deletion input: {:?}", input);
+    //Only rows the user could delete through RLS are removed from the vault
+    let deleted_row = serde_json::from_str::<Vec<Value>>(&body)
+        .ok()
+        .and_then(|rows| rows.into_iter().next());
 
-    let rpc_response = match client
-        .rpc("delete_secret", serde_json::to_string(&input).unwrap())
-        .auth(supabase_service_role_api_key.clone())
-        .execute()
-        .await
+    if let Some(vault_secret_id) = deleted_row
+        .as_ref()
+        .and_then(|row| row["vault_secret_id"].as_str())
     {
-        Ok(response) => response,
-        Err(_) => {
-            println!("[DELETE API KEY] Failed to delete from vault");
+        println!("[DELETE API KEY] Deleting secret from vault");
+        if let Err(e) = crate::vault::delete_secret_from_vault(client, vault_secret_id).await {
+            println!("[DELETE API KEY] Failed to delete from vault: {:?}", e);
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Failed to execute request",
             )
                 .into_response();
         }
-    };
+    }
 
-    let rpc_body = match rpc_response.text().await {
-        Ok(body) => body,
-        Err(_) => {
-            println!("[DELETE API KEY] Failed to read vault deletion response");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to read response body",
-            )
-                .into_response();
-        }
-    };
+    let secret_name = deleted_row
+        .as_ref()
+        .and_then(|row| row["secret_name"].as_str().map(str::to_string));
+    record_secret_access(
+        state.clone(),
+        vec![SecretAccessEvent::by_user(
+            &user,
+            &account_id,
+            SecretAccessAction::Delete,
+            SecretResourceType::ApiKey,
+            Some(secret_id.clone()),
+            secret_name,
+        )],
+    );
 
-    println!("[DELETE API KEY] Vault deletion response: {:?}", rpc_body);
     println!("[DELETE API KEY] API key deletion completed successfully");
 
     Json(body).into_response()
@@ -690,6 +934,20 @@ pub struct SecretByValueResponse {
     pub created_at: String,
     pub updated_by: String,
     pub created_by: String,
+    #[serde(default)]
+    pub api_key_scopes: Option<Vec<String>>,
+    #[serde(default)]
+    pub api_key_expires_at: Option<String>,
+    #[serde(default)]
+    pub api_key_allowed_ips: Option<Vec<String>>,
+    #[serde(default)]
+    pub api_key_last_used_at: Option<String>,
+}
+
+//API keys are stored hashed next to the vault id so a request can find its key without
+//decrypting every key
+pub fn api_key_hash(api_key: &str) -> String {
+    hex::encode(Sha256::digest(api_key.as_bytes()))
 }
 
 pub async fn get_secret_by_secret_value(
@@ -697,54 +955,75 @@ pub async fn get_secret_by_secret_value(
     secret_value: String,
 ) -> Result<SecretByValueResponse, StatusCode> {
     println!("[GET SECRET BY SECRET VALUE] Starting get_secret_by_value");
-    println!(
-        "[GET SECRET BY SECRET VALUE] Secret Value: {:?}",
-        secret_value
-    );
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-
-    let input = GetSecretBySecretValueInput {
-        secret_value: secret_value.clone(),
-    };
 
     let client = &state.anything_client;
 
-    println!("[GET SECRET BY SECRET VALUE] Making RPC call to get_secret_by_secret_value");
-    let response = client
-        .rpc(
-            "get_secret_by_secret_value",
-            serde_json::to_string(&input).unwrap(),
-        )
-        .auth(supabase_service_role_api_key)
-        .execute()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    println!(
-        "[GET SECRET BY SECRET VALUE] Got response from RPC call: {:?}",
-        response
-    );
-    let body = response.text().await.map_err(|e| {
-        println!(
-            "[GET SECRET BY SECRET VALUE] Error getting response text: {:?}",
-            e
-        );
+    println!("[GET SECRET BY SECRET VALUE] Looking up API key by hash");
+    let mut secrets: Vec<SecretByValueResponse> = service_role_rows(
+        client
+            .from("secrets")
+            .eq("api_key_hash", api_key_hash(&secret_value))
+            .eq("anything_api_key", "true")
+            .select("secret_id,account_id,secret_name,vault_secret_id,secret_description,anything_api_key,updated_at,created_at,updated_by,created_by,api_key_scopes,api_key_expires_at,api_key_allowed_ips"),
+    )
+    .await
+    .map_err(|e| {
+        println!("[GET SECRET BY SECRET VALUE] Error reading secret: {:?}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    println!("[GET SECRET BY SECRET VALUE] Response body: {}", body);
+    // Hashes are unique so there is at most one match
+    let mut secret = secrets.pop().ok_or_else(|| {
+        println!("[GET SECRET BY SECRET VALUE] No secret found for hash");
+        StatusCode::NOT_FOUND
+    })?;
 
-    if body.contains("[]") {
-        println!("[GET SECRET BY SECRET VALUE] No secret found - body was empty array");
+    //The vault is the source of truth, a key removed from it is revoked
+    let values = read_secrets_from_vault(client, &[secret.vault_secret_id.clone()])
+        .await
+        .map_err(|e| {
+            println!("[GET SECRET BY SECRET VALUE] Error reading vault: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if values.get(&secret.vault_secret_id) != Some(&secret_value) {
+        println!("[GET SECRET BY SECRET VALUE] Vault value does not match, rejecting key");
         return Err(StatusCode::NOT_FOUND);
     }
 
-    println!("[GET SECRET BY SECRET VALUE] Parsing response body");
-    let mut secrets: Vec<SecretByValueResponse> =
-        serde_json::from_str(&body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let usage: Vec<ApiKeyUsageRow> = service_role_rows(
+        client
+            .from("api_key_usage")
+            .eq("secret_id", &secret.secret_id)
+            .select("secret_id,last_used_at"),
+    )
+    .await
+    .unwrap_or_default();
+    secret.api_key_last_used_at = usage.into_iter().next().and_then(|row| row.last_used_at);
 
-    // We expect only one result since secret values should be unique
     println!("[GET SECRET BY SECRET VALUE] Returning secret");
-    secrets.pop().ok_or(StatusCode::NOT_FOUND)
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_updates_only_write_the_fields_that_were_sent() {
+        let only_testing_value: UpdateSecretScopePayload =
+            serde_json::from_str(r#"{ "testing_secret_value": "sk_test_123" }"#).unwrap();
+        assert_eq!(secret_scope_update(&only_testing_value), json!({}));
+
+        let opened: UpdateSecretScopePayload =
+            serde_json::from_str(r#"{ "flow_ids": null }"#).unwrap();
+        assert_eq!(secret_scope_update(&opened), json!({ "flow_ids": null }));
+
+        let flow_id = Uuid::new_v4();
+        let restricted: UpdateSecretScopePayload =
+            serde_json::from_value(json!({ "flow_ids": [flow_id] })).unwrap();
+        assert_eq!(
+            secret_scope_update(&restricted),
+            json!({ "flow_ids": [flow_id] })
+        );
+    }
 }
@@ -2,11 +2,16 @@ use chrono::{DateTime, Duration, NaiveDateTime, Offset, Utc};
 use chrono_tz::Tz;
 use serde_json::{json, Value};
 
+use crate::bundler::redaction::describe_value;
+
 pub fn process_date_task(
     bundled_context: &Value,
 ) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
     println!("[DATE FORMATTER] Starting date task processing");
-    println!("[DATE FORMATTER] Bundled context: {:?}", bundled_context);
+    println!(
+        "[DATE FORMATTER] Bundled context: {}",
+        describe_value(bundled_context)
+    );
 
     let input = bundled_context
         .get("input")
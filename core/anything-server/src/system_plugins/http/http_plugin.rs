@@ -12,7 +12,7 @@ pub async fn process_http_task(
 ) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
     let start = Instant::now();
     println!("[TASK_ENGINE] Entering process_http_task");
-    println!("[TASK_ENGINE] Bundled context: {:?}", bundled_context);
+    //Header and body values are never logged, they hold rendered secrets and account tokens
 
     if let (Some(method), Some(url)) = (
         bundled_context.get("method").and_then(Value::as_str),
@@ -41,21 +41,21 @@ pub async fn process_http_task(
         println!("[TASK_ENGINE] Processing headers");
         let headers = parse_headers(bundled_context);
         for (key, value) in headers {
-            println!("[TASK_ENGINE] Adding header: {} = {}", key, value);
+            println!("[TASK_ENGINE] Adding header: {}", key);
             request_builder = request_builder.header(key, value);
         }
 
         if let Some(body) = bundled_context.get("body") {
             if let Some(body_str) = body.as_str() {
                 if !body_str.is_empty() {
-                    println!("[TASK_ENGINE] Adding body: {}", body_str);
+                    println!("[TASK_ENGINE] Adding body: {} bytes", body_str.len());
                     request_builder = request_builder.body(body_str.to_string());
                 } else {
                     println!("[TASK_ENGINE] Body is an empty string, sending request without body");
                 }
             } else if let Some(body_object) = body.as_object() {
                 let body_json = serde_json::to_string(body_object)?;
-                println!("[TASK_ENGINE] Adding body: {}", body_json);
+                println!("[TASK_ENGINE] Adding body: {} bytes", body_json.len());
                 request_builder = request_builder.body(body_json);
             } else {
                 println!("[TASK_ENGINE] Body is not a string or an object");
@@ -131,22 +131,21 @@ pub fn parse_headers(bundled_context: &Value) -> Vec<(String, String)> {
     if let Some(headers_value) = bundled_context.get("headers") {
         match headers_value {
             Value::Object(headers_obj) => {
-                println!("[TASK_ENGINE] Headers are an object: {:?}", headers_obj);
+                println!("[TASK_ENGINE] Headers are an object");
                 for (key, value) in headers_obj {
                     if let Some(value_str) = value.as_str() {
-                        println!("[TASK_ENGINE] Adding header: {} = {}", key, value_str);
+                        println!("[TASK_ENGINE] Adding header: {}", key);
                         headers.push((key.to_string(), value_str.to_string()));
                     }
                 }
             }
             Value::String(headers_str) => {
-                println!("[TASK_ENGINE] Headers are a string: {}", headers_str);
+                println!("[TASK_ENGINE] Headers are a string");
                 match serde_json::from_str::<Value>(headers_str) {
                     Ok(Value::Object(parsed_headers)) => {
-                        println!("[TASK_ENGINE] Parsed headers: {:?}", parsed_headers);
                         for (key, value) in parsed_headers {
                             if let Some(value_str) = value.as_str() {
-                                println!("[TASK_ENGINE] Adding header: {} = {}", key, value_str);
+                                println!("[TASK_ENGINE] Adding header: {}", key);
                                 headers.push((key.to_string(), value_str.to_string()));
                             }
                         }
@@ -0,0 +1,324 @@
+use dotenv::dotenv;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, Url};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::system_plugins::webhook_trigger::rate_limit::IpCidr;
+
+//Per script, a loop of fetches should not turn a task into a crawler
+pub const MAX_JS_FETCHES: usize = 20;
+
+const MAX_JS_FETCH_REDIRECTS: usize = 5;
+const MAX_JS_FETCH_RESPONSE_BYTES: usize = 5 * 1024 * 1024;
+
+//Loopback, private, link local (cloud metadata lives here), CGNAT and their IPv6 equivalents
+const INTERNAL_NETWORKS: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8",
+];
+
+pub fn is_internal_ip(ip: &IpAddr) -> bool {
+    INTERNAL_NETWORKS.iter().any(|network| {
+        IpCidr::parse(network)
+            .map(|network| network.contains(ip))
+            .unwrap_or(true)
+    })
+}
+
+//Hosts are matched exactly, "*.example.com" also matches every subdomain
+#[derive(Debug, Clone, Default)]
+pub struct JsFetchPolicy {
+    allowed_hosts: Vec<String>,
+    denied_hosts: Vec<String>,
+}
+
+impl JsFetchPolicy {
+    //ANYTHING_JS_FETCH_ALLOWED_HOSTS (empty allows any public host) and ANYTHING_JS_FETCH_DENIED_HOSTS
+    pub fn from_env() -> Self {
+        dotenv().ok();
+        JsFetchPolicy {
+            allowed_hosts: host_list(env::var("ANYTHING_JS_FETCH_ALLOWED_HOSTS").ok()),
+            denied_hosts: host_list(env::var("ANYTHING_JS_FETCH_DENIED_HOSTS").ok()),
+        }
+    }
+
+    //Checked before the request and again on every redirect
+    pub fn check_url(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("fetch only supports http and https, got {}", url));
+        }
+
+        let host = match url.host_str() {
+            Some(host) => host.trim_matches(|c| c == '[' || c == ']').to_lowercase(),
+            None => return Err(format!("fetch URL has no host: {}", url)),
+        };
+
+        //IP literals never reach the resolver
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            if is_internal_ip(&ip) {
+                return Err(format!("fetch to internal address {} is not allowed", host));
+            }
+        }
+
+        if self.denied_hosts.iter().any(|p| host_matches(p, &host)) {
+            return Err(format!("fetch to {} is not allowed", host));
+        }
+        if !self.allowed_hosts.is_empty()
+            && !self.allowed_hosts.iter().any(|p| host_matches(p, &host))
+        {
+            return Err(format!("fetch to {} is not in the allowed hosts", host));
+        }
+        Ok(())
+    }
+}
+
+fn host_list(value: Option<String>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+        None => host == pattern,
+    }
+}
+
+//Resolving here instead of checking up front means a host can't pass the check and then
+//rebind to an internal address before the connection is made
+struct PublicOnlyResolver;
+
+impl Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = resolve_public_host(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+async fn resolve_public_host(host: &str) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+        .await
+        .map_err(|e| format!("{} did not resolve: {}", host, e))?
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{} did not resolve", host));
+    }
+    if addrs.iter().any(|addr| is_internal_ip(&addr.ip())) {
+        return Err(format!("{} resolves to an internal address", host));
+    }
+    Ok(addrs)
+}
+
+//check_url plus a lookup of the host, for urls we want to reject before anything is sent.
+//Requests still have to go through public_only_client, the answer can change later.
+pub async fn check_public_url(policy: &JsFetchPolicy, url: &Url) -> Result<(), String> {
+    policy.check_url(url)?;
+    //check_url already covered IP literals
+    match url.host_str() {
+        Some(host)
+            if host
+                .trim_matches(|c| c == '[' || c == ']')
+                .parse::<IpAddr>()
+                .is_err() =>
+        {
+            resolve_public_host(host).await.map(|_| ())
+        }
+        _ => Ok(()),
+    }
+}
+
+//Client that can only reach public addresses, redirects are checked against the policy too
+pub fn public_only_client(policy: Arc<JsFetchPolicy>) -> Client {
+    Client::builder()
+        .dns_resolver(Arc::new(PublicOnlyResolver))
+        .redirect(Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_JS_FETCH_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            match policy.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(e) => attempt.error(e),
+            }
+        }))
+        .build()
+        .expect("Failed to build public only HTTP client")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JsFetchRequest {
+    pub url: String,
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JsFetchResponse {
+    pub url: String,
+    pub status: u16,
+    pub status_text: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+//Backs fetch() in the JavaScript action. Has its own client because the resolver and
+//redirect policy apply to every request it sends.
+pub struct JsFetcher {
+    client: Client,
+    policy: Arc<JsFetchPolicy>,
+}
+
+impl JsFetcher {
+    pub fn new(policy: JsFetchPolicy) -> Self {
+        let policy = Arc::new(policy);
+        let client = public_only_client(policy.clone());
+        JsFetcher { client, policy }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(JsFetchPolicy::from_env())
+    }
+
+    pub async fn fetch(
+        &self,
+        request: JsFetchRequest,
+        timeout: Duration,
+    ) -> Result<JsFetchResponse, String> {
+        let url = Url::parse(&request.url).map_err(|e| format!("Invalid fetch URL: {}", e))?;
+        self.policy.check_url(&url)?;
+
+        let method = match request.method {
+            Some(method) => Method::from_bytes(method.to_uppercase().as_bytes())
+                .map_err(|_| format!("Invalid fetch method: {}", method))?,
+            None => Method::GET,
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &request.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid fetch header name: {}", name))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid value for fetch header {}", name))?;
+            headers.insert(name, value);
+        }
+
+        let mut builder = self
+            .client
+            .request(method, url)
+            .headers(headers)
+            .timeout(timeout);
+        if let Some(body) = request.body {
+            builder = builder.body(body);
+        }
+
+        let mut response = builder
+            .send()
+            .await
+            .map_err(|e| format!("fetch failed: {}", e))?;
+
+        let status = response.status();
+        let url = response.url().to_string();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.as_str().to_string(), value.to_string()))
+            })
+            .collect();
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| format!("fetch failed reading the response: {}", e))?
+        {
+            if body.len() + chunk.len() > MAX_JS_FETCH_RESPONSE_BYTES {
+                return Err(format!(
+                    "fetch response is larger than {} bytes",
+                    MAX_JS_FETCH_RESPONSE_BYTES
+                ));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(JsFetchResponse {
+            url,
+            status: status.as_u16(),
+            status_text: status.canonical_reason().unwrap_or_default().to_string(),
+            headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_internal_addresses_and_applies_host_lists() {
+        let open = JsFetchPolicy::default();
+        for url in [
+            "http://127.0.0.1:3001/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/",
+            "http://[::1]/",
+            "http://[::ffff:192.168.0.1]/",
+            "file:///etc/passwd",
+        ] {
+            assert!(
+                open.check_url(&Url::parse(url).unwrap()).is_err(),
+                "{}",
+                url
+            );
+        }
+        assert!(open
+            .check_url(&Url::parse("https://93.184.215.14/").unwrap())
+            .is_ok());
+
+        let restricted = JsFetchPolicy {
+            allowed_hosts: host_list(Some("*.example.com, api.github.com".to_string())),
+            denied_hosts: host_list(Some("internal.example.com".to_string())),
+        };
+        let allowed = |url: &str| restricted.check_url(&Url::parse(url).unwrap()).is_ok();
+        assert!(allowed("https://example.com/"));
+        assert!(allowed("https://hooks.example.com/a"));
+        assert!(allowed("https://api.github.com/repos"));
+        assert!(!allowed("https://internal.example.com/"));
+        assert!(!allowed("https://notexample.com/"));
+        assert!(!allowed("https://github.com/"));
+    }
+}
@@ -0,0 +1,374 @@
+use dotenv::dotenv;
+use futures::future::BoxFuture;
+use rustyscript::deno_core::anyhow;
+use rustyscript::deno_core::{ModuleSpecifier, RequestedModuleType};
+use rustyscript::module_loader::ImportProvider;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+use crate::AppState;
+
+//Actions import account libraries as "library:<name>"
+pub const JS_LIBRARY_SCHEME: &str = "library";
+
+//Versions and published pins never change, this only keeps the maps from growing forever
+const MAX_CACHED_JS_LIBRARIES: usize = 1_000;
+
+//Library name -> version, stored on flow_versions.js_library_versions when a version is published
+pub type JsLibraryPins = HashMap<String, i64>;
+
+//Fetches the source of a library by name
+pub type JsLibraryLoader =
+    Arc<dyn Fn(String) -> BoxFuture<'static, Result<String, String>> + Send + Sync>;
+
+pub fn is_valid_library_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name.len() <= 64
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+pub struct JsLibraryCache {
+    versions: HashMap<(String, String, i64), Arc<String>>, // (account_id, name, version) -> code
+    pins: HashMap<String, JsLibraryPins>,                  // flow_version_id -> pins
+}
+
+impl JsLibraryCache {
+    pub fn new() -> Self {
+        Self {
+            versions: HashMap::new(),
+            pins: HashMap::new(),
+        }
+    }
+
+    fn insert_version(&mut self, account_id: &str, name: &str, version: i64, code: Arc<String>) {
+        if self.versions.len() >= MAX_CACHED_JS_LIBRARIES {
+            self.versions.clear();
+        }
+        self.versions
+            .insert((account_id.to_string(), name.to_string(), version), code);
+    }
+
+    fn insert_pins(&mut self, flow_version_id: &str, pins: JsLibraryPins) {
+        if self.pins.len() >= MAX_CACHED_JS_LIBRARIES {
+            self.pins.clear();
+        }
+        self.pins.insert(flow_version_id.to_string(), pins);
+    }
+}
+
+impl Default for JsLibraryCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LatestVersion {
+    name: String,
+    latest_version: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct LibraryVersionCode {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct FlowVersionPins {
+    published: bool,
+    js_library_versions: Option<JsLibraryPins>,
+}
+
+//Latest version of every active library, pinned onto a workflow version when it is published
+pub async fn current_js_library_pins(
+    state: &AppState,
+    jwt: &str,
+    account_id: &str,
+) -> Result<JsLibraryPins, Box<dyn std::error::Error + Send + Sync>> {
+    let response = state
+        .anything_client
+        .from("js_libraries")
+        .auth(jwt)
+        .select("name,latest_version")
+        .eq("account_id", account_id)
+        .eq("archived", "false")
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+    let libraries: Vec<LatestVersion> = serde_json::from_str(&body)?;
+    Ok(libraries
+        .into_iter()
+        .map(|library| (library.name, library.latest_version))
+        .collect())
+}
+
+//None for drafts, they import the latest version of each library. Published versions only
+//import what was pinned when they were published.
+async fn js_library_pins_for_flow_version(
+    state: &AppState,
+    flow_version_id: &str,
+) -> Result<Option<JsLibraryPins>, Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(pins) = state
+        .js_library_cache
+        .read()
+        .await
+        .pins
+        .get(flow_version_id)
+    {
+        return Ok(Some(pins.clone()));
+    }
+
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")?;
+
+    let response = state
+        .anything_client
+        .from("flow_versions")
+        .auth(supabase_service_role_api_key)
+        .select("published,js_library_versions")
+        .eq("flow_version_id", flow_version_id)
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+    let mut rows: Vec<FlowVersionPins> = serde_json::from_str(&body)?;
+    let row = rows
+        .pop()
+        .ok_or_else(|| format!("Workflow version {} does not exist", flow_version_id))?;
+    if !row.published {
+        return Ok(None);
+    }
+
+    //Versions published before libraries existed have nothing to import
+    let pins = row.js_library_versions.unwrap_or_default();
+    //Only published pins are cached, a draft can still be published
+    state
+        .js_library_cache
+        .write()
+        .await
+        .insert_pins(flow_version_id, pins.clone());
+    Ok(Some(pins))
+}
+
+async fn load_js_library(
+    state: &AppState,
+    account_id: &str,
+    name: &str,
+    pinned_version: Option<i64>,
+) -> Result<Arc<String>, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")?;
+
+    let version = match pinned_version {
+        Some(version) => version,
+        None => {
+            let response = state
+                .anything_client
+                .from("js_libraries")
+                .auth(supabase_service_role_api_key.clone())
+                .select("name,latest_version")
+                .eq("account_id", account_id)
+                .eq("name", name)
+                .eq("archived", "false")
+                .execute()
+                .await?;
+            let body = response.text().await?;
+            let mut rows: Vec<LatestVersion> = serde_json::from_str(&body)?;
+            rows.pop()
+                .ok_or_else(|| format!("JavaScript library \"{}\" does not exist", name))?
+                .latest_version
+        }
+    };
+
+    let cache_key = (account_id.to_string(), name.to_string(), version);
+    if let Some(code) = state.js_library_cache.read().await.versions.get(&cache_key) {
+        return Ok(code.clone());
+    }
+
+    let response = state
+        .anything_client
+        .from("js_library_versions")
+        .auth(supabase_service_role_api_key)
+        .select("code")
+        .eq("account_id", account_id)
+        .eq("name", name)
+        .eq("version", version.to_string())
+        .execute()
+        .await?;
+    let body = response.text().await?;
+    let mut rows: Vec<LibraryVersionCode> = serde_json::from_str(&body)?;
+    let code = Arc::new(
+        rows.pop()
+            .ok_or_else(|| {
+                format!(
+                    "Version {} of JavaScript library \"{}\" does not exist",
+                    version, name
+                )
+            })?
+            .code,
+    );
+
+    state
+        .js_library_cache
+        .write()
+        .await
+        .insert_version(account_id, name, version, code.clone());
+    Ok(code)
+}
+
+//Loads libraries at the versions the task's workflow version was published with. Fails when
+//the pins can't be read, running a published version against the latest code would not be
+//what was published.
+pub async fn js_library_loader(
+    state: Arc<AppState>,
+    account_id: String,
+    flow_version_id: String,
+) -> Result<JsLibraryLoader, Box<dyn std::error::Error + Send + Sync>> {
+    let pins = js_library_pins_for_flow_version(&state, &flow_version_id)
+        .await?
+        .map(Arc::new);
+
+    Ok(Arc::new(
+        move |name: String| -> BoxFuture<'static, Result<String, String>> {
+            let state = state.clone();
+            let account_id = account_id.clone();
+            let pins = pins.clone();
+            Box::pin(async move {
+                let pinned_version = match &pins {
+                    Some(pins) => Some(pins.get(&name).copied().ok_or_else(|| {
+                        format!(
+                            "JavaScript library \"{}\" was not part of this workflow version when it was published",
+                            name
+                        )
+                    })?),
+                    None => None,
+                };
+                load_js_library(&state, &account_id, &name, pinned_version)
+                    .await
+                    .map(|code| code.as_str().to_string())
+                    .map_err(|e| e.to_string())
+            })
+        },
+    ))
+}
+
+//User code runs inside a function, static imports have to move up to module scope.
+//Returns (imports, rest of the code). Dynamic import() calls stay where they are.
+pub fn hoist_imports(code: &str) -> (String, String) {
+    let mut imports = Vec::new();
+    let mut body = Vec::new();
+    let mut in_import = false;
+
+    for line in code.lines() {
+        let trimmed = line.trim_start();
+        let starts_import = trimmed.starts_with("import ") || trimmed.starts_with("import{");
+        if in_import || (starts_import && !trimmed.starts_with("import(")) {
+            imports.push(line);
+            //Done once the module specifier shows up
+            in_import = !(trimmed.contains(" from ")
+                || trimmed.contains("}from")
+                || trimmed.starts_with("import '")
+                || trimmed.starts_with("import \""))
+                || !(trimmed.contains('\'') || trimmed.contains('"'));
+        } else {
+            body.push(line);
+        }
+    }
+
+    (imports.join("\n"), body.join("\n"))
+}
+
+//Serves "library:<name>" imports. Module loading is synchronous, so the load runs on the
+//server runtime while the script's thread waits for it.
+pub struct JsLibraryImporter {
+    pub loader: JsLibraryLoader,
+    pub server_runtime: Handle,
+    pub timeout: Duration,
+}
+
+impl ImportProvider for JsLibraryImporter {
+    fn import(
+        &mut self,
+        specifier: &ModuleSpecifier,
+        _referrer: Option<&ModuleSpecifier>,
+        _is_dyn_import: bool,
+        _requested_module_type: RequestedModuleType,
+    ) -> Option<Result<String, anyhow::Error>> {
+        if specifier.scheme() != JS_LIBRARY_SCHEME {
+            return None;
+        }
+
+        let name = specifier.path().to_string();
+        if !is_valid_library_name(&name) {
+            return Some(Err(anyhow::Error::msg(format!(
+                "Invalid JavaScript library name: {}",
+                name
+            ))));
+        }
+
+        println!("[RUSTYSCRIPT] Loading JavaScript library {}", name);
+        let (sender, receiver) = mpsc::channel();
+        let load = (self.loader)(name.clone());
+        self.server_runtime.spawn(async move {
+            let _ = sender.send(load.await);
+        });
+
+        Some(match receiver.recv_timeout(self.timeout) {
+            Ok(result) => result.map_err(anyhow::Error::msg),
+            Err(_) => Err(anyhow::Error::msg(format!(
+                "Timed out loading JavaScript library {}",
+                name
+            ))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn library_names_match_the_database_constraint() {
+        for name in ["helpers", "date-utils", "v2_formatters", "0x"] {
+            assert!(is_valid_library_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            "Helpers",
+            "-helpers",
+            "../secrets",
+            "a/b",
+            &"a".repeat(65),
+        ] {
+            assert!(!is_valid_library_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn hoists_static_imports_out_of_the_function_body() {
+        let code = r#"import { slugify } from "library:strings";
+import {
+    formatDate,
+    parseDate,
+} from 'library:dates';
+const { sum } = await import("library:math");
+return slugify(inputs.title);"#;
+
+        let (imports, body) = hoist_imports(code);
+        assert_eq!(
+            imports,
+            "import { slugify } from \"library:strings\";\nimport {\n    formatDate,\n    parseDate,\n} from 'library:dates';"
+        );
+        assert_eq!(
+            body,
+            "const { sum } = await import(\"library:math\");\nreturn slugify(inputs.title);"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use dotenv::dotenv;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::AppState;
+
+const DEFAULT_JS_TIMEOUT_MS: u64 = 5_000;
+const DEFAULT_JS_MAX_HEAP_MB: usize = 128;
+
+//Ceilings for account overrides so one account can't hold a worker or the host's memory
+const MAX_JS_TIMEOUT_MS: u64 = 60_000;
+const MAX_JS_MAX_HEAP_MB: usize = 1_024;
+
+//Smallest heap V8 starts reliably with
+const MIN_JS_MAX_HEAP_MB: usize = 16;
+
+//Limits change with plans, not per request
+const JS_LIMITS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JsLimits {
+    pub timeout: Duration,
+    pub max_heap_mb: usize,
+}
+
+impl JsLimits {
+    //ANYTHING_JS_TIMEOUT_MS and ANYTHING_JS_MAX_HEAP_MB, for accounts without their own limits
+    pub fn server_default() -> Self {
+        dotenv().ok();
+        let timeout_ms = env::var("ANYTHING_JS_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_JS_TIMEOUT_MS);
+        let max_heap_mb = env::var("ANYTHING_JS_MAX_HEAP_MB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_JS_MAX_HEAP_MB);
+        Self::clamped(timeout_ms, max_heap_mb)
+    }
+
+    fn clamped(timeout_ms: u64, max_heap_mb: usize) -> Self {
+        JsLimits {
+            timeout: Duration::from_millis(timeout_ms.clamp(1, MAX_JS_TIMEOUT_MS)),
+            max_heap_mb: max_heap_mb.clamp(MIN_JS_MAX_HEAP_MB, MAX_JS_MAX_HEAP_MB),
+        }
+    }
+
+    //Account settings override the server default one field at a time
+    fn with_overrides(self, overrides: &AccountJsLimits) -> Self {
+        Self::clamped(
+            overrides
+                .js_timeout_ms
+                .map(|ms| ms.max(0) as u64)
+                .unwrap_or(self.timeout.as_millis() as u64),
+            overrides
+                .js_max_heap_mb
+                .map(|mb| mb.max(0) as usize)
+                .unwrap_or(self.max_heap_mb),
+        )
+    }
+
+    pub fn max_heap_bytes(&self) -> usize {
+        self.max_heap_mb * 1024 * 1024
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountJsLimits {
+    js_timeout_ms: Option<i64>,
+    js_max_heap_mb: Option<i64>,
+}
+
+//Returned by process_js_task when a script hits its limits
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsLimitError {
+    Timeout(Duration),
+    OutOfMemory(usize),
+}
+
+impl JsLimitError {
+    //Extra fields for the task error so the UI can tell limits apart from script errors
+    pub fn details(&self) -> Value {
+        match self {
+            JsLimitError::Timeout(timeout) => json!({
+                "code": "js_timeout",
+                "limit_ms": timeout.as_millis() as u64,
+            }),
+            JsLimitError::OutOfMemory(max_heap_mb) => json!({
+                "code": "js_out_of_memory",
+                "limit_mb": max_heap_mb,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for JsLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsLimitError::Timeout(timeout) => write!(
+                f,
+                "JavaScript execution timed out after {} ms",
+                timeout.as_millis()
+            ),
+            JsLimitError::OutOfMemory(max_heap_mb) => write!(
+                f,
+                "JavaScript execution ran out of memory (limit {} MB)",
+                max_heap_mb
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JsLimitError {}
+
+pub struct JsLimitsCache {
+    cache: HashMap<String, (JsLimits, SystemTime)>, // account_id -> limits, expires_at
+    ttl: Duration,
+}
+
+impl JsLimitsCache {
+    pub fn new() -> Self {
+        Self {
+            cache: HashMap::new(),
+            ttl: JS_LIMITS_CACHE_TTL,
+        }
+    }
+
+    fn get(&self, account_id: &str) -> Option<JsLimits> {
+        self.cache
+            .get(account_id)
+            .filter(|(_, expires_at)| *expires_at > SystemTime::now())
+            .map(|(limits, _)| *limits)
+    }
+
+    fn set(&mut self, account_id: &str, limits: JsLimits) {
+        self.cache
+            .retain(|_, (_, expires_at)| *expires_at > SystemTime::now());
+        self.cache.insert(
+            account_id.to_string(),
+            (limits, SystemTime::now() + self.ttl),
+        );
+    }
+}
+
+impl Default for JsLimitsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub async fn js_limits_for_account(state: &AppState, account_id: &str) -> JsLimits {
+    if let Some(limits) = state.js_limits_cache.read().await.get(account_id) {
+        return limits;
+    }
+
+    let defaults = JsLimits::server_default();
+    let limits = match fetch_account_js_limits(state, account_id).await {
+        Ok(Some(overrides)) => defaults.with_overrides(&overrides),
+        Ok(None) => defaults,
+        Err(e) => {
+            //Don't cache, the next task tries again
+            println!(
+                "[RUSTYSCRIPT] Failed to load JS limits for account {}, using defaults: {}",
+                account_id, e
+            );
+            return defaults;
+        }
+    };
+
+    state.js_limits_cache.write().await.set(account_id, limits);
+    limits
+}
+
+async fn fetch_account_js_limits(
+    state: &AppState,
+    account_id: &str,
+) -> Result<Option<AccountJsLimits>, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")?;
+
+    let response = state
+        .anything_client
+        .from("accounts_billing")
+        .auth(supabase_service_role_api_key)
+        .select("js_timeout_ms,js_max_heap_mb")
+        .eq("account_id", account_id)
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+    let mut rows: Vec<AccountJsLimits> = serde_json::from_str(&body)?;
+    Ok(rows.pop())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_overrides_stay_within_ceilings() {
+        let defaults = JsLimits::clamped(5_000, 128);
+
+        let partial = defaults.with_overrides(&AccountJsLimits {
+            js_timeout_ms: Some(30_000),
+            js_max_heap_mb: None,
+        });
+        assert_eq!(partial.timeout, Duration::from_secs(30));
+        assert_eq!(partial.max_heap_mb, 128);
+
+        let excessive = defaults.with_overrides(&AccountJsLimits {
+            js_timeout_ms: Some(3_600_000),
+            js_max_heap_mb: Some(-1),
+        });
+        assert_eq!(excessive.timeout, Duration::from_millis(MAX_JS_TIMEOUT_MS));
+        assert_eq!(excessive.max_heap_mb, MIN_JS_MAX_HEAP_MB);
+        assert_eq!(excessive.max_heap_bytes(), MIN_JS_MAX_HEAP_MB * 1024 * 1024);
+    }
+}
@@ -1,21 +1,108 @@
+use rustyscript::deno_core::v8::IsolateHandle;
 use rustyscript::{json_args, Module, Runtime, RuntimeOptions};
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
+use tokio::runtime::Handle;
 use tokio::task;
 use tokio::time::Instant;
 
+use crate::bundler::redaction::describe_value;
+
+pub mod fetch;
+pub mod libraries;
+pub mod limits;
+pub mod typescript;
+
+use fetch::{JsFetchRequest, JsFetcher, MAX_JS_FETCHES};
+use libraries::{hoist_imports, JsLibraryImporter, JsLibraryLoader};
+use limits::{JsLimitError, JsLimits};
+
+//Terminates the isolate once the wall clock limit passes. RuntimeOptions.timeout only
+//covers waiting on the event loop, a synchronous busy loop never yields back to it.
+struct Watchdog {
+    finished: mpsc::Sender<()>,
+    thread: JoinHandle<bool>,
+}
+
+impl Watchdog {
+    fn start(isolate: IsolateHandle, timeout: Duration) -> Self {
+        let (finished, done) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || match done.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                isolate.terminate_execution();
+                true
+            }
+            _ => false,
+        });
+        Watchdog { finished, thread }
+    }
+
+    //True when the script was terminated for running too long
+    fn finish(self) -> bool {
+        drop(self.finished);
+        self.thread.join().unwrap_or(false)
+    }
+}
+
+//Console output kept per run, anything past this is dropped
+const MAX_JS_LOGS: usize = 100;
+const MAX_JS_LOG_LENGTH: usize = 10_000;
+
+//A script error, with whatever the script logged before it failed
+#[derive(Debug)]
+pub struct JsScriptError {
+    pub message: String,
+    pub logs: Vec<Value>,
+}
+
+impl fmt::Display for JsScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for JsScriptError {}
+
+//Extra fields for the task error when the JavaScript action fails
+pub fn js_error_details(error: &(dyn std::error::Error + Send + Sync + 'static)) -> Option<Value> {
+    if let Some(limit_error) = error.downcast_ref::<JsLimitError>() {
+        return Some(limit_error.details());
+    }
+    error
+        .downcast_ref::<JsScriptError>()
+        .filter(|script_error| !script_error.logs.is_empty())
+        .map(|script_error| json!({ "logs": script_error.logs }))
+}
+
 pub async fn process_js_task(
     bundled_inputs: &Value,
     bundled_plugin_config: &Value,
+    limits: JsLimits,
+    fetcher: Arc<JsFetcher>,
+    libraries: JsLibraryLoader,
 ) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
     let start = Instant::now();
-    println!("[RUSTYSCRIPT] Starting process_js_task");
-    println!("[RUSTYSCRIPT] Bundled variables: {:?}", bundled_inputs);
+    println!(
+        "[RUSTYSCRIPT] Starting process_js_task with limits {:?}",
+        limits
+    );
+    println!(
+        "[RUSTYSCRIPT] Bundled variables: {}",
+        describe_value(bundled_inputs)
+    );
 
     // Clone the context since we need to move it to the new thread
     let bundled_plugin_config_clone = bundled_plugin_config.clone();
     let bundled_inputs_clone = bundled_inputs.clone();
 
+    //fetch and library loads run on the server runtime, the script's runtime goes away when it finishes
+    let server_runtime = Handle::current();
+
     // Spawn blocking task in a separate thread
     let result = task::spawn_blocking(move || {
         // Move the JavaScript execution logic into this closure
@@ -23,72 +110,208 @@ pub async fn process_js_task(
             .as_str()
             .ok_or("JS code not found in context")?;
 
-        println!("[RUSTYSCRIPT] Extracted JS code: {:?}", js_code);
-        println!(
-            "[RUSTYSCRIPT] Extracted inputs: {:?}",
-            bundled_inputs_clone
-        );
+        //Rendered code and inputs can hold secrets, only their size is logged
+        println!("[RUSTYSCRIPT] Extracted JS code: {} bytes", js_code.len());
+
+        let (js_imports, js_code) = hoist_imports(js_code);
 
         // Create a module that wraps the user's code with context and exports
         let wrapped_code = format!(
             r#"
+            {js_imports}
+
             // Inject variables into globalThis.inputs to match autocomplete
-            Object.assign(globalThis, {{ inputs: {} }});
+            Object.assign(globalThis, {{ inputs: {inputs} }});
+
+            // Capture console output so it can be returned with the result
+            const __anythingLogs = [];
+            const __anythingFormat = (value) => {{
+                if (typeof value === 'string') return value;
+                if (value instanceof Error) return `${{value.name}}: ${{value.message}}`;
+                try {{
+                    return JSON.stringify(value) ?? String(value);
+                }} catch {{
+                    return String(value);
+                }}
+            }};
+            globalThis.console = globalThis.console ?? {{}};
+            for (const level of ['log', 'info', 'warn', 'error', 'debug']) {{
+                globalThis.console[level] = (...args) => {{
+                    if (__anythingLogs.length < {max_logs}) {{
+                        __anythingLogs.push({{
+                            level,
+                            message: args.map(__anythingFormat).join(' ').slice(0, {max_log_length}),
+                        }});
+                    }}
+                }};
+            }}
+
+            // fetch goes through the server so internal addresses stay out of reach
+            const __anythingHeaders = (headers) => {{
+                if (!headers) return {{}};
+                const entries = Array.isArray(headers)
+                    ? headers
+                    : typeof headers.entries === 'function'
+                        ? Array.from(headers.entries())
+                        : Object.entries(headers);
+                return Object.fromEntries(entries.map(([name, value]) => [String(name), String(value)]));
+            }};
+            globalThis.fetch = async (resource, init = {{}}) => {{
+                const body = init.body === undefined || init.body === null
+                    ? null
+                    : typeof init.body === 'string' ? init.body : JSON.stringify(init.body);
+                const response = await rustyscript.async_functions.anything_fetch({{
+                    url: String(resource?.url ?? resource),
+                    method: init.method ?? 'GET',
+                    headers: __anythingHeaders(init.headers),
+                    body,
+                }});
+                const headers = response.headers;
+                return {{
+                    ok: response.status >= 200 && response.status < 300,
+                    status: response.status,
+                    statusText: response.status_text,
+                    url: response.url,
+                    headers: {{
+                        get: (name) => headers[String(name).toLowerCase()] ?? null,
+                        has: (name) => String(name).toLowerCase() in headers,
+                        entries: () => Object.entries(headers),
+                    }},
+                    text: async () => response.body,
+                    json: async () => JSON.parse(response.body),
+                }};
+            }};
 
             // Export the user's code as default function and let errors propagate
-            export default () => {{
+            export default async () => {{
                 try {{
-                    const result = (() => {{
+                    const result = await (async () => {{
                         {js_code}
                     }})();
                     
                     // Ensure the user returned a value
                     if (result === undefined) {{
-                        return {{ internal_error: 'Please explicitly return a value in your code' }};
+                        return {{ internal_error: 'Please explicitly return a value in your code', logs: __anythingLogs }};
                     }}
 
                     // If result is not an object, wrap it in an object
                     if (result === null || typeof result !== 'object') {{
-                        return {{ result }};
+                        return {{ value: {{ result }}, logs: __anythingLogs }};
                     }}
                     
-                    return result;
+                    return {{ value: result, logs: __anythingLogs }};
                 }} catch (error) {{
                     return {{ 
                         internal_error: `JavaScript execution error: ${{error.message}}`,
                         error_type: error.name,
-                        error_stack: error.stack
+                        error_stack: error.stack,
+                        logs: __anythingLogs
                     }};
                 }}
             }}
             "#,
-            serde_json::to_string(&bundled_inputs_clone)?
+            inputs = serde_json::to_string(&bundled_inputs_clone)?,
+            max_logs = MAX_JS_LOGS,
+            max_log_length = MAX_JS_LOG_LENGTH,
         );
 
-        println!("[RUSTYSCRIPT] Generated wrapped code: {:?}", wrapped_code);
+        println!(
+            "[RUSTYSCRIPT] Generated wrapped code: {} bytes",
+            wrapped_code.len()
+        );
 
-        // Create the module
-        let module = Module::new("user_code.js", &wrapped_code);
+        // Create the module, TypeScript is transpiled to JavaScript when it loads
+        let module = Module::new(
+            typescript::module_name(&bundled_plugin_config_clone),
+            &wrapped_code,
+        );
         println!("[RUSTYSCRIPT] Created module");
 
         // Execute the module
         let script_start = Instant::now();
         println!("[RUSTYSCRIPT] Starting script execution");
 
-        let result: Value = Runtime::execute_module(
-            &module,
-            vec![], // No additional modules needed
-            RuntimeOptions {
-                timeout: Duration::from_secs(1), //TODO: this actually does not prevent script from long runs
-                ..Default::default()
-            },
-            json_args!(), // No arguments needed since we inject via globalThis
-        )?;
+        let mut runtime = Runtime::new(RuntimeOptions {
+            timeout: limits.timeout,
+            max_heap_size: Some(limits.max_heap_bytes()),
+            //Resolves `import ... from "library:<name>"` from the account's libraries
+            import_provider: Some(Box::new(JsLibraryImporter {
+                loader: libraries,
+                server_runtime: server_runtime.clone(),
+                timeout: limits.timeout,
+            })),
+            ..Default::default()
+        })?;
+
+        let fetch_count = Arc::new(AtomicUsize::new(0));
+        runtime.register_async_function("anything_fetch", move |args: Vec<Value>| {
+            let fetcher = fetcher.clone();
+            let server_runtime = server_runtime.clone();
+            let fetch_count = fetch_count.clone();
+            Box::pin(async move {
+                if fetch_count.fetch_add(1, Ordering::SeqCst) >= MAX_JS_FETCHES {
+                    return Err(rustyscript::Error::Runtime(format!(
+                        "fetch can be called at most {} times per run",
+                        MAX_JS_FETCHES
+                    )));
+                }
+                let request: JsFetchRequest =
+                    serde_json::from_value(args.into_iter().next().unwrap_or_default())
+                        .map_err(|e| rustyscript::Error::Runtime(e.to_string()))?;
+                let response = server_runtime
+                    .spawn(async move { fetcher.fetch(request, limits.timeout).await })
+                    .await
+                    .map_err(|e| rustyscript::Error::Runtime(e.to_string()))?
+                    .map_err(rustyscript::Error::Runtime)?;
+                serde_json::to_value(response)
+                    .map_err(|e| rustyscript::Error::Runtime(e.to_string()))
+            })
+        })?;
+
+        let watchdog = Watchdog::start(
+            runtime.deno_runtime().v8_isolate().thread_safe_handle(),
+            limits.timeout,
+        );
+        //The entrypoint is async, call_entrypoint waits for its promise to settle
+        let execution = runtime.load_module(&module).and_then(|module_handle| {
+            // No arguments needed since we inject via globalThis
+            runtime.call_entrypoint::<Value>(&module_handle, json_args!())
+        });
+        let timed_out = watchdog.finish();
+
+        let mut result = match execution {
+            Ok(result) => result,
+            Err(_) if timed_out => return Err(JsLimitError::Timeout(limits.timeout).into()),
+            Err(rustyscript::Error::HeapExhausted) => {
+                return Err(JsLimitError::OutOfMemory(limits.max_heap_mb).into())
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let logs = match result.get_mut("logs").map(Value::take) {
+            Some(Value::Array(logs)) => logs,
+            _ => Vec::new(),
+        };
 
         // Check if the result is our error object and convert it to a Rust error
         if let Some(error) = result.get("internal_error") {
             if let Some(error_msg) = error.as_str() {
-                return Err(error_msg.into());
+                return Err(JsScriptError {
+                    message: error_msg.to_string(),
+                    logs,
+                }
+                .into());
+            }
+        }
+
+        let mut result = result.get_mut("value").map(Value::take).unwrap_or_default();
+
+        //Arrays are returned as is, an object already holding logs keeps its own
+        if !logs.is_empty() {
+            if let Some(result) = result.as_object_mut() {
+                result
+                    .entry("logs")
+                    .or_insert_with(|| Value::Array(logs));
             }
         }
 
@@ -96,7 +319,7 @@ pub async fn process_js_task(
             "[RUSTYSCRIPT] Script execution completed in {:?}",
             script_start.elapsed()
         );
-        println!("[RUSTYSCRIPT] Execution result: {:?}", result);
+        println!("[RUSTYSCRIPT] Execution result: {}", describe_value(&result));
 
         Ok::<Value, Box<dyn std::error::Error + Send + Sync>>(result)
     })
@@ -107,4 +330,182 @@ pub async fn process_js_task(
         start.elapsed()
     );
     Ok(Some(result))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fetch::JsFetchPolicy;
+    use futures::future::BoxFuture;
+
+    fn fetcher() -> Arc<JsFetcher> {
+        Arc::new(JsFetcher::new(JsFetchPolicy::default()))
+    }
+
+    fn libraries() -> JsLibraryLoader {
+        Arc::new(
+            |name: String| -> BoxFuture<'static, Result<String, String>> {
+                Box::pin(async move {
+                    match name.as_str() {
+                        "strings" => {
+                            Ok("export const shout = (value) => `${value.toUpperCase()}!`;"
+                                .to_string())
+                        }
+                        _ => Err(format!("JavaScript library \"{}\" does not exist", name)),
+                    }
+                })
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn terminates_scripts_that_run_past_the_timeout() {
+        let limits = JsLimits {
+            timeout: Duration::from_millis(200),
+            max_heap_mb: 64,
+        };
+
+        let started = Instant::now();
+        let error = process_js_task(
+            &json!({}),
+            &json!({ "code": "while (true) {}" }),
+            limits,
+            fetcher(),
+            libraries(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<JsLimitError>(),
+            Some(&JsLimitError::Timeout(limits.timeout))
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let result = process_js_task(
+            &json!({ "a": 2 }),
+            &json!({ "code": "return inputs.a * 21;" }),
+            limits,
+            fetcher(),
+            libraries(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, Some(json!({ "result": 42 })));
+    }
+
+    #[tokio::test]
+    async fn runs_async_code_and_captures_console_output() {
+        let limits = JsLimits {
+            timeout: Duration::from_secs(5),
+            max_heap_mb: 64,
+        };
+
+        let code = r#"
+            console.log('doubling', { a: inputs.a });
+            const doubled = await Promise.resolve(inputs.a * 2);
+            return { doubled };
+        "#;
+        let result = process_js_task(
+            &json!({ "a": 21 }),
+            &json!({ "code": code }),
+            limits,
+            fetcher(),
+            libraries(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            result,
+            Some(json!({
+                "doubled": 42,
+                "logs": [{ "level": "log", "message": "doubling {\"a\":21}" }]
+            }))
+        );
+
+        let code = r#"
+            console.warn('calling metadata');
+            await fetch('http://169.254.169.254/latest/meta-data');
+            return true;
+        "#;
+        let error = process_js_task(
+            &json!({}),
+            &json!({ "code": code }),
+            limits,
+            fetcher(),
+            libraries(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("internal address"), "{}", error);
+        assert_eq!(
+            js_error_details(error.as_ref()),
+            Some(json!({ "logs": [{ "level": "warn", "message": "calling metadata" }] }))
+        );
+    }
+
+    #[tokio::test]
+    async fn imports_account_libraries() {
+        let limits = JsLimits {
+            timeout: Duration::from_secs(5),
+            max_heap_mb: 64,
+        };
+
+        let code = r#"
+            import { shout } from "library:strings";
+            return shout(inputs.name);
+        "#;
+        let result = process_js_task(
+            &json!({ "name": "hello" }),
+            &json!({ "code": code }),
+            limits,
+            fetcher(),
+            libraries(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, Some(json!({ "result": "HELLO!" })));
+
+        let code = r#"
+            import { missing } from "library:nope";
+            return missing();
+        "#;
+        let error = process_js_task(
+            &json!({}),
+            &json!({ "code": code }),
+            limits,
+            fetcher(),
+            libraries(),
+        )
+        .await
+        .unwrap_err();
+        assert!(error.to_string().contains("does not exist"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn transpiles_typescript_before_running_it() {
+        let limits = JsLimits {
+            timeout: Duration::from_secs(5),
+            max_heap_mb: 64,
+        };
+
+        let code = r#"
+            interface Order {
+                price: number;
+                quantity: number;
+            }
+            const total = (orders: Order[]): number =>
+                orders.reduce((sum, order) => sum + order.price * order.quantity, 0);
+            return { total: total(inputs.orders as Order[]) };
+        "#;
+        let result = process_js_task(
+            &json!({ "orders": [{ "price": 2, "quantity": 3 }, { "price": 4, "quantity": 1 }] }),
+            &json!({ "language": "typescript", "code": code }),
+            limits,
+            fetcher(),
+            libraries(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, Some(json!({ "total": 10 })));
+    }
+}
@@ -0,0 +1,169 @@
+use serde_json::Value;
+
+//Nested results past this are typed as unknown, deep API responses make unreadable declarations
+const MAX_DECLARATION_DEPTH: usize = 8;
+
+//plugin_config.language, actions saved before it existed are JavaScript
+pub fn is_typescript(plugin_config: &Value) -> bool {
+    plugin_config["language"]
+        .as_str()
+        .map(|language| language.eq_ignore_ascii_case("typescript"))
+        .unwrap_or(false)
+}
+
+//Module name the script is loaded under. rustyscript picks the media type from the
+//extension and strips types from .ts modules before V8 sees them.
+pub fn module_name(plugin_config: &Value) -> &'static str {
+    if is_typescript(plugin_config) {
+        "user_code.ts"
+    } else {
+        "user_code.js"
+    }
+}
+
+//Declares the injected `inputs` global for the editor. Types come from the rendered
+//inputs when the upstream actions have run, otherwise from the inputs schema.
+pub fn inputs_declaration(
+    rendered_inputs: Option<&Value>,
+    inputs_schema: Option<&Value>,
+) -> String {
+    //Sorted so the declaration doesn't change between requests
+    let mut keys: Vec<String> = match rendered_inputs {
+        Some(Value::Object(inputs)) => inputs.keys().cloned().collect(),
+        _ => Vec::new(),
+    };
+    keys.sort();
+    if let Some(Value::Object(properties)) = inputs_schema.map(|schema| &schema["properties"]) {
+        let mut schema_keys: Vec<String> = properties
+            .keys()
+            .filter(|key| !keys.contains(key))
+            .cloned()
+            .collect();
+        schema_keys.sort();
+        keys.extend(schema_keys);
+    }
+
+    let mut declaration = String::from("interface AnythingInputs {\n");
+    for key in keys {
+        let value_type = match rendered_inputs.and_then(|inputs| inputs.get(&key)) {
+            Some(value) => type_of(value, 1),
+            None => inputs_schema
+                .map(|schema| schema_type(&schema["properties"][&key]))
+                .unwrap_or_else(|| "unknown".to_string()),
+        };
+        declaration.push_str(&format!("  {}: {};\n", property_name(&key), value_type));
+    }
+    declaration.push_str("}\n\ndeclare const inputs: AnythingInputs;\n");
+    declaration
+}
+
+fn type_of(value: &Value, depth: usize) -> String {
+    if depth > MAX_DECLARATION_DEPTH {
+        return "unknown".to_string();
+    }
+
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(_) => "boolean".to_string(),
+        Value::Number(_) => "number".to_string(),
+        Value::String(_) => "string".to_string(),
+        Value::Array(items) => {
+            let mut item_types: Vec<String> = Vec::new();
+            for item in items {
+                let item_type = type_of(item, depth + 1);
+                if !item_types.contains(&item_type) {
+                    item_types.push(item_type);
+                }
+            }
+            match item_types.len() {
+                0 => "unknown[]".to_string(),
+                1 => format!("{}[]", item_types[0]),
+                _ => format!("({})[]", item_types.join(" | ")),
+            }
+        }
+        Value::Object(fields) if fields.is_empty() => "Record<string, unknown>".to_string(),
+        Value::Object(fields) => {
+            let indent = "  ".repeat(depth);
+            let mut fields: Vec<_> = fields.iter().collect();
+            fields.sort_by(|a, b| a.0.cmp(b.0));
+            let mut object_type = String::from("{\n");
+            for (key, field) in fields {
+                object_type.push_str(&format!(
+                    "{}  {}: {};\n",
+                    indent,
+                    property_name(key),
+                    type_of(field, depth + 1)
+                ));
+            }
+            object_type.push_str(&format!("{}}}", indent));
+            object_type
+        }
+    }
+}
+
+fn schema_type(property: &Value) -> String {
+    match property["type"].as_str() {
+        Some("string") => "string",
+        Some("number") | Some("integer") => "number",
+        Some("boolean") => "boolean",
+        Some("array") => "unknown[]",
+        Some("object") => "Record<string, unknown>",
+        Some("null") => "null",
+        _ => "unknown",
+    }
+    .to_string()
+}
+
+fn property_name(key: &str) -> String {
+    let mut chars = key.chars();
+    let is_identifier = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        key.to_string()
+    } else {
+        serde_json::to_string(key).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn declares_inputs_from_rendered_values_and_schema() {
+        let rendered = json!({
+            "user": { "id": 7, "tags": ["a", 1], "first-name": "Ada" },
+            "items": [],
+            "active": true
+        });
+        let schema = json!({
+            "properties": {
+                "active": { "type": "string" },
+                "limit": { "type": "integer" }
+            }
+        });
+
+        assert_eq!(
+            inputs_declaration(Some(&rendered), Some(&schema)),
+            r#"interface AnythingInputs {
+  active: boolean;
+  items: unknown[];
+  user: {
+    "first-name": string;
+    id: number;
+    tags: (string | number)[];
+  };
+  limit: number;
+}
+
+declare const inputs: AnythingInputs;
+"#
+        );
+
+        assert!(is_typescript(
+            &json!({ "language": "typescript", "code": "" })
+        ));
+        assert_eq!(module_name(&json!({ "code": "" })), "user_code.js");
+    }
+}
@@ -1,12 +1,17 @@
 use serde_json::Value;
 
+use crate::bundler::redaction::describe_value;
+
 // This is meant to be used for function calls if we do agents and voice call type thing
 // And to be how we do reusable flows or subflows
 pub fn process_output_task(
     bundled_context: &Value,
 ) -> Result<Option<Value>, Box<dyn std::error::Error + Send + Sync>> {
     println!("[OUTPUT] Starting output task processing");
-    println!("[OUTPUT] Bundled context: {:?}", bundled_context);
+    println!(
+        "[OUTPUT] Bundled context: {}",
+        describe_value(bundled_context)
+    );
 
     // Deep parse JSON to handle common escape issues helpful for all the dirty json we have
     fn deep_parse_json(input: &str) -> Result<Value, serde_json::Error> {
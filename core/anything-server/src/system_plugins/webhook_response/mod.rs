@@ -80,8 +80,6 @@ pub fn deep_parse_json(input: &str) -> Result<Value, serde_json::Error> {
     Ok(Value::String(input.to_string()))
 }
 
-//TODO: Something in here I thik is what makes it so we can only support returning JSON from webhooks.
-//For now just going to make webhooks only return json
 pub async fn process_webhook_response_task(
     state: Arc<AppState>,
     flow_session_id: String,
@@ -93,48 +91,57 @@ pub async fn process_webhook_response_task(
         bundled_context
     );
 
+    let get_str = |key: &str| {
+        bundled_context
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+
     // Get the required fields from the bundled context
-    let status_code = bundled_context
-        .get("status_code")
-        .and_then(|v| v.as_str())
-        .unwrap_or("200");
+    let status_code = match bundled_context.get("status_code") {
+        Some(Value::Number(n)) => n.as_u64().unwrap_or(200),
+        Some(Value::String(s)) => s.trim().parse::<u64>().unwrap_or(200),
+        _ => 200,
+    };
+    if !(100..=599).contains(&status_code) {
+        return Err(format!("Invalid response status code: {}", status_code).into());
+    }
 
     let content_type = bundled_context
         .get("content_type")
         .and_then(|v| v.as_str())
+        .filter(|v| !v.is_empty())
         .unwrap_or("application/json");
 
-    let headers = bundled_context
-        .get("headers")
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
+    // Headers come through as an object or as a JSON string depending on how they were templated
+    let headers = match bundled_context.get("headers") {
+        Some(Value::Object(headers)) => Value::Object(headers.clone()).to_string(),
+        Some(Value::String(headers)) => headers.clone(),
+        _ => String::new(),
+    };
+    let redirect_url = get_str("redirect_url");
+    let filename = get_str("filename");
+    let binary_body = get_str("binary_body");
 
     // Get body based on content type
+    let mut body_encoding = None;
     let body = match content_type {
         "application/json" => bundled_context
             .get("json_body")
             .map(|v| v.to_string())
             .unwrap_or_else(|| "{}".to_string()),
-        "text/plain" => bundled_context
-            .get("text_body")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        "text/html" => bundled_context
-            .get("html_body")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        "text/xml" => bundled_context
-            .get("xml_body")
-            .and_then(|v| v.as_str())
-            .unwrap_or("")
-            .to_string(),
-        _ => bundled_context
-            .get("json_body")
-            .and_then(|v| v.as_str())
-            .unwrap_or("{}")
-            .to_string(),
+        "text/plain" => get_str("text_body"),
+        "text/html" => get_str("html_body"),
+        "text/xml" | "application/xml" => get_str("xml_body"),
+        "text/csv" => get_str("csv_body"),
+        // Files and any other type can be sent as base64 that we decode when responding
+        _ if !binary_body.is_empty() => {
+            body_encoding = Some("base64");
+            binary_body
+        }
+        _ => get_str("text_body"),
     };
 
     println!("[PROCESS RESPONSE] Status code: {}", status_code);
@@ -144,10 +151,7 @@ pub async fn process_webhook_response_task(
 
     // Build response object
     let mut response = serde_json::Map::new();
-    response.insert(
-        "status_code".to_string(),
-        Value::String(status_code.to_string()),
-    );
+    response.insert("status_code".to_string(), Value::from(status_code));
 
     // Build headers map
     let mut headers_map = serde_json::Map::new();
@@ -156,12 +160,23 @@ pub async fn process_webhook_response_task(
         Value::String(content_type.to_string()),
     );
 
+    if !filename.is_empty() {
+        headers_map.insert(
+            "content-disposition".to_string(),
+            Value::String(format!(
+                "attachment; filename=\"{}\"",
+                filename.replace('"', "")
+            )),
+        );
+    }
+
     // Parse and add additional headers if present
+    // Values can be lists to send a header more than once like Set-Cookie
     if !headers.is_empty() {
-        match deep_parse_json(headers) {
+        match deep_parse_json(&headers) {
             Ok(Value::Object(parsed_headers)) => {
                 for (key, value) in parsed_headers {
-                    headers_map.insert(key, value);
+                    headers_map.insert(key.to_lowercase(), value);
                 }
             }
             Ok(Value::Null) | Ok(Value::Bool(_)) | Ok(Value::Number(_)) | Ok(Value::String(_))
@@ -176,6 +191,17 @@ pub async fn process_webhook_response_task(
 
     response.insert("headers".to_string(), Value::Object(headers_map));
 
+    if !redirect_url.is_empty() {
+        response.insert("redirect_url".to_string(), Value::String(redirect_url));
+    }
+
+    if let Some(body_encoding) = body_encoding {
+        response.insert(
+            "body_encoding".to_string(),
+            Value::String(body_encoding.to_string()),
+        );
+    }
+
     // Parse and add body if present
     if !body.is_empty() {
         if content_type == "application/json" {
@@ -0,0 +1,460 @@
+use axum::{
+    body::Bytes,
+    extract::{ConnectInfo, Extension, Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{sync::oneshot, time::timeout};
+use uuid::Uuid;
+
+use crate::{
+    bundler::{bundle_cached_inputs, secrets::SecretScope},
+    processor::db_calls::{get_session_tasks, get_workflow_definition},
+    system_plugins::javascript::fetch::{check_public_url, JsFetchPolicy},
+    types::{
+        action_types::{ActionType, PluginName},
+        task_types::{Task, TaskStatus},
+    },
+    AppState,
+};
+
+use super::rate_limit::{enforce_webhook_access_limits, get_client_ip};
+use super::request_log::WebhookRequestLog;
+use super::webhook_trigger::{run_workflow_and_respond, run_workflow_version_and_respond};
+use super::webhook_trigger_utils::validate_security_model;
+
+//How long we wait on a workflow before telling the callback it timed out. One Hour.
+pub const ASYNC_WEBHOOK_TIMEOUT: u64 = 3600;
+
+//Prefer header per RFC 7240, may hold several comma separated preferences
+pub fn prefers_async(headers: &HeaderMap) -> bool {
+    headers.get_all("prefer").iter().any(|value| {
+        value
+            .to_str()
+            .unwrap_or("")
+            .split(',')
+            .any(|preference| preference.trim().eq_ignore_ascii_case("respond-async"))
+    })
+}
+
+//Optional url we POST the final webhook_response payload to. Callers pick it, so loopback,
+//private and metadata addresses are rejected here and again when the result is delivered.
+pub async fn get_callback_url(headers: &HeaderMap) -> Result<Option<String>, Response> {
+    let callback_url = match headers.get("x-callback-url").and_then(|v| v.to_str().ok()) {
+        Some(url) if !url.trim().is_empty() => url.trim().to_string(),
+        _ => return Ok(None),
+    };
+
+    let url = match reqwest::Url::parse(&callback_url) {
+        Ok(url) => url,
+        Err(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "X-Callback-Url must be an http or https url",
+            )
+                .into_response())
+        }
+    };
+
+    match check_public_url(&JsFetchPolicy::default(), &url).await {
+        Ok(()) => Ok(Some(callback_url)),
+        Err(e) => {
+            println!("[WEBHOOK API] Rejected callback url: {}", e);
+            Err((
+                StatusCode::BAD_REQUEST,
+                "X-Callback-Url must be a public http or https url",
+            )
+                .into_response())
+        }
+    }
+}
+
+pub fn flow_session_status_path(flow_session_id: &str) -> String {
+    format!("/api/v1/flow_session/{}", flow_session_id)
+}
+
+pub fn accepted_response(flow_session_id: &str) -> Response {
+    let status_url = flow_session_status_path(flow_session_id);
+    let mut response = (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "workflow_session_id": flow_session_id,
+            "status": "running",
+            "status_url": status_url
+        })),
+    )
+        .into_response();
+
+    if let Ok(location) = HeaderValue::from_str(&status_url) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("location"), location);
+    }
+    response
+}
+
+//Same as /start/respond with Prefer: respond-async for callers that can't set headers
+pub async fn run_workflow_and_respond_async(
+    method: Method,
+    path: Path<String>,
+    state: State<Arc<AppState>>,
+    connect_info: ConnectInfo<SocketAddr>,
+    request_log: Extension<WebhookRequestLog>,
+    mut headers: HeaderMap,
+    query: Option<Query<HashMap<String, String>>>,
+    raw_body: Bytes,
+) -> impl IntoResponse {
+    headers.insert("prefer", HeaderValue::from_static("respond-async"));
+    run_workflow_and_respond(
+        method,
+        path,
+        state,
+        connect_info,
+        request_log,
+        headers,
+        query,
+        raw_body,
+    )
+    .await
+    .into_response()
+}
+
+pub async fn run_workflow_version_and_respond_async(
+    method: Method,
+    path: Path<(String, String)>,
+    state: State<Arc<AppState>>,
+    connect_info: ConnectInfo<SocketAddr>,
+    request_log: Extension<WebhookRequestLog>,
+    mut headers: HeaderMap,
+    query: Option<Query<HashMap<String, String>>>,
+    raw_body: Bytes,
+) -> impl IntoResponse {
+    headers.insert("prefer", HeaderValue::from_static("respond-async"));
+    run_workflow_version_and_respond(
+        method,
+        path,
+        state,
+        connect_info,
+        request_log,
+        headers,
+        query,
+        raw_body,
+    )
+    .await
+    .into_response()
+}
+
+//Waits for the webhook_response action and POSTs its result to the callback url
+pub async fn deliver_async_result(
+    state: Arc<AppState>,
+    flow_session_id: String,
+    callback_url: String,
+    rx: oneshot::Receiver<Value>,
+) {
+    let payload = match timeout(Duration::from_secs(ASYNC_WEBHOOK_TIMEOUT), rx).await {
+        Ok(Ok(result)) => json!({
+            "workflow_session_id": flow_session_id,
+            "status": "completed",
+            "response": result
+        }),
+        Ok(Err(_)) => json!({
+            "workflow_session_id": flow_session_id,
+            "status": "failed",
+            "error": "Workflow execution channel closed unexpectedly"
+        }),
+        Err(_) => {
+            state.flow_completions.lock().await.remove(&flow_session_id);
+            json!({
+                "workflow_session_id": flow_session_id,
+                "status": "timed_out",
+                "error": "Workflow execution timed out"
+            })
+        }
+    };
+
+    println!(
+        "[WEBHOOK API] Sending async result for {} to callback url",
+        flow_session_id
+    );
+
+    match state
+        .webhook_callback_client
+        .post(&callback_url)
+        .json(&payload)
+        .send()
+        .await
+    {
+        Ok(response) => println!(
+            "[WEBHOOK API] Callback for {} returned status {}",
+            flow_session_id,
+            response.status()
+        ),
+        Err(e) => println!(
+            "[WEBHOOK API] Failed to deliver callback for {}: {:?}",
+            flow_session_id, e
+        ),
+    }
+}
+
+//Trigger inputs validate_security_model reads
+fn security_inputs(inputs: Option<&Value>) -> Value {
+    let inputs = match inputs {
+        Some(Value::Object(inputs)) => inputs,
+        _ => return json!({}),
+    };
+    Value::Object(
+        inputs
+            .iter()
+            .filter(|(key, _)| {
+                matches!(
+                    key.as_str(),
+                    "security_model"
+                        | "username"
+                        | "password"
+                        | "custom_header_name"
+                        | "custom_header_value"
+                ) || key.starts_with("hmac_")
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    )
+}
+
+//The trigger is always processed first
+fn find_trigger_task(tasks: &mut [Task]) -> Option<&Task> {
+    tasks.sort_by_key(|task| task.processing_order);
+    tasks.first()
+}
+
+//Status of an async webhook run. Uses the workflow's webhook security model so only the caller can read it.
+pub async fn get_flow_session_status(
+    Path(flow_session_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    raw_body: Bytes,
+) -> impl IntoResponse {
+    println!(
+        "[WEBHOOK API] Handling get flow session status for {}",
+        flow_session_id
+    );
+
+    let session_uuid = match Uuid::parse_str(&flow_session_id) {
+        Ok(uuid) => uuid,
+        Err(_) => {
+            return (StatusCode::BAD_REQUEST, "Invalid flow session id").into_response();
+        }
+    };
+
+    //Running sessions live in the processor cache and may not be written to the db yet
+    let cached_tasks = {
+        let cache = state.flow_session_cache.read().await;
+        cache
+            .get(&session_uuid)
+            .map(|data| data.tasks.into_values().collect::<Vec<_>>())
+            .filter(|tasks| !tasks.is_empty())
+    };
+
+    let mut tasks = match cached_tasks {
+        Some(tasks) => tasks,
+        None => match get_session_tasks(state.clone(), &session_uuid).await {
+            Ok(tasks) => tasks,
+            Err(_) => {
+                return (StatusCode::NOT_FOUND, "Flow session not found").into_response();
+            }
+        },
+    };
+    //Unknown sessions come back as an empty list
+    let trigger_task = match find_trigger_task(&mut tasks) {
+        Some(trigger_task) => trigger_task.clone(),
+        None => {
+            return (StatusCode::NOT_FOUND, "Flow session not found").into_response();
+        }
+    };
+
+    let workflow_version = match get_workflow_definition(
+        state.clone(),
+        &trigger_task.flow_id,
+        Some(&trigger_task.flow_version_id),
+    )
+    .await
+    {
+        Ok(version) => version,
+        Err(e) => {
+            println!("[WEBHOOK API] Failed to get workflow definition: {}", e);
+            return (StatusCode::NOT_FOUND, "Flow session not found").into_response();
+        }
+    };
+
+    let webhook_plugin = PluginName::new("@anything/webhook".to_string()).unwrap();
+    let trigger_node = match workflow_version
+        .flow_definition
+        .actions
+        .iter()
+        .find(|action| action.r#type == ActionType::Trigger && action.plugin_name == webhook_plugin)
+    {
+        Some(trigger_node) => trigger_node,
+        None => {
+            //Only sessions started by a webhook can be read here
+            return (StatusCode::NOT_FOUND, "Flow session not found").into_response();
+        }
+    };
+
+    //Polls count against the webhook's rate limit and allowlist like the calls that start it
+    let client_ip = get_client_ip(&headers, &peer_addr);
+    if let Some(response) = enforce_webhook_access_limits(
+        state.clone(),
+        &trigger_task.flow_id.to_string(),
+        trigger_node.inputs.as_ref(),
+        &headers,
+        client_ip,
+    )
+    .await
+    {
+        return response;
+    }
+
+    //Only what the security model needs, and without audit rows, every poll would add some
+    let security_inputs = security_inputs(trigger_node.inputs.as_ref());
+    let rendered_inputs = match bundle_cached_inputs(
+        state.clone(),
+        &state.anything_client,
+        &trigger_task.account_id.to_string(),
+        &flow_session_id,
+        &SecretScope::new(Some(trigger_task.flow_id), trigger_task.stage.clone()),
+        Some(&security_inputs),
+        trigger_node.inputs_schema.as_ref(),
+        false,
+        None,
+        None,
+    )
+    .await
+    {
+        Ok((rendered_inputs, _)) => rendered_inputs,
+        Err(e) => {
+            println!("[WEBHOOK API] Failed to render security inputs: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to render trigger security inputs",
+            )
+                .into_response();
+        }
+    };
+
+    if let Some(response) = validate_security_model(
+        &rendered_inputs,
+        &headers,
+        &raw_body,
+        state.clone(),
+        &trigger_task.account_id.to_string(),
+        &trigger_task.flow_id.to_string(),
+        client_ip,
+        false,
+    )
+    .await
+    {
+        return response.into_response();
+    }
+
+    let webhook_response_plugin =
+        PluginName::new("@anything/webhook_response".to_string()).unwrap();
+    let response = tasks
+        .iter()
+        .find(|task| {
+            task.plugin_name.as_ref() == Some(&webhook_response_plugin)
+                && task.task_status == TaskStatus::Completed
+        })
+        .and_then(|task| task.result.clone());
+
+    let error = tasks
+        .iter()
+        .find(|task| task.task_status == TaskStatus::Failed)
+        .and_then(|task| task.error.clone());
+
+    Json(json!({
+        "workflow_session_id": flow_session_id,
+        "workflow_id": trigger_task.flow_id,
+        "workflow_version_id": trigger_task.flow_version_id,
+        "status": trigger_task.flow_session_status.as_str(),
+        "response": response,
+        "error": error
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(action_id: &str, processing_order: i32) -> Task {
+        serde_json::from_value(json!({
+            "task_id": Uuid::new_v4(),
+            "account_id": Uuid::new_v4(),
+            "task_status": "completed",
+            "flow_id": Uuid::new_v4(),
+            "flow_version_id": Uuid::new_v4(),
+            "action_label": action_id,
+            "trigger_id": "webhook",
+            "trigger_session_id": Uuid::new_v4().to_string(),
+            "trigger_session_status": "completed",
+            "flow_session_id": Uuid::new_v4().to_string(),
+            "flow_session_status": "completed",
+            "action_id": action_id,
+            "type": "action",
+            "plugin_name": null,
+            "plugin_version": null,
+            "stage": "production",
+            "test_config": null,
+            "config": {},
+            "context": null,
+            "started_at": null,
+            "ended_at": null,
+            "debug_result": null,
+            "result": null,
+            "error": null,
+            "archived": false,
+            "updated_at": null,
+            "created_at": null,
+            "updated_by": null,
+            "created_by": null,
+            "processing_order": processing_order
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_callback_urls_pointing_inside_the_network() {
+        for callback_url in [
+            "http://127.0.0.1:3001/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/",
+            "ftp://example.com/",
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-callback-url", HeaderValue::from_static(callback_url));
+            let response = get_callback_url(&headers).await.unwrap_err();
+            assert_eq!(
+                response.status(),
+                StatusCode::BAD_REQUEST,
+                "{}",
+                callback_url
+            );
+        }
+
+        assert!(matches!(
+            get_callback_url(&HeaderMap::new()).await,
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn unknown_sessions_have_no_trigger_task() {
+        assert!(find_trigger_task(&mut []).is_none());
+
+        let mut tasks = vec![task("respond", 1), task("trigger", 0)];
+        assert_eq!(find_trigger_task(&mut tasks).unwrap().action_id, "trigger");
+    }
+}
@@ -0,0 +1,467 @@
+use axum::http::HeaderMap;
+use base64::Engine;
+use chrono::Utc;
+use dotenv::dotenv;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+
+//Default window for timestamped signatures
+pub const DEFAULT_TOLERANCE_SECONDS: i64 = 300;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HmacAlgorithm {
+    Sha256,
+    Sha1,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignatureEncoding {
+    Hex,
+    Base64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HmacPreset {
+    Custom,
+    GitHub,  // X-Hub-Signature-256: sha256=<hex>
+    Stripe,  // Stripe-Signature: t=<ts>,v1=<hex> over "<ts>.<body>"
+    Slack,   // X-Slack-Signature: v0=<hex> over "v0:<ts>:<body>"
+    Shopify, // X-Shopify-Hmac-Sha256: <base64>
+}
+
+impl HmacPreset {
+    pub fn from_config(value: &str) -> Result<Self, String> {
+        match value {
+            "" | "custom" => Ok(HmacPreset::Custom),
+            "github" => Ok(HmacPreset::GitHub),
+            "stripe" => Ok(HmacPreset::Stripe),
+            "slack" => Ok(HmacPreset::Slack),
+            "shopify" => Ok(HmacPreset::Shopify),
+            _ => Err(format!("Unknown HMAC preset: {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HmacConfig {
+    pub preset: HmacPreset,
+    pub secret: String,
+    pub algorithm: HmacAlgorithm,
+    pub encoding: SignatureEncoding,
+    pub header: String,
+    pub prefix: String,
+    pub tolerance_seconds: i64,
+}
+
+impl HmacConfig {
+    //Presets fix everything but the secret and tolerance so users only paste the signing secret
+    pub fn from_rendered_inputs(rendered_inputs: &Value) -> Result<Self, String> {
+        let get_str = |key: &str| {
+            rendered_inputs
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string()
+        };
+
+        let secret = get_str("hmac_secret");
+        if secret.is_empty() {
+            return Err("No HMAC secret configured".to_string());
+        }
+
+        let tolerance_seconds = rendered_inputs
+            .get("hmac_tolerance_seconds")
+            .and_then(|v| v.as_f64())
+            .map(|v| v as i64)
+            .filter(|v| *v > 0)
+            .unwrap_or(DEFAULT_TOLERANCE_SECONDS);
+
+        let preset = HmacPreset::from_config(&get_str("hmac_preset"))?;
+
+        let (algorithm, encoding, header, prefix) = match preset {
+            HmacPreset::GitHub => (
+                HmacAlgorithm::Sha256,
+                SignatureEncoding::Hex,
+                "x-hub-signature-256".to_string(),
+                "sha256=".to_string(),
+            ),
+            HmacPreset::Stripe => (
+                HmacAlgorithm::Sha256,
+                SignatureEncoding::Hex,
+                "stripe-signature".to_string(),
+                String::new(),
+            ),
+            HmacPreset::Slack => (
+                HmacAlgorithm::Sha256,
+                SignatureEncoding::Hex,
+                "x-slack-signature".to_string(),
+                "v0=".to_string(),
+            ),
+            HmacPreset::Shopify => (
+                HmacAlgorithm::Sha256,
+                SignatureEncoding::Base64,
+                "x-shopify-hmac-sha256".to_string(),
+                String::new(),
+            ),
+            HmacPreset::Custom => {
+                let algorithm = match get_str("hmac_algorithm").to_lowercase().as_str() {
+                    "" | "sha256" => HmacAlgorithm::Sha256,
+                    "sha1" => HmacAlgorithm::Sha1,
+                    other => return Err(format!("Unsupported HMAC algorithm: {}", other)),
+                };
+                let encoding = match get_str("hmac_encoding").to_lowercase().as_str() {
+                    "" | "hex" => SignatureEncoding::Hex,
+                    "base64" => SignatureEncoding::Base64,
+                    other => return Err(format!("Unsupported signature encoding: {}", other)),
+                };
+                let header = get_str("hmac_header");
+                if header.is_empty() {
+                    return Err("No signature header configured".to_string());
+                }
+                (algorithm, encoding, header, get_str("hmac_prefix"))
+            }
+        };
+
+        Ok(HmacConfig {
+            preset,
+            secret,
+            algorithm,
+            encoding,
+            header,
+            prefix,
+            tolerance_seconds,
+        })
+    }
+}
+
+//Verifies the signature over the raw body and returns the signature that matched.
+//It covers the body, so unlike a delivery id header a replayer can't swap it for a new one.
+pub fn verify_hmac_signature(
+    config: &HmacConfig,
+    headers: &HeaderMap,
+    raw_body: &[u8],
+    now: i64,
+) -> Result<String, String> {
+    let header_value = headers
+        .get(config.header.as_str())
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| format!("Missing signature header: {}", config.header))?;
+
+    match config.preset {
+        HmacPreset::Stripe => {
+            let mut timestamp = None;
+            let mut signatures = Vec::new();
+            for part in header_value.split(',') {
+                match part.trim().split_once('=') {
+                    Some(("t", value)) => timestamp = Some(value.to_string()),
+                    Some(("v1", value)) => signatures.push(value.to_string()),
+                    _ => {}
+                }
+            }
+            let timestamp = timestamp.ok_or("Missing timestamp in signature header")?;
+            check_timestamp(&timestamp, now, config.tolerance_seconds)?;
+
+            let payload = [timestamp.as_bytes(), b".", raw_body].concat();
+            //Stripe sends several v1 signatures while a secret is being rolled
+            signatures
+                .iter()
+                .find(|signature| signature_matches(config, &payload, signature))
+                .cloned()
+                .ok_or_else(|| "Invalid signature".to_string())
+        }
+        HmacPreset::Slack => {
+            let timestamp = headers
+                .get("x-slack-request-timestamp")
+                .and_then(|v| v.to_str().ok())
+                .ok_or("Missing header: x-slack-request-timestamp")?;
+            check_timestamp(timestamp, now, config.tolerance_seconds)?;
+
+            let signature = strip_prefix(header_value, &config.prefix)?;
+            let payload = [&b"v0:"[..], timestamp.as_bytes(), b":", raw_body].concat();
+            if signature_matches(config, &payload, signature) {
+                Ok(signature.to_string())
+            } else {
+                Err("Invalid signature".to_string())
+            }
+        }
+        _ => {
+            let signature = strip_prefix(header_value, &config.prefix)?;
+            if signature_matches(config, raw_body, signature) {
+                Ok(signature.to_string())
+            } else {
+                Err("Invalid signature".to_string())
+            }
+        }
+    }
+}
+
+fn strip_prefix<'a>(header_value: &'a str, prefix: &str) -> Result<&'a str, String> {
+    header_value
+        .trim()
+        .strip_prefix(prefix)
+        .ok_or_else(|| format!("Signature header must start with '{}'", prefix))
+}
+
+fn check_timestamp(timestamp: &str, now: i64, tolerance_seconds: i64) -> Result<(), String> {
+    let timestamp: i64 = timestamp
+        .trim()
+        .parse()
+        .map_err(|_| "Invalid signature timestamp".to_string())?;
+    if (now - timestamp).abs() > tolerance_seconds {
+        return Err("Signature timestamp outside of tolerance".to_string());
+    }
+    Ok(())
+}
+
+fn signature_matches(config: &HmacConfig, payload: &[u8], signature: &str) -> bool {
+    let expected = match config.encoding {
+        SignatureEncoding::Hex => hex::decode(signature.trim()).ok(),
+        SignatureEncoding::Base64 => base64::engine::general_purpose::STANDARD
+            .decode(signature.trim())
+            .ok(),
+    };
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return false,
+    };
+
+    //verify_slice compares in constant time
+    match config.algorithm {
+        HmacAlgorithm::Sha256 => match Hmac::<Sha256>::new_from_slice(config.secret.as_bytes()) {
+            Ok(mut mac) => {
+                mac.update(payload);
+                mac.verify_slice(&expected).is_ok()
+            }
+            Err(_) => false,
+        },
+        HmacAlgorithm::Sha1 => match Hmac::<Sha1>::new_from_slice(config.secret.as_bytes()) {
+            Ok(mut mac) => {
+                mac.update(payload);
+                mac.verify_slice(&expected).is_ok()
+            }
+            Err(_) => false,
+        },
+    }
+}
+
+//Stripe and Slack sign a timestamp, a replay after the tolerance fails the timestamp check
+fn signs_timestamp(preset: &HmacPreset) -> bool {
+    matches!(preset, HmacPreset::Stripe | HmacPreset::Slack)
+}
+
+//Hex signatures are case insensitive, the same one shouldn't pass twice by changing case
+fn signature_hash(config: &HmacConfig, signature: &str) -> String {
+    let signature = match config.encoding {
+        SignatureEncoding::Hex => signature.trim().to_lowercase(),
+        SignatureEncoding::Base64 => signature.trim().to_string(),
+    };
+    hex::encode(Sha256::digest(signature.as_bytes()))
+}
+
+//Records the signature for the workflow, false when it was already used. Kept in the database so
+//every instance sees it. Presets without a timestamp keep their signatures for good, so a
+//provider redelivering the exact same request is rejected as a replay.
+async fn record_signature(
+    state: &AppState,
+    workflow_id: &str,
+    config: &HmacConfig,
+    signature: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")?;
+
+    //A tolerance too large to add keeps the signature for good like the untimestamped presets
+    let expires_at = signs_timestamp(&config.preset)
+        .then(|| chrono::TimeDelta::try_seconds(config.tolerance_seconds))
+        .flatten()
+        .and_then(|tolerance| Utc::now().checked_add_signed(tolerance));
+
+    let response = state
+        .anything_client
+        .from("webhook_signatures")
+        .auth(supabase_service_role_api_key)
+        .insert(
+            json!({
+                "flow_id": workflow_id,
+                "signature_hash": signature_hash(config, signature),
+                "expires_at": expires_at,
+            })
+            .to_string(),
+        )
+        .execute()
+        .await?;
+
+    match response.status().as_u16() {
+        409 => Ok(false),
+        status if (200..300).contains(&status) => Ok(true),
+        status => Err(format!(
+            "Failed to record webhook signature: {} {}",
+            status,
+            response.text().await.unwrap_or_default()
+        )
+        .into()),
+    }
+}
+
+pub async fn validate_hmac_signature(
+    rendered_inputs: &Value,
+    headers: &HeaderMap,
+    raw_body: &[u8],
+    state: Arc<AppState>,
+    workflow_id: &str,
+    check_replays: bool,
+) -> Result<(), String> {
+    let config = HmacConfig::from_rendered_inputs(rendered_inputs)?;
+    let signature = verify_hmac_signature(&config, headers, raw_body, Utc::now().timestamp())?;
+    //Reads like status polls can be repeated, they don't start anything
+    if !check_replays {
+        return Ok(());
+    }
+
+    match record_signature(&state, workflow_id, &config, &signature).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("Webhook signature has already been used".to_string()),
+        Err(e) => {
+            println!("[WEBHOOK API] {}", e);
+            Err("Failed to check webhook signature for replays".to_string())
+        }
+    }
+}
+
+//Timestamped signatures can't be replayed once their tolerance has passed
+pub async fn cleanup_webhook_signatures(state: Arc<AppState>) {
+    let cleanup_interval = Duration::from_secs(600);
+    loop {
+        tokio::time::sleep(cleanup_interval).await;
+        println!("[WEBHOOK API] Deleting expired webhook signatures");
+
+        dotenv().ok();
+        let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+            .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+        match state
+            .anything_client
+            .from("webhook_signatures")
+            .auth(supabase_service_role_api_key)
+            .lt("expires_at", Utc::now().to_rfc3339())
+            .delete()
+            .execute()
+            .await
+        {
+            Ok(response) if !response.status().is_success() => println!(
+                "[WEBHOOK API] Failed to delete expired webhook signatures: {}",
+                response.status()
+            ),
+            Ok(_) => {}
+            Err(e) => println!(
+                "[WEBHOOK API] Failed to delete expired webhook signatures: {:?}",
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sign_hex(secret: &str, payload: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(payload);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn github_preset_verifies_raw_body() {
+        let body = br#"{"action":"opened"}"#;
+        let config = HmacConfig::from_rendered_inputs(&json!({
+            "hmac_preset": "github",
+            "hmac_secret": "shhh"
+        }))
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-hub-signature-256",
+            format!("sha256={}", sign_hex("shhh", body))
+                .parse()
+                .unwrap(),
+        );
+        headers.insert("x-github-delivery", "delivery-1".parse().unwrap());
+
+        //Replays are keyed on the signature, the delivery id isn't signed
+        let signature = verify_hmac_signature(&config, &headers, body, 0).unwrap();
+        assert_eq!(signature, sign_hex("shhh", body));
+
+        assert!(verify_hmac_signature(&config, &headers, b"tampered", 0).is_err());
+    }
+
+    #[test]
+    fn stripe_preset_checks_timestamp_tolerance() {
+        let body = br#"{"id":"evt_1"}"#;
+        let config = HmacConfig::from_rendered_inputs(&json!({
+            "hmac_preset": "stripe",
+            "hmac_secret": "whsec_test"
+        }))
+        .unwrap();
+
+        let signature = sign_hex("whsec_test", &[&b"1700000000."[..], &body[..]].concat());
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "stripe-signature",
+            format!("t=1700000000,v1=deadbeef,v1={}", signature)
+                .parse()
+                .unwrap(),
+        );
+
+        assert!(verify_hmac_signature(&config, &headers, body, 1700000100).is_ok());
+        assert!(verify_hmac_signature(&config, &headers, body, 1700001000).is_err());
+    }
+
+    #[test]
+    fn custom_sha1_base64_signature() {
+        let body = b"hello";
+        let mut mac = Hmac::<Sha1>::new_from_slice(b"key").unwrap();
+        mac.update(body);
+        let signature =
+            base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+
+        let config = HmacConfig::from_rendered_inputs(&json!({
+            "hmac_preset": "custom",
+            "hmac_secret": "key",
+            "hmac_algorithm": "sha1",
+            "hmac_encoding": "base64",
+            "hmac_header": "x-signature",
+            "hmac_prefix": ""
+        }))
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-signature", signature.parse().unwrap());
+
+        assert!(verify_hmac_signature(&config, &headers, body, 0).is_ok());
+    }
+
+    #[test]
+    fn replay_keys_ignore_hex_case_and_only_timestamped_presets_expire() {
+        let config = HmacConfig::from_rendered_inputs(&json!({
+            "hmac_preset": "github",
+            "hmac_secret": "shhh"
+        }))
+        .unwrap();
+        assert_eq!(
+            signature_hash(&config, "ABCDEF"),
+            signature_hash(&config, "abcdef")
+        );
+        assert!(!signs_timestamp(&config.preset));
+        assert!(signs_timestamp(&HmacPreset::Stripe));
+    }
+}
@@ -1,3 +1,8 @@
 pub mod webhook_trigger;
 pub use webhook_trigger::*;
 pub mod webhook_trigger_utils;
+pub mod hmac_signature;
+pub mod request_body;
+pub mod async_webhook;
+pub mod rate_limit;
+pub mod request_log;
@@ -0,0 +1,505 @@
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::AppState;
+
+pub const DEFAULT_RATE_LIMIT_WINDOW_SECONDS: u64 = 60;
+
+//Callers can pick any header value or IPv6 address, these keep them from growing the map forever
+const MAX_BUCKETS_PER_WORKFLOW: usize = 10_000;
+const MAX_BUCKETS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitKey {
+    Workflow,       // One bucket shared by every caller of the workflow
+    Ip,             // One bucket per client IP
+    Header(String), // One bucket per value of a request header like an API key or tenant id
+}
+
+//An IP address or CIDR range like 10.0.0.0/8 or 2001:db8::/32
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpCidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("Invalid IP address in allowlist: {}", value))?;
+
+        let max_len = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("Invalid CIDR prefix in allowlist: {}", value))?,
+            None => max_len,
+        };
+
+        Ok(IpCidr {
+            network,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        //IPv4 clients often show up as ::ffff:a.b.c.d on dual stack sockets
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+//Rate limit and allowlist settings. Read from the raw trigger inputs so we can reject before rendering anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookAccessConfig {
+    pub rate_limit_requests: u32,
+    pub rate_limit_window: Duration,
+    pub rate_limit_key: RateLimitKey,
+    pub ip_allowlist: Vec<IpCidr>,
+}
+
+impl WebhookAccessConfig {
+    pub fn from_inputs(inputs: Option<&Value>) -> Result<Self, String> {
+        let empty = Value::Null;
+        let inputs = inputs.unwrap_or(&empty);
+
+        let rate_limit_requests = read_number(inputs, "rate_limit_requests")?.unwrap_or(0);
+        let rate_limit_window_seconds = read_number(inputs, "rate_limit_window_seconds")?
+            .filter(|seconds| *seconds > 0)
+            .unwrap_or(DEFAULT_RATE_LIMIT_WINDOW_SECONDS as u32);
+
+        let rate_limit_key = match inputs["rate_limit_key"].as_str().unwrap_or("workflow") {
+            "ip" => RateLimitKey::Ip,
+            "header" => {
+                let header = inputs["rate_limit_header"].as_str().unwrap_or("").trim();
+                if header.is_empty() {
+                    return Err("Rate limiting by header needs a rate_limit_header".to_string());
+                }
+                RateLimitKey::Header(header.to_lowercase())
+            }
+            _ => RateLimitKey::Workflow,
+        };
+
+        let ip_allowlist = inputs["ip_allowlist"]
+            .as_str()
+            .unwrap_or("")
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|entry| !entry.is_empty())
+            .map(IpCidr::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(WebhookAccessConfig {
+            rate_limit_requests,
+            rate_limit_window: Duration::from_secs(rate_limit_window_seconds as u64),
+            rate_limit_key,
+            ip_allowlist,
+        })
+    }
+}
+
+//Inputs hold numbers, but older workflows and the editor sometimes store them as strings
+fn read_number(inputs: &Value, field: &str) -> Result<Option<u32>, String> {
+    match &inputs[field] {
+        Value::Null => Ok(None),
+        Value::Number(n) => n
+            .as_u64()
+            .map(|n| Some(n.min(u32::MAX as u64) as u32))
+            .ok_or_else(|| format!("{} must be a positive whole number", field)),
+        Value::String(s) if s.trim().is_empty() => Ok(None),
+        Value::String(s) => s
+            .trim()
+            .parse::<u32>()
+            .map(Some)
+            .map_err(|_| format!("{} must be a positive whole number", field)),
+        _ => Err(format!("{} must be a positive whole number", field)),
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    window: Duration,
+}
+
+//Token buckets keyed by workflow and caller. Each bucket holds `capacity` tokens and refills over the window.
+#[derive(Default)]
+pub struct WebhookRateLimiter {
+    buckets: HashMap<String, TokenBucket>, // "workflow_id:caller" -> bucket
+    workflow_buckets: HashMap<String, usize>, // workflow_id -> number of buckets
+}
+
+fn bucket_workflow_id(key: &str) -> &str {
+    key.split_once(':')
+        .map_or(key, |(workflow_id, _)| workflow_id)
+}
+
+impl WebhookRateLimiter {
+    pub fn new() -> Self {
+        Self {
+            buckets: HashMap::new(),
+            workflow_buckets: HashMap::new(),
+        }
+    }
+
+    //First caller that already has a bucket or still fits under the caps, most specific first.
+    //The last one is the workflow's shared bucket and is always used when nothing else fits.
+    pub fn bucket_key(&self, workflow_id: &str, callers: &[String]) -> String {
+        let workflow_buckets = self.workflow_buckets.get(workflow_id).copied().unwrap_or(0);
+        let has_room =
+            workflow_buckets < MAX_BUCKETS_PER_WORKFLOW && self.buckets.len() < MAX_BUCKETS;
+
+        callers
+            .iter()
+            .map(|caller| format!("{}:{}", workflow_id, caller))
+            .find(|key| has_room || self.buckets.contains_key(key))
+            .unwrap_or_else(|| format!("{}:", workflow_id))
+    }
+
+    //Takes a token for the key, or returns how long until one is available
+    pub fn try_acquire(
+        &mut self,
+        key: &str,
+        capacity: u32,
+        window: Duration,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let capacity = capacity as f64;
+        let refill_per_second = capacity / window.as_secs_f64();
+
+        if !self.buckets.contains_key(key) {
+            *self
+                .workflow_buckets
+                .entry(bucket_workflow_id(key).to_string())
+                .or_insert(0) += 1;
+        }
+        let bucket = self
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket {
+                tokens: capacity,
+                last_refill: now,
+                window,
+            });
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * refill_per_second).min(capacity);
+        bucket.last_refill = now;
+        bucket.window = window;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / refill_per_second;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+
+    //Buckets idle for a full window are back at capacity so we can forget them
+    pub fn cleanup(&mut self, now: Instant) {
+        self.buckets
+            .retain(|_, bucket| now.saturating_duration_since(bucket.last_refill) < bucket.window);
+
+        self.workflow_buckets.clear();
+        for key in self.buckets.keys() {
+            *self
+                .workflow_buckets
+                .entry(bucket_workflow_id(key).to_string())
+                .or_insert(0) += 1;
+        }
+    }
+}
+
+//Peer address, or the address our trusted proxies saw when we run behind them
+pub fn get_client_ip(headers: &HeaderMap, peer_addr: &SocketAddr) -> IpAddr {
+    let trust_forwarded_for = env::var("TRUST_FORWARDED_FOR")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+
+    if trust_forwarded_for {
+        //How many proxies in front of us append to X-Forwarded-For, usually just the load balancer
+        let trusted_proxy_hops = env::var("TRUSTED_PROXY_HOPS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(1);
+        if let Some(ip) = forwarded_client_ip(headers, trusted_proxy_hops) {
+            return ip;
+        }
+    }
+
+    peer_addr.ip()
+}
+
+//Clients can send their own X-Forwarded-For, only the entries our proxies appended on the
+//right are trustworthy. The last trusted proxy's entry is the address it saw the client on.
+fn forwarded_client_ip(headers: &HeaderMap, trusted_proxy_hops: usize) -> Option<IpAddr> {
+    let entries: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    let index = entries.len().checked_sub(trusted_proxy_hops.max(1))?;
+    entries[index].parse::<IpAddr>().ok()
+}
+
+//Checks the allowlist and rate limit for a webhook call. Runs before bundling so rejected calls cost us nothing.
+pub async fn enforce_webhook_access_limits(
+    state: Arc<AppState>,
+    workflow_id: &str,
+    trigger_inputs: Option<&Value>,
+    headers: &HeaderMap,
+    client_ip: IpAddr,
+) -> Option<Response> {
+    let config = match WebhookAccessConfig::from_inputs(trigger_inputs) {
+        Ok(config) => config,
+        Err(e) => {
+            println!(
+                "[WEBHOOK API] Invalid access limits for workflow {}: {}",
+                workflow_id, e
+            );
+            return Some(
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Invalid webhook access limits: {}", e),
+                )
+                    .into_response(),
+            );
+        }
+    };
+
+    if !config.ip_allowlist.is_empty()
+        && !config
+            .ip_allowlist
+            .iter()
+            .any(|cidr| cidr.contains(&client_ip))
+    {
+        println!(
+            "[WEBHOOK API] Rejected request from {} for workflow {}: not in IP allowlist",
+            client_ip, workflow_id
+        );
+        return Some((StatusCode::FORBIDDEN, "IP address not allowed").into_response());
+    }
+
+    if config.rate_limit_requests == 0 {
+        return None;
+    }
+
+    //Callers to key on, most specific first. Past the bucket caps new header values fall back
+    //to the client's IP and new IPs to the workflow's shared bucket.
+    let ip_caller = format!("ip:{}", client_ip);
+    let callers = match &config.rate_limit_key {
+        RateLimitKey::Workflow => vec![],
+        RateLimitKey::Ip => vec![ip_caller],
+        //Requests without the header share one bucket
+        RateLimitKey::Header(name) => vec![
+            format!(
+                "header:{}",
+                headers
+                    .get(name.as_str())
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("")
+            ),
+            ip_caller,
+        ],
+    };
+
+    let result = {
+        let mut limiter = state.webhook_rate_limiter.write().await;
+        let bucket_key = limiter.bucket_key(workflow_id, &callers);
+        limiter.try_acquire(
+            &bucket_key,
+            config.rate_limit_requests,
+            config.rate_limit_window,
+            Instant::now(),
+        )
+    };
+
+    match result {
+        Ok(()) => None,
+        Err(retry_after) => {
+            println!(
+                "[WEBHOOK API] Rate limited request from {} for workflow {}",
+                client_ip, workflow_id
+            );
+            let mut response =
+                (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded").into_response();
+            let retry_after_seconds = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            if let Ok(value) = HeaderValue::from_str(&retry_after_seconds.to_string()) {
+                response.headers_mut().insert("retry-after", value);
+            }
+            Some(response)
+        }
+    }
+}
+
+pub async fn cleanup_webhook_rate_limiter(state: Arc<AppState>) {
+    let cleanup_interval = Duration::from_secs(600);
+    loop {
+        tokio::time::sleep(cleanup_interval).await;
+        println!("[WEBHOOK API] Running scheduled rate limiter cleanup");
+        let mut limiter = state.webhook_rate_limiter.write().await;
+        limiter.cleanup(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn cidr_matches_ipv4_and_ipv6_ranges() {
+        let v4 = IpCidr::parse("10.1.0.0/16").unwrap();
+        assert!(v4.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(v4.contains(&"::ffff:10.1.0.9".parse().unwrap()));
+        assert!(!v4.contains(&"10.2.0.1".parse().unwrap()));
+
+        let v6 = IpCidr::parse("2001:db8::/32").unwrap();
+        assert!(v6.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!v6.contains(&"2001:db9::1".parse().unwrap()));
+
+        let single = IpCidr::parse("192.168.1.5").unwrap();
+        assert!(single.contains(&"192.168.1.5".parse().unwrap()));
+        assert!(!single.contains(&"192.168.1.6".parse().unwrap()));
+
+        assert!(IpCidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains(&"8.8.8.8".parse().unwrap()));
+        assert!(IpCidr::parse("10.0.0.0/33").is_err());
+        assert!(IpCidr::parse("not-an-ip").is_err());
+    }
+
+    #[test]
+    fn reads_access_config_from_inputs() {
+        let config = WebhookAccessConfig::from_inputs(Some(&json!({
+            "rate_limit_requests": "5",
+            "rate_limit_window_seconds": 10,
+            "rate_limit_key": "header",
+            "rate_limit_header": "X-Tenant",
+            "ip_allowlist": "10.0.0.0/8, 192.168.1.1"
+        })))
+        .unwrap();
+
+        assert_eq!(config.rate_limit_requests, 5);
+        assert_eq!(config.rate_limit_window, Duration::from_secs(10));
+        assert_eq!(
+            config.rate_limit_key,
+            RateLimitKey::Header("x-tenant".to_string())
+        );
+        assert_eq!(config.ip_allowlist.len(), 2);
+
+        //Workflows saved before these inputs existed have no limits
+        let config = WebhookAccessConfig::from_inputs(Some(&json!({}))).unwrap();
+        assert_eq!(config.rate_limit_requests, 0);
+        assert!(config.ip_allowlist.is_empty());
+    }
+
+    #[test]
+    fn token_bucket_limits_and_refills() {
+        let mut limiter = WebhookRateLimiter::new();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire("wf:", 2, window, start).is_ok());
+        assert!(limiter.try_acquire("wf:", 2, window, start).is_ok());
+        let retry_after = limiter.try_acquire("wf:", 2, window, start).unwrap_err();
+        assert_eq!(retry_after.as_secs_f64().round() as u64, 30);
+
+        //Other callers have their own bucket
+        assert!(limiter.try_acquire("wf:1.2.3.4", 2, window, start).is_ok());
+
+        //Two tokens per minute refills one every 30 seconds
+        assert!(limiter
+            .try_acquire("wf:", 2, window, start + Duration::from_secs(31))
+            .is_ok());
+
+        limiter.cleanup(start + Duration::from_secs(120));
+        assert!(limiter.buckets.is_empty());
+        assert!(limiter.workflow_buckets.is_empty());
+    }
+
+    #[test]
+    fn new_callers_past_the_bucket_cap_share_a_fallback_bucket() {
+        let mut limiter = WebhookRateLimiter::new();
+        let window = Duration::from_secs(60);
+        let start = Instant::now();
+
+        for i in 0..MAX_BUCKETS_PER_WORKFLOW {
+            let key = limiter.bucket_key("wf", &[format!("header:{}", i)]);
+            assert!(limiter.try_acquire(&key, 5, window, start).is_ok());
+        }
+
+        //Known callers keep their bucket, new ones share the workflow's bucket once it is full
+        let callers = ["header:0".to_string(), "ip:1.2.3.4".to_string()];
+        assert_eq!(limiter.bucket_key("wf", &callers), "wf:header:0");
+        let callers = ["header:new".to_string(), "ip:1.2.3.4".to_string()];
+        assert_eq!(limiter.bucket_key("wf", &callers), "wf:");
+
+        //Other workflows are only held to the global cap
+        assert_eq!(
+            limiter.bucket_key("other", &["header:new".to_string()]),
+            "other:header:new"
+        );
+    }
+
+    #[test]
+    fn ignores_client_supplied_forwarded_for_entries() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "x-forwarded-for",
+            HeaderValue::from_static("10.0.0.1, 198.51.100.4"),
+        );
+        headers.append("x-forwarded-for", HeaderValue::from_static("203.0.113.7"));
+
+        //The spoofed leading entries are skipped, the rightmost hop is what our proxy saw
+        assert_eq!(
+            forwarded_client_ip(&headers, 1),
+            Some("203.0.113.7".parse().unwrap())
+        );
+        assert_eq!(
+            forwarded_client_ip(&headers, 2),
+            Some("198.51.100.4".parse().unwrap())
+        );
+        //More hops than entries means the header didn't come from our proxies
+        assert_eq!(forwarded_client_ip(&headers, 4), None);
+        assert_eq!(forwarded_client_ip(&HeaderMap::new(), 1), None);
+    }
+}
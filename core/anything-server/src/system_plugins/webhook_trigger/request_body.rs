@@ -0,0 +1,247 @@
+use axum::{body::Bytes, http::HeaderMap};
+use base64::Engine;
+use serde_json::{json, Map, Value};
+
+//Parses a webhook body into JSON based on its Content-Type so workflows can template into it
+pub async fn parse_request_body(headers: &HeaderMap, raw_body: &Bytes) -> Value {
+    if raw_body.is_empty() {
+        return json!({});
+    }
+
+    let content_type = get_content_type(headers);
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+
+    println!("[WEBHOOK API] Parsing request body as: {}", mime);
+
+    match mime.as_str() {
+        "application/x-www-form-urlencoded" => parse_form_body(raw_body),
+        "multipart/form-data" => match parse_multipart_body(&content_type, raw_body).await {
+            Ok(value) => value,
+            Err(e) => {
+                println!("[WEBHOOK API] Failed to parse multipart body: {}", e);
+                json!({})
+            }
+        },
+        m if m == "application/xml" || m == "text/xml" || m.ends_with("+xml") => {
+            match parse_xml_body(raw_body) {
+                Ok(value) => value,
+                Err(e) => {
+                    println!("[WEBHOOK API] Failed to parse XML body: {}", e);
+                    json!({})
+                }
+            }
+        }
+        m if m.starts_with("text/") => Value::String(String::from_utf8_lossy(raw_body).into_owned()),
+        m if m.is_empty() || m == "application/json" || m.ends_with("+json") => {
+            //Callers often skip the Content-Type so fall back to text when it isn't JSON
+            match serde_json::from_slice::<Value>(raw_body) {
+                Ok(value) => value,
+                Err(_) if m.is_empty() => match std::str::from_utf8(raw_body) {
+                    Ok(text) => Value::String(text.to_string()),
+                    Err(_) => binary_to_value(&mime, raw_body),
+                },
+                Err(e) => {
+                    println!("[WEBHOOK API] Failed to parse JSON body: {}", e);
+                    json!({})
+                }
+            }
+        }
+        _ => binary_to_value(&mime, raw_body),
+    }
+}
+
+//Raw body as text when it is UTF-8, otherwise base64, with the encoding used
+pub fn raw_body_to_value(raw_body: &Bytes) -> (Value, &'static str) {
+    match std::str::from_utf8(raw_body) {
+        Ok(text) => (Value::String(text.to_string()), "utf8"),
+        Err(_) => (
+            Value::String(base64::engine::general_purpose::STANDARD.encode(raw_body)),
+            "base64",
+        ),
+    }
+}
+
+pub fn get_content_type(headers: &HeaderMap) -> String {
+    headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+fn binary_to_value(content_type: &str, raw_body: &Bytes) -> Value {
+    json!({
+        "content_type": content_type,
+        "size": raw_body.len(),
+        "content_base64": base64::engine::general_purpose::STANDARD.encode(raw_body),
+    })
+}
+
+//Repeated keys and keys ending in [] become arrays
+fn insert_field(map: &mut Map<String, Value>, key: &str, value: Value) {
+    let (key, force_array) = match key.strip_suffix("[]") {
+        Some(base) => (base, true),
+        None => (key, false),
+    };
+
+    match map.get_mut(key) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+        None if force_array => {
+            map.insert(key.to_string(), Value::Array(vec![value]));
+        }
+        None => {
+            map.insert(key.to_string(), value);
+        }
+    }
+}
+
+fn parse_form_body(raw_body: &Bytes) -> Value {
+    let mut map = Map::new();
+    for (key, value) in form_urlencoded::parse(raw_body) {
+        insert_field(&mut map, &key, Value::String(value.into_owned()));
+    }
+    Value::Object(map)
+}
+
+async fn parse_multipart_body(
+    content_type: &str,
+    raw_body: &Bytes,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let boundary = multer::parse_boundary(content_type)?;
+    let body = raw_body.clone();
+    let stream = futures::stream::once(async move { Ok::<Bytes, std::io::Error>(body) });
+    let mut multipart = multer::Multipart::new(stream, boundary);
+
+    let mut map = Map::new();
+    while let Some(field) = multipart.next_field().await? {
+        let name = field.name().unwrap_or("").to_string();
+        let file_name = field.file_name().map(|f| f.to_string());
+        let field_content_type = field.content_type().map(|m| m.to_string());
+        let data = field.bytes().await?;
+
+        //Files keep their metadata and content, plain fields are just text
+        let value = match file_name {
+            Some(file_name) => json!({
+                "filename": file_name,
+                "content_type": field_content_type.unwrap_or_else(|| "application/octet-stream".to_string()),
+                "size": data.len(),
+                "content_base64": base64::engine::general_purpose::STANDARD.encode(&data),
+            }),
+            None => Value::String(String::from_utf8_lossy(&data).into_owned()),
+        };
+        insert_field(&mut map, &name, value);
+    }
+
+    Ok(Value::Object(map))
+}
+
+//Elements become objects keyed by tag, attributes are prefixed with @ and mixed text is under #text
+fn parse_xml_body(raw_body: &Bytes) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    let text = std::str::from_utf8(raw_body)?;
+    let document = roxmltree::Document::parse(text)?;
+    let root = document.root_element();
+
+    let mut map = Map::new();
+    map.insert(root.tag_name().name().to_string(), xml_node_to_value(root));
+    Ok(Value::Object(map))
+}
+
+fn xml_node_to_value(node: roxmltree::Node) -> Value {
+    let mut map = Map::new();
+
+    for attribute in node.attributes() {
+        map.insert(
+            format!("@{}", attribute.name()),
+            Value::String(attribute.value().to_string()),
+        );
+    }
+
+    let mut text = String::new();
+    for child in node.children() {
+        if child.is_element() {
+            insert_field(&mut map, child.tag_name().name(), xml_node_to_value(child));
+        } else if child.is_text() {
+            text.push_str(child.text().unwrap_or(""));
+        }
+    }
+
+    let text = text.trim();
+    if map.is_empty() {
+        return Value::String(text.to_string());
+    }
+    if !text.is_empty() {
+        map.insert("#text".to_string(), Value::String(text.to_string()));
+    }
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with_content_type(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", content_type.parse().unwrap());
+        headers
+    }
+
+    #[tokio::test]
+    async fn parses_form_body_with_repeated_keys() {
+        let headers = headers_with_content_type("application/x-www-form-urlencoded");
+        let body = Bytes::from("From=%2B15551234567&Body=hello+world&tag=a&tag=b");
+
+        let result = parse_request_body(&headers, &body).await;
+
+        assert_eq!(
+            result,
+            json!({ "From": "+15551234567", "Body": "hello world", "tag": ["a", "b"] })
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_xml_body() {
+        let headers = headers_with_content_type("application/xml; charset=utf-8");
+        let body = Bytes::from(r#"<order id="7"><item>a</item><item>b</item><note>hi</note></order>"#);
+
+        let result = parse_request_body(&headers, &body).await;
+
+        assert_eq!(
+            result,
+            json!({ "order": { "@id": "7", "item": ["a", "b"], "note": "hi" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_multipart_body_with_file() {
+        let headers = headers_with_content_type("multipart/form-data; boundary=X");
+        let body = Bytes::from(
+            "--X\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nreport\r\n\
+             --X\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Type: text/plain\r\n\r\nhi\r\n\
+             --X--\r\n",
+        );
+
+        let result = parse_request_body(&headers, &body).await;
+
+        assert_eq!(result["title"], json!("report"));
+        assert_eq!(result["file"]["filename"], json!("a.txt"));
+        assert_eq!(result["file"]["content_type"], json!("text/plain"));
+        assert_eq!(result["file"]["content_base64"], json!("aGk="));
+    }
+
+    #[tokio::test]
+    async fn keeps_plain_text_as_string() {
+        let headers = headers_with_content_type("text/plain");
+        let body = Bytes::from("just text");
+
+        assert_eq!(parse_request_body(&headers, &body).await, json!("just text"));
+    }
+}
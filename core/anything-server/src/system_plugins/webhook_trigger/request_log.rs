@@ -0,0 +1,400 @@
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{ConnectInfo, Path, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use chrono::Utc;
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    env,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::AppState;
+
+use super::hmac_signature::HmacConfig;
+use super::rate_limit::get_client_ip;
+use super::request_body::raw_body_to_value;
+
+//Matches the default body limit axum applies to the webhook handlers
+pub const WEBHOOK_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+//How long we keep webhook requests when WEBHOOK_REQUEST_RETENTION_DAYS is not set
+pub const DEFAULT_WEBHOOK_REQUEST_RETENTION_DAYS: i64 = 7;
+
+//Only the start of an error response is kept as the rejection reason
+const MAX_REJECTION_REASON_LENGTH: usize = 1000;
+
+//Credentials we never want sitting in the request log. Signatures are included so a stored
+//request can't be resent as is, the webhook's own custom header is added per request.
+const REDACTED_HEADERS: [&str; 8] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+    "x-hub-signature-256",
+    "stripe-signature",
+    "x-slack-signature",
+    "x-shopify-hmac-sha256",
+];
+
+//Query parameters providers put credentials in, matched without case
+const REDACTED_QUERY_KEYS: [&str; 9] = [
+    "token",
+    "access_token",
+    "api_key",
+    "apikey",
+    "key",
+    "secret",
+    "signature",
+    "sig",
+    "password",
+];
+
+//What the webhook handler learned about a request. Shared with the middleware through request extensions.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookRequestOutcome {
+    pub account_id: Option<String>,
+    pub flow_version_id: Option<String>,
+    pub security_result: Option<String>,
+    pub flow_session_id: Option<String>,
+    pub secret_headers: Vec<String>, // lowercase names from the webhook's security config
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WebhookRequestLog(Arc<Mutex<WebhookRequestOutcome>>);
+
+impl WebhookRequestLog {
+    pub fn set_workflow(&self, account_id: &str, flow_version_id: &str) {
+        let mut outcome = self.0.lock().unwrap();
+        outcome.account_id = Some(account_id.to_string());
+        outcome.flow_version_id = Some(flow_version_id.to_string());
+    }
+
+    pub fn set_security_result(&self, security_result: &str) {
+        self.0.lock().unwrap().security_result = Some(security_result.to_string());
+    }
+
+    pub fn set_flow_session_id(&self, flow_session_id: &str) {
+        self.0.lock().unwrap().flow_session_id = Some(flow_session_id.to_string());
+    }
+
+    //Headers the webhook checks against a configured secret, the custom header and a custom
+    //HMAC signature header
+    pub fn set_secret_headers(&self, rendered_inputs: &Value) {
+        let mut secret_headers = Vec::new();
+        if let Some(name) = rendered_inputs
+            .get("custom_header_name")
+            .and_then(|v| v.as_str())
+        {
+            secret_headers.push(name.to_lowercase());
+        }
+        if let Ok(config) = HmacConfig::from_rendered_inputs(rendered_inputs) {
+            secret_headers.push(config.header.to_lowercase());
+        }
+        self.0.lock().unwrap().secret_headers = secret_headers;
+    }
+
+    fn outcome(&self) -> WebhookRequestOutcome {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WebhookRequestRecord {
+    pub account_id: String,
+    pub flow_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_version_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub headers: Value,
+    pub query: Value,
+    pub body: Option<String>,
+    pub body_encoding: String,
+    pub client_ip: String,
+    pub status_code: u16,
+    pub security_result: Option<String>,
+    pub flow_session_id: Option<String>,
+    pub rejection_reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay_of: Option<String>,
+}
+
+pub fn headers_to_log_value(headers: &HeaderMap, secret_headers: &[String]) -> Value {
+    let mut map = serde_json::Map::new();
+    for (name, value) in headers.iter() {
+        let value = if REDACTED_HEADERS.contains(&name.as_str())
+            || secret_headers.iter().any(|secret| secret == name.as_str())
+        {
+            "[REDACTED]".to_string()
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        map.insert(name.as_str().to_string(), Value::String(value));
+    }
+    Value::Object(map)
+}
+
+fn query_to_log_value(query: Option<&str>) -> Value {
+    let mut map = serde_json::Map::new();
+    if let Some(query) = query {
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            let value = if REDACTED_QUERY_KEYS
+                .iter()
+                .any(|redacted| key.eq_ignore_ascii_case(redacted))
+            {
+                "[REDACTED]".to_string()
+            } else {
+                value.into_owned()
+            };
+            map.insert(key.into_owned(), Value::String(value));
+        }
+    }
+    Value::Object(map)
+}
+
+//Records every call to the webhook start routes with what the handler did with it
+pub async fn webhook_request_log_middleware(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    //Buffer the body so we can store it and still hand it to the handler
+    let raw_body = match to_bytes(body, WEBHOOK_BODY_LIMIT).await {
+        Ok(raw_body) => raw_body,
+        Err(_) => {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Request body too large").into_response();
+        }
+    };
+
+    let request_log = WebhookRequestLog::default();
+    parts.extensions.insert(request_log.clone());
+
+    let method = parts.method.to_string();
+    let path = parts.uri.path().to_string();
+    //Logged after the handler has run, it knows which headers carry the webhook's secret
+    let request_headers = parts.headers.clone();
+    let query = query_to_log_value(parts.uri.query());
+    let client_ip = get_client_ip(&parts.headers, &peer_addr).to_string();
+
+    let request = Request::from_parts(parts, Body::from(raw_body.clone()));
+    let response = next.run(request).await;
+
+    let status_code = response.status();
+    let (response, rejection_reason) =
+        if status_code.is_client_error() || status_code.is_server_error() {
+            let (response_parts, response_body) = response.into_parts();
+            let response_bytes = to_bytes(response_body, usize::MAX)
+                .await
+                .unwrap_or_default();
+            let mut reason = String::from_utf8_lossy(&response_bytes).into_owned();
+            if reason.len() > MAX_REJECTION_REASON_LENGTH {
+                let mut end = MAX_REJECTION_REASON_LENGTH;
+                while !reason.is_char_boundary(end) {
+                    end -= 1;
+                }
+                reason.truncate(end);
+            }
+            (
+                Response::from_parts(response_parts, Body::from(response_bytes)),
+                Some(reason),
+            )
+        } else {
+            (response, None)
+        };
+
+    let outcome = request_log.outcome();
+
+    //Requests for workflows we could not find have no account to file them under
+    let account_id = match outcome.account_id {
+        Some(account_id) => account_id,
+        None => {
+            println!(
+                "[WEBHOOK REQUEST LOG] Not logging {} {} with status {}: no workflow found",
+                method, path, status_code
+            );
+            return response;
+        }
+    };
+
+    let headers = headers_to_log_value(&request_headers, &outcome.secret_headers);
+
+    let (body, body_encoding) = match raw_body_to_value(&raw_body) {
+        (Value::String(body), encoding) => (Some(body), encoding.to_string()),
+        (_, encoding) => (None, encoding.to_string()),
+    };
+
+    let record = WebhookRequestRecord {
+        account_id,
+        flow_id: params.get("workflow_id").cloned().unwrap_or_default(),
+        flow_version_id: outcome.flow_version_id,
+        method,
+        path,
+        headers,
+        query,
+        body,
+        body_encoding,
+        client_ip,
+        status_code: status_code.as_u16(),
+        security_result: outcome.security_result,
+        flow_session_id: outcome.flow_session_id,
+        rejection_reason,
+        replay_of: None,
+    };
+
+    //Don't make the caller wait on the log write
+    tokio::spawn(async move {
+        if let Err(e) = insert_webhook_request(&state, &record).await {
+            println!(
+                "[WEBHOOK REQUEST LOG] Failed to store webhook request: {}",
+                e
+            );
+        }
+    });
+
+    response
+}
+
+pub async fn insert_webhook_request(
+    state: &AppState,
+    record: &WebhookRequestRecord,
+) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let response = state
+        .anything_client
+        .from("webhook_requests")
+        .auth(supabase_service_role_api_key)
+        .insert(serde_json::to_string(record)?)
+        .execute()
+        .await?;
+
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        return Err(format!("Failed to insert webhook request: {}", body).into());
+    }
+
+    let mut rows: Vec<Value> = serde_json::from_str(&body)?;
+    Ok(rows.pop().unwrap_or(json!({})))
+}
+
+pub fn webhook_request_retention_days() -> i64 {
+    env::var("WEBHOOK_REQUEST_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse::<i64>().ok())
+        .filter(|days| *days > 0)
+        .unwrap_or(DEFAULT_WEBHOOK_REQUEST_RETENTION_DAYS)
+}
+
+//Deletes webhook requests older than the retention window
+pub async fn cleanup_webhook_requests(state: Arc<AppState>) {
+    let cleanup_interval = Duration::from_secs(3600);
+    loop {
+        tokio::time::sleep(cleanup_interval).await;
+
+        let cutoff = Utc::now() - chrono::Duration::days(webhook_request_retention_days());
+        println!(
+            "[WEBHOOK REQUEST LOG] Deleting webhook requests older than {}",
+            cutoff
+        );
+
+        dotenv().ok();
+        let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+            .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+        match state
+            .anything_client
+            .from("webhook_requests")
+            .auth(supabase_service_role_api_key)
+            .lt("created_at", cutoff.to_rfc3339())
+            .delete()
+            .execute()
+            .await
+        {
+            Ok(response) if !response.status().is_success() => println!(
+                "[WEBHOOK REQUEST LOG] Failed to delete old webhook requests: {}",
+                response.status()
+            ),
+            Ok(_) => {}
+            Err(e) => println!(
+                "[WEBHOOK REQUEST LOG] Failed to delete old webhook requests: {:?}",
+                e
+            ),
+        }
+    }
+}
+
+//Rebuilds the request body we stored so it can be replayed
+pub fn stored_body_to_bytes(body: Option<&str>, body_encoding: &str) -> Result<Bytes, String> {
+    let body = body.unwrap_or("");
+    match body_encoding {
+        "base64" => base64::engine::general_purpose::STANDARD
+            .decode(body)
+            .map(Bytes::from)
+            .map_err(|e| format!("Stored body is not valid base64: {}", e)),
+        _ => Ok(Bytes::from(body.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_credentials_in_logged_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers.insert("content-type", "application/json".parse().unwrap());
+        headers.insert("stripe-signature", "t=1,v1=abc".parse().unwrap());
+        headers.insert("x-webhook-token", "shared-secret".parse().unwrap());
+
+        let request_log = WebhookRequestLog::default();
+        request_log.set_secret_headers(&json!({
+            "security_model": "custom_header",
+            "custom_header_name": "X-Webhook-Token",
+        }));
+        let logged = headers_to_log_value(&headers, &request_log.outcome().secret_headers);
+
+        assert_eq!(logged["authorization"], json!("[REDACTED]"));
+        assert_eq!(logged["stripe-signature"], json!("[REDACTED]"));
+        assert_eq!(logged["x-webhook-token"], json!("[REDACTED]"));
+        assert_eq!(logged["content-type"], json!("application/json"));
+
+        assert_eq!(
+            query_to_log_value(Some("Token=abc&api_key=def&page=2")),
+            json!({ "Token": "[REDACTED]", "api_key": "[REDACTED]", "page": "2" })
+        );
+    }
+
+    #[test]
+    fn stored_bodies_round_trip() {
+        let binary = Bytes::from(vec![0u8, 159, 146, 150]);
+        let (value, encoding) = raw_body_to_value(&binary);
+        assert_eq!(encoding, "base64");
+        assert_eq!(
+            stored_body_to_bytes(value.as_str(), encoding).unwrap(),
+            binary
+        );
+
+        assert_eq!(
+            stored_body_to_bytes(Some("{\"a\":1}"), "utf8").unwrap(),
+            Bytes::from("{\"a\":1}")
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use axum::{
-    extract::{Path, Query, State},
+    body::Bytes,
+    extract::{ConnectInfo, Extension, Path, Query, State},
     http::{HeaderMap, Method, StatusCode},
     response::IntoResponse,
     Json,
@@ -11,11 +12,11 @@ use std::time::Duration;
 
 use dotenv::dotenv;
 use serde_json::{json, Value};
-use std::{collections::HashMap, env, sync::Arc};
+use std::{collections::HashMap, env, net::SocketAddr, sync::Arc};
 use uuid::Uuid;
 
 use crate::{
-    bundler::bundle_context_from_parts,
+    bundler::{bundle_context_from_parts, redaction::describe_value, secrets::SecretScope},
     types::{
         action_types::ActionType,
         task_types::{
@@ -33,6 +34,12 @@ use crate::{
 use tokio::sync::oneshot;
 use tokio::time::timeout;
 
+use super::async_webhook::{
+    accepted_response, deliver_async_result, get_callback_url, prefers_async,
+};
+use super::rate_limit::{enforce_webhook_access_limits, get_client_ip};
+use super::request_body::{get_content_type, raw_body_to_value};
+use super::request_log::WebhookRequestLog;
 use super::webhook_trigger_utils::{
     convert_request_to_payload, parse_response_action_response_into_api_response,
     validate_request_method, validate_required_input_and_response_plugins, validate_security_model,
@@ -45,14 +52,23 @@ pub async fn run_workflow_and_respond(
     method: Method,
     Path(workflow_id): Path<String>,
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Extension(request_log): Extension<WebhookRequestLog>,
     headers: HeaderMap,
     query: Option<Query<HashMap<String, String>>>,
-    body: Option<Json<Value>>,
+    raw_body: Bytes,
 ) -> impl IntoResponse {
     println!("[WEBHOOK API] Handling run workflow and respond");
     // println!("[WEBHOOK API] Payload: {:?}", payload);
     println!("[WEBHOOK API] Workflow ID: {}: ", workflow_id);
 
+    //Prefer: respond-async returns a status url right away instead of waiting on the workflow
+    let respond_async = prefers_async(&headers);
+    let callback_url = match get_callback_url(&headers).await {
+        Ok(callback_url) => callback_url,
+        Err(response) => return response,
+    };
+
     //Super User Access
     dotenv().ok();
     let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
@@ -111,6 +127,10 @@ pub async fn run_workflow_and_respond(
 
     // Get account_id from workflow_version
     let account_id = workflow_version.account_id.clone();
+    request_log.set_workflow(
+        &account_id.to_string(),
+        &workflow_version.flow_version_id.to_string(),
+    );
 
     // Parse the flow definition into a Workflow
     println!("[WEBHOOK API] Parsing workflow definition");
@@ -135,6 +155,21 @@ pub async fn run_workflow_and_respond(
         plugin_config_schema: Some(trigger_node.plugin_config_schema.clone()),
     };
 
+    //Reject blocked and rate limited callers before we render anything
+    let client_ip = get_client_ip(&headers, &peer_addr);
+    if let Some(response) = enforce_webhook_access_limits(
+        state.clone(),
+        &workflow_id,
+        trigger_node.inputs.as_ref(),
+        &headers,
+        client_ip,
+    )
+    .await
+    {
+        request_log.set_security_result("blocked");
+        return response;
+    }
+
     // Bundle the context for the trigger node
     println!("[WEBHOOK API] Bundling context for trigger node");
     let rendered_inputs = match bundle_context_from_parts(
@@ -142,6 +177,7 @@ pub async fn run_workflow_and_respond(
         &state.anything_client,
         &account_id.to_string(),
         &flow_session_id.to_string(),
+        &SecretScope::for_flow_version(&workflow_version),
         Some(&trigger_node.inputs.clone().unwrap()),
         Some(&trigger_node.inputs_schema.clone().unwrap()),
         Some(&trigger_node.plugin_config.clone()),
@@ -161,20 +197,38 @@ pub async fn run_workflow_and_respond(
         }
     };
 
-    println!("[WEBHOOK API] Bundled context: {:?}", rendered_inputs);
+    println!(
+        "[WEBHOOK API] Bundled context: {}",
+        describe_value(&rendered_inputs)
+    );
+    request_log.set_secret_headers(&rendered_inputs);
 
     //Validate security model
-    if let Some(response) = validate_security_model(&rendered_inputs, &headers, state.clone()).await
+    if let Some(response) = validate_security_model(
+        &rendered_inputs,
+        &headers,
+        &raw_body,
+        state.clone(),
+        &account_id.to_string(),
+        &workflow_id,
+        client_ip,
+        true,
+    )
+    .await
     {
+        request_log.set_security_result("failed");
         return response.into_response();
     }
+    request_log.set_security_result("passed");
 
     // Validate request method
     if let Some(response) = validate_request_method(&rendered_inputs, &method.to_string()) {
         return response.into_response();
     }
 
-    let processed_payload = convert_request_to_payload(method.clone(), query, body);
+    let processed_payload =
+        convert_request_to_payload(method.clone(), query, &headers, &raw_body).await;
+    let (raw_body_value, raw_body_encoding) = raw_body_to_value(&raw_body);
 
     // Create a task to initiate the flow
     println!("[WEBHOOK API] Creating task for workflow execution");
@@ -203,6 +257,9 @@ pub async fn run_workflow_and_respond(
         result: Some(json!({
             "headers": headers.iter().map(|(k,v)| (k.as_str(), String::from_utf8_lossy(v.as_bytes()).into_owned())).collect::<HashMap<_,_>>(),
             "body": processed_payload.clone(),
+            "raw_body": raw_body_value,
+            "raw_body_encoding": raw_body_encoding,
+            "content_type": get_content_type(&headers),
             "method": method.to_string(),
         })),
         error: None,
@@ -217,8 +274,8 @@ pub async fn run_workflow_and_respond(
     // Create a channel for receiving the completion result
     let (tx, rx) = oneshot::channel();
 
-    // Store the sender in the state
-    {
+    // Store the sender in the state. Async calls only need it to deliver a callback.
+    if !respond_async || callback_url.is_some() {
         let mut completions = state.flow_completions.lock().await;
         completions.insert(
             flow_session_id.to_string(),
@@ -262,6 +319,20 @@ pub async fn run_workflow_and_respond(
         )
             .into_response();
     }
+    request_log.set_flow_session_id(&flow_session_id.to_string());
+
+    if respond_async {
+        println!("[WEBHOOK API] Responding async for {}", flow_session_id);
+        if let Some(callback_url) = callback_url {
+            tokio::spawn(deliver_async_result(
+                state.clone(),
+                flow_session_id.to_string(),
+                callback_url,
+                rx,
+            ));
+        }
+        return accepted_response(&flow_session_id.to_string());
+    }
 
     println!("[WEBHOOK API] Waiting for workflow completion");
 
@@ -306,15 +377,24 @@ pub async fn run_workflow_version_and_respond(
     method: Method,
     Path((workflow_id, workflow_version_id)): Path<(String, String)>,
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Extension(request_log): Extension<WebhookRequestLog>,
     headers: HeaderMap,
     query: Option<Query<HashMap<String, String>>>,
-    body: Option<Json<Value>>,
+    raw_body: Bytes,
 ) -> impl IntoResponse {
     println!("[WEBHOOK API] Handling run workflow and respond");
-    println!("[WEBHOOK API] Payload: {:?}", body);
+    println!("[WEBHOOK API] Payload: {} bytes", raw_body.len());
 
     println!("[WEBHOOK API] Workflow ID: {}: ", workflow_id);
 
+    //Prefer: respond-async returns a status url right away instead of waiting on the workflow
+    let respond_async = prefers_async(&headers);
+    let callback_url = match get_callback_url(&headers).await {
+        Ok(callback_url) => callback_url,
+        Err(response) => return response,
+    };
+
     //Super User Access
     dotenv().ok();
     let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
@@ -373,6 +453,10 @@ pub async fn run_workflow_version_and_respond(
 
     // Get account_id from workflow_version
     let account_id = workflow_version.account_id.clone();
+    request_log.set_workflow(
+        &account_id.to_string(),
+        &workflow_version.flow_version_id.to_string(),
+    );
 
     // Validate the webhook trigger node and outputs
     let (trigger_node, _output_node) =
@@ -391,6 +475,21 @@ pub async fn run_workflow_version_and_respond(
         plugin_config_schema: Some(trigger_node.plugin_config_schema.clone()),
     };
 
+    //Reject blocked and rate limited callers before we render anything
+    let client_ip = get_client_ip(&headers, &peer_addr);
+    if let Some(response) = enforce_webhook_access_limits(
+        state.clone(),
+        &workflow_id,
+        trigger_node.inputs.as_ref(),
+        &headers,
+        client_ip,
+    )
+    .await
+    {
+        request_log.set_security_result("blocked");
+        return response;
+    }
+
     // Bundle the context for the trigger node
     println!("[WEBHOOK API] Bundling context for trigger node");
     let rendered_inputs = match bundle_context_from_parts(
@@ -398,6 +497,7 @@ pub async fn run_workflow_version_and_respond(
         &state.anything_client,
         &account_id.to_string(),
         &flow_session_id,
+        &SecretScope::for_flow_version(&workflow_version),
         Some(&trigger_node.inputs.clone().unwrap()),
         Some(&trigger_node.inputs_schema.clone().unwrap()),
         Some(&trigger_node.plugin_config.clone()),
@@ -417,20 +517,38 @@ pub async fn run_workflow_version_and_respond(
         }
     };
 
-    println!("[WEBHOOK API] Bundled context: {:?}", rendered_inputs);
+    println!(
+        "[WEBHOOK API] Bundled context: {}",
+        describe_value(&rendered_inputs)
+    );
+    request_log.set_secret_headers(&rendered_inputs);
 
     //Validate security model
-    if let Some(response) = validate_security_model(&rendered_inputs, &headers, state.clone()).await
+    if let Some(response) = validate_security_model(
+        &rendered_inputs,
+        &headers,
+        &raw_body,
+        state.clone(),
+        &account_id.to_string(),
+        &workflow_id,
+        client_ip,
+        true,
+    )
+    .await
     {
+        request_log.set_security_result("failed");
         return response.into_response();
     }
+    request_log.set_security_result("passed");
 
     // Validate request method
     if let Some(response) = validate_request_method(&rendered_inputs, &method.to_string()) {
         return response.into_response();
     }
 
-    let processed_payload = convert_request_to_payload(method.clone(), query, body);
+    let processed_payload =
+        convert_request_to_payload(method.clone(), query, &headers, &raw_body).await;
+    let (raw_body_value, raw_body_encoding) = raw_body_to_value(&raw_body);
 
     // Create a task to initiate the flow
     println!("[WEBHOOK API] Creating task for workflow execution");
@@ -460,6 +578,9 @@ pub async fn run_workflow_version_and_respond(
         result: Some(json!({
             "headers": headers.iter().map(|(k,v)| (k.as_str(), String::from_utf8_lossy(v.as_bytes()).into_owned())).collect::<HashMap<_,_>>(),
             "body": processed_payload.clone(),
+            "raw_body": raw_body_value,
+            "raw_body_encoding": raw_body_encoding,
+            "content_type": get_content_type(&headers),
             "method": method.to_string(),
         })),
         error: None,
@@ -472,8 +593,8 @@ pub async fn run_workflow_version_and_respond(
     // Create a channel for receiving the completion result
     let (tx, rx) = oneshot::channel();
 
-    // Store the sender in the state
-    {
+    // Store the sender in the state. Async calls only need it to deliver a callback.
+    if !respond_async || callback_url.is_some() {
         let mut completions = state.flow_completions.lock().await;
         completions.insert(
             flow_session_id.clone(),
@@ -520,6 +641,20 @@ pub async fn run_workflow_version_and_respond(
         )
             .into_response();
     }
+    request_log.set_flow_session_id(&flow_session_id);
+
+    if respond_async {
+        println!("[WEBHOOK API] Responding async for {}", flow_session_id);
+        if let Some(callback_url) = callback_url {
+            tokio::spawn(deliver_async_result(
+                state.clone(),
+                flow_session_id.clone(),
+                callback_url,
+                rx,
+            ));
+        }
+        return accepted_response(&flow_session_id);
+    }
 
     println!("[WEBHOOK API] Waiting for workflow completion");
 
@@ -564,12 +699,14 @@ pub async fn run_workflow(
     method: Method,
     Path(workflow_id): Path<String>,
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Extension(request_log): Extension<WebhookRequestLog>,
     headers: HeaderMap,
     query: Option<Query<HashMap<String, String>>>,
-    body: Option<Json<Value>>,
+    raw_body: Bytes,
 ) -> impl IntoResponse {
     println!("[WEBHOOK API] Handling run workflow and respond");
-    println!("[WEBHOOK API] Payload: {:?}", body);
+    println!("[WEBHOOK API] Payload: {} bytes", raw_body.len());
 
     println!("[WEBHOOK API] Workflow ID: {}: ", workflow_id);
 
@@ -631,6 +768,10 @@ pub async fn run_workflow(
 
     // Get account_id from workflow_version
     let account_id = workflow_version.account_id.clone();
+    request_log.set_workflow(
+        &account_id.to_string(),
+        &workflow_version.flow_version_id.to_string(),
+    );
 
     // Validate the webhook trigger node and outputs
     let (trigger_node, _output_node) = match validate_required_input_and_response_plugins(
@@ -652,6 +793,21 @@ pub async fn run_workflow(
         plugin_config_schema: Some(trigger_node.plugin_config_schema.clone()),
     };
 
+    //Reject blocked and rate limited callers before we render anything
+    let client_ip = get_client_ip(&headers, &peer_addr);
+    if let Some(response) = enforce_webhook_access_limits(
+        state.clone(),
+        &workflow_id,
+        trigger_node.inputs.as_ref(),
+        &headers,
+        client_ip,
+    )
+    .await
+    {
+        request_log.set_security_result("blocked");
+        return response;
+    }
+
     // Bundle the context for the trigger node
     println!("[WEBHOOK API] Bundling context for trigger node");
     let rendered_inputs = match bundle_context_from_parts(
@@ -659,6 +815,7 @@ pub async fn run_workflow(
         &state.anything_client,
         &account_id.to_string(),
         &flow_session_id,
+        &SecretScope::for_flow_version(&workflow_version),
         Some(&trigger_node.inputs.clone().unwrap()),
         Some(&trigger_node.inputs_schema.clone().unwrap()),
         Some(&trigger_node.plugin_config.clone()),
@@ -678,20 +835,38 @@ pub async fn run_workflow(
         }
     };
 
-    println!("[WEBHOOK API] Bundled context: {:?}", rendered_inputs);
+    println!(
+        "[WEBHOOK API] Bundled context: {}",
+        describe_value(&rendered_inputs)
+    );
+    request_log.set_secret_headers(&rendered_inputs);
 
     //Validate security model
-    if let Some(response) = validate_security_model(&rendered_inputs, &headers, state.clone()).await
+    if let Some(response) = validate_security_model(
+        &rendered_inputs,
+        &headers,
+        &raw_body,
+        state.clone(),
+        &account_id.to_string(),
+        &workflow_id,
+        client_ip,
+        true,
+    )
+    .await
     {
+        request_log.set_security_result("failed");
         return response.into_response();
     }
+    request_log.set_security_result("passed");
 
     // Validate request method
     if let Some(response) = validate_request_method(&rendered_inputs, &method.to_string()) {
         return response.into_response();
     }
 
-    let processed_payload = convert_request_to_payload(method.clone(), query, body);
+    let processed_payload =
+        convert_request_to_payload(method.clone(), query, &headers, &raw_body).await;
+    let (raw_body_value, raw_body_encoding) = raw_body_to_value(&raw_body);
 
     // Create a task to initiate the flow
     println!("[WEBHOOK API] Creating task for workflow execution");
@@ -720,6 +895,9 @@ pub async fn run_workflow(
         result: Some(json!({
             "headers": headers.iter().map(|(k,v)| (k.as_str(), String::from_utf8_lossy(v.as_bytes()).into_owned())).collect::<HashMap<_,_>>(),
             "body": processed_payload.clone(),
+            "raw_body": raw_body_value,
+            "raw_body_encoding": raw_body_encoding,
+            "content_type": get_content_type(&headers),
             "method": method.to_string(),
         })),
         error: None,
@@ -765,6 +943,7 @@ pub async fn run_workflow(
         )
             .into_response();
     }
+    request_log.set_flow_session_id(&flow_session_id);
 
     println!("[WEBHOOK API] Task created successfully");
     Json(serde_json::json!({
@@ -781,12 +960,14 @@ pub async fn run_workflow_version(
     method: Method,
     Path((workflow_id, workflow_version_id)): Path<(String, String)>,
     State(state): State<Arc<AppState>>,
+    ConnectInfo(peer_addr): ConnectInfo<SocketAddr>,
+    Extension(request_log): Extension<WebhookRequestLog>,
     headers: HeaderMap,
     query: Option<Query<HashMap<String, String>>>,
-    body: Option<Json<Value>>,
+    raw_body: Bytes,
 ) -> impl IntoResponse {
     println!("[WEBHOOK API] Handling run workflow and respond");
-    println!("[WEBHOOK API] Payload: {:?}", body);
+    println!("[WEBHOOK API] Payload: {} bytes", raw_body.len());
 
     println!("[WEBHOOK API] Workflow ID: {}: ", workflow_id);
 
@@ -848,6 +1029,10 @@ pub async fn run_workflow_version(
 
     // Get account_id from workflow_version
     let account_id = workflow_version.account_id.clone();
+    request_log.set_workflow(
+        &account_id.to_string(),
+        &workflow_version.flow_version_id.to_string(),
+    );
 
     // Validate the webhook trigger node and outputs
     let (trigger_node, _output_node) = match validate_required_input_and_response_plugins(
@@ -869,6 +1054,21 @@ pub async fn run_workflow_version(
         plugin_config_schema: Some(trigger_node.plugin_config_schema.clone()),
     };
 
+    //Reject blocked and rate limited callers before we render anything
+    let client_ip = get_client_ip(&headers, &peer_addr);
+    if let Some(response) = enforce_webhook_access_limits(
+        state.clone(),
+        &workflow_id,
+        trigger_node.inputs.as_ref(),
+        &headers,
+        client_ip,
+    )
+    .await
+    {
+        request_log.set_security_result("blocked");
+        return response;
+    }
+
     // Bundle the context for the trigger node
     println!("[WEBHOOK API] Bundling context for trigger node");
     let rendered_inputs = match bundle_context_from_parts(
@@ -876,6 +1076,7 @@ pub async fn run_workflow_version(
         &state.anything_client,
         &account_id.to_string(),
         &flow_session_id.to_string(),
+        &SecretScope::for_flow_version(&workflow_version),
         Some(&trigger_node.inputs.clone().unwrap()),
         Some(&trigger_node.inputs_schema.clone().unwrap()),
         Some(&trigger_node.plugin_config.clone()),
@@ -895,20 +1096,38 @@ pub async fn run_workflow_version(
         }
     };
 
-    println!("[WEBHOOK API] Bundled context: {:?}", rendered_inputs);
+    println!(
+        "[WEBHOOK API] Bundled context: {}",
+        describe_value(&rendered_inputs)
+    );
+    request_log.set_secret_headers(&rendered_inputs);
 
     //Validate security model
-    if let Some(response) = validate_security_model(&rendered_inputs, &headers, state.clone()).await
+    if let Some(response) = validate_security_model(
+        &rendered_inputs,
+        &headers,
+        &raw_body,
+        state.clone(),
+        &account_id.to_string(),
+        &workflow_id,
+        client_ip,
+        true,
+    )
+    .await
     {
+        request_log.set_security_result("failed");
         return response.into_response();
     }
+    request_log.set_security_result("passed");
 
     // Validate request method
     if let Some(response) = validate_request_method(&rendered_inputs, &method.to_string()) {
         return response.into_response();
     }
 
-    let processed_payload = convert_request_to_payload(method.clone(), query, body);
+    let processed_payload =
+        convert_request_to_payload(method.clone(), query, &headers, &raw_body).await;
+    let (raw_body_value, raw_body_encoding) = raw_body_to_value(&raw_body);
 
     // Create a task to initiate the flow
     println!("[WEBHOOK API] Creating task for workflow execution");
@@ -937,6 +1156,9 @@ pub async fn run_workflow_version(
         result: Some(json!({
             "headers": headers.iter().map(|(k,v)| (k.as_str(), String::from_utf8_lossy(v.as_bytes()).into_owned())).collect::<HashMap<_,_>>(),
             "body": processed_payload.clone(),
+            "raw_body": raw_body_value,
+            "raw_body_encoding": raw_body_encoding,
+            "content_type": get_content_type(&headers),
             "method": method.to_string(),
         })),
         error: None,
@@ -979,6 +1201,7 @@ pub async fn run_workflow_version(
         )
             .into_response();
     }
+    request_log.set_flow_session_id(&flow_session_id.to_string());
 
     println!("[WEBHOOK API] Task created successfully");
     Json(serde_json::json!({
@@ -1,21 +1,28 @@
 use axum::{
+    body::Bytes,
     extract::Query,
     http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
     response::IntoResponse,
     Json,
 };
 
+use base64::Engine;
 use serde_json::{json, Value};
 
 use std::collections::HashMap;
+use std::net::IpAddr;
 
 use std::sync::Arc;
+use uuid::Uuid;
+
+use super::hmac_signature::validate_hmac_signature;
+use super::request_body::parse_request_body;
 
 use crate::{
-    secrets::get_secret_by_secret_value,
+    api_key_middleware::{authenticate_api_key, ApiKeyScope},
     types::action_types::{Action, ActionType, PluginName},
     types::workflow_types::WorkflowVersionDefinition,
-    AppState, CachedApiKey,
+    AppState,
 };
 
 pub fn validate_required_input_and_response_plugins(
@@ -71,69 +78,42 @@ pub fn validate_required_input_and_response_plugins(
     Ok((Box::new(trigger_node), output_node))
 }
 
-pub async fn validate_api_key(state: Arc<AppState>, api_key: String) -> Result<String, StatusCode> {
+//Checks the key and that its scopes allow running this workflow. Returns the key's account id.
+pub async fn validate_api_key(
+    state: Arc<AppState>,
+    api_key: String,
+    workflow_id: &str,
+    client_ip: IpAddr,
+) -> Result<String, (StatusCode, &'static str)> {
     println!("[VALIDATE API KEY] Starting API key validation");
 
-    // Check cache first
-    let cached_account = {
-        println!("[VALIDATE API KEY] Checking cache for API key");
-        let cache = state.api_key_cache.read().await;
-        if let Some(cached) = cache.get(&api_key) {
-            println!("[VALIDATE API KEY] Found cached API key");
-            Some(cached.account_id.clone())
-        } else {
-            println!("[VALIDATE API KEY] API key not found in cache");
-            None
-        }
-    };
-
-    // Return early if we have a valid cached value
-    if let Some(account_id) = cached_account {
-        println!("[VALIDATE API KEY] Returning cached account ID");
-        return Ok(account_id);
-    }
-
-    // Not in cache, check database
-    println!("[VALIDATE API KEY] Checking database for API key");
-    let secret = match get_secret_by_secret_value(state.clone(), api_key.clone()).await {
-        Ok(secret) => {
-            println!("[VALIDATE API KEY] Found secret in database");
-            secret
-        }
-        Err(_) => {
-            println!("[VALIDATE API KEY] Secret not found in database");
-            return Err(StatusCode::UNAUTHORIZED);
-        }
-    };
+    let key = authenticate_api_key(state, &api_key, client_ip).await?;
 
-    // Verify this is an API key secret
-    if !secret.anything_api_key {
-        println!("[VALIDATE API KEY] Secret is not an API key");
-        return Err(StatusCode::UNAUTHORIZED);
-    }
+    let workflow_id = Uuid::parse_str(workflow_id)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid workflow id"))?;
 
-    // Update cache with new value
-    {
-        println!("[VALIDATE API KEY] Updating cache with new API key");
-        let mut cache = state.api_key_cache.write().await;
-        cache.insert(
-            api_key,
-            CachedApiKey {
-                account_id: secret.account_id.clone(),
-                secret_id: uuid::Uuid::parse_str(&secret.secret_id).unwrap(),
-                secret_name: secret.secret_name.clone(),
-            },
+    if !key.has_scope(&ApiKeyScope::RunWorkflow(workflow_id)) {
+        println!(
+            "[VALIDATE API KEY] API key {} is not scoped to run workflow {}",
+            key.secret_id, workflow_id
         );
+        return Err((StatusCode::FORBIDDEN, "API key can't run this workflow"));
     }
 
     println!("[VALIDATE API KEY] API key validation successful");
-    Ok(secret.account_id)
+    Ok(key.account_id)
 }
 
+//check_replays is off for reads that are fine to repeat, like async status polls
 pub async fn validate_security_model(
     rendered_inputs: &Value,
     headers: &HeaderMap,
+    raw_body: &[u8],
     state: Arc<AppState>,
+    account_id: &str,
+    workflow_id: &str,
+    client_ip: IpAddr,
+    check_replays: bool,
 ) -> Option<impl IntoResponse> {
     // Extract the security model from the rendered inputs
     println!("[WEBHOOK API] Extracting security model from rendered inputs");
@@ -207,9 +187,13 @@ pub async fn validate_security_model(
             };
 
             // Validate the API key
-            match validate_api_key(state, api_key.clone()).await {
-                Ok(_account_id) => None,
-                Err(status) => Some((status, "Invalid API key").into_response()),
+            match validate_api_key(state, api_key, workflow_id, client_ip).await {
+                // Keys only run workflows in the account that owns them
+                Ok(key_account_id) if key_account_id == account_id => None,
+                Ok(_) => {
+                    Some((StatusCode::FORBIDDEN, "API key can't run this workflow").into_response())
+                }
+                Err(rejection) => Some(rejection.into_response()),
             }
         }
         "custom_header" => {
@@ -256,6 +240,25 @@ pub async fn validate_security_model(
             }
             None
         }
+        "hmac_signature" => {
+            println!("[WEBHOOK API] Validating HMAC signature");
+            match validate_hmac_signature(
+                rendered_inputs,
+                headers,
+                raw_body,
+                state,
+                workflow_id,
+                check_replays,
+            )
+            .await
+            {
+                Ok(()) => None,
+                Err(message) => {
+                    println!("[WEBHOOK API] Invalid HMAC signature: {}", message);
+                    Some((StatusCode::UNAUTHORIZED, message).into_response())
+                }
+            }
+        }
         _ => {
             println!("[WEBHOOK API] Invalid security model specified");
             Some((StatusCode::BAD_REQUEST, "Invalid security model").into_response())
@@ -303,10 +306,11 @@ pub fn validate_request_method(
     None
 }
 
-pub fn convert_request_to_payload(
+pub async fn convert_request_to_payload(
     method: axum::http::Method,
     query: Option<Query<HashMap<String, String>>>,
-    body: Option<Json<Value>>,
+    headers: &HeaderMap,
+    raw_body: &Bytes,
 ) -> Value {
     match method {
         axum::http::Method::GET => {
@@ -370,7 +374,7 @@ pub fn convert_request_to_payload(
         }
         _ => {
             // For non-GET requests, merge query params with body if both exist
-            let mut final_payload = body.map_or(json!({}), |Json(payload)| payload);
+            let mut final_payload = parse_request_body(headers, raw_body).await;
 
             if let Some(Query(params)) = query {
                 if let Value::Object(ref mut map) = final_payload {
@@ -388,6 +392,32 @@ pub fn convert_request_to_payload(
     }
 }
 
+// Status may be stored as a number or as the string picked in the editor. Unset means 200,
+// anything that isn't a valid status is a broken response action and gets a 500.
+fn response_status_code(stored_result: &Value) -> StatusCode {
+    let value = match stored_result.get("status_code") {
+        None | Some(Value::Null) => return StatusCode::OK,
+        Some(Value::String(s)) if s.trim().is_empty() => return StatusCode::OK,
+        Some(value) => value,
+    };
+
+    match value
+        .as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+        .and_then(|code| u16::try_from(code).ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+    {
+        Some(status_code) => status_code,
+        None => {
+            println!(
+                "[WEBHOOK API] [CREATE RESPONSE] Invalid status code {}, returning 500",
+                value
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
 pub fn parse_response_action_response_into_api_response(stored_result: Value) -> impl IntoResponse {
     // Check for error first
     if let Some(error) = stored_result.get("error") {
@@ -399,105 +429,163 @@ pub fn parse_response_action_response_into_api_response(stored_result: Value) ->
         return (StatusCode::INTERNAL_SERVER_ERROR, error_message.to_string()).into_response();
     }
 
-    let status_code = stored_result
-        .get("status_code")
-        .and_then(Value::as_u64)
-        .unwrap_or(200) as u16;
+    let mut status_code = response_status_code(&stored_result);
 
-    let mut headers = HeaderMap::new();
+    let headers = parse_response_headers(&stored_result);
+
+    let content_type = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string();
 
-    let content_type = stored_result
-        .get("headers")
-        .and_then(Value::as_object)
-        .and_then(|h| h.get("content-type"))
+    // Redirects only need a Location header
+    if let Some(redirect_url) = stored_result
+        .get("redirect_url")
         .and_then(Value::as_str)
-        .unwrap_or("application/json");
-
-    // Handle different content types
-    match content_type {
-        // Handle HTML responses
-        ct if ct.contains("text/html") => {
-            if let Some(body) = stored_result.get("body").and_then(Value::as_str) {
-                headers.insert(
-                    HeaderName::from_static("content-type"),
-                    HeaderValue::from_static("text/html"),
-                );
-                println!(
-                    "[WEBHOOK API] [CREATE RESPONSE] Returning HTML response with status {}: {}",
-                    status_code, body
-                );
-                (
-                    StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK),
-                    headers,
-                    body.to_string(),
-                )
-                    .into_response()
-            } else {
-                println!("[WEBHOOK API] [CREATE RESPONSE] Returning invalid HTML response error");
-                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid HTML response").into_response()
+        .filter(|url| !url.is_empty())
+    {
+        let location = match HeaderValue::from_str(redirect_url) {
+            Ok(location) => location,
+            Err(_) => {
+                println!("[WEBHOOK API] [CREATE RESPONSE] Returning invalid redirect error");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Invalid redirect URL").into_response();
             }
+        };
+        if !status_code.is_redirection() {
+            status_code = StatusCode::FOUND;
         }
+        let mut headers = headers;
+        headers.remove("content-type");
+        headers.insert(HeaderName::from_static("location"), location);
+        println!(
+            "[WEBHOOK API] [CREATE RESPONSE] Returning redirect with status {} to {}",
+            status_code, redirect_url
+        );
+        return (status_code, headers).into_response();
+    }
 
-        // Handle XML responses
-        ct if ct.contains("xml") => {
-            if let Some(body) = stored_result.get("body").and_then(Value::as_str) {
-                headers.insert(
-                    HeaderName::from_static("content-type"),
-                    HeaderValue::from_static("application/xml"),
-                );
+    if status_code == StatusCode::NO_CONTENT || status_code == StatusCode::NOT_MODIFIED {
+        return (status_code, headers).into_response();
+    }
+
+    let body = stored_result.get("body").cloned().unwrap_or(Value::Null);
+
+    // Binary bodies (file downloads) are stored base64 encoded
+    if stored_result.get("body_encoding").and_then(Value::as_str) == Some("base64") {
+        let encoded = body.as_str().unwrap_or("");
+        return match base64::engine::general_purpose::STANDARD.decode(encoded.trim()) {
+            Ok(bytes) => {
                 println!(
-                    "[WEBHOOK API] [CREATE RESPONSE] Returning XML response with status status_code: {} body: {} headers: {:?}",
-                    status_code, body, headers
+                    "[WEBHOOK API] [CREATE RESPONSE] Returning binary response with status {}: {} bytes",
+                    status_code,
+                    bytes.len()
                 );
-                (
-                    StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK),
-                    headers,
-                    body.to_string(),
-                )
-                    .into_response()
-            } else {
-                println!("[WEBHOOK API] [CREATE RESPONSE] Returning invalid XML response error");
-                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid XML response").into_response()
+                (status_code, headers, bytes).into_response()
             }
-        }
+            Err(e) => {
+                println!("[WEBHOOK API] [CREATE RESPONSE] Failed to decode binary body: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid binary response").into_response()
+            }
+        };
+    }
 
-        // Handle plain text responses
-        ct if ct.contains("text/plain") => {
-            if let Some(body) = stored_result.get("body").and_then(Value::as_str) {
-                headers.insert(
-                    HeaderName::from_static("content-type"),
-                    HeaderValue::from_static("text/plain"),
-                );
-                println!("[WEBHOOK API] [CREATE RESPONSE] Returning plain text response with status {}: {}", status_code, body);
-                (
-                    StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK),
-                    headers,
-                    body.to_string(),
-                )
-                    .into_response()
-            } else {
-                println!("[WEBHOOK API] [CREATE RESPONSE] Returning invalid text response error");
-                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid text response").into_response()
+    if content_type.contains("json") {
+        let body = if body.is_null() { json!({}) } else { body };
+        println!(
+            "[WEBHOOK API] [CREATE RESPONSE] Returning JSON response with status {}: {}",
+            status_code, body
+        );
+        // Json sets its own content-type so keep any custom json type the workflow chose
+        let mut response = (status_code, Json(body)).into_response();
+        response.headers_mut().extend(headers);
+        return response;
+    }
+
+    // HTML, XML, CSV, plain text and anything else are sent as the text we were given
+    let body = match body {
+        Value::String(text) => text,
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    println!(
+        "[WEBHOOK API] [CREATE RESPONSE] Returning {} response with status {}: {}",
+        content_type, status_code, body
+    );
+    (status_code, headers, body).into_response()
+}
+
+// Header values may be a string or a list of strings so Set-Cookie can be sent more than once
+fn parse_response_headers(stored_result: &Value) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    let stored_headers = match stored_result.get("headers").and_then(Value::as_object) {
+        Some(stored_headers) => stored_headers,
+        None => return headers,
+    };
+
+    for (key, value) in stored_headers {
+        let name = match HeaderName::from_bytes(key.to_lowercase().as_bytes()) {
+            Ok(name) => name,
+            Err(_) => {
+                println!("[WEBHOOK API] [CREATE RESPONSE] Skipping invalid header name: {}", key);
+                continue;
+            }
+        };
+
+        let values = match value {
+            Value::Array(values) => values.clone(),
+            other => vec![other.clone()],
+        };
+
+        for value in values {
+            let value = match value {
+                Value::String(s) => s,
+                other => other.to_string(),
+            };
+            match HeaderValue::from_str(&value) {
+                Ok(header_value) => {
+                    headers.append(name.clone(), header_value);
+                }
+                Err(_) => {
+                    println!(
+                        "[WEBHOOK API] [CREATE RESPONSE] Skipping invalid value for header: {}",
+                        key
+                    );
+                }
             }
         }
+    }
 
-        // Default to JSON responses
-        _ => {
-            headers.insert(
-                HeaderName::from_static("content-type"),
-                HeaderValue::from_static("application/json"),
-            );
-            let body = stored_result.get("body").cloned().unwrap_or(json!({}));
-            println!(
-                "[WEBHOOK API] [CREATE RESPONSE] Returning JSON response with status {}: {}",
-                status_code, body
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn response_status_codes_are_validated() {
+        assert_eq!(response_status_code(&json!({})), StatusCode::OK);
+        assert_eq!(
+            response_status_code(&json!({ "status_code": "" })),
+            StatusCode::OK
+        );
+        assert_eq!(
+            response_status_code(&json!({ "status_code": 201 })),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            response_status_code(&json!({ "status_code": " 404 " })),
+            StatusCode::NOT_FOUND
+        );
+        //65736 used to wrap around to 200
+        for invalid in [json!(65736), json!(42), json!(-1), json!("teapot")] {
+            assert_eq!(
+                response_status_code(&json!({ "status_code": invalid })),
+                StatusCode::INTERNAL_SERVER_ERROR
             );
-            (
-                StatusCode::from_u16(status_code).unwrap_or(StatusCode::OK),
-                headers,
-                Json(body),
-            )
-                .into_response()
         }
     }
 }
@@ -0,0 +1,440 @@
+use serde_json::{Map, Value};
+
+use super::conditions::Condition;
+use super::filters::TemplateExpression;
+use super::{TemplateError, Templater};
+use crate::types::json_schema::ValidationFieldType;
+
+//What a single {{ }} tag holds
+#[derive(Debug, Clone, PartialEq)]
+pub enum BlockTag {
+    If(String),
+    ElseIf(String),
+    Else,
+    EndIf,
+    Each(String),
+    EndEach,
+    Variable(String),
+}
+
+pub fn parse_tag(content: &str) -> BlockTag {
+    let content = content.trim();
+    let (keyword, rest) = match content.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (content, ""),
+    };
+
+    match keyword {
+        "#if" => BlockTag::If(rest.to_string()),
+        "#each" => BlockTag::Each(rest.to_string()),
+        "/if" if rest.is_empty() => BlockTag::EndIf,
+        "/each" if rest.is_empty() => BlockTag::EndEach,
+        "else" if rest.is_empty() => BlockTag::Else,
+        "else" => match rest.split_once(char::is_whitespace) {
+            Some(("if", condition)) => BlockTag::ElseIf(condition.trim().to_string()),
+            _ => BlockTag::Variable(content.to_string()),
+        },
+        _ => BlockTag::Variable(content.to_string()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    Variable(TemplateExpression),
+    If {
+        condition: Condition,
+        then: Vec<Node>,
+        otherwise: Option<Vec<Node>>,
+    },
+    Each {
+        expression: TemplateExpression,
+        body: Vec<Node>,
+    },
+}
+
+enum Frame {
+    If {
+        condition: Condition,
+        then: Vec<Node>,
+        otherwise: Option<Vec<Node>>,
+        //Opened by {{else if}}, so it closes together with its parent
+        chained: bool,
+    },
+    Each {
+        expression: TemplateExpression,
+        body: Vec<Node>,
+    },
+}
+
+//True when the string uses {{#if}} or {{#each}} and has to go through parse_blocks
+pub fn has_blocks(template: &str) -> bool {
+    let mut start = 0;
+    while let Some(open_idx) = template[start..].find("{{") {
+        let open_idx = start + open_idx;
+        if template[open_idx + 2..].trim_start().starts_with('#') {
+            return true;
+        }
+        start = open_idx + 2;
+    }
+    false
+}
+
+pub fn parse_blocks(template: &str) -> Result<Vec<Node>, TemplateError> {
+    let error = |message: &str| TemplateError {
+        message: message.to_string(),
+        variable: template.to_string(),
+    };
+
+    let mut root = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+
+    fn push(root: &mut Vec<Node>, stack: &mut [Frame], node: Node) {
+        match stack.last_mut() {
+            Some(Frame::If {
+                otherwise: Some(otherwise),
+                ..
+            }) => otherwise.push(node),
+            Some(Frame::If { then, .. }) => then.push(node),
+            Some(Frame::Each { body, .. }) => body.push(node),
+            None => root.push(node),
+        }
+    }
+
+    let mut start = 0;
+    while let Some(open_idx) = template[start..].find("{{") {
+        let open_idx = start + open_idx;
+        let close_idx = template[open_idx..]
+            .find("}}")
+            .ok_or_else(|| error("Unclosed template variable"))?;
+        let close_idx = open_idx + close_idx;
+
+        if open_idx > start {
+            push(
+                &mut root,
+                &mut stack,
+                Node::Text(template[start..open_idx].to_string()),
+            );
+        }
+
+        match parse_tag(&template[open_idx + 2..close_idx]) {
+            BlockTag::If(condition) => stack.push(Frame::If {
+                condition: Condition::parse(&condition)?,
+                then: Vec::new(),
+                otherwise: None,
+                chained: false,
+            }),
+            BlockTag::ElseIf(condition) => {
+                match stack.last_mut() {
+                    Some(Frame::If { otherwise, .. }) if otherwise.is_none() => {
+                        *otherwise = Some(Vec::new())
+                    }
+                    _ => return Err(error("Unexpected {{else if}}")),
+                }
+                stack.push(Frame::If {
+                    condition: Condition::parse(&condition)?,
+                    then: Vec::new(),
+                    otherwise: None,
+                    chained: true,
+                });
+            }
+            BlockTag::Else => match stack.last_mut() {
+                Some(Frame::If { otherwise, .. }) if otherwise.is_none() => {
+                    *otherwise = Some(Vec::new())
+                }
+                _ => return Err(error("Unexpected {{else}}")),
+            },
+            BlockTag::EndIf => loop {
+                match stack.pop() {
+                    Some(Frame::If {
+                        condition,
+                        then,
+                        otherwise,
+                        chained,
+                    }) => {
+                        push(
+                            &mut root,
+                            &mut stack,
+                            Node::If {
+                                condition,
+                                then,
+                                otherwise,
+                            },
+                        );
+                        if !chained {
+                            break;
+                        }
+                    }
+                    _ => return Err(error("Unexpected {{/if}}")),
+                }
+            },
+            BlockTag::Each(expression) => stack.push(Frame::Each {
+                expression: TemplateExpression::parse(&expression)?,
+                body: Vec::new(),
+            }),
+            BlockTag::EndEach => match stack.pop() {
+                Some(Frame::Each { expression, body }) => {
+                    push(&mut root, &mut stack, Node::Each { expression, body })
+                }
+                _ => return Err(error("Unexpected {{/each}}")),
+            },
+            BlockTag::Variable(variable) => push(
+                &mut root,
+                &mut stack,
+                Node::Variable(TemplateExpression::parse(&variable)?),
+            ),
+        }
+
+        start = close_idx + 2;
+    }
+
+    if start < template.len() {
+        push(
+            &mut root,
+            &mut stack,
+            Node::Text(template[start..].to_string()),
+        );
+    }
+
+    match stack.last() {
+        Some(Frame::If { .. }) => Err(error("Unclosed {{#if}} block")),
+        Some(Frame::Each { .. }) => Err(error("Unclosed {{#each}} block")),
+        None => Ok(root),
+    }
+}
+
+//A lone block or variable, ignoring whitespace around it, renders as typed JSON
+fn single_node(nodes: &[Node]) -> Option<&Node> {
+    let mut significant = nodes
+        .iter()
+        .filter(|node| !matches!(node, Node::Text(text) if text.trim().is_empty()));
+    match (significant.next(), significant.next()) {
+        (Some(node), None) if !matches!(node, Node::Text(_)) => Some(node),
+        _ => None,
+    }
+}
+
+fn to_template_string(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+pub fn render_nodes(nodes: &[Node], context: &Value) -> Result<Value, TemplateError> {
+    if let Some(node) = single_node(nodes) {
+        return render_node(node, context);
+    }
+
+    let mut result = String::new();
+    for node in nodes {
+        result.push_str(&to_template_string(render_node(node, context)?));
+    }
+    Ok(Value::String(result))
+}
+
+fn render_node(node: &Node, context: &Value) -> Result<Value, TemplateError> {
+    match node {
+        Node::Text(text) => Ok(Value::String(text.clone())),
+        Node::Variable(expression) => {
+            Templater::resolve_expression(context, expression, &ValidationFieldType::Unknown)?
+                .ok_or_else(|| TemplateError {
+                    message: "Variable not found in context".to_string(),
+                    variable: expression.source.clone(),
+                })
+        }
+        Node::If {
+            condition,
+            then,
+            otherwise,
+        } => {
+            if condition.evaluate(context)? {
+                render_nodes(then, context)
+            } else if let Some(otherwise) = otherwise {
+                render_nodes(otherwise, context)
+            } else {
+                Ok(Value::Null)
+            }
+        }
+        Node::Each { expression, body } => {
+            let items = for_each_item(context, expression, |scope| render_nodes(body, scope))?;
+            //A body that is just one value builds an array, anything else joins as text
+            if single_node(body).is_some() {
+                Ok(Value::Array(items))
+            } else {
+                Ok(Value::String(
+                    items.into_iter().map(to_template_string).collect(),
+                ))
+            }
+        }
+    }
+}
+
+//Runs render for every item of an array or object. Inside the body `this` is the item and
+//`@index`, `@first`, `@last` and `@key` (objects only) describe where we are.
+pub fn for_each_item<F>(
+    context: &Value,
+    expression: &TemplateExpression,
+    mut render: F,
+) -> Result<Vec<Value>, TemplateError>
+where
+    F: FnMut(&Value) -> Result<Value, TemplateError>,
+{
+    let items = Templater::resolve_expression(context, expression, &ValidationFieldType::Unknown)?
+        .unwrap_or(Value::Null);
+
+    let entries: Vec<(Option<String>, Value)> = match items {
+        Value::Array(items) => items.into_iter().map(|item| (None, item)).collect(),
+        Value::Object(map) => map.into_iter().map(|(k, v)| (Some(k), v)).collect(),
+        Value::Null => Vec::new(),
+        other => {
+            return Err(TemplateError {
+                message: format!("{{{{#each}}}} needs an array or object, got: {}", other),
+                variable: expression.source.clone(),
+            })
+        }
+    };
+
+    let mut scope = match context {
+        Value::Object(map) => Value::Object(map.clone()),
+        _ => Value::Object(Map::new()),
+    };
+
+    let count = entries.len();
+    let mut rendered = Vec::with_capacity(count);
+    for (index, (key, item)) in entries.into_iter().enumerate() {
+        if let Value::Object(map) = &mut scope {
+            map.insert("this".to_string(), item);
+            map.insert("@index".to_string(), Value::from(index));
+            map.insert("@first".to_string(), Value::Bool(index == 0));
+            map.insert("@last".to_string(), Value::Bool(index + 1 == count));
+            match key {
+                Some(key) => map.insert("@key".to_string(), Value::String(key)),
+                None => map.remove("@key"),
+            };
+        }
+        rendered.push(render(&scope)?);
+    }
+    Ok(rendered)
+}
+
+//Blocks in object and array templates are written as objects keyed by the tag:
+//{"{{#if cond}}": ..., "{{else}}": ...} or {"{{#each items}}": ...}
+pub enum ObjectBlock<'a> {
+    If {
+        condition: String,
+        then: &'a Value,
+        otherwise: Option<&'a Value>,
+    },
+    Each {
+        expression: String,
+        body: &'a Value,
+    },
+}
+
+fn key_tag(key: &str) -> Option<BlockTag> {
+    let key = key.trim();
+    key.strip_prefix("{{")?.strip_suffix("}}").map(parse_tag)
+}
+
+pub fn object_block(map: &Map<String, Value>) -> Option<ObjectBlock<'_>> {
+    let mut block = None;
+    let mut otherwise = None;
+
+    for (key, value) in map {
+        match key_tag(key)? {
+            BlockTag::If(condition) if block.is_none() => {
+                block = Some(ObjectBlock::If {
+                    condition,
+                    then: value,
+                    otherwise: None,
+                })
+            }
+            BlockTag::Each(expression) if block.is_none() && map.len() == 1 => {
+                block = Some(ObjectBlock::Each {
+                    expression,
+                    body: value,
+                })
+            }
+            BlockTag::Else if otherwise.is_none() => otherwise = Some(value),
+            _ => return None,
+        }
+    }
+
+    match block {
+        Some(ObjectBlock::If {
+            condition, then, ..
+        }) => Some(ObjectBlock::If {
+            condition,
+            then,
+            otherwise,
+        }),
+        Some(each) if otherwise.is_none() => Some(each),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(template: &str, context: &Value) -> Value {
+        render_nodes(&parse_blocks(template).unwrap(), context).unwrap()
+    }
+
+    #[test]
+    fn renders_if_else_chains() {
+        let template =
+            "Hi {{#if user.vip}}VIP{{else if user.name}}{{user.name}}{{else}}guest{{/if}}!";
+
+        assert_eq!(
+            render(template, &json!({"user": {"vip": true}})),
+            json!("Hi VIP!")
+        );
+        assert_eq!(
+            render(template, &json!({"user": {"name": "Ann"}})),
+            json!("Hi Ann!")
+        );
+        assert_eq!(render(template, &json!({"user": {}})), json!("Hi guest!"));
+    }
+
+    #[test]
+    fn renders_each_blocks() {
+        let context = json!({"items": [{"id": 1, "name": "a"}, {"id": 2, "name": "b"}]});
+
+        assert_eq!(
+            render(
+                "{{#each items}}{{this.name}}{{#if @last}}.{{else}}, {{/if}}{{/each}}",
+                &context
+            ),
+            json!("a, b.")
+        );
+        assert_eq!(
+            render(" {{#each items}}{{this.id}}{{/each}} ", &context),
+            json!([1, 2])
+        );
+    }
+
+    #[test]
+    fn whole_value_if_keeps_types() {
+        let context = json!({"body": {"count": 3, "list": [1]}});
+
+        assert_eq!(
+            render("{{#if body.count > 2}}{{body.list}}{{/if}}", &context),
+            json!([1])
+        );
+        assert_eq!(
+            render("{{#if body.count > 5}}{{body.list}}{{/if}}", &context),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn reports_unbalanced_blocks() {
+        assert!(parse_blocks("{{#if a}}x").is_err());
+        assert!(parse_blocks("{{#each a}}x{{/if}}").is_err());
+        assert!(parse_blocks("x{{else}}").is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::blocks::{
+    for_each_item, has_blocks, object_block, parse_blocks, render_nodes, Node, ObjectBlock,
+};
+use super::conditions::Condition;
+use super::filters::TemplateExpression;
+use super::{TemplateError, Templater};
+use crate::types::json_schema::ValidationFieldType;
+
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Text(String),
+    Variable(TemplateExpression),
+}
+
+//A template parsed once so rendering only walks the tree and looks up values.
+//Top level object keys are validated against the schema like Templater::render always has.
+#[derive(Debug, Clone)]
+pub enum CompiledTemplate {
+    //Nothing to render, handed back as is
+    Literal(Value),
+    Object(Vec<(String, CompiledTemplate)>),
+    Array(Vec<CompiledTemplate>),
+    //"{{path | filter}}" as the whole string keeps the value's type
+    Variable(TemplateExpression),
+    //Text with variables in it always renders to a string
+    Interpolated(Vec<Segment>),
+    //Strings using {{#if}} or {{#each}}
+    Blocks(Vec<Node>),
+    If {
+        condition: Condition,
+        then: Box<CompiledTemplate>,
+        otherwise: Option<Box<CompiledTemplate>>,
+    },
+    Each {
+        expression: TemplateExpression,
+        body: Box<CompiledTemplate>,
+    },
+}
+
+impl CompiledTemplate {
+    pub fn compile(template: &Value) -> Result<Self, TemplateError> {
+        Self::compile_value(template, true)
+    }
+
+    fn compile_value(template: &Value, top_level: bool) -> Result<Self, TemplateError> {
+        match template {
+            Value::Object(map) => {
+                if let Some(block) = object_block(map) {
+                    return Self::compile_object_block(block, top_level);
+                }
+
+                let fields = map
+                    .iter()
+                    .map(|(k, v)| Ok((k.clone(), Self::compile_value(v, false)?)))
+                    .collect::<Result<Vec<_>, TemplateError>>()?;
+
+                //Top level keys still need validating even when they hold plain values
+                if !top_level && fields.iter().all(|(_, v)| v.is_literal()) {
+                    Ok(CompiledTemplate::Literal(template.clone()))
+                } else {
+                    Ok(CompiledTemplate::Object(fields))
+                }
+            }
+            Value::Array(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| Self::compile_value(item, top_level))
+                    .collect::<Result<Vec<_>, TemplateError>>()?;
+
+                if !top_level && items.iter().all(|item| item.is_literal()) {
+                    Ok(CompiledTemplate::Literal(template.clone()))
+                } else {
+                    Ok(CompiledTemplate::Array(items))
+                }
+            }
+            Value::String(s) => Self::compile_string(s),
+            _ => Ok(CompiledTemplate::Literal(template.clone())),
+        }
+    }
+
+    fn compile_string(s: &str) -> Result<Self, TemplateError> {
+        if !s.contains("{{") {
+            return Ok(CompiledTemplate::Literal(Value::String(s.to_string())));
+        }
+
+        if has_blocks(s) {
+            return Ok(CompiledTemplate::Blocks(parse_blocks(s)?));
+        }
+
+        let trimmed = s.trim();
+        if trimmed.starts_with("{{")
+            && trimmed.ends_with("}}")
+            && !trimmed[2..trimmed.len() - 2].contains("{{")
+        {
+            let variable = trimmed[2..trimmed.len() - 2].trim();
+            return Ok(CompiledTemplate::Variable(TemplateExpression::parse(
+                variable,
+            )?));
+        }
+
+        let mut segments = Vec::new();
+        let mut start = 0;
+        while let Some(open_idx) = s[start..].find("{{") {
+            let open_idx = start + open_idx;
+            let close_idx = s[open_idx..].find("}}").ok_or_else(|| TemplateError {
+                message: "Unclosed template variable".to_string(),
+                variable: s.to_string(),
+            })?;
+            let close_idx = open_idx + close_idx;
+
+            if open_idx > start {
+                segments.push(Segment::Text(s[start..open_idx].to_string()));
+            }
+            segments.push(Segment::Variable(TemplateExpression::parse(
+                s[open_idx + 2..close_idx].trim(),
+            )?));
+            start = close_idx + 2;
+        }
+        if start < s.len() {
+            segments.push(Segment::Text(s[start..].to_string()));
+        }
+
+        Ok(CompiledTemplate::Interpolated(segments))
+    }
+
+    fn compile_object_block(block: ObjectBlock, top_level: bool) -> Result<Self, TemplateError> {
+        match block {
+            ObjectBlock::If {
+                condition,
+                then,
+                otherwise,
+            } => Ok(CompiledTemplate::If {
+                condition: Condition::parse(&condition)?,
+                then: Box::new(Self::compile_value(then, top_level)?),
+                otherwise: match otherwise {
+                    Some(otherwise) => Some(Box::new(Self::compile_value(otherwise, top_level)?)),
+                    None => None,
+                },
+            }),
+            ObjectBlock::Each { expression, body } => Ok(CompiledTemplate::Each {
+                expression: TemplateExpression::parse(&expression)?,
+                body: Box::new(Self::compile_value(body, top_level)?),
+            }),
+        }
+    }
+
+    fn is_literal(&self) -> bool {
+        matches!(self, CompiledTemplate::Literal(_))
+    }
+
+    pub fn render(
+        &self,
+        context: &Value,
+        validations: &HashMap<String, ValidationFieldType>,
+    ) -> Result<Value, TemplateError> {
+        self.render_value(context, validations, true)
+    }
+
+    fn render_value(
+        &self,
+        context: &Value,
+        validations: &HashMap<String, ValidationFieldType>,
+        top_level: bool,
+    ) -> Result<Value, TemplateError> {
+        match self {
+            CompiledTemplate::Literal(value) => Ok(value.clone()),
+            CompiledTemplate::Object(fields) => {
+                let mut result = serde_json::Map::new();
+                for (k, v) in fields {
+                    if top_level {
+                        let validation_type = validations.get(k).ok_or_else(|| TemplateError {
+                            message: format!("Validation not found for key '{}'", k),
+                            variable: k.clone(),
+                        })?;
+                        let rendered = v.render_value(context, validations, false)?;
+                        let validated =
+                            Templater::validate_and_convert_value(rendered, validation_type, k)?;
+                        result.insert(k.clone(), validated);
+                    } else {
+                        result.insert(k.clone(), v.render_value(context, validations, false)?);
+                    }
+                }
+                Ok(Value::Object(result))
+            }
+            CompiledTemplate::Array(items) => {
+                let mut result = Vec::new();
+                for item in items {
+                    //Blocks inside arrays drop out when false and spread their items when looping
+                    match item {
+                        CompiledTemplate::If { .. } => {
+                            if let Some(rendered) =
+                                item.render_if(context, validations, top_level)?
+                            {
+                                result.push(rendered);
+                            }
+                        }
+                        CompiledTemplate::Each { expression, body } => {
+                            result.extend(for_each_item(context, expression, |scope| {
+                                body.render_value(scope, validations, top_level)
+                            })?);
+                        }
+                        _ => result.push(item.render_value(context, validations, top_level)?),
+                    }
+                }
+                Ok(Value::Array(result))
+            }
+            CompiledTemplate::Variable(expression) => {
+                // Only validate if this is a top-level path
+                if top_level {
+                    let expected_type = Self::expected_type(validations, expression)?;
+                    let value = Templater::resolve_expression(context, expression, expected_type)?
+                        .ok_or_else(|| TemplateError {
+                            message: format!(
+                                "Variable not found in context: {}",
+                                expression.source
+                            ),
+                            variable: expression.source.clone(),
+                        })?;
+                    Templater::validate_and_convert_value(value, expected_type, &expression.source)
+                } else {
+                    Templater::resolve_expression(
+                        context,
+                        expression,
+                        &ValidationFieldType::Unknown,
+                    )?
+                    .ok_or_else(|| TemplateError {
+                        message: format!("Variable not found in context: {}", expression.source),
+                        variable: expression.source.clone(),
+                    })
+                }
+            }
+            CompiledTemplate::Interpolated(segments) => {
+                let mut result = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Text(text) => result.push_str(text),
+                        Segment::Variable(expression) => {
+                            let expected_type = if top_level {
+                                Self::expected_type(validations, expression)?
+                            } else {
+                                &ValidationFieldType::Unknown
+                            };
+                            let value =
+                                Templater::resolve_expression(context, expression, expected_type)?
+                                    .ok_or_else(|| TemplateError {
+                                        message: "Variable not found in context".to_string(),
+                                        variable: expression.source.clone(),
+                                    })?;
+                            let value = if top_level {
+                                Templater::validate_and_convert_value(
+                                    value,
+                                    expected_type,
+                                    &expression.source,
+                                )?
+                            } else {
+                                value
+                            };
+                            match value {
+                                Value::String(s) => result.push_str(&s),
+                                _ => result.push_str(&value.to_string()),
+                            }
+                        }
+                    }
+                }
+                Ok(Value::String(result))
+            }
+            CompiledTemplate::Blocks(nodes) => render_nodes(nodes, context),
+            CompiledTemplate::If { .. } => Ok(self
+                .render_if(context, validations, top_level)?
+                .unwrap_or(Value::Null)),
+            CompiledTemplate::Each { expression, body } => {
+                Ok(Value::Array(for_each_item(context, expression, |scope| {
+                    body.render_value(scope, validations, top_level)
+                })?))
+            }
+        }
+    }
+
+    //None for a false if without an else
+    fn render_if(
+        &self,
+        context: &Value,
+        validations: &HashMap<String, ValidationFieldType>,
+        top_level: bool,
+    ) -> Result<Option<Value>, TemplateError> {
+        match self {
+            CompiledTemplate::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let branch = if condition.evaluate(context)? {
+                    Some(then)
+                } else {
+                    otherwise.as_ref()
+                };
+                branch
+                    .map(|branch| branch.render_value(context, validations, top_level))
+                    .transpose()
+            }
+            _ => self.render_value(context, validations, top_level).map(Some),
+        }
+    }
+
+    fn expected_type<'a>(
+        validations: &'a HashMap<String, ValidationFieldType>,
+        expression: &TemplateExpression,
+    ) -> Result<&'a ValidationFieldType, TemplateError> {
+        validations
+            .get(&expression.source)
+            .ok_or_else(|| TemplateError {
+                message: format!("Validation not found for key '{}'", expression.source),
+                variable: expression.source.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Instant;
+
+    fn webhook_context() -> Value {
+        json!({
+            "actions": {
+                "webhook": {
+                    "result": {
+                        "body": {
+                            "customer": {"name": "Ada", "email": "ada@example.com"},
+                            "items": [{"sku": "a", "qty": 2}, {"sku": "b", "qty": 1}],
+                            "total": "42.50"
+                        }
+                    }
+                }
+            },
+            "secrets": {"API_KEY": "key"},
+            "system": {"date": "2024-01-01"}
+        })
+    }
+
+    fn webhook_template() -> Value {
+        json!({
+            "url": "https://api.example.com/customers/{{actions.webhook.result.body.customer.email | url_encode}}",
+            "headers": {"Authorization": "Bearer {{secrets.API_KEY}}", "Content-Type": "application/json"},
+            "body": {
+                "name": "{{actions.webhook.result.body.customer.name | upper}}",
+                "total": "{{actions.webhook.result.body.total}}",
+                "lines": [{"{{#each actions.webhook.result.body.items}}": {"sku": "{{this.sku}}", "qty": "{{this.qty}}"}}],
+                "note": "{{#if actions.webhook.result.body.total > 40}}large{{else}}small{{/if}}"
+            },
+            "method": "POST"
+        })
+    }
+
+    fn webhook_validations() -> HashMap<String, ValidationFieldType> {
+        HashMap::from([
+            ("url".to_string(), ValidationFieldType::String),
+            ("headers".to_string(), ValidationFieldType::Object),
+            ("body".to_string(), ValidationFieldType::Object),
+            ("method".to_string(), ValidationFieldType::String),
+        ])
+    }
+
+    #[test]
+    fn compiled_render_matches_templater() {
+        let mut templater = Templater::new();
+        templater.add_template("inputs", webhook_template());
+
+        let compiled = CompiledTemplate::compile(&webhook_template()).unwrap();
+        let context = webhook_context();
+
+        let expected = templater
+            .render("inputs", &context, webhook_validations())
+            .unwrap();
+        assert_eq!(
+            compiled.render(&context, &webhook_validations()).unwrap(),
+            expected
+        );
+        assert_eq!(expected["body"]["lines"][1], json!({"sku": "b", "qty": 1}));
+        assert_eq!(
+            expected["url"],
+            json!("https://api.example.com/customers/ada%40example.com")
+        );
+    }
+
+    #[test]
+    fn compile_reports_template_errors() {
+        assert!(CompiledTemplate::compile(&json!({"a": "{{#if x}}open"})).is_err());
+        assert!(CompiledTemplate::compile(&json!({"a": "Hi {{name"})).is_err());
+    }
+
+    //cargo test --release benchmark_compiled_templates -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn benchmark_compiled_templates() {
+        const ITERATIONS: u32 = 50_000;
+        let context = webhook_context();
+        let template = webhook_template();
+        let validations = webhook_validations();
+
+        //What the bundler did before: a new Templater for every task
+        let started = Instant::now();
+        for _ in 0..ITERATIONS {
+            let mut templater = Templater::new();
+            templater.add_template("inputs", template.clone());
+            templater
+                .render("inputs", &context, validations.clone())
+                .unwrap();
+        }
+        let uncompiled = started.elapsed();
+
+        let compiled = CompiledTemplate::compile(&template).unwrap();
+        let started = Instant::now();
+        for _ in 0..ITERATIONS {
+            compiled.render(&context, &validations).unwrap();
+        }
+        let precompiled = started.elapsed();
+
+        println!(
+            "[TEMPLATER BENCHMARK] {} renders: parse every time {:?}, compiled once {:?} ({:.1}x faster)",
+            ITERATIONS,
+            uncompiled,
+            precompiled,
+            uncompiled.as_secs_f64() / precompiled.as_secs_f64()
+        );
+    }
+}
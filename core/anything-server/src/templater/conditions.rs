@@ -0,0 +1,428 @@
+use serde_json::Value;
+
+use super::filters::{parse_literal, split_outside_quotes};
+use super::{TemplateError, Templater};
+use crate::types::json_schema::ValidationFieldType;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Compare(String),
+    And,
+    Or,
+    Not,
+    In,
+    Exists,
+    Literal(Value),
+    Path(String),
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c == '&' && next == Some('&') {
+            tokens.push(Token::And);
+            i += 2;
+        } else if c == '|' && next == Some('|') {
+            tokens.push(Token::Or);
+            i += 2;
+        } else if matches!(c, '=' | '!' | '>' | '<') && next == Some('=') {
+            tokens.push(Token::Compare(format!("{}=", c)));
+            i += 2;
+        } else if c == '>' || c == '<' {
+            tokens.push(Token::Compare(c.to_string()));
+            i += 1;
+        } else if c == '!' {
+            tokens.push(Token::Not);
+            i += 1;
+        } else if c == '"' || c == '\'' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err("Unclosed string".to_string());
+            }
+            i += 1;
+            let literal: String = chars[start..i].iter().collect();
+            tokens.push(Token::Literal(parse_literal(&literal)?));
+        } else if c == '[' {
+            let start = i;
+            let mut quote = None;
+            while i < chars.len() {
+                match (quote, chars[i]) {
+                    (Some(q), ch) if ch == q => quote = None,
+                    (Some(_), '\\') => i += 1,
+                    (None, '"') | (None, '\'') => quote = Some(chars[i]),
+                    (None, ']') => break,
+                    _ => {}
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err("Unclosed list".to_string());
+            }
+            let inner: String = chars[start + 1..i].iter().collect();
+            i += 1;
+            let items = if inner.trim().is_empty() {
+                Vec::new()
+            } else {
+                split_outside_quotes(&inner, ',')
+                    .iter()
+                    .map(|item| parse_literal(item.trim()))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            tokens.push(Token::Literal(Value::Array(items)));
+        } else if c.is_ascii_digit() || (c == '-' && next.map_or(false, |n| n.is_ascii_digit())) {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            tokens.push(Token::Literal(parse_literal(&literal)?));
+        } else {
+            let start = i;
+            while i < chars.len()
+                && !chars[i].is_whitespace()
+                && !matches!(
+                    chars[i],
+                    '(' | ')' | '=' | '!' | '<' | '>' | '&' | '|' | '"' | '\''
+                )
+            {
+                i += 1;
+            }
+            //A lone `=`, `&` or `|` doesn't start any token
+            if i == start {
+                return Err(format!("Unexpected character '{}'", c));
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(match word.as_str() {
+                "and" => Token::And,
+                "or" => Token::Or,
+                "not" => Token::Not,
+                "in" => Token::In,
+                "exists" => Token::Exists,
+                "true" => Token::Literal(Value::Bool(true)),
+                "false" => Token::Literal(Value::Bool(false)),
+                "null" => Token::Literal(Value::Null),
+                _ => Token::Path(word),
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+//Paths a condition reads from the context
+pub fn condition_paths(expression: &str) -> Vec<String> {
+    tokenize(expression)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Path(path) => Some(path),
+            _ => None,
+        })
+        .collect()
+}
+
+//A tokenized expression like `body.total > 100 and not (body.status in ["void", "draft"])`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    expression: String,
+    tokens: Vec<Token>,
+}
+
+impl Condition {
+    pub fn parse(expression: &str) -> Result<Self, TemplateError> {
+        let condition = Condition {
+            expression: expression.to_string(),
+            tokens: tokenize(expression).map_err(|message| condition_error(expression, message))?,
+        };
+        if condition.tokens.is_empty() {
+            return Err(condition_error(
+                expression,
+                "condition is empty".to_string(),
+            ));
+        }
+
+        //Every path is null here so this only fails on syntax errors
+        condition.evaluate(&Value::Null)?;
+        Ok(condition)
+    }
+
+    pub fn evaluate(&self, context: &Value) -> Result<bool, TemplateError> {
+        let error = |message: String| condition_error(&self.expression, message);
+
+        let mut parser = ConditionParser {
+            tokens: &self.tokens,
+            pos: 0,
+            context,
+        };
+        let result = parser.parse_or().map_err(error)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(error(format!("unexpected {:?}", parser.tokens[parser.pos])));
+        }
+        Ok(result)
+    }
+}
+
+fn condition_error(expression: &str, message: String) -> TemplateError {
+    TemplateError {
+        message: format!("Invalid condition: {}", message),
+        variable: expression.to_string(),
+    }
+}
+
+struct ConditionParser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    context: &'a Value,
+}
+
+impl ConditionParser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<bool, String> {
+        let mut result = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            let rhs = self.parse_and()?;
+            result = result || rhs;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self) -> Result<bool, String> {
+        let mut result = self.parse_not()?;
+        while self.eat(&Token::And) {
+            let rhs = self.parse_not()?;
+            result = result && rhs;
+        }
+        Ok(result)
+    }
+
+    fn parse_not(&mut self) -> Result<bool, String> {
+        if self.eat(&Token::Not) {
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<bool, String> {
+        if self.eat(&Token::Exists) {
+            return match self.tokens.get(self.pos).cloned() {
+                Some(Token::Path(path)) => {
+                    self.pos += 1;
+                    Ok(!self.lookup(&path).is_null())
+                }
+                _ => Err("exists needs a path".to_string()),
+            };
+        }
+
+        if self.eat(&Token::LParen) {
+            let result = self.parse_or()?;
+            if !self.eat(&Token::RParen) {
+                return Err("missing ')'".to_string());
+            }
+            return Ok(result);
+        }
+
+        let left = self.parse_operand()?;
+        match self.peek().cloned() {
+            Some(Token::Compare(op)) => {
+                self.pos += 1;
+                let right = self.parse_operand()?;
+                Ok(compare(&left, &op, &right))
+            }
+            Some(Token::In) => {
+                self.pos += 1;
+                let right = self.parse_operand()?;
+                Ok(contains(&right, &left))
+            }
+            Some(Token::Not) if self.tokens.get(self.pos + 1) == Some(&Token::In) => {
+                self.pos += 2;
+                let right = self.parse_operand()?;
+                Ok(!contains(&right, &left))
+            }
+            _ => Ok(is_truthy(&left)),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Value, String> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Literal(value)) => {
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(Token::Path(path)) => {
+                self.pos += 1;
+                Ok(self.lookup(&path))
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of condition".to_string()),
+        }
+    }
+
+    //Missing paths are null so `{{#if body.optional}}` works without exists
+    fn lookup(&self, path: &str) -> Value {
+        Templater::get_value_from_path(self.context, path, &ValidationFieldType::Unknown)
+            .unwrap_or(Value::Null)
+    }
+}
+
+pub fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map_or(false, |n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn as_scalar_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+//Numbers compare numerically even when one side came in as a string
+fn loose_equals(left: &Value, right: &Value) -> bool {
+    if left.is_number() || right.is_number() {
+        if let (Some(l), Some(r)) = (as_f64(left), as_f64(right)) {
+            return l == r;
+        }
+    }
+    match (as_scalar_string(left), as_scalar_string(right)) {
+        (Some(l), Some(r)) => l == r,
+        _ => left == right,
+    }
+}
+
+fn compare(left: &Value, op: &str, right: &Value) -> bool {
+    match op {
+        "==" => loose_equals(left, right),
+        "!=" => !loose_equals(left, right),
+        _ => {
+            let ordering = match (as_f64(left), as_f64(right)) {
+                (Some(l), Some(r)) => l.partial_cmp(&r),
+                _ => match (left, right) {
+                    (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+                    _ => None,
+                },
+            };
+            match ordering {
+                Some(ordering) => match op {
+                    ">" => ordering.is_gt(),
+                    ">=" => ordering.is_ge(),
+                    "<" => ordering.is_lt(),
+                    "<=" => ordering.is_le(),
+                    _ => false,
+                },
+                None => false,
+            }
+        }
+    }
+}
+
+fn contains(haystack: &Value, needle: &Value) -> bool {
+    match haystack {
+        Value::Array(items) => items.iter().any(|item| loose_equals(item, needle)),
+        Value::String(s) => as_scalar_string(needle).map_or(false, |n| s.contains(&n)),
+        Value::Object(map) => needle.as_str().map_or(false, |key| map.contains_key(key)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn evaluates_comparisons_and_logic() {
+        let context = json!({
+            "body": {"total": "150", "status": "paid", "tags": ["a", "b"], "note": ""}
+        });
+
+        let cases = [
+            ("body.total > 100", true),
+            ("body.total == 150 and body.status == 'paid'", true),
+            ("body.status in ['void', 'draft'] || body.note", false),
+            ("not (body.status in [\"void\", \"draft\"])", true),
+            ("'b' in body.tags && body.status not in ['void']", true),
+            ("exists body.total and !exists body.missing", true),
+            ("body.missing", false),
+        ];
+
+        for (expression, expected) in cases {
+            assert_eq!(
+                Condition::parse(expression)
+                    .unwrap()
+                    .evaluate(&context)
+                    .unwrap(),
+                expected,
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn reports_invalid_conditions() {
+        let error = Condition::parse("body.total >").unwrap_err();
+        assert_eq!(error.variable, "body.total >");
+        assert!(error.message.starts_with("Invalid condition"));
+
+        for expression in ["a = 1", "a & b", "a | upper"] {
+            let error = Condition::parse(expression).unwrap_err();
+            assert!(
+                error.message.contains("Unexpected character"),
+                "{}: {}",
+                expression,
+                error.message
+            );
+        }
+    }
+}
@@ -0,0 +1,599 @@
+use base64::Engine;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use hmac::{Hmac, Mac};
+use serde_json::{Number, Value};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+use super::query::run_query;
+use super::TemplateError;
+
+//f64 has about 17 significant digits, more decimals only pad zeros
+const MAX_NUMBER_FORMAT_DECIMALS: f64 = 20.0;
+
+//A filter after a pipe like `replace("a", "b")`. Arguments are JSON literals, single quoted strings also work.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub name: String,
+    pub args: Vec<Value>,
+}
+
+//What goes between {{ }}: a path into the context and the filters to run on it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateExpression {
+    //The text between the braces, used in errors and to look up validations
+    pub source: String,
+    pub path: String,
+    pub filters: Vec<Filter>,
+}
+
+impl TemplateExpression {
+    pub fn parse(expression: &str) -> Result<Self, TemplateError> {
+        let mut parts = split_outside_quotes(expression, '|').into_iter();
+        let path = parts.next().unwrap_or_default().trim().to_string();
+
+        if path.is_empty() {
+            return Err(TemplateError {
+                message: "Template variable is empty".to_string(),
+                variable: expression.to_string(),
+            });
+        }
+
+        let filters = parts
+            .map(|part| parse_filter(part.trim(), &path))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(TemplateExpression {
+            source: expression.trim().to_string(),
+            path,
+            filters,
+        })
+    }
+}
+
+//Splits on the separator when it is not inside quotes, parentheses or brackets
+pub(super) fn split_outside_quotes(input: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut depth = 0;
+    let mut escaped = false;
+
+    for c in input.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+            continue;
+        }
+        match quote {
+            Some(q) => {
+                if c == '\\' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+                current.push(c);
+            }
+            None => match c {
+                '"' | '\'' => {
+                    quote = Some(c);
+                    current.push(c);
+                }
+                '(' | '[' => {
+                    depth += 1;
+                    current.push(c);
+                }
+                ')' | ']' => {
+                    depth -= 1;
+                    current.push(c);
+                }
+                c if c == separator && depth == 0 => {
+                    parts.push(std::mem::take(&mut current));
+                }
+                _ => current.push(c),
+            },
+        }
+    }
+    parts.push(current);
+    parts
+}
+
+fn parse_filter(filter: &str, variable: &str) -> Result<Filter, TemplateError> {
+    let error = |message: String| TemplateError {
+        message,
+        variable: variable.to_string(),
+    };
+
+    let (name, args) = match filter.find('(') {
+        Some(open_idx) => {
+            if !filter.ends_with(')') {
+                return Err(error(format!(
+                    "Filter '{}' is missing a closing ')'",
+                    filter
+                )));
+            }
+            let args = &filter[open_idx + 1..filter.len() - 1];
+            let args = if args.trim().is_empty() {
+                Vec::new()
+            } else {
+                split_outside_quotes(args, ',')
+                    .iter()
+                    .map(|arg| parse_literal(arg.trim()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|message| error(format!("Filter '{}': {}", filter, message)))?
+            };
+            (filter[..open_idx].trim(), args)
+        }
+        None => (filter, Vec::new()),
+    };
+
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(error(format!("Invalid filter name '{}'", name)));
+    }
+
+    Ok(Filter {
+        name: name.to_string(),
+        args,
+    })
+}
+
+pub(super) fn parse_literal(arg: &str) -> Result<Value, String> {
+    if arg.len() >= 2 && arg.starts_with('\'') && arg.ends_with('\'') {
+        return Ok(Value::String(
+            arg[1..arg.len() - 1]
+                .replace("\\'", "'")
+                .replace("\\\\", "\\"),
+        ));
+    }
+    serde_json::from_str(arg).map_err(|_| format!("Invalid argument {}", arg))
+}
+
+//Runs the filters in order. A missing value skips every filter until `default` fills it in.
+pub fn apply_filters(
+    value: Option<Value>,
+    filters: &[Filter],
+    variable: &str,
+) -> Result<Option<Value>, TemplateError> {
+    let mut current = value;
+    for filter in filters {
+        current = match current {
+            Some(value) if filter.name != "default" => Some(apply_filter(value, filter).map_err(
+                |message| TemplateError {
+                    message: format!("Filter '{}' failed: {}", filter.name, message),
+                    variable: variable.to_string(),
+                },
+            )?),
+            current if filter.name == "default" => {
+                let is_empty = matches!(&current, None | Some(Value::Null))
+                    || matches!(&current, Some(Value::String(s)) if s.is_empty());
+                if is_empty {
+                    Some(filter.args.first().cloned().unwrap_or(Value::Null))
+                } else {
+                    current
+                }
+            }
+            None => None,
+        };
+    }
+    Ok(current)
+}
+
+fn apply_filter(value: Value, filter: &Filter) -> Result<Value, String> {
+    let args = &filter.args;
+    match filter.name.as_str() {
+        // Strings
+        "trim" => Ok(Value::String(as_string(&value).trim().to_string())),
+        "lower" => Ok(Value::String(as_string(&value).to_lowercase())),
+        "upper" => Ok(Value::String(as_string(&value).to_uppercase())),
+        "slice" => slice(value, args),
+        "replace" => {
+            let from = string_arg(args, 0, "replace")?;
+            let to = string_arg(args, 1, "replace")?;
+            Ok(Value::String(as_string(&value).replace(&from, &to)))
+        }
+        "split" => {
+            let separator = string_arg(args, 0, "split")?;
+            Ok(Value::Array(
+                as_string(&value)
+                    .split(separator.as_str())
+                    .map(|s| Value::String(s.to_string()))
+                    .collect(),
+            ))
+        }
+        "join" => {
+            let separator = optional_string_arg(args, 0).unwrap_or_default();
+            match value {
+                Value::Array(items) => Ok(Value::String(
+                    items
+                        .iter()
+                        .map(as_string)
+                        .collect::<Vec<_>>()
+                        .join(&separator),
+                )),
+                _ => Err("join needs an array".to_string()),
+            }
+        }
+
+        // Numbers
+        "round" => {
+            let digits = number_arg(args, 0).unwrap_or(0.0) as i32;
+            let factor = 10f64.powi(digits);
+            let rounded = (as_number(&value)? * factor).round() / factor;
+            Ok(number_value(rounded))
+        }
+        "number_format" => {
+            let decimals = number_arg(args, 0)
+                .unwrap_or(0.0)
+                .clamp(0.0, MAX_NUMBER_FORMAT_DECIMALS) as usize;
+            let separator = optional_string_arg(args, 1).unwrap_or_default();
+            Ok(Value::String(format_number(
+                as_number(&value)?,
+                decimals,
+                &separator,
+            )))
+        }
+
+        // Dates
+        "date_format" => {
+            let format = string_arg(args, 0, "date_format")?;
+            let date = as_date(&value)?;
+            let mut formatted = String::new();
+            write!(formatted, "{}", date.format(&format))
+                .map_err(|_| format!("Invalid date format '{}'", format))?;
+            Ok(Value::String(formatted))
+        }
+        "date_add" => {
+            let amount = number_arg(args, 0).ok_or("date_add needs an amount")? as i64;
+            let unit = optional_string_arg(args, 1).unwrap_or_else(|| "days".to_string());
+            let duration = match unit.trim_end_matches('s') {
+                "second" => TimeDelta::try_seconds(amount),
+                "minute" => TimeDelta::try_minutes(amount),
+                "hour" => TimeDelta::try_hours(amount),
+                "day" => TimeDelta::try_days(amount),
+                "week" => TimeDelta::try_weeks(amount),
+                _ => return Err(format!("Unknown date unit '{}'", unit)),
+            };
+            //Amounts come from user templates, out of range ones fail the filter instead of panicking
+            let date = as_date(&value)?;
+            let date = duration
+                .and_then(|duration| date.checked_add_signed(duration))
+                .ok_or_else(|| format!("Adding {} {} is out of range", amount, unit))?;
+            Ok(Value::String(date.to_rfc3339()))
+        }
+
+        // JSON
+        "json" => Ok(Value::String(value.to_string())),
+        "query" => {
+            let query = string_arg(args, 0, "query")?;
+            run_query(&value, &query)
+        }
+        "first" => match value {
+            Value::Array(items) => Ok(items.into_iter().next().unwrap_or(Value::Null)),
+            _ => Err("first needs an array".to_string()),
+        },
+        "last" => match value {
+            Value::Array(items) => Ok(items.into_iter().last().unwrap_or(Value::Null)),
+            _ => Err("last needs an array".to_string()),
+        },
+        "parse" => match value {
+            Value::String(s) => serde_json::from_str(&s).map_err(|e| e.to_string()),
+            other => Ok(other),
+        },
+        "keys" => match value {
+            Value::Object(map) => Ok(Value::Array(
+                map.keys().map(|k| Value::String(k.clone())).collect(),
+            )),
+            _ => Err("keys needs an object".to_string()),
+        },
+        "length" => match value {
+            Value::Array(items) => Ok(Value::from(items.len())),
+            Value::Object(map) => Ok(Value::from(map.len())),
+            Value::String(s) => Ok(Value::from(s.chars().count())),
+            Value::Null => Ok(Value::from(0)),
+            _ => Err("length needs a string, array or object".to_string()),
+        },
+
+        // Encoding
+        "url_encode" => Ok(Value::String(
+            urlencoding::encode(&as_string(&value)).into_owned(),
+        )),
+        "url_decode" => urlencoding::decode(&as_string(&value))
+            .map(|s| Value::String(s.into_owned()))
+            .map_err(|e| e.to_string()),
+        "base64_encode" => Ok(Value::String(
+            base64::engine::general_purpose::STANDARD.encode(as_string(&value)),
+        )),
+        "base64_decode" => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(as_string(&value).trim())
+                .map_err(|e| e.to_string())?;
+            String::from_utf8(bytes)
+                .map(Value::String)
+                .map_err(|_| "Decoded value is not valid UTF-8".to_string())
+        }
+
+        // Hashing
+        "sha256" => Ok(Value::String(hex::encode(Sha256::digest(
+            as_string(&value).as_bytes(),
+        )))),
+        "sha1" => Ok(Value::String(hex::encode(Sha1::digest(
+            as_string(&value).as_bytes(),
+        )))),
+        "hmac_sha256" => {
+            let key = string_arg(args, 0, "hmac_sha256")?;
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(|e| e.to_string())?;
+            mac.update(as_string(&value).as_bytes());
+            Ok(Value::String(hex::encode(mac.finalize().into_bytes())))
+        }
+
+        _ => Err(format!("Unknown filter '{}'", filter.name)),
+    }
+}
+
+fn as_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn as_number(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => n.as_f64().ok_or_else(|| format!("Invalid number {}", n)),
+        Value::String(s) => s
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Cannot convert value to number: {}", s)),
+        other => Err(format!("Expected number, got: {}", other)),
+    }
+}
+
+//Whole numbers stay integers so they don't render as 3.0
+fn number_value(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::Number(Number::from(n as i64))
+    } else {
+        Number::from_f64(n)
+            .map(Value::Number)
+            .unwrap_or(Value::Null)
+    }
+}
+
+fn format_number(n: f64, decimals: usize, thousands_separator: &str) -> String {
+    let formatted = format!("{:.*}", decimals, n.abs());
+    let (integer, fraction) = match formatted.split_once('.') {
+        Some((integer, fraction)) => (integer.to_string(), Some(fraction.to_string())),
+        None => (formatted, None),
+    };
+
+    let mut grouped = String::new();
+    for (i, c) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push_str(thousands_separator);
+        }
+        grouped.push(c);
+    }
+
+    let sign = if n < 0.0 && formatted_is_nonzero(&grouped, &fraction) {
+        "-"
+    } else {
+        ""
+    };
+    match fraction {
+        Some(fraction) => format!("{}{}.{}", sign, grouped, fraction),
+        None => format!("{}{}", sign, grouped),
+    }
+}
+
+fn formatted_is_nonzero(integer: &str, fraction: &Option<String>) -> bool {
+    integer.chars().any(|c| c.is_ascii_digit() && c != '0')
+        || fraction
+            .as_ref()
+            .map(|f| f.chars().any(|c| c != '0'))
+            .unwrap_or(false)
+}
+
+//Accepts RFC 3339, plain dates and datetimes, or unix timestamps in seconds
+fn as_date(value: &Value) -> Result<DateTime<FixedOffset>, String> {
+    let utc = FixedOffset::east_opt(0).unwrap();
+    match value {
+        Value::Number(n) => {
+            let seconds = n.as_i64().ok_or("Timestamp must be whole seconds")?;
+            DateTime::<Utc>::from_timestamp(seconds, 0)
+                .map(|date| date.with_timezone(&utc))
+                .ok_or_else(|| format!("Invalid timestamp {}", seconds))
+        }
+        Value::String(s) => {
+            let s = s.trim();
+            if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+                return Ok(date);
+            }
+            for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+                if let Ok(date) = NaiveDateTime::parse_from_str(s, format) {
+                    return Ok(date.and_utc().with_timezone(&utc));
+                }
+            }
+            if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+                return Ok(date
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+                    .and_utc()
+                    .with_timezone(&utc));
+            }
+            if let Ok(seconds) = s.parse::<i64>() {
+                return as_date(&Value::from(seconds));
+            }
+            Err(format!("Cannot parse date: {}", s))
+        }
+        other => Err(format!("Expected date, got: {}", other)),
+    }
+}
+
+//Python style slicing for strings and arrays, negative indexes count from the end
+fn slice(value: Value, args: &[Value]) -> Result<Value, String> {
+    let start = number_arg(args, 0).ok_or("slice needs a start index")? as i64;
+    let end = number_arg(args, 1).map(|n| n as i64);
+
+    let bounds = |len: usize| {
+        let clamp = |i: i64| {
+            if i < 0 {
+                (len as i64 + i).max(0) as usize
+            } else {
+                (i as usize).min(len)
+            }
+        };
+        let start = clamp(start);
+        let end = end.map(clamp).unwrap_or(len);
+        (start, end.max(start))
+    };
+
+    match value {
+        Value::Array(items) => {
+            let (start, end) = bounds(items.len());
+            Ok(Value::Array(items[start..end].to_vec()))
+        }
+        other => {
+            let chars: Vec<char> = as_string(&other).chars().collect();
+            let (start, end) = bounds(chars.len());
+            Ok(Value::String(chars[start..end].iter().collect()))
+        }
+    }
+}
+
+fn number_arg(args: &[Value], index: usize) -> Option<f64> {
+    args.get(index).and_then(|arg| as_number(arg).ok())
+}
+
+fn optional_string_arg(args: &[Value], index: usize) -> Option<String> {
+    args.get(index).map(as_string)
+}
+
+fn string_arg(args: &[Value], index: usize, filter: &str) -> Result<String, String> {
+    optional_string_arg(args, index)
+        .ok_or_else(|| format!("{} needs argument {}", filter, index + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(value: Value, expression: &str) -> Result<Option<Value>, TemplateError> {
+        let expression = TemplateExpression::parse(expression)?;
+        apply_filters(Some(value), &expression.filters, &expression.path)
+    }
+
+    #[test]
+    fn parses_pipes_and_quoted_arguments() {
+        let expression =
+            TemplateExpression::parse(r#"body.name | replace("|", ', ') | default("n/a")"#)
+                .unwrap();
+
+        assert_eq!(expression.path, "body.name");
+        assert_eq!(
+            expression.filters,
+            vec![
+                Filter {
+                    name: "replace".to_string(),
+                    args: vec![json!("|"), json!(", ")],
+                },
+                Filter {
+                    name: "default".to_string(),
+                    args: vec![json!("n/a")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn string_filters() {
+        assert_eq!(
+            run(json!("  Hi  "), "x | trim | upper").unwrap(),
+            Some(json!("HI"))
+        );
+        assert_eq!(
+            run(json!("hello"), "x | slice(1, -1)").unwrap(),
+            Some(json!("ell"))
+        );
+        assert_eq!(
+            run(json!("a,b,c"), "x | split(',') | join(' + ')").unwrap(),
+            Some(json!("a + b + c"))
+        );
+    }
+
+    #[test]
+    fn number_and_date_filters() {
+        assert_eq!(
+            run(json!(3.14159), "x | round(2)").unwrap(),
+            Some(json!(3.14))
+        );
+        assert_eq!(run(json!("2.6"), "x | round").unwrap(), Some(json!(3)));
+        assert_eq!(
+            run(json!(1234567.891), "x | number_format(2, ',')").unwrap(),
+            Some(json!("1,234,567.89"))
+        );
+        assert_eq!(
+            run(
+                json!("2024-01-31"),
+                "x | date_add(1, 'day') | date_format('%Y-%m-%d')"
+            )
+            .unwrap(),
+            Some(json!("2024-02-01"))
+        );
+        assert!(run(json!("2024-01-31"), "x | date_add(1e18, 'week')").is_err());
+        assert!(run(json!("2024-01-31"), "x | date_add(-1e15, 'second')").is_err());
+        assert_eq!(
+            run(json!(1.5), "x | number_format(1e9)").unwrap(),
+            Some(json!(format!("1.5{}", "0".repeat(19))))
+        );
+    }
+
+    #[test]
+    fn json_encoding_and_hash_filters() {
+        assert_eq!(
+            run(json!("{\"b\":1,\"a\":2}"), "x | parse | keys | length").unwrap(),
+            Some(json!(2))
+        );
+        assert_eq!(
+            run(json!({"a": 1}), "x | json").unwrap(),
+            Some(json!("{\"a\":1}"))
+        );
+        assert_eq!(
+            run(json!("a b&c"), "x | url_encode").unwrap(),
+            Some(json!("a%20b%26c"))
+        );
+        assert_eq!(
+            run(json!("hi"), "x | base64_encode | base64_decode").unwrap(),
+            Some(json!("hi"))
+        );
+        assert_eq!(
+            run(json!("abc"), "x | sha256").unwrap(),
+            Some(json!(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+            ))
+        );
+    }
+
+    #[test]
+    fn default_fills_missing_values() {
+        let expression = TemplateExpression::parse("x | upper | default('n/a')").unwrap();
+        assert_eq!(
+            apply_filters(None, &expression.filters, "x").unwrap(),
+            Some(json!("n/a"))
+        );
+        assert_eq!(run(json!(""), "x | default(0)").unwrap(), Some(json!(0)));
+    }
+
+    #[test]
+    fn filter_errors_name_the_variable() {
+        let error = run(json!("abc"), "body.count | round").unwrap_err();
+        assert_eq!(error.variable, "body.count");
+        assert!(error.message.contains("round"));
+
+        let error = run(json!("abc"), "body.count | nope").unwrap_err();
+        assert!(error.message.contains("Unknown filter 'nope'"));
+    }
+}
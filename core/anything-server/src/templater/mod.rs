@@ -4,6 +4,18 @@ use std::error::Error;
 
 use crate::types::json_schema::ValidationFieldType;
 
+mod blocks;
+mod compiled;
+mod conditions;
+mod filters;
+mod query;
+
+use blocks::{parse_tag, BlockTag};
+use conditions::condition_paths;
+use filters::{apply_filters, TemplateExpression};
+
+pub use compiled::CompiledTemplate;
+
 #[derive(Debug)]
 pub struct TemplateError {
     pub message: String,
@@ -56,7 +68,8 @@ impl Templater {
         let mut variables = Vec::new();
         match value {
             Value::Object(map) => {
-                for (_, v) in map {
+                for (k, v) in map {
+                    variables.extend(self.extract_variables(&Value::String(k.clone()))?);
                     variables.extend(self.extract_variables(v)?);
                 }
             }
@@ -74,8 +87,15 @@ impl Templater {
                         variable: s.to_string(),
                     })?;
                     let close_idx = open_idx + close_idx;
-                    let variable = s[open_idx + 2..close_idx].trim().to_string();
-                    variables.push(variable);
+                    match parse_tag(&s[open_idx + 2..close_idx]) {
+                        BlockTag::Variable(variable) | BlockTag::Each(variable) => {
+                            variables.push(TemplateExpression::parse(&variable)?.path)
+                        }
+                        BlockTag::If(condition) | BlockTag::ElseIf(condition) => {
+                            variables.extend(condition_paths(&condition))
+                        }
+                        _ => {}
+                    }
                     start = close_idx + 2;
                 }
             }
@@ -130,6 +150,26 @@ impl Templater {
         Some(current.clone())
     }
 
+    //Looks up the path or $ JSONPath query before the first pipe and runs any filters after it.
+    //Returns None when the path is missing and no `default` filter filled it in.
+    fn resolve_expression(
+        context: &Value,
+        expression: &TemplateExpression,
+        expected_type: &ValidationFieldType,
+    ) -> Result<Option<Value>, TemplateError> {
+        let value = if query::is_query(&expression.path) {
+            let matches =
+                query::run_query(context, &expression.path).map_err(|message| TemplateError {
+                    message,
+                    variable: expression.path.clone(),
+                })?;
+            Some(matches)
+        } else {
+            Self::get_value_from_path(context, &expression.path, expected_type)
+        };
+        apply_filters(value, &expression.filters, &expression.path)
+    }
+
     pub fn render(
         &self,
         template_name: &str,
@@ -144,144 +184,10 @@ impl Templater {
                 variable: template_name.to_string(),
             })?;
 
-        self.render_value(template, context, &validations, &[])
-    }
-
-    fn render_value(
-        &self,
-        value: &Value,
-        context: &Value,
-        validations: &HashMap<String, ValidationFieldType>,
-        path: &[String],
-    ) -> Result<Value, TemplateError> {
-        match value {
-            Value::Object(map) => {
-                let mut result = serde_json::Map::new();
-                for (k, v) in map {
-                    let mut current_path = path.to_vec();
-                    current_path.push(k.clone());
-                    if path.is_empty() {
-                        let validation_type = validations.get(k).ok_or_else(|| TemplateError {
-                            message: format!("Validation not found for key '{}'", k),
-                            variable: k.clone(),
-                        })?;
-                        let rendered = self.render_value(v, context, validations, &current_path)?;
-                        let validated =
-                            self.validate_and_convert_value(rendered, validation_type, k)?;
-                        result.insert(k.clone(), validated);
-                    } else {
-                        result.insert(
-                            k.clone(),
-                            self.render_value(v, context, validations, &current_path)?,
-                        );
-                    }
-                }
-                Ok(Value::Object(result))
-            }
-            Value::Array(arr) => {
-                let mut result = Vec::new();
-                for v in arr.iter() {
-                    result.push(self.render_value(v, context, validations, path)?);
-                }
-                Ok(Value::Array(result))
-            }
-            Value::String(s) => {
-                let trimmed = s.trim();
-                if trimmed.starts_with("{{") && trimmed.ends_with("}}") {
-                    let variable = trimmed[2..trimmed.len() - 2].trim();
-                    // Only validate if this is a top-level path
-                    if path.is_empty() {
-                        let validation_key = variable.to_string();
-                        let expected_type =
-                            validations
-                                .get(&validation_key)
-                                .ok_or_else(|| TemplateError {
-                                    message: format!(
-                                        "Validation not found for key '{}'",
-                                        validation_key
-                                    ),
-                                    variable: validation_key.clone(),
-                                })?;
-                        let value = Self::get_value_from_path(context, variable, expected_type)
-                            .ok_or_else(|| TemplateError {
-                                message: format!("Variable not found in context: {}", variable),
-                                variable: variable.to_string(),
-                            })?;
-                        let value =
-                            self.validate_and_convert_value(value, expected_type, &validation_key)?;
-                        return Ok(value);
-                    } else {
-                        // For nested variables, just get the value without validation
-                        let value = Self::get_value_from_path(
-                            context,
-                            variable,
-                            &ValidationFieldType::Unknown,
-                        )
-                        .ok_or_else(|| TemplateError {
-                            message: format!("Variable not found in context: {}", variable),
-                            variable: variable.to_string(),
-                        })?;
-                        return Ok(value);
-                    }
-                }
-
-                // Regular string interpolation logic
-                let mut result = s.clone();
-                let mut start = 0;
-
-                while let Some(open_idx) = result[start..].find("{{") {
-                    let open_idx = start + open_idx;
-                    let close_idx = result[open_idx..].find("}}").ok_or_else(|| TemplateError {
-                        message: "Unclosed template variable".to_string(),
-                        variable: result.clone(),
-                    })?;
-                    let close_idx = open_idx + close_idx;
-                    let variable = result[open_idx + 2..close_idx].trim();
-
-                    // Only validate if this is a top-level path
-                    let value = if path.is_empty() {
-                        let validation_key = variable.to_string();
-                        let expected_type =
-                            validations
-                                .get(&validation_key)
-                                .ok_or_else(|| TemplateError {
-                                    message: format!(
-                                        "Validation not found for key '{}'",
-                                        validation_key
-                                    ),
-                                    variable: validation_key.clone(),
-                                })?;
-                        let value = Self::get_value_from_path(context, variable, expected_type)
-                            .ok_or_else(|| TemplateError {
-                                message: "Variable not found in context".to_string(),
-                                variable: variable.to_string(),
-                            })?;
-                        self.validate_and_convert_value(value, expected_type, &validation_key)?
-                    } else {
-                        // For nested variables, just get the value without validation
-                        Self::get_value_from_path(context, variable, &ValidationFieldType::Unknown)
-                            .ok_or_else(|| TemplateError {
-                                message: "Variable not found in context".to_string(),
-                                variable: variable.to_string(),
-                            })?
-                    };
-
-                    let replacement = match value {
-                        Value::String(s) => s.clone(),
-                        _ => value.to_string(),
-                    };
-                    result.replace_range(open_idx..close_idx + 2, &replacement);
-                    start = open_idx + replacement.len();
-                }
-
-                Ok(Value::String(result))
-            }
-            _ => Ok(value.clone()),
-        }
+        CompiledTemplate::compile(template)?.render(context, &validations)
     }
 
     fn validate_and_convert_value(
-        &self,
         value: Value,
         expected_type: &ValidationFieldType,
         variable: &str,
@@ -757,4 +663,146 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn pipe_filters_in_templates() {
+        let mut templater = Templater::new();
+        templater.add_template(
+            "test_template",
+            json!({
+                "name": "{{actions.http.result.body.name | upper | default(\"n/a\")}}",
+                "nickname": "{{actions.http.result.body.nickname | upper | default(\"n/a\")}}",
+                "greeting": "Hi {{actions.http.result.body.name | slice(0, 3)}}!",
+                "count": "{{actions.http.result.body.tags | length}}"
+            }),
+        );
+
+        let context = json!({
+            "actions": {
+                "http": {
+                    "result": {
+                        "body": "{\"name\": \"bobby\", \"tags\": [\"a\", \"b\"]}"
+                    }
+                }
+            }
+        });
+
+        let mut validations = HashMap::new();
+        validations.insert("name".to_string(), ValidationFieldType::String);
+        validations.insert("nickname".to_string(), ValidationFieldType::String);
+        validations.insert("greeting".to_string(), ValidationFieldType::String);
+        validations.insert("count".to_string(), ValidationFieldType::Number);
+
+        let result = templater
+            .render("test_template", &context, validations)
+            .unwrap();
+
+        assert_eq!(
+            result,
+            json!({
+                "name": "BOBBY",
+                "nickname": "n/a",
+                "greeting": "Hi bob!",
+                "count": 2
+            })
+        );
+    }
+
+    #[test]
+    fn object_and_array_blocks() {
+        let mut templater = Templater::new();
+        templater.add_template(
+            "test_template",
+            json!({
+                "body": {
+                    "customer": {
+                        "{{#if actions.lookup.result.found}}": {"id": "{{actions.lookup.result.id}}"},
+                        "{{else}}": null
+                    },
+                    "lines": [
+                        {"sku": "shipping"},
+                        {"{{#each actions.cart.result.items}}": {"sku": "{{this.sku}}", "position": "{{@index}}"}},
+                        {"{{#if actions.cart.result.coupon}}": {"sku": "coupon"}}
+                    ]
+                },
+                "summary": "{{#if actions.lookup.result.found and actions.cart.result.items}}{{actions.cart.result.items | length}}{{/if}}"
+            }),
+        );
+
+        let context = json!({
+            "actions": {
+                "lookup": {"result": {"found": true, "id": 7}},
+                "cart": {"result": {"items": [{"sku": "a"}, {"sku": "b"}], "coupon": ""}}
+            }
+        });
+
+        let mut validations = HashMap::new();
+        validations.insert("body".to_string(), ValidationFieldType::Object);
+        validations.insert("summary".to_string(), ValidationFieldType::Number);
+
+        let result = templater
+            .render("test_template", &context, validations)
+            .unwrap();
+
+        assert_eq!(
+            result,
+            json!({
+                "body": {
+                    "customer": {"id": 7},
+                    "lines": [
+                        {"sku": "shipping"},
+                        {"sku": "a", "position": 0},
+                        {"sku": "b", "position": 1}
+                    ]
+                },
+                "summary": 2
+            })
+        );
+    }
+
+    #[test]
+    fn jsonpath_queries() {
+        let mut templater = Templater::new();
+        templater.add_template(
+            "test_template",
+            json!({
+                "active_ids": "{{$.actions.http.result.items[?@.status == 'active'].id}}",
+                "first_large": "{{actions.http.result.body | query('$.orders[?@.total > 100]') | first}}",
+                "summary": "Active: {{$.actions.http.result.items[?@.status == 'active'].id | join(', ')}}"
+            }),
+        );
+
+        let context = json!({
+            "actions": {
+                "http": {
+                    "result": {
+                        "items": [
+                            {"id": 1, "status": "active"},
+                            {"id": 2, "status": "archived"},
+                            {"id": 3, "status": "active"}
+                        ],
+                        "body": "{\"orders\": [{\"id\": \"a\", \"total\": 50}, {\"id\": \"b\", \"total\": 150}]}"
+                    }
+                }
+            }
+        });
+
+        let mut validations = HashMap::new();
+        validations.insert("active_ids".to_string(), ValidationFieldType::Array);
+        validations.insert("first_large".to_string(), ValidationFieldType::Object);
+        validations.insert("summary".to_string(), ValidationFieldType::String);
+
+        let result = templater
+            .render("test_template", &context, validations)
+            .unwrap();
+
+        assert_eq!(
+            result,
+            json!({
+                "active_ids": [1, 3],
+                "first_large": {"id": "b", "total": 150},
+                "summary": "Active: 1, 3"
+            })
+        );
+    }
 }
@@ -0,0 +1,58 @@
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+//Template variables starting with $ are JSONPath queries against the whole context
+pub fn is_query(path: &str) -> bool {
+    path.starts_with('$')
+}
+
+//Runs a JSONPath query (RFC 9535) like `$.items[?@.status == 'active'].id`.
+//Always returns an array of every match, use the `first` filter for a single value.
+pub fn run_query(value: &Value, query: &str) -> Result<Value, String> {
+    let path = JsonPath::parse(query).map_err(|e| format!("Invalid JSONPath query: {}", e))?;
+
+    //Action results often hold JSON as a string, query what's inside it
+    let parsed;
+    let value = match value {
+        Value::String(s) => match serde_json::from_str::<Value>(s) {
+            Ok(inner) => {
+                parsed = inner;
+                &parsed
+            }
+            Err(_) => value,
+        },
+        _ => value,
+    };
+
+    Ok(Value::Array(
+        path.query(value).all().into_iter().cloned().collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn queries_return_every_match() {
+        let value = json!({
+            "items": [
+                {"id": 1, "status": "active"},
+                {"id": 2, "status": "archived"},
+                {"id": 3, "status": "active"}
+            ]
+        });
+
+        assert_eq!(
+            run_query(&value, "$.items[?@.status == 'active'].id").unwrap(),
+            json!([1, 3])
+        );
+        assert_eq!(run_query(&value, "$.missing").unwrap(), json!([]));
+        assert_eq!(
+            run_query(&json!("{\"a\": {\"b\": 2}}"), "$..b").unwrap(),
+            json!([2])
+        );
+        assert!(run_query(&value, "$.items[?").is_err());
+    }
+}
@@ -8,8 +8,12 @@ use std::env;
 use node_semver::Version;
 
 use crate::{
-    bundler::bundle_context_from_parts,
+    bundler::{bundle_context_from_parts, secrets::SecretScope},
+    polling_triggers::{poll_trigger, POLLING_PLUGIN_NAME},
     processor::processor::ProcessorMessage,
+    scheduled_runs::{
+        fetch_pending_scheduled_runs, fire_scheduled_run, index_scheduled_runs, ScheduledRun,
+    },
     types::{
         action_types::{ActionType, PluginName},
         task_types::{
@@ -20,9 +24,12 @@ use crate::{
     AppState,
 };
 
-use std::collections::HashMap;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
+use tracing::debug;
 
 use cron::Schedule;
 use std::str::FromStr;
@@ -43,49 +50,112 @@ pub struct InMemoryTrigger {
     pub cron_expression: String,
 }
 
+//Upper bound on how long we sleep when no trigger is due soon so we never stall on a missed wakeup
+const MAX_IDLE_SLEEP: Duration = Duration::from_secs(60);
+
+//What a timer in the heap points at
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum TimerKey {
+    Cron(String),         // workflow_id
+    ScheduledRun(String), // scheduled_run_id
+}
+
+//Min-heap of (fire_at, key). Entries are lazily invalidated by checking against the in memory stores.
+type TriggerTimerHeap = BinaryHeap<Reverse<(DateTime<Utc>, TimerKey)>>;
+
+type TriggerStore = Arc<RwLock<HashMap<String, InMemoryTrigger>>>;
+type ScheduledRunStore = Arc<RwLock<HashMap<String, ScheduledRun>>>;
+
 pub async fn cron_job_loop(state: Arc<AppState>) {
     //worfklow_id => trigger
-    let trigger_state: Arc<RwLock<HashMap<String, InMemoryTrigger>>> =
-        Arc::new(RwLock::new(HashMap::new()));
+    let trigger_state: TriggerStore = Arc::new(RwLock::new(HashMap::new()));
+    //scheduled_run_id => one off run
+    let scheduled_run_state: ScheduledRunStore = Arc::new(RwLock::new(HashMap::new()));
+    //worfklow_id of polling triggers with a request in flight
+    let polls_in_flight: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
 
     // Receive info from other systems like CRUD over workflows that have triggers
-    let mut trigger_engine_signal_rx = state.trigger_engine_signal.subscribe();
+    // Queued so back to back updates to different workflows are each applied
+    let mut trigger_engine_signal_rx = state.trigger_engine_receiver.lock().await;
     let client = state.anything_client.clone();
     hydrate_triggers(state.clone(), &client, &trigger_state).await;
+    hydrate_scheduled_runs(&state, &scheduled_run_state, None).await;
 
-    //How often we check for triggers to run
-    let refresh_interval = Duration::from_secs(60);
-
-    // Clone state once here for use in the loop
-    let state = Arc::new(state);
+    //Timers for when each trigger should fire next
+    let mut timers = build_timer_heap(&trigger_state, &scheduled_run_state).await;
 
     loop {
+        //Sleep until the earliest trigger is due instead of polling on a fixed interval
+        let sleep_duration =
+            time_until_next_fire(&mut timers, &trigger_state, &scheduled_run_state).await;
+        debug!(
+            "[TRIGGER_ENGINE] Sleeping for {:?} until next trigger check",
+            sleep_duration
+        );
+
         tokio::select! {
-            _ = sleep(refresh_interval) => {
+            _ = sleep(sleep_duration) => {
                 println!("[TRIGGER_ENGINE] Starting trigger check loop");
 
                 //find triggers to run
-                let triggers_to_run = {
-                    let triggers = trigger_state.read().await;
-                    triggers
-                        .iter()
-                        .filter(|(_, trigger)| should_trigger_run(trigger))
-                        .map(|(id, trigger)| (id.clone(), trigger.clone()))
-                        .collect::<Vec<_>>()
-                };
+                let timers_to_run =
+                    pop_due_timers(&mut timers, &trigger_state, &scheduled_run_state).await;
 
                 //Create tasks for triggers that should run
                 //Then update trigger to get next time to run in memory
-                for (id, trigger) in triggers_to_run {
-                    println!(
-                        "[TRIGGER_ENGINE] Trigger should run for trigger_id ie workflow_id: {}",
-                        trigger.plugin_name
-                    );
-                    if let Err(e) = create_trigger_task(&state, &trigger).await {
-                        println!("[TRIGGER_ENGINE] Error creating trigger task: {:?}", e);
-                    } else {
-                        if let Err(e) = update_trigger_last_run(&id, &trigger, &trigger_state).await {
-                            println!("[TRIGGER_ENGINE] Error updating trigger last run: {:?}", e);
+                for timer in timers_to_run {
+                    match timer {
+                        TimerKey::Cron(id) => {
+                            let trigger = match trigger_state.read().await.get(&id) {
+                                Some(trigger) => trigger.clone(),
+                                None => continue,
+                            };
+                            println!(
+                                "[TRIGGER_ENGINE] Trigger should run for trigger_id ie workflow_id: {}",
+                                trigger.plugin_name
+                            );
+                            if trigger.plugin_name.as_str() == POLLING_PLUGIN_NAME {
+                                //Polling waits on a remote API so run it off the loop, one poll per trigger at a time
+                                if polls_in_flight.lock().await.insert(id.clone()) {
+                                    let state = state.clone();
+                                    let polls_in_flight = polls_in_flight.clone();
+                                    let trigger = trigger.clone();
+                                    let id = id.clone();
+                                    tokio::spawn(async move {
+                                        if let Err(e) = poll_trigger(&state, &trigger).await {
+                                            println!("[TRIGGER_ENGINE] Error polling trigger: {:?}", e);
+                                        }
+                                        polls_in_flight.lock().await.remove(&id);
+                                    });
+                                } else {
+                                    println!("[TRIGGER_ENGINE] Previous poll still running for trigger: {}", id);
+                                }
+                            } else if let Err(e) = create_trigger_task(&state, &trigger).await {
+                                println!("[TRIGGER_ENGINE] Error creating trigger task: {:?}", e);
+                            }
+
+                            //Always reschedule so a failing trigger does not spin the loop
+                            match update_trigger_last_run(&id, &trigger, &trigger_state).await {
+                                Ok(Some(next_fire)) => {
+                                    timers.push(Reverse((next_fire, TimerKey::Cron(id))))
+                                }
+                                Ok(None) => {
+                                    println!("[TRIGGER_ENGINE] Trigger {} has no upcoming fire time", id);
+                                }
+                                Err(e) => {
+                                    println!("[TRIGGER_ENGINE] Error updating trigger last run: {:?}", e);
+                                }
+                            }
+                        }
+                        TimerKey::ScheduledRun(id) => {
+                            //One off runs leave memory once they fire whether or not they succeed
+                            let run = match scheduled_run_state.write().await.remove(&id) {
+                                Some(run) => run,
+                                None => continue,
+                            };
+                            if let Err(e) = fire_scheduled_run(&state, &run).await {
+                                println!("[TRIGGER_ENGINE] Error firing scheduled run {}: {:?}", id, e);
+                            }
                         }
                     }
                     println!("[TRIGGER_ENGINE] Trigger Loop Successfully LOOPED");
@@ -93,50 +163,186 @@ pub async fn cron_job_loop(state: Arc<AppState>) {
 
                 println!("[TRIGGER_ENGINE] Finished trigger check loop");
             }
-            _ = trigger_engine_signal_rx.changed() => {
-                let workflow_id = trigger_engine_signal_rx.borrow().clone();
+            Some(workflow_id) = trigger_engine_signal_rx.recv() => {
                 println!("[TRIGGER_ENGINE] Received workflow_id: {}", workflow_id);
                 if let Err(e) = update_triggers_for_workflow(&state, &client, &trigger_state, &workflow_id).await {
                     println!("[TRIGGER_ENGINE] Error updating triggers for workflow: {:?}", e);
                 }
+                hydrate_scheduled_runs(&state, &scheduled_run_state, Some(&workflow_id)).await;
+                //Triggers may have been added, removed or rescheduled so start from a fresh heap
+                timers = build_timer_heap(&trigger_state, &scheduled_run_state).await;
             }
         }
     }
 }
 
-//From Claude and very untested so far
-//Ment to lightly update triggers so we don't need to refresh the entire memory each time we update something
-async fn update_triggers_for_workflow(
-    state: &Arc<AppState>,
-    client: &Postgrest,
-    triggers: &Arc<RwLock<HashMap<String, InMemoryTrigger>>>,
-    workflow_id: &String,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//Loads pending one off runs into memory. With a workflow_id only that workflow's runs are replaced.
+async fn hydrate_scheduled_runs(
+    state: &AppState,
+    scheduled_runs: &ScheduledRunStore,
+    workflow_id: Option<&str>,
+) {
     println!(
-        "[TRIGGER_ENGINE] Updating triggers for workflow: {}",
+        "[TRIGGER_ENGINE] Hydrating scheduled runs for workflow: {:?}",
         workflow_id
     );
 
+    let runs = match fetch_pending_scheduled_runs(state, workflow_id).await {
+        Ok(runs) => runs,
+        Err(e) => {
+            println!("[TRIGGER_ENGINE] Error fetching scheduled runs: {:?}", e);
+            return;
+        }
+    };
+
+    let mut scheduled_runs = scheduled_runs.write().await;
+    if let Some(workflow_id) = workflow_id {
+        //Drop runs that were canceled or fired elsewhere before adding the current pending set
+        scheduled_runs.retain(|_, run| run.flow_id.to_string() != workflow_id);
+    }
+    scheduled_runs.extend(index_scheduled_runs(runs));
+
+    println!(
+        "[TRIGGER_ENGINE] Scheduled runs in memory: {}",
+        scheduled_runs.len()
+    );
+}
+
+async fn build_timer_heap(
+    triggers: &TriggerStore,
+    scheduled_runs: &ScheduledRunStore,
+) -> TriggerTimerHeap {
+    let triggers = triggers.read().await;
+    let scheduled_runs = scheduled_runs.read().await;
+
+    let cron_timers = triggers.iter().filter_map(|(id, trigger)| {
+        trigger
+            .next_fire
+            .map(|next| Reverse((next, TimerKey::Cron(id.clone()))))
+    });
+    let scheduled_run_timers = scheduled_runs
+        .iter()
+        .map(|(id, run)| Reverse((run.run_at, TimerKey::ScheduledRun(id.clone()))));
+
+    cron_timers.chain(scheduled_run_timers).collect()
+}
+
+//Discards stale heap entries and returns how long until the earliest live timer is due
+async fn time_until_next_fire(
+    timers: &mut TriggerTimerHeap,
+    triggers: &TriggerStore,
+    scheduled_runs: &ScheduledRunStore,
+) -> Duration {
+    let triggers = triggers.read().await;
+    let scheduled_runs = scheduled_runs.read().await;
+    while let Some(Reverse((fire_at, key))) = timers.peek().cloned() {
+        if !is_timer_current(&triggers, &scheduled_runs, &key, &fire_at) {
+            timers.pop();
+            continue;
+        }
+        return (fire_at - Utc::now())
+            .to_std()
+            .unwrap_or(Duration::ZERO)
+            .min(MAX_IDLE_SLEEP);
+    }
+    MAX_IDLE_SLEEP
+}
+
+//Pops every heap entry that is due and still matches what is in memory
+async fn pop_due_timers(
+    timers: &mut TriggerTimerHeap,
+    triggers: &TriggerStore,
+    scheduled_runs: &ScheduledRunStore,
+) -> Vec<TimerKey> {
+    let triggers = triggers.read().await;
+    let scheduled_runs = scheduled_runs.read().await;
+    let now = Utc::now();
+    let mut due = Vec::new();
+    while let Some(Reverse((fire_at, key))) = timers.peek().cloned() {
+        if !is_timer_current(&triggers, &scheduled_runs, &key, &fire_at) {
+            timers.pop();
+            continue;
+        }
+        if fire_at > now {
+            break;
+        }
+        timers.pop();
+        due.push(key);
+    }
+    due
+}
+
+fn is_timer_current(
+    triggers: &HashMap<String, InMemoryTrigger>,
+    scheduled_runs: &HashMap<String, ScheduledRun>,
+    key: &TimerKey,
+    fire_at: &DateTime<Utc>,
+) -> bool {
+    match key {
+        TimerKey::Cron(id) => triggers
+            .get(id)
+            .map(|trigger| trigger.next_fire == Some(*fire_at))
+            .unwrap_or(false),
+        TimerKey::ScheduledRun(id) => scheduled_runs
+            .get(id)
+            .map(|run| run.run_at == *fire_at)
+            .unwrap_or(false),
+    }
+}
+
+//Rows fetched per request when loading published flow versions
+const FLOW_VERSION_PAGE_SIZE: usize = 500;
+
+//Fetches one page of published versions for active flows, optionally limited to a single workflow
+async fn fetch_published_flow_versions(
+    client: &Postgrest,
+    workflow_id: Option<&str>,
+    offset: usize,
+) -> Result<Vec<DatabaseFlowVersion>, Box<dyn std::error::Error + Send + Sync>> {
     dotenv().ok();
     let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
         .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
 
-    //Get current published workflow version
-    let response = client
+    let mut query = client
         .from("flow_versions")
-        .auth(supabase_service_role_api_key.clone())
-        .select("*, flows!inner(active)") // TODO: only fetch active flows
+        .auth(supabase_service_role_api_key)
+        .select("*, flows!inner(active)")
         .eq("published", "true")
-        .eq("flows.active", "true")
+        .eq("flows.active", "true");
+
+    if let Some(workflow_id) = workflow_id {
+        query = query.eq("flow_id", workflow_id);
+    }
+
+    let response = query
+        .order("flow_version_id.asc")
+        .range(offset, offset + FLOW_VERSION_PAGE_SIZE - 1)
         .execute()
         .await?;
 
     let body = response.text().await?;
     let flow_versions: Vec<DatabaseFlowVersion> = serde_json::from_str(&body)?;
+    Ok(flow_versions)
+}
+
+//Lightly update triggers for a single workflow so we don't need to refresh the entire memory each time we update something
+//Removes the workflow's triggers when it has been unpublished, deactivated or deleted
+async fn update_triggers_for_workflow(
+    state: &Arc<AppState>,
+    client: &Postgrest,
+    triggers: &Arc<RwLock<HashMap<String, InMemoryTrigger>>>,
+    workflow_id: &String,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!(
+        "[TRIGGER_ENGINE] Updating triggers for workflow: {}",
+        workflow_id
+    );
+
+    //Only one version of a workflow is published at a time so a single page is enough
+    let flow_versions = fetch_published_flow_versions(client, Some(workflow_id), 0).await?;
 
     let mut new_triggers = HashMap::new();
 
-    //Add new triggers to new_triggers
     for flow_version in flow_versions {
         let triggers_from_flow =
             create_in_memory_triggers_from_flow_definition(state.clone(), &flow_version, client)
@@ -147,20 +353,29 @@ async fn update_triggers_for_workflow(
     //Delete Existing trigger for workflow_id from hashmap
     //Can't just overwrite because the workflow update may have removed the trigger completely.
     let mut triggers = triggers.write().await;
-    let old_value = triggers.remove(workflow_id);
-    if let Some(old_trigger) = old_value {
-        println!("[TRIGGER_ENGINE] Removing old trigger: {:?}", old_trigger);
+    let old_trigger = triggers.remove(workflow_id);
+    if new_triggers.is_empty() {
+        if let Some(old_trigger) = old_trigger {
+            println!("[TRIGGER_ENGINE] Removing old trigger: {:?}", old_trigger);
+        }
     }
-    //Write new riggers in memory for workflow_id
-    // let mut triggers = triggers.write().await;
+
     for (id, trigger) in new_triggers.into_iter() {
-        //Write New Trigger
-        let old_value = triggers.insert(id, trigger);
-        if let Some(old_trigger) = old_value {
-            println!("[TRIGGER_ENGINE] Replaced old trigger: {:?}", old_trigger);
+        //Keep the schedule we already have if the cron expression did not change
+        let trigger = match &old_trigger {
+            Some(old) if old.cron_expression == trigger.cron_expression => InMemoryTrigger {
+                last_fired: old.last_fired,
+                next_fire: old.next_fire,
+                ..trigger
+            },
+            _ => trigger,
+        };
+        if old_trigger.is_some() {
+            println!("[TRIGGER_ENGINE] Replaced old trigger for workflow: {}", id);
         } else {
-            println!("[TRIGGER_ENGINE] Added new trigger");
+            println!("[TRIGGER_ENGINE] Added new trigger for workflow: {}", id);
         }
+        triggers.insert(id, trigger);
     }
 
     println!(
@@ -177,47 +392,22 @@ pub async fn hydrate_triggers(
 ) {
     println!("[TRIGGER_ENGINE] Hydrating triggers from the database");
 
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-
-    let response = match client //TODO: pagination for large number of triggers
-        .from("flow_versions")
-        .auth(supabase_service_role_api_key.clone())
-        .select("*, flows!inner(active)") // TODO: only fetch active flows
-        .eq("published", "true")
-        .eq("flows.active", "true")
-        .execute()
-        .await
-    {
-        Ok(response) => response,
-        Err(e) => {
-            println!("[TRIGGER_ENGINE] Error fetching flow versions: {:?}", e);
-            return;
-        }
-    };
-
-    let body = match response.text().await {
-        Ok(body) => {
-            // println!(
-            //     "[TRIGGER_ENGINE] Response body for active and published triggers: {}",
-            //     body
-            // );
-            body
-        }
-        Err(e) => {
-            println!("[TRIGGER_ENGINE] Error reading response body: {:?}", e);
-            return;
-        }
-    };
-
-    let flow_versions: Vec<DatabaseFlowVersion> = match serde_json::from_str(&body) {
-        Ok(flow_versions) => flow_versions,
-        Err(e) => {
-            println!("[TRIGGER_ENGINE] Error parsing JSON: {:?}", e);
-            return;
+    //Page through published versions so large installs don't load everything in one response
+    let mut flow_versions: Vec<DatabaseFlowVersion> = Vec::new();
+    loop {
+        let page = match fetch_published_flow_versions(client, None, flow_versions.len()).await {
+            Ok(page) => page,
+            Err(e) => {
+                println!("[TRIGGER_ENGINE] Error fetching flow versions: {:?}", e);
+                return;
+            }
+        };
+        let page_len = page.len();
+        flow_versions.extend(page);
+        if page_len < FLOW_VERSION_PAGE_SIZE {
+            break;
         }
-    };
+    }
 
     println!(
         "[TRIGGER_ENGINE] Found flow_versions vector: {}",
@@ -301,7 +491,7 @@ async fn update_trigger_last_run(
     id: &str,
     trigger: &InMemoryTrigger,
     triggers: &Arc<RwLock<HashMap<String, InMemoryTrigger>>>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error + Send + Sync>> {
     println!("[TRIGGER_ENGINE] Updating trigger last run and next_run time");
 
     let new_next_fire = match Schedule::from_str(&trigger.cron_expression) {
@@ -328,7 +518,7 @@ async fn update_trigger_last_run(
     triggers.insert(id.to_string(), updated_trigger);
     println!("[TRIGGER_ENGINE] Successfully updated trigger last run and next_run time");
 
-    Ok(())
+    Ok(new_next_fire)
 }
 
 async fn create_trigger_task(
@@ -337,6 +527,20 @@ async fn create_trigger_task(
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     println!("[CRON TRIGGER] Handling create task from cron trigger");
 
+    let result = serde_json::json!({
+        "message": format!("Successfully triggered task"),
+        "created_at": Utc::now()
+    });
+
+    create_trigger_task_with_result(state, trigger, result).await
+}
+
+//Starts a flow session for an in memory trigger with the given trigger result
+pub async fn create_trigger_task_with_result(
+    state: &Arc<AppState>,
+    trigger: &InMemoryTrigger,
+    result: Value,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let input = CreateTaskInput {
         account_id: trigger.account_id.clone(),
         task_status: TaskStatus::Running.as_str().to_string(),
@@ -354,10 +558,7 @@ async fn create_trigger_task(
         plugin_version: trigger.plugin_version.clone(),
         stage: Stage::Production.as_str().to_string(),
         config: trigger.config.clone(),
-        result: Some(serde_json::json!({
-            "message": format!("Successfully triggered task"),
-            "created_at": Utc::now()
-        })),
+        result: Some(result),
         error: None,
         test_config: None,
         processing_order: 0,
@@ -415,8 +616,10 @@ pub async fn create_in_memory_triggers_from_flow_definition(
             action.r#type.clone(),
             action.action_id.clone(),
         );
+        //Polling triggers run on the same cron schedule as cron triggers
         if r#type == ActionType::Trigger
-            && plugin_name == PluginName::new("@anything/cron".to_string()).unwrap()
+            && (plugin_name == PluginName::new("@anything/cron".to_string()).unwrap()
+                || plugin_name == PluginName::new(POLLING_PLUGIN_NAME.to_string()).unwrap())
         {
             println!(
                 "[TRIGGER ENGINE] Processing trigger action with ID: {}",
@@ -446,6 +649,7 @@ pub async fn create_in_memory_triggers_from_flow_definition(
                 client,
                 &account_id,
                 &Uuid::new_v4().to_string(),
+                &SecretScope::for_flow_version(flow_version),
                 Some(&inputs.clone().unwrap()),
                 Some(&inputs_schema.clone().unwrap()),
                 Some(&plugin_config.clone()),
@@ -9,8 +9,9 @@ use serde_json::Value;
 use std::sync::Arc;
 
 use crate::{
-    bundler::bundle_cached_inputs,
+    bundler::{bundle_cached_inputs, secrets::SecretScope},
     supabase_jwt_middleware::User,
+    system_plugins::javascript::typescript::inputs_declaration,
     types::{
         task_types::Task,
         workflow_types::{DatabaseFlowVersion, WorkflowVersionDefinition},
@@ -317,6 +318,9 @@ pub async fn get_flow_version_variables(
         }
     };
 
+    //Render with the secrets this workflow would see when it runs
+    let secret_scope = SecretScope::for_flow_version(&flow_version);
+
     // Parse the flow definition into a Workflow struct
     let workflow: WorkflowVersionDefinition = flow_version.flow_definition;
 
@@ -357,15 +361,19 @@ pub async fn get_flow_version_variables(
         client,
         &account_id,
         &session_id,
+        &secret_scope,
         Some(&variables.clone().unwrap()),
         Some(&variables_schema.clone().unwrap()),
         // Some(&input),
         // Some(&input_schema),
         false,
+        None,
+        None, //previews aren't workflow reads, values are redacted below
     )
     .await
     {
-        Ok(vars) => vars,
+        //The explorer only shows the values, secrets stay hidden
+        Ok((vars, redactor)) => redactor.redact_value(&vars),
         Err(_e) => return Json(serde_json::Value::Null).into_response(),
     };
 
@@ -378,10 +386,24 @@ pub async fn get_flow_version_variables(
     //TODO: build some sort of tool that can "smell out" if the same api endpoint is being hit? like parse the urls to know its the same endpoint across users etc
     //Store this metadata somewhere usefull
 
+    //The code editor types the injected `inputs` global with this
+    let inputs_declaration = if action.plugin_name.as_str() == "@anything/javascript" {
+        let inputs_schema = variables_schema
+            .as_ref()
+            .and_then(|schema| serde_json::to_value(schema).ok());
+        Some(inputs_declaration(
+            Some(&rendered_variables),
+            inputs_schema.as_ref(),
+        ))
+    } else {
+        None
+    };
+
     //Returning both so we can show the keys no matter what if the bundling fails we can still show top level keys
     let response = serde_json::json!({
         "variables": variables,
-        "rendered_variables": rendered_variables
+        "rendered_variables": rendered_variables,
+        "inputs_declaration": inputs_declaration
     });
 
     println!("[VARIABLES] Returning response");
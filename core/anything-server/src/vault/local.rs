@@ -0,0 +1,492 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::future::BoxFuture;
+use postgrest::Postgrest;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use dotenv::dotenv;
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use uuid::Uuid;
+
+use super::{SecretVault, VaultResult};
+
+//Master key as base64 (32 bytes) or a path to a file holding it
+const MASTER_KEY_ENV: &str = "ANYTHING_VAULT_MASTER_KEY";
+const MASTER_KEY_FILE_ENV: &str = "ANYTHING_VAULT_MASTER_KEY_FILE";
+//Key being rotated away from, only needed until rotate-vault-key has run
+const PREVIOUS_MASTER_KEY_ENV: &str = "ANYTHING_VAULT_PREVIOUS_MASTER_KEY";
+const PREVIOUS_MASTER_KEY_FILE_ENV: &str = "ANYTHING_VAULT_PREVIOUS_MASTER_KEY_FILE";
+
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+const ROTATION_BATCH_SIZE: usize = 500;
+
+#[derive(Clone)]
+pub struct MasterKey {
+    id: String, // fingerprint stored next to every data key it wraps
+    key: Key<Aes256Gcm>,
+}
+
+impl MasterKey {
+    pub fn from_base64(encoded: &str) -> VaultResult<Self> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("Vault master key is not valid base64: {}", e))?;
+        if bytes.len() != KEY_LENGTH {
+            return Err(format!(
+                "Vault master key must be {} bytes, got {}",
+                KEY_LENGTH,
+                bytes.len()
+            )
+            .into());
+        }
+        Ok(Self {
+            id: hex::encode(&Sha256::digest(&bytes)[..8]),
+            key: Key::<Aes256Gcm>::clone_from_slice(&bytes),
+        })
+    }
+
+    //The value itself wins over the file when both are set, empty means unset
+    fn load(key_env: &str, file_env: &str) -> VaultResult<Option<Self>> {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.trim().is_empty());
+        if let Some(encoded) = var(key_env) {
+            return Self::from_base64(&encoded).map(Some);
+        }
+        if let Some(path) = var(file_env) {
+            let encoded = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read vault master key file {}: {}", path, e))?;
+            return Self::from_base64(&encoded).map(Some);
+        }
+        Ok(None)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.key)
+    }
+}
+
+//Never print key material
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey").field("id", &self.id).finish()
+    }
+}
+
+//Envelope encrypted value. The value is sealed with its own random data key and only the
+//data key is sealed with the master key, so rotating the master key never touches values.
+//Both are bound to the secret id so ciphertext can't be moved between rows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncryptedSecret {
+    pub ciphertext: String,
+    pub nonce: String,
+    pub encrypted_data_key: String,
+    pub data_key_nonce: String,
+    pub master_key_id: String,
+}
+
+pub fn seal(master: &MasterKey, secret_id: &str, plaintext: &str) -> VaultResult<EncryptedSecret> {
+    let data_key = Aes256Gcm::generate_key(OsRng);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(&data_key)
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: secret_id.as_bytes(),
+            },
+        )
+        .map_err(|_| "Failed to encrypt secret")?;
+
+    let (encrypted_data_key, data_key_nonce) = wrap_data_key(master, secret_id, &data_key)?;
+
+    Ok(EncryptedSecret {
+        ciphertext: STANDARD.encode(ciphertext),
+        nonce: STANDARD.encode(nonce),
+        encrypted_data_key,
+        data_key_nonce,
+        master_key_id: master.id.clone(),
+    })
+}
+
+pub fn open(master: &MasterKey, secret_id: &str, secret: &EncryptedSecret) -> VaultResult<String> {
+    let data_key = unwrap_data_key(master, secret_id, secret)?;
+    let plaintext = Aes256Gcm::new(&data_key)
+        .decrypt(
+            &decode_nonce(&secret.nonce)?,
+            Payload {
+                msg: &STANDARD.decode(&secret.ciphertext)?,
+                aad: secret_id.as_bytes(),
+            },
+        )
+        .map_err(|_| "Failed to decrypt secret")?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+//Re-seals only the data key under a new master key, the ciphertext is kept as is
+pub fn rewrap(
+    from: &MasterKey,
+    to: &MasterKey,
+    secret_id: &str,
+    secret: &EncryptedSecret,
+) -> VaultResult<EncryptedSecret> {
+    let data_key = unwrap_data_key(from, secret_id, secret)?;
+    let (encrypted_data_key, data_key_nonce) = wrap_data_key(to, secret_id, &data_key)?;
+    Ok(EncryptedSecret {
+        encrypted_data_key,
+        data_key_nonce,
+        master_key_id: to.id.clone(),
+        ..secret.clone()
+    })
+}
+
+fn wrap_data_key(
+    master: &MasterKey,
+    secret_id: &str,
+    data_key: &Key<Aes256Gcm>,
+) -> VaultResult<(String, String)> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let encrypted = master
+        .cipher()
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: data_key.as_slice(),
+                aad: secret_id.as_bytes(),
+            },
+        )
+        .map_err(|_| "Failed to wrap data key")?;
+    Ok((STANDARD.encode(encrypted), STANDARD.encode(nonce)))
+}
+
+fn unwrap_data_key(
+    master: &MasterKey,
+    secret_id: &str,
+    secret: &EncryptedSecret,
+) -> VaultResult<Key<Aes256Gcm>> {
+    if secret.master_key_id != master.id {
+        return Err(format!(
+            "Secret {} is wrapped by master key {}, not {}",
+            secret_id, secret.master_key_id, master.id
+        )
+        .into());
+    }
+    let data_key = master
+        .cipher()
+        .decrypt(
+            &decode_nonce(&secret.data_key_nonce)?,
+            Payload {
+                msg: &STANDARD.decode(&secret.encrypted_data_key)?,
+                aad: secret_id.as_bytes(),
+            },
+        )
+        .map_err(|_| "Failed to unwrap data key, wrong master key?")?;
+    if data_key.len() != KEY_LENGTH {
+        return Err("Unwrapped data key has the wrong length".into());
+    }
+    Ok(Key::<Aes256Gcm>::clone_from_slice(&data_key))
+}
+
+fn decode_nonce(encoded: &str) -> VaultResult<Nonce<Aes256Gcm>> {
+    let bytes = STANDARD.decode(encoded)?;
+    if bytes.len() != NONCE_LENGTH {
+        return Err("Nonce has the wrong length".into());
+    }
+    Ok(Nonce::<Aes256Gcm>::clone_from_slice(&bytes))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LocalVaultRow {
+    id: String,
+    #[serde(flatten)]
+    secret: EncryptedSecret,
+}
+
+//Secrets encrypted by the server and stored in anything.local_vault_secrets
+#[derive(Debug, Clone)]
+pub struct LocalVault {
+    master: MasterKey,
+    previous: Option<MasterKey>,
+}
+
+impl LocalVault {
+    pub fn new(master: MasterKey, previous: Option<MasterKey>) -> Self {
+        Self { master, previous }
+    }
+
+    pub fn from_env() -> VaultResult<Self> {
+        dotenv().ok();
+        let master = MasterKey::load(MASTER_KEY_ENV, MASTER_KEY_FILE_ENV)?
+            .ok_or_else(|| format!("{} or {} must be set", MASTER_KEY_ENV, MASTER_KEY_FILE_ENV))?;
+        let previous = MasterKey::load(PREVIOUS_MASTER_KEY_ENV, PREVIOUS_MASTER_KEY_FILE_ENV)?;
+        Ok(Self::new(master, previous))
+    }
+
+    //Values wrapped by the previous key stay readable until rotation has run
+    fn key_for(&self, master_key_id: &str) -> VaultResult<&MasterKey> {
+        if self.master.id == master_key_id {
+            return Ok(&self.master);
+        }
+        match &self.previous {
+            Some(previous) if previous.id == master_key_id => Ok(previous),
+            _ => Err(format!("Unknown vault master key: {}", master_key_id).into()),
+        }
+    }
+
+    pub fn decrypt(&self, secret_id: &str, secret: &EncryptedSecret) -> VaultResult<String> {
+        open(self.key_for(&secret.master_key_id)?, secret_id, secret)
+    }
+}
+
+fn service_role_key() -> VaultResult<String> {
+    dotenv().ok();
+    Ok(env::var("SUPABASE_SERVICE_ROLE_API_KEY")?)
+}
+
+//Postgrest answers with {code, message} when a statement fails
+fn check_response(body: &str) -> VaultResult<Value> {
+    if body.trim().is_empty() {
+        return Ok(Value::Null);
+    }
+    let json_response: Value = serde_json::from_str(body)?;
+    if let Some(error) = json_response.get("code") {
+        let error_code = error.as_str().unwrap_or("Unknown");
+        let error_message = json_response["message"].as_str().unwrap_or("Unknown error");
+
+        println!(
+            "[VAULT] Error in response - code: {}, message: {}",
+            error_code, error_message
+        );
+
+        if error_code == "23505" {
+            return Err(format!("Duplicate key error: {}", error_message).into());
+        }
+        return Err(format!("Database error: {} - {}", error_code, error_message).into());
+    }
+    Ok(json_response)
+}
+
+impl SecretVault for LocalVault {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn insert_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_name: &'a str,
+        secret_value: &'a str,
+        description: &'a str,
+    ) -> BoxFuture<'a, VaultResult<String>> {
+        Box::pin(async move {
+            let secret_id = Uuid::new_v4().to_string();
+            let secret = seal(&self.master, &secret_id, secret_value)?;
+
+            let mut row = serde_json::to_value(LocalVaultRow {
+                id: secret_id.clone(),
+                secret,
+            })?;
+            row["name"] = Value::String(secret_name.to_string());
+            row["description"] = Value::String(description.to_string());
+
+            let response = client
+                .from("local_vault_secrets")
+                .auth(service_role_key()?)
+                .insert(row.to_string())
+                .execute()
+                .await?;
+
+            check_response(&response.text().await?)?;
+
+            println!(
+                "[VAULT] Successfully inserted secret with vault_id: {}",
+                secret_id
+            );
+
+            Ok(secret_id)
+        })
+    }
+
+    fn update_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_id: &'a str,
+        new_secret_value: &'a str,
+    ) -> BoxFuture<'a, VaultResult<()>> {
+        Box::pin(async move {
+            //New data key on every write
+            let secret = seal(&self.master, secret_id, new_secret_value)?;
+
+            let response = client
+                .from("local_vault_secrets")
+                .auth(service_role_key()?)
+                .eq("id", secret_id)
+                .update(serde_json::to_string(&secret)?)
+                .execute()
+                .await?;
+
+            let updated = check_response(&response.text().await?)?;
+            if updated.as_array().map_or(false, |rows| rows.is_empty()) {
+                return Err(format!("Secret {} not found in vault", secret_id).into());
+            }
+
+            println!("[VAULT] Successfully updated secret");
+
+            Ok(())
+        })
+    }
+
+    fn read_secrets<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_ids: &'a [String],
+    ) -> BoxFuture<'a, VaultResult<HashMap<String, String>>> {
+        Box::pin(async move {
+            let response = client
+                .from("local_vault_secrets")
+                .auth(service_role_key()?)
+                .in_("id", secret_ids)
+                .select("id,ciphertext,nonce,encrypted_data_key,data_key_nonce,master_key_id")
+                .execute()
+                .await?;
+
+            let rows: Vec<LocalVaultRow> =
+                serde_json::from_value(check_response(&response.text().await?)?)?;
+
+            let mut values = HashMap::with_capacity(rows.len());
+            for row in rows {
+                //One row under a rotated away master key shouldn't fail every secret of the
+                //account, callers already skip secrets without a value
+                match self.decrypt(&row.id, &row.secret) {
+                    Ok(value) => {
+                        values.insert(row.id, value);
+                    }
+                    Err(e) => println!("[VAULT] Skipping secret {}: {}", row.id, e),
+                }
+            }
+            Ok(values)
+        })
+    }
+
+    fn delete_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_id: &'a str,
+    ) -> BoxFuture<'a, VaultResult<()>> {
+        Box::pin(async move {
+            let response = client
+                .from("local_vault_secrets")
+                .auth(service_role_key()?)
+                .eq("id", secret_id)
+                .delete()
+                .execute()
+                .await?;
+
+            check_response(&response.text().await?)?;
+            Ok(())
+        })
+    }
+}
+
+//Backs `anything-server rotate-vault-key`. Run it after setting the new key as the master key
+//and the old one as the previous key. Every data key still wrapped by the previous key is
+//re-wrapped under the master key, values are never decrypted. Returns how many were moved.
+pub async fn rotate_master_key(client: &Postgrest) -> VaultResult<usize> {
+    let vault = LocalVault::from_env()?;
+    let previous = vault.previous.as_ref().ok_or_else(|| {
+        format!(
+            "{} or {} must be set to the key being rotated away from",
+            PREVIOUS_MASTER_KEY_ENV, PREVIOUS_MASTER_KEY_FILE_ENV
+        )
+    })?;
+    println!(
+        "[VAULT] Rotating vault master key {} -> {}",
+        previous.id, vault.master.id
+    );
+
+    let mut rotated = 0;
+    loop {
+        //Rotated rows drop out of the filter so every batch starts from the top
+        let response = client
+            .from("local_vault_secrets")
+            .auth(service_role_key()?)
+            .neq("master_key_id", &vault.master.id)
+            .select("id,ciphertext,nonce,encrypted_data_key,data_key_nonce,master_key_id")
+            .limit(ROTATION_BATCH_SIZE)
+            .execute()
+            .await?;
+
+        let rows: Vec<LocalVaultRow> =
+            serde_json::from_value(check_response(&response.text().await?)?)?;
+        if rows.is_empty() {
+            break;
+        }
+
+        for row in rows {
+            let from = vault.key_for(&row.secret.master_key_id)?;
+            let secret = rewrap(from, &vault.master, &row.id, &row.secret)?;
+
+            let response = client
+                .from("local_vault_secrets")
+                .auth(service_role_key()?)
+                .eq("id", &row.id)
+                .update(
+                    serde_json::json!({
+                        "encrypted_data_key": secret.encrypted_data_key,
+                        "data_key_nonce": secret.data_key_nonce,
+                        "master_key_id": secret.master_key_id,
+                    })
+                    .to_string(),
+                )
+                .execute()
+                .await?;
+            check_response(&response.text().await?)?;
+            rotated += 1;
+        }
+
+        println!("[VAULT] Re-wrapped {} secrets so far", rotated);
+    }
+
+    Ok(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master_key() -> MasterKey {
+        MasterKey::from_base64(&STANDARD.encode(Aes256Gcm::generate_key(OsRng))).unwrap()
+    }
+
+    #[test]
+    fn envelope_encryption_survives_key_rotation() {
+        let old_key = master_key();
+        let new_key = master_key();
+        let secret_id = Uuid::new_v4().to_string();
+
+        let sealed = seal(&old_key, &secret_id, "sk_live_123").unwrap();
+        assert!(!sealed.ciphertext.contains("sk_live_123"));
+        assert_eq!(sealed.master_key_id, old_key.id);
+        assert_eq!(open(&old_key, &secret_id, &sealed).unwrap(), "sk_live_123");
+        //Ciphertext is bound to its row
+        assert!(open(&old_key, &Uuid::new_v4().to_string(), &sealed).is_err());
+
+        let rotated = rewrap(&old_key, &new_key, &secret_id, &sealed).unwrap();
+        assert_eq!(rotated.ciphertext, sealed.ciphertext);
+        assert_eq!(rotated.master_key_id, new_key.id);
+        assert!(open(&old_key, &secret_id, &rotated).is_err());
+
+        //Both generations stay readable while a rotation is in flight
+        let vault = LocalVault::new(new_key, Some(old_key));
+        assert_eq!(vault.decrypt(&secret_id, &rotated).unwrap(), "sk_live_123");
+        assert_eq!(vault.decrypt(&secret_id, &sealed).unwrap(), "sk_live_123");
+
+        assert!(MasterKey::from_base64(&STANDARD.encode([0u8; 16])).is_err());
+    }
+}
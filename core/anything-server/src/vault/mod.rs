@@ -1,30 +1,75 @@
+use futures::future::BoxFuture;
 use postgrest::Postgrest;
-use serde_json::Value;
 
 use dotenv::dotenv;
 
-use serde::{Deserialize, Serialize};
-
+use std::collections::HashMap;
 use std::env;
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ReadVaultSecretInput {
-    secret_id: String,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct CreateSecretInput {
-    name: String,
-    secret: String,
-    description: String,
+use std::sync::{Arc, OnceLock};
+
+pub mod local;
+pub mod supabase;
+
+pub use local::LocalVault;
+pub use supabase::SupabaseVault;
+
+pub type VaultResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+//Encrypted storage for secret values. Rows that own a secret only keep the id handed
+//back by insert_secret.
+pub trait SecretVault: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn insert_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_name: &'a str,
+        secret_value: &'a str,
+        description: &'a str,
+    ) -> BoxFuture<'a, VaultResult<String>>;
+
+    fn update_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_id: &'a str,
+        new_secret_value: &'a str,
+    ) -> BoxFuture<'a, VaultResult<()>>;
+
+    //Decrypted values by id, ids that don't exist are left out
+    fn read_secrets<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_ids: &'a [String],
+    ) -> BoxFuture<'a, VaultResult<HashMap<String, String>>>;
+
+    fn delete_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_id: &'a str,
+    ) -> BoxFuture<'a, VaultResult<()>>;
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct UpdateSecretInput {
-    id: String,
-    secret: String,
-    name: String,
-    description: String,
+static SECRET_VAULT: OnceLock<Arc<dyn SecretVault>> = OnceLock::new();
+
+//Backend is picked once from ANYTHING_VAULT_BACKEND, "supabase" (default) or "local"
+pub fn secret_vault() -> Arc<dyn SecretVault> {
+    SECRET_VAULT
+        .get_or_init(|| {
+            dotenv().ok();
+            let vault: Arc<dyn SecretVault> = match env::var("ANYTHING_VAULT_BACKEND")
+                .unwrap_or_default()
+                .as_str()
+            {
+                "local" => Arc::new(
+                    LocalVault::from_env()
+                        .unwrap_or_else(|e| panic!("Failed to load local vault: {}", e)),
+                ),
+                _ => Arc::new(SupabaseVault),
+            };
+            println!("[VAULT] Using {} secret vault", vault.name());
+            vault
+        })
+        .clone()
 }
 
 pub async fn insert_secret_to_vault(
@@ -32,9 +77,9 @@ pub async fn insert_secret_to_vault(
     secret_name: &str,
     secret_value: &str,
     description: &str,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+) -> VaultResult<String> {
     println!("[VAULT] Starting insert_secret_to_vault");
-    
+
     // Validate secret value is not empty or whitespace-only
     if secret_value.trim().is_empty() {
         println!("[VAULT] Error: Secret value cannot be empty or whitespace-only");
@@ -47,67 +92,20 @@ pub async fn insert_secret_to_vault(
         return Err("Secret name cannot be empty".into());
     }
 
-    println!("[VAULT] Loading environment variables");
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-
-    let secret_input = CreateSecretInput {
-        name: secret_name.to_string(),
-        secret: secret_value.to_string(), // Use original value, we've already validated it contains non-whitespace
-        description: description.to_string(),
-    };
-
-    println!("[VAULT] Making RPC call to insert_secret with input: {:?}", secret_input);
-
-    let response = client
-        .rpc(
-            "insert_secret",
-            serde_json::to_string(&secret_input).unwrap(),
-        )
-        .auth(supabase_service_role_api_key)
-        .execute()
-        .await?;
-
-    let body = response.text().await?;
-
-    println!("[VAULT] Response from vault insert: {:?}", body);
-
-    // Parse the response body as JSON
-    let json_response: Value = serde_json::from_str(&body)?;
-
-    // Check if there's an error in the response
-    if let Some(error) = json_response.get("code") {
-        let error_code = error.as_str().unwrap_or("Unknown");
-        let error_message = json_response["message"].as_str().unwrap_or("Unknown error");
-
-        println!("[VAULT] Error in response - code: {}, message: {}", error_code, error_message);
-
-        if error_code == "23505" {
-            return Err(format!("Duplicate key error: {}", error_message).into());
-        } else {
-            return Err(format!("Database error: {} - {}", error_code, error_message).into());
-        }
-    }
-
-    // If no error, extract the secret_vault_id
-    let secret_vault_id = json_response
-        .as_str()
-        .ok_or("Invalid response format")?
-        .trim_matches('"')
-        .to_string();
-
-    println!("[VAULT] Successfully inserted secret with vault_id: {}", secret_vault_id);
-
-    Ok(secret_vault_id)
+    secret_vault()
+        .insert_secret(client, secret_name, secret_value, description)
+        .await
 }
 
 pub async fn update_secret_in_vault(
     client: &Postgrest,
     secret_id: &str,
     new_secret_value: &str,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    println!("[VAULT] Starting update_secret_in_vault for secret_id: {}", secret_id);
+) -> VaultResult<()> {
+    println!(
+        "[VAULT] Starting update_secret_in_vault for secret_id: {}",
+        secret_id
+    );
 
     // Validate new secret value is not empty or whitespace-only
     if new_secret_value.trim().is_empty() {
@@ -121,61 +119,25 @@ pub async fn update_secret_in_vault(
         return Err("Secret ID cannot be empty".into());
     }
 
-    println!("[VAULT] Loading environment variables");
-    dotenv().ok();
-    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
-        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
-
-    let read_secret_input = ReadVaultSecretInput {
-        secret_id: secret_id.to_string(),
-    };
-    
-    println!("[VAULT] Fetching existing secret details");
-    //TODO: fetch existing secret to populate name and description
-    // Read Secret in Vault
-    let response = client
-        .rpc(
-            "read_secret",
-            serde_json::to_string(&read_secret_input).unwrap(),
-        )
-        .auth(supabase_service_role_api_key.clone()) //Need to put service role key here I guess for it to show up current_setting in sql function
-        .execute()
-        .await?;
-
-    let vault_secret_body = response.text().await?;
-
-    println!("[VAULT] Existing secret details response: {:?}", vault_secret_body);
-
-    let vault_secret_json: serde_json::Value = serde_json::from_str(&vault_secret_body).unwrap();
-    let secret_name = vault_secret_json[0]["name"].as_str().unwrap_or_default();
-    let secret_description = vault_secret_json[0]["description"]
-        .as_str()
-        .unwrap_or_default();
-
-    println!("[VAULT] Retrieved existing secret name: {}", secret_name);
-
-    let update_secret_input = UpdateSecretInput {
-        id: secret_id.to_string(),
-        secret: new_secret_value.to_string(), // Use original value, we've already validated it contains non-whitespace
-        name: secret_name.to_string(),
-        description: secret_description.to_string(),
-    };
-
-    println!("[VAULT] Making RPC call to update_secret with input: {:?}", update_secret_input);
-
-    let response = client
-        .rpc(
-            "update_secret",
-            serde_json::to_string(&update_secret_input).unwrap(),
-        )
-        .auth(supabase_service_role_api_key)
-        .execute()
-        .await?;
-
-    let body = response.text().await?;
-
-    println!("[VAULT] Response from vault update: {:?}", body);
-    println!("[VAULT] Successfully updated secret");
-
-    Ok(())
+    secret_vault()
+        .update_secret(client, secret_id, new_secret_value)
+        .await
+}
+
+pub async fn read_secrets_from_vault(
+    client: &Postgrest,
+    secret_ids: &[String],
+) -> VaultResult<HashMap<String, String>> {
+    if secret_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    secret_vault().read_secrets(client, secret_ids).await
+}
+
+pub async fn delete_secret_from_vault(client: &Postgrest, secret_id: &str) -> VaultResult<()> {
+    println!(
+        "[VAULT] Starting delete_secret_from_vault for secret_id: {}",
+        secret_id
+    );
+    secret_vault().delete_secret(client, secret_id).await
 }
@@ -0,0 +1,276 @@
+use futures::future::BoxFuture;
+use postgrest::Postgrest;
+use serde_json::Value;
+
+use dotenv::dotenv;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::env;
+
+use super::{SecretVault, VaultResult};
+
+//Secrets live in Supabase Vault (pgsodium) and are decrypted by SQL functions
+pub struct SupabaseVault;
+
+impl SecretVault for SupabaseVault {
+    fn name(&self) -> &'static str {
+        "supabase"
+    }
+
+    fn insert_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_name: &'a str,
+        secret_value: &'a str,
+        description: &'a str,
+    ) -> BoxFuture<'a, VaultResult<String>> {
+        Box::pin(insert_secret(
+            client,
+            secret_name,
+            secret_value,
+            description,
+        ))
+    }
+
+    fn update_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_id: &'a str,
+        new_secret_value: &'a str,
+    ) -> BoxFuture<'a, VaultResult<()>> {
+        Box::pin(update_secret(client, secret_id, new_secret_value))
+    }
+
+    fn read_secrets<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_ids: &'a [String],
+    ) -> BoxFuture<'a, VaultResult<HashMap<String, String>>> {
+        Box::pin(read_secrets(client, secret_ids))
+    }
+
+    fn delete_secret<'a>(
+        &'a self,
+        client: &'a Postgrest,
+        secret_id: &'a str,
+    ) -> BoxFuture<'a, VaultResult<()>> {
+        Box::pin(delete_secret(client, secret_id))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReadVaultSecretInput {
+    secret_id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateSecretInput {
+    name: String,
+    secret: String,
+    description: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UpdateSecretInput {
+    id: String,
+    secret: String,
+    name: String,
+    description: String,
+}
+
+async fn insert_secret(
+    client: &Postgrest,
+    secret_name: &str,
+    secret_value: &str,
+    description: &str,
+) -> VaultResult<String> {
+    println!("[VAULT] Loading environment variables");
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let secret_input = CreateSecretInput {
+        name: secret_name.to_string(),
+        secret: secret_value.to_string(), // Use original value, we've already validated it contains non-whitespace
+        description: description.to_string(),
+    };
+
+    println!(
+        "[VAULT] Making RPC call to insert_secret with input: {:?}",
+        secret_input
+    );
+
+    let response = client
+        .rpc(
+            "insert_secret",
+            serde_json::to_string(&secret_input).unwrap(),
+        )
+        .auth(supabase_service_role_api_key)
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+
+    println!("[VAULT] Response from vault insert: {:?}", body);
+
+    // Parse the response body as JSON
+    let json_response: Value = serde_json::from_str(&body)?;
+
+    // Check if there's an error in the response
+    if let Some(error) = json_response.get("code") {
+        let error_code = error.as_str().unwrap_or("Unknown");
+        let error_message = json_response["message"].as_str().unwrap_or("Unknown error");
+
+        println!(
+            "[VAULT] Error in response - code: {}, message: {}",
+            error_code, error_message
+        );
+
+        if error_code == "23505" {
+            return Err(format!("Duplicate key error: {}", error_message).into());
+        } else {
+            return Err(format!("Database error: {} - {}", error_code, error_message).into());
+        }
+    }
+
+    // If no error, extract the secret_vault_id
+    let secret_vault_id = json_response
+        .as_str()
+        .ok_or("Invalid response format")?
+        .trim_matches('"')
+        .to_string();
+
+    println!(
+        "[VAULT] Successfully inserted secret with vault_id: {}",
+        secret_vault_id
+    );
+
+    Ok(secret_vault_id)
+}
+
+async fn update_secret(
+    client: &Postgrest,
+    secret_id: &str,
+    new_secret_value: &str,
+) -> VaultResult<()> {
+    println!("[VAULT] Loading environment variables");
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")
+        .expect("SUPABASE_SERVICE_ROLE_API_KEY must be set");
+
+    let read_secret_input = ReadVaultSecretInput {
+        secret_id: secret_id.to_string(),
+    };
+
+    println!("[VAULT] Fetching existing secret details");
+    //TODO: fetch existing secret to populate name and description
+    // Read Secret in Vault
+    let response = client
+        .rpc(
+            "read_secret",
+            serde_json::to_string(&read_secret_input).unwrap(),
+        )
+        .auth(supabase_service_role_api_key.clone()) //Need to put service role key here I guess for it to show up current_setting in sql function
+        .execute()
+        .await?;
+
+    let vault_secret_body = response.text().await?;
+
+    println!(
+        "[VAULT] Existing secret details response: {:?}",
+        vault_secret_body
+    );
+
+    let vault_secret_json: serde_json::Value = serde_json::from_str(&vault_secret_body).unwrap();
+    let secret_name = vault_secret_json[0]["name"].as_str().unwrap_or_default();
+    let secret_description = vault_secret_json[0]["description"]
+        .as_str()
+        .unwrap_or_default();
+
+    println!("[VAULT] Retrieved existing secret name: {}", secret_name);
+
+    let update_secret_input = UpdateSecretInput {
+        id: secret_id.to_string(),
+        secret: new_secret_value.to_string(), // Use original value, we've already validated it contains non-whitespace
+        name: secret_name.to_string(),
+        description: secret_description.to_string(),
+    };
+
+    println!(
+        "[VAULT] Making RPC call to update_secret with input: {:?}",
+        update_secret_input
+    );
+
+    let response = client
+        .rpc(
+            "update_secret",
+            serde_json::to_string(&update_secret_input).unwrap(),
+        )
+        .auth(supabase_service_role_api_key)
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+
+    println!("[VAULT] Response from vault update: {:?}", body);
+    println!("[VAULT] Successfully updated secret");
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct DecryptedVaultSecret {
+    id: String,
+    decrypted_secret: Option<String>,
+}
+
+async fn read_secrets(
+    client: &Postgrest,
+    secret_ids: &[String],
+) -> VaultResult<HashMap<String, String>> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")?;
+
+    let input = serde_json::json!({ "secret_ids": secret_ids }).to_string();
+
+    let response = client
+        .rpc("read_decrypted_secrets", input)
+        .auth(supabase_service_role_api_key)
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+    let rows: Vec<DecryptedVaultSecret> = match serde_json::from_str(&body) {
+        Ok(rows) => rows,
+        Err(e) => {
+            println!("[VAULT] Error parsing decrypted secrets: {}", e);
+            return Err(Box::new(e));
+        }
+    };
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| row.decrypted_secret.map(|value| (row.id, value)))
+        .collect())
+}
+
+async fn delete_secret(client: &Postgrest, secret_id: &str) -> VaultResult<()> {
+    dotenv().ok();
+    let supabase_service_role_api_key = env::var("SUPABASE_SERVICE_ROLE_API_KEY")?;
+
+    let input = serde_json::json!({ "secret_id": secret_id }).to_string();
+
+    let response = client
+        .rpc("delete_secret", input)
+        .auth(supabase_service_role_api_key)
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+
+    println!("[VAULT] Response from vault delete: {:?}", body);
+
+    Ok(())
+}
@@ -0,0 +1,419 @@
+use axum::{
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    processor::{db_calls::get_workflow_definition, processor::ProcessorMessage},
+    supabase_jwt_middleware::User,
+    system_plugins::webhook_trigger::{
+        request_body::{get_content_type, raw_body_to_value},
+        request_log::{insert_webhook_request, stored_body_to_bytes, WebhookRequestRecord},
+        webhook_trigger_utils::convert_request_to_payload,
+    },
+    types::{
+        action_types::{ActionType, PluginName},
+        task_types::{
+            CreateTaskInput, FlowSessionStatus, Stage, TaskConfig, TaskStatus, TriggerSessionStatus,
+        },
+    },
+    AppState,
+};
+
+const WEBHOOK_REQUESTS_PAGE_SIZE: usize = 50;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StoredWebhookRequest {
+    pub webhook_request_id: Uuid,
+    pub account_id: Uuid,
+    pub flow_id: Uuid,
+    pub flow_version_id: Option<Uuid>,
+    pub method: String,
+    pub path: String,
+    pub headers: Value,
+    pub query: Value,
+    pub body: Option<String>,
+    pub body_encoding: String,
+    pub client_ip: String,
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebhookRequestsQuery {
+    pub security_result: Option<String>,
+    pub page: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct ReplayWebhookRequestPayload {
+    pub workflow_version_id: Option<Uuid>,
+}
+
+pub async fn get_webhook_requests(
+    Path((account_id, workflow_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Query(query): Query<WebhookRequestsQuery>,
+) -> impl IntoResponse {
+    println!(
+        "[WEBHOOK REQUESTS] Handling get_webhook_requests for workflow {}",
+        workflow_id
+    );
+
+    let page = query.page.unwrap_or(0);
+    let start = page * WEBHOOK_REQUESTS_PAGE_SIZE;
+
+    let mut request = state
+        .anything_client
+        .from("webhook_requests")
+        .auth(&user.jwt)
+        .select("*")
+        .eq("account_id", &account_id)
+        .eq("flow_id", &workflow_id);
+
+    if let Some(security_result) = &query.security_result {
+        request = request.eq("security_result", security_result);
+    }
+
+    let response = match request
+        .order("created_at.desc")
+        .range(start, start + WEBHOOK_REQUESTS_PAGE_SIZE - 1)
+        .execute()
+        .await
+    {
+        Ok(response) => response,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+                .into_response()
+        }
+    };
+
+    let body = match response.text().await {
+        Ok(body) => body,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to read response body",
+            )
+                .into_response()
+        }
+    };
+
+    let items: Value = match serde_json::from_str(&body) {
+        Ok(items) => items,
+        Err(_) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON").into_response()
+        }
+    };
+
+    Json(items).into_response()
+}
+
+async fn fetch_webhook_request(
+    state: &AppState,
+    user: &User,
+    account_id: &str,
+    webhook_request_id: &str,
+) -> Result<Option<Value>, (StatusCode, &'static str)> {
+    let response = state
+        .anything_client
+        .from("webhook_requests")
+        .auth(&user.jwt)
+        .select("*")
+        .eq("account_id", account_id)
+        .eq("webhook_request_id", webhook_request_id)
+        .execute()
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+        })?;
+
+    let body = response.text().await.map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to read response body",
+        )
+    })?;
+
+    let mut rows: Vec<Value> = serde_json::from_str(&body)
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON"))?;
+
+    Ok(rows.pop())
+}
+
+pub async fn get_webhook_request(
+    Path((account_id, webhook_request_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    match fetch_webhook_request(&state, &user, &account_id, &webhook_request_id).await {
+        Ok(Some(item)) => Json(item).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "Webhook request not found").into_response(),
+        Err(response) => response.into_response(),
+    }
+}
+
+//Replays skip the webhook's security model, so only account owners get to run them
+async fn is_account_owner(
+    state: &AppState,
+    user: &User,
+    account_id: &str,
+) -> Result<bool, (StatusCode, &'static str)> {
+    let response = state
+        .public_client
+        .rpc(
+            "current_user_account_role",
+            json!({ "account_id": account_id }).to_string(),
+        )
+        .auth(&user.jwt)
+        .execute()
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to execute request",
+            )
+        })?;
+
+    if !response.status().is_success() {
+        return Ok(false);
+    }
+
+    let role: Value = response
+        .json()
+        .await
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON"))?;
+    Ok(role["account_role"].as_str() == Some("owner"))
+}
+
+//Starts the workflow again with a stored request. Skips the security model since signatures
+//on old requests have usually expired and stored signature and secret headers are redacted,
+//which is why the caller has to be an account owner rather than any member.
+pub async fn replay_webhook_request(
+    Path((account_id, webhook_request_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    payload: Option<Json<ReplayWebhookRequestPayload>>,
+) -> impl IntoResponse {
+    println!(
+        "[WEBHOOK REQUESTS] Replaying webhook request {}",
+        webhook_request_id
+    );
+
+    match is_account_owner(&state, &user, &account_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (
+                StatusCode::FORBIDDEN,
+                "Only account owners can replay webhook requests",
+            )
+                .into_response()
+        }
+        Err(response) => return response.into_response(),
+    }
+
+    let payload = payload.map(|Json(payload)| payload).unwrap_or_default();
+
+    let stored: StoredWebhookRequest =
+        match fetch_webhook_request(&state, &user, &account_id, &webhook_request_id).await {
+            Ok(Some(item)) => match serde_json::from_value(item) {
+                Ok(stored) => stored,
+                Err(e) => {
+                    println!("[WEBHOOK REQUESTS] Failed to parse stored request: {}", e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse JSON")
+                        .into_response();
+                }
+            },
+            Ok(None) => {
+                return (StatusCode::NOT_FOUND, "Webhook request not found").into_response()
+            }
+            Err(response) => return response.into_response(),
+        };
+
+    let workflow_version = match get_workflow_definition(
+        state.clone(),
+        &stored.flow_id,
+        payload.workflow_version_id.as_ref(),
+    )
+    .await
+    {
+        Ok(workflow_version) => workflow_version,
+        Err(e) => {
+            println!(
+                "[WEBHOOK REQUESTS] Failed to get workflow definition: {}",
+                e
+            );
+            return (StatusCode::NOT_FOUND, "Workflow version not found").into_response();
+        }
+    };
+
+    let webhook_plugin = PluginName::new("@anything/webhook".to_string()).unwrap();
+    let trigger_node = match workflow_version
+        .flow_definition
+        .actions
+        .iter()
+        .find(|action| action.r#type == ActionType::Trigger && action.plugin_name == webhook_plugin)
+    {
+        Some(trigger_node) => trigger_node,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "Workflow version does not start with a webhook trigger",
+            )
+                .into_response()
+        }
+    };
+
+    let raw_body = match stored_body_to_bytes(stored.body.as_deref(), &stored.body_encoding) {
+        Ok(raw_body) => raw_body,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let method = Method::from_str(&stored.method).unwrap_or(Method::POST);
+
+    let mut headers = HeaderMap::new();
+    if let Some(stored_headers) = stored.headers.as_object() {
+        for (name, value) in stored_headers {
+            if let (Ok(name), Some(Ok(value))) = (
+                HeaderName::from_str(name),
+                value.as_str().map(HeaderValue::from_str),
+            ) {
+                headers.insert(name, value);
+            }
+        }
+    }
+
+    let query_params: HashMap<String, String> = stored
+        .query
+        .as_object()
+        .map(|query| {
+            query
+                .iter()
+                .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let processed_payload = convert_request_to_payload(
+        method.clone(),
+        Some(Query(query_params)),
+        &headers,
+        &raw_body,
+    )
+    .await;
+    let (raw_body_value, raw_body_encoding) = raw_body_to_value(&raw_body);
+
+    let flow_session_id = Uuid::new_v4();
+    let trigger_session_id = Uuid::new_v4();
+
+    let task = CreateTaskInput {
+        account_id: stored.account_id.to_string(),
+        processing_order: 0,
+        task_status: TaskStatus::Running.as_str().to_string(),
+        flow_id: stored.flow_id.to_string(),
+        flow_version_id: workflow_version.flow_version_id.to_string(),
+        action_label: trigger_node.label.clone(),
+        trigger_id: trigger_node.action_id.clone(),
+        trigger_session_id: trigger_session_id.to_string(),
+        trigger_session_status: TriggerSessionStatus::Running.as_str().to_string(),
+        flow_session_id: flow_session_id.to_string(),
+        flow_session_status: FlowSessionStatus::Running.as_str().to_string(),
+        action_id: trigger_node.action_id.clone(),
+        r#type: ActionType::Trigger,
+        plugin_name: trigger_node.plugin_name.clone(),
+        plugin_version: trigger_node.plugin_version.clone(),
+        stage: if workflow_version.published {
+            Stage::Production.as_str().to_string()
+        } else {
+            Stage::Testing.as_str().to_string()
+        },
+        config: TaskConfig {
+            inputs: trigger_node.inputs.clone(),
+            inputs_schema: trigger_node.inputs_schema.clone(),
+            plugin_config: Some(trigger_node.plugin_config.clone()),
+            plugin_config_schema: Some(trigger_node.plugin_config_schema.clone()),
+        },
+        result: Some(json!({
+            "headers": stored.headers,
+            "body": processed_payload,
+            "raw_body": raw_body_value,
+            "raw_body_encoding": raw_body_encoding,
+            "content_type": get_content_type(&headers),
+            "method": method.to_string(),
+            "replayed_from": stored.webhook_request_id,
+        })),
+        error: None,
+        test_config: None,
+        started_at: Some(Utc::now()),
+    };
+
+    let processor_message = ProcessorMessage {
+        workflow_id: stored.flow_id,
+        version_id: Some(workflow_version.flow_version_id),
+        flow_session_id,
+        trigger_session_id,
+        trigger_task: Some(task),
+    };
+
+    if let Err(e) = state.processor_sender.send(processor_message).await {
+        println!(
+            "[WEBHOOK REQUESTS] Failed to send message to processor: {}",
+            e
+        );
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to send message to processor: {}", e),
+        )
+            .into_response();
+    }
+
+    //Log the replay next to the original so the history shows both runs
+    let record = WebhookRequestRecord {
+        account_id: stored.account_id.to_string(),
+        flow_id: stored.flow_id.to_string(),
+        flow_version_id: Some(workflow_version.flow_version_id.to_string()),
+        method: stored.method.clone(),
+        path: stored.path.clone(),
+        headers: stored.headers.clone(),
+        query: stored.query.clone(),
+        body: stored.body.clone(),
+        body_encoding: stored.body_encoding.clone(),
+        client_ip: stored.client_ip.clone(),
+        status_code: StatusCode::OK.as_u16(),
+        security_result: Some("replayed".to_string()),
+        flow_session_id: Some(flow_session_id.to_string()),
+        rejection_reason: None,
+        replay_of: Some(stored.webhook_request_id.to_string()),
+    };
+
+    let replay = match insert_webhook_request(&state, &record).await {
+        Ok(replay) => replay,
+        Err(e) => {
+            println!("[WEBHOOK REQUESTS] Failed to store replayed request: {}", e);
+            Value::Null
+        }
+    };
+
+    Json(json!({
+        "success": true,
+        "message": "Workflow started!",
+        "workflow_session_id": flow_session_id,
+        "workflow_id": stored.flow_id,
+        "workflow_version_id": workflow_version.flow_version_id,
+        "webhook_request": replay
+    }))
+    .into_response()
+}
@@ -0,0 +1,716 @@
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    bundler::accounts::fetch_cached_auth_accounts,
+    supabase_jwt_middleware::User,
+    templater::Templater,
+    types::{
+        action_types::Action,
+        json_schema::{JsonSchema, ValidationFieldType},
+        workflow_types::WorkflowVersionDefinition,
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateReferenceIssueKind {
+    InvalidTemplate,
+    MissingAction,
+    NotUpstream,
+    MissingSecret,
+    MissingAccount,
+    MissingInput,
+    UnknownVariable,
+    TypeMismatch,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueSeverity {
+    //Will fail when the workflow runs
+    Error,
+    //Might fail depending on the data
+    Warning,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TemplateReferenceIssue {
+    pub action_id: String,
+    pub field: String,
+    pub variable: String,
+    pub kind: TemplateReferenceIssueKind,
+    pub severity: IssueSeverity,
+    pub message: String,
+}
+
+//Which top level names a section of an action can reference
+#[derive(Clone, Copy, PartialEq)]
+enum Section {
+    Inputs,
+    PluginConfig,
+}
+
+impl Section {
+    fn as_str(&self) -> &str {
+        match self {
+            Section::Inputs => "inputs",
+            Section::PluginConfig => "plugin_config",
+        }
+    }
+}
+
+//Splits a variable into its root and first key. `$` queries are read when they start with plain names.
+pub fn root_and_key(variable: &str) -> Option<(String, Option<String>)> {
+    let mut segments = Vec::new();
+
+    if let Some(query) = variable.strip_prefix('$') {
+        let mut rest = query;
+        while segments.len() < 2 && !rest.is_empty() {
+            if rest.starts_with("..") {
+                break;
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                segments.push(after[..end].to_string());
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix("['").or_else(|| rest.strip_prefix("[\""))
+            {
+                let end = after.find(['\'', '"'])?;
+                segments.push(after[..end].to_string());
+                rest = after[end + 1..].strip_prefix(']')?;
+            } else {
+                break;
+            }
+        }
+    } else {
+        segments = variable
+            .split('.')
+            .take(2)
+            .map(|segment| segment.split('[').next().unwrap_or_default().to_string())
+            .collect();
+    }
+
+    let mut segments = segments.into_iter();
+    let root = segments.next().filter(|root| !root.is_empty())?;
+    Some((root, segments.next().filter(|key| !key.is_empty())))
+}
+
+//Every action that can run before action_id, following edges backwards
+fn upstream_actions(definition: &WorkflowVersionDefinition, action_id: &str) -> HashSet<String> {
+    let mut parents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for edge in &definition.edges {
+        parents
+            .entry(edge.target.as_str())
+            .or_default()
+            .push(edge.source.as_str());
+    }
+
+    let mut upstream = HashSet::new();
+    let mut queue = VecDeque::from([action_id]);
+    while let Some(current) = queue.pop_front() {
+        for parent in parents.get(current).into_iter().flatten() {
+            if upstream.insert(parent.to_string()) {
+                queue.push_back(*parent);
+            }
+        }
+    }
+    upstream
+}
+
+fn field_validation(schema: Option<&JsonSchema>, field: &str) -> ValidationFieldType {
+    schema
+        .and_then(|schema| schema.properties.as_ref())
+        .and_then(|properties| properties.get(field))
+        .and_then(|property| property.x_any_validation.as_ref())
+        .map(|validation| validation.r#type.clone())
+        .unwrap_or(ValidationFieldType::Unknown)
+}
+
+//Text left when every {{ }} is removed from a template string
+fn text_outside_tags(template: &str) -> String {
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(open_idx) = rest.find("{{") {
+        text.push_str(&rest[..open_idx]);
+        match rest[open_idx..].find("}}") {
+            Some(close_idx) => rest = &rest[open_idx + close_idx + 2..],
+            None => return text,
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+struct ActionAnalysis<'a> {
+    definition: &'a WorkflowVersionDefinition,
+    secret_names: &'a HashSet<String>,
+    account_slugs: &'a HashSet<String>,
+    issues: Vec<TemplateReferenceIssue>,
+}
+
+impl ActionAnalysis<'_> {
+    fn report(
+        &mut self,
+        action_id: &str,
+        field: &str,
+        variable: &str,
+        kind: TemplateReferenceIssueKind,
+        severity: IssueSeverity,
+        message: String,
+    ) {
+        self.issues.push(TemplateReferenceIssue {
+            action_id: action_id.to_string(),
+            field: field.to_string(),
+            variable: variable.to_string(),
+            kind,
+            severity,
+            message,
+        });
+    }
+
+    fn check_section(&mut self, action: &Action, section: Section, upstream: &HashSet<String>) {
+        let action_id = action.action_id.as_str();
+        let (values, schema) = match section {
+            Section::Inputs => (action.inputs.as_ref(), action.inputs_schema.as_ref()),
+            Section::PluginConfig => (
+                Some(&action.plugin_config),
+                Some(&action.plugin_config_schema),
+            ),
+        };
+        let Some(Value::Object(values)) = values else {
+            return;
+        };
+
+        for (key, value) in values {
+            let field = format!("{}.{}", section.as_str(), key);
+            let expected_type = field_validation(schema, key);
+
+            let mut templater = Templater::new();
+            templater.add_template(&field, value.clone());
+            let variables = match templater.get_template_variables(&field) {
+                Ok(variables) => variables,
+                Err(e) => {
+                    self.report(
+                        action_id,
+                        &field,
+                        &e.variable,
+                        TemplateReferenceIssueKind::InvalidTemplate,
+                        IssueSeverity::Error,
+                        e.message,
+                    );
+                    continue;
+                }
+            };
+
+            for variable in &variables {
+                self.check_reference(action, &field, section, variable, upstream);
+            }
+
+            self.check_type(action, &field, key, value, &expected_type, section);
+        }
+    }
+
+    fn check_reference(
+        &mut self,
+        action: &Action,
+        field: &str,
+        section: Section,
+        variable: &str,
+        upstream: &HashSet<String>,
+    ) {
+        let action_id = action.action_id.as_str();
+        let Some((root, key)) = root_and_key(variable) else {
+            return;
+        };
+
+        //Loop variables from {{#each}}
+        if root == "this" || root.starts_with('@') {
+            return;
+        }
+
+        match (section, root.as_str(), key) {
+            (Section::Inputs, "actions", Some(referenced)) => {
+                if referenced == action_id {
+                    self.report(
+                        action_id,
+                        field,
+                        variable,
+                        TemplateReferenceIssueKind::NotUpstream,
+                        IssueSeverity::Error,
+                        "Action references its own results".to_string(),
+                    );
+                } else if !self
+                    .definition
+                    .actions
+                    .iter()
+                    .any(|action| action.action_id == referenced)
+                {
+                    self.report(
+                        action_id,
+                        field,
+                        variable,
+                        TemplateReferenceIssueKind::MissingAction,
+                        IssueSeverity::Error,
+                        format!("No action with id '{}' in this workflow", referenced),
+                    );
+                } else if !upstream.contains(&referenced) {
+                    self.report(
+                        action_id,
+                        field,
+                        variable,
+                        TemplateReferenceIssueKind::NotUpstream,
+                        IssueSeverity::Error,
+                        format!("Action '{}' does not run before this action", referenced),
+                    );
+                }
+            }
+            (Section::Inputs, "secrets", Some(secret)) => {
+                if !self.secret_names.contains(&secret) {
+                    self.report(
+                        action_id,
+                        field,
+                        variable,
+                        TemplateReferenceIssueKind::MissingSecret,
+                        IssueSeverity::Error,
+                        format!("No secret named '{}'", secret),
+                    );
+                }
+            }
+            (Section::Inputs, "accounts", Some(slug)) => {
+                if !self.account_slugs.contains(&slug) {
+                    self.report(
+                        action_id,
+                        field,
+                        variable,
+                        TemplateReferenceIssueKind::MissingAccount,
+                        IssueSeverity::Error,
+                        format!("No connected account '{}'", slug),
+                    );
+                }
+            }
+            (Section::Inputs, "system", _) | (Section::Inputs, "actions", None) => {}
+            (Section::PluginConfig, "inputs", Some(input)) => {
+                let has_input = action
+                    .inputs
+                    .as_ref()
+                    .and_then(|inputs| inputs.as_object())
+                    .map_or(false, |inputs| inputs.contains_key(&input));
+                if !has_input {
+                    self.report(
+                        action_id,
+                        field,
+                        variable,
+                        TemplateReferenceIssueKind::MissingInput,
+                        IssueSeverity::Error,
+                        format!("No input named '{}'", input),
+                    );
+                }
+            }
+            (Section::PluginConfig, "inputs", None) => {}
+            (section, root, _) => self.report(
+                action_id,
+                field,
+                variable,
+                TemplateReferenceIssueKind::UnknownVariable,
+                IssueSeverity::Warning,
+                format!("'{}' is not available in {}", root, section.as_str()),
+            ),
+        }
+    }
+
+    fn check_type(
+        &mut self,
+        action: &Action,
+        field: &str,
+        key: &str,
+        value: &Value,
+        expected_type: &ValidationFieldType,
+        section: Section,
+    ) {
+        let action_id = action.action_id.as_str();
+        if matches!(
+            expected_type,
+            ValidationFieldType::String | ValidationFieldType::Unknown
+        ) {
+            return;
+        }
+
+        let template = match value {
+            Value::String(template) => template,
+            _ => return,
+        };
+
+        //Plain values go through the same conversion the templater runs at render time
+        if !template.contains("{{") {
+            let mut templater = Templater::new();
+            templater.add_template(field, json!({ key: value }));
+            let validations = HashMap::from([(key.to_string(), expected_type.clone())]);
+            if let Err(e) = templater.render(field, &json!({}), validations) {
+                self.report(
+                    action_id,
+                    field,
+                    template,
+                    TemplateReferenceIssueKind::TypeMismatch,
+                    IssueSeverity::Warning,
+                    e.message,
+                );
+            }
+            return;
+        }
+
+        let trimmed = template.trim();
+        let is_whole_value = trimmed.starts_with("{{")
+            && trimmed.ends_with("}}")
+            && trimmed[2..trimmed.len() - 2].find("{{").is_none();
+
+        if !is_whole_value {
+            let text = text_outside_tags(template);
+            let text = text.trim();
+            let never_converts = match expected_type {
+                ValidationFieldType::Number => text
+                    .chars()
+                    .any(|c| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'))),
+                ValidationFieldType::Boolean => !text.is_empty(),
+                _ => false,
+            };
+            if never_converts && !trimmed.starts_with("{{#") {
+                self.report(
+                    action_id,
+                    field,
+                    template,
+                    TemplateReferenceIssueKind::TypeMismatch,
+                    IssueSeverity::Warning,
+                    format!(
+                        "Text around the variables will not convert to {}",
+                        expected_type.to_string()
+                    ),
+                );
+            }
+            return;
+        }
+
+        //Plugin config fields that take an input as is should agree with the input's type
+        if section == Section::PluginConfig {
+            let variable = trimmed[2..trimmed.len() - 2].trim();
+            if let Some((root, Some(input))) = root_and_key(variable) {
+                let is_plain_path = !variable.contains('|') && variable.matches('.').count() == 1;
+                let input_type = field_validation(action.inputs_schema.as_ref(), &input);
+                if root == "inputs"
+                    && is_plain_path
+                    && !matches!(
+                        input_type,
+                        ValidationFieldType::String | ValidationFieldType::Unknown
+                    )
+                    && input_type != *expected_type
+                {
+                    self.report(
+                        action_id,
+                        field,
+                        variable,
+                        TemplateReferenceIssueKind::TypeMismatch,
+                        IssueSeverity::Warning,
+                        format!(
+                            "Input '{}' is {} but this field expects {}",
+                            input,
+                            input_type.to_string(),
+                            expected_type.to_string()
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+//Checks every {{ }} reference in a workflow against its actions, edges, secrets and accounts
+pub fn analyze_workflow_references(
+    definition: &WorkflowVersionDefinition,
+    secret_names: &HashSet<String>,
+    account_slugs: &HashSet<String>,
+) -> Vec<TemplateReferenceIssue> {
+    let mut analysis = ActionAnalysis {
+        definition,
+        secret_names,
+        account_slugs,
+        issues: Vec::new(),
+    };
+
+    for action in &definition.actions {
+        let upstream = upstream_actions(definition, &action.action_id);
+
+        analysis.check_section(action, Section::Inputs, &upstream);
+        analysis.check_section(action, Section::PluginConfig, &upstream);
+    }
+
+    analysis.issues
+}
+
+pub fn has_blocking_issues(issues: &[TemplateReferenceIssue]) -> bool {
+    issues
+        .iter()
+        .any(|issue| issue.severity == IssueSeverity::Error)
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretNameRow {
+    secret_name: String,
+    flow_ids: Option<Vec<Uuid>>, // null or empty means every workflow
+}
+
+//Secret names and account slugs templates in this account can reference.
+//Without a workflow secrets limited to other workflows still count.
+//Only names are read, secret values never leave the vault for a lint.
+async fn fetch_reference_names(
+    state: Arc<AppState>,
+    user: &User,
+    account_id: &str,
+    flow_id: Option<&Uuid>,
+) -> Result<(HashSet<String>, HashSet<String>), Box<dyn std::error::Error + Send + Sync>> {
+    let client = &state.anything_client;
+    let secrets_request = async {
+        //RLS limits this to secrets the user can see
+        let response = client
+            .from("secrets")
+            .auth(&user.jwt)
+            .select("secret_name,flow_ids")
+            .eq("account_id", account_id)
+            .eq("anything_api_key", "false")
+            .execute()
+            .await?;
+        let body = response.text().await?;
+        let rows: Vec<SecretNameRow> = serde_json::from_str(&body)?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(rows)
+    };
+    let (secrets, accounts) = tokio::join!(
+        secrets_request,
+        fetch_cached_auth_accounts(state.clone(), client, account_id, false)
+    );
+
+    let secret_names = secrets?
+        .into_iter()
+        .filter(|secret| match (flow_id, &secret.flow_ids) {
+            (Some(flow_id), Some(flow_ids)) if !flow_ids.is_empty() => flow_ids.contains(flow_id),
+            _ => true,
+        })
+        .map(|secret| secret.secret_name)
+        .collect();
+    let account_slugs = accounts?
+        .into_iter()
+        .map(|account| account.account_auth_provider_account_slug)
+        .collect();
+
+    Ok((secret_names, account_slugs))
+}
+
+pub async fn analyze_workflow_definition(
+    state: Arc<AppState>,
+    user: &User,
+    account_id: &str,
+    flow_id: Option<&Uuid>,
+    definition: &WorkflowVersionDefinition,
+) -> Result<Vec<TemplateReferenceIssue>, Box<dyn std::error::Error + Send + Sync>> {
+    let (secret_names, account_slugs) =
+        fetch_reference_names(state, user, account_id, flow_id).await?;
+    Ok(analyze_workflow_references(
+        definition,
+        &secret_names,
+        &account_slugs,
+    ))
+}
+
+//Checks a saved workflow version, used before publishing
+pub async fn analyze_workflow_version(
+    state: Arc<AppState>,
+    user: &User,
+    account_id: &str,
+    workflow_version_id: &str,
+) -> Result<Vec<TemplateReferenceIssue>, Box<dyn std::error::Error + Send + Sync>> {
+    let response = state
+        .anything_client
+        .from("flow_versions")
+        .auth(&user.jwt)
+        .eq("flow_version_id", workflow_version_id)
+        .eq("account_id", account_id)
+        .select("flow_id,flow_definition")
+        .single()
+        .execute()
+        .await?;
+
+    let body = response.text().await?;
+    let row: Value = serde_json::from_str(&body)?;
+    let definition: WorkflowVersionDefinition = serde_json::from_value(
+        row.get("flow_definition")
+            .cloned()
+            .ok_or("Workflow version not found")?,
+    )?;
+    let flow_id = row
+        .get("flow_id")
+        .and_then(Value::as_str)
+        .and_then(|flow_id| Uuid::parse_str(flow_id).ok());
+
+    analyze_workflow_definition(state, user, account_id, flow_id.as_ref(), &definition).await
+}
+
+//Used by the editor to check a definition before it is saved
+pub async fn analyze_workflow(
+    Path(account_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Json(definition): Json<WorkflowVersionDefinition>,
+) -> impl IntoResponse {
+    println!("[WORKFLOW ANALYSIS] Analyzing workflow definition");
+
+    match analyze_workflow_definition(state, &user, &account_id, None, &definition).await {
+        Ok(issues) => Json(json!({
+            "valid": !has_blocking_issues(&issues),
+            "issues": issues
+        }))
+        .into_response(),
+        Err(e) => {
+            println!("[WORKFLOW ANALYSIS] Failed to analyze workflow: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to analyze workflow",
+            )
+                .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(action_id: &str, inputs: Value, inputs_schema: Value) -> Value {
+        json!({
+            "anything_action_version": "0.1.0",
+            "type": "action",
+            "plugin_name": "@anything/http",
+            "plugin_version": "0.1.0",
+            "action_id": action_id,
+            "label": action_id,
+            "description": null,
+            "icon": "",
+            "inputs": inputs,
+            "inputs_locked": false,
+            "inputs_schema": inputs_schema,
+            "inputs_schema_locked": false,
+            "plugin_config": {"url": "{{inputs.url}}", "count": "{{inputs.count}}", "body": "{{inputs.missing}}"},
+            "plugin_config_locked": false,
+            "plugin_config_schema": {
+                "type": "object",
+                "properties": {
+                    "url": {"x-any-validation": {"type": "string"}},
+                    "count": {"x-any-validation": {"type": "number"}},
+                    "body": {"x-any-validation": {"type": "object"}}
+                }
+            },
+            "plugin_config_schema_locked": true,
+            "presentation": null,
+            "handles": null
+        })
+    }
+
+    #[test]
+    fn reports_broken_references() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "url": {"x-any-validation": {"type": "string"}},
+                "count": {"x-any-validation": {"type": "object"}}
+            }
+        });
+
+        let definition: WorkflowVersionDefinition = serde_json::from_value(json!({
+            "actions": [
+                action("first", json!({"url": "{{secrets.API_KEY}}", "count": "{}"}), schema.clone()),
+                action("second", json!({
+                    "url": "{{actions.first.result.url}}/{{actions.third.result.id}}?k={{secrets.MISSING | url_encode}}",
+                    "count": "{{#if actions.ghost.result}}{}{{/if}}"
+                }), schema.clone()),
+                action("third", json!({"url": "{{accounts.slack.access_token}}", "count": "{{actions.first.result}}"}), schema)
+            ],
+            "edges": [
+                {"id": "a", "source": "first", "target": "second", "source_handle": null, "target_handle": null, "type": "anything"},
+                {"id": "b", "source": "second", "target": "third", "source_handle": null, "target_handle": null, "type": "anything"}
+            ]
+        }))
+        .unwrap();
+
+        let secret_names = HashSet::from(["API_KEY".to_string()]);
+        let issues = analyze_workflow_references(&definition, &secret_names, &HashSet::new());
+
+        let found: Vec<(&str, &str, TemplateReferenceIssueKind)> = issues
+            .iter()
+            .map(|issue| {
+                (
+                    issue.action_id.as_str(),
+                    issue.variable.as_str(),
+                    issue.kind.clone(),
+                )
+            })
+            .collect();
+
+        assert!(found.contains(&(
+            "second",
+            "actions.third.result.id",
+            TemplateReferenceIssueKind::NotUpstream
+        )));
+        assert!(found.contains(&(
+            "second",
+            "secrets.MISSING",
+            TemplateReferenceIssueKind::MissingSecret
+        )));
+        assert!(found.contains(&(
+            "second",
+            "actions.ghost.result",
+            TemplateReferenceIssueKind::MissingAction
+        )));
+        assert!(found.contains(&(
+            "third",
+            "accounts.slack.access_token",
+            TemplateReferenceIssueKind::MissingAccount
+        )));
+        assert!(found.contains(&(
+            "first",
+            "inputs.missing",
+            TemplateReferenceIssueKind::MissingInput
+        )));
+        assert!(found.contains(&(
+            "first",
+            "inputs.count",
+            TemplateReferenceIssueKind::TypeMismatch
+        )));
+        assert!(!found
+            .iter()
+            .any(|(_, variable, _)| *variable == "actions.first.result.url"));
+        assert!(has_blocking_issues(&issues));
+    }
+
+    #[test]
+    fn reads_roots_from_queries() {
+        assert_eq!(
+            root_and_key("$.actions.http.result[?@.id > 1]"),
+            Some(("actions".to_string(), Some("http".to_string())))
+        );
+        assert_eq!(
+            root_and_key("$['secrets']['API_KEY']"),
+            Some(("secrets".to_string(), Some("API_KEY".to_string())))
+        );
+        assert_eq!(root_and_key("$..id"), None);
+        assert_eq!(
+            root_and_key("actions.http[0].result"),
+            Some(("actions".to_string(), Some("http".to_string())))
+        );
+    }
+}
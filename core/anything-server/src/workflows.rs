@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
 
+use crate::cache_invalidation::{invalidate_caches, CacheInvalidation};
 use crate::supabase_jwt_middleware::User;
 use crate::types::workflow_types::WorkflowVersionDefinition;
 use crate::AppState;
@@ -19,7 +20,9 @@ use std::env;
 use chrono::Utc;
 
 use crate::agents::tools::update_agent_tool_if_needed_on_workflow_publish;
+use crate::system_plugins::javascript::libraries::current_js_library_pins;
 use crate::system_workflows::create_workflow_from_template;
+use crate::workflow_analysis::{analyze_workflow_version, has_blocking_issues};
 #[derive(Debug, Deserialize, Serialize)]
 pub struct BaseFlowVersionInput {
     account_id: String,
@@ -834,6 +837,15 @@ pub async fn update_workflow_version(
         }
     };
 
+    //Drop templates compiled from the old definition on every instance
+    if let Ok(flow_version_id) = Uuid::parse_str(&workflow_version_id) {
+        invalidate_caches(
+            &state,
+            CacheInvalidation::CompiledTemplates { flow_version_id },
+        )
+        .await;
+    }
+
     Json(body).into_response()
 }
 
@@ -851,6 +863,30 @@ pub async fn publish_workflow_version(
     println!("workflow id: {}", workflow_id);
     println!("flow-version id: {}", workflow_version_id);
 
+    //Don't publish workflows with templates that will fail at runtime
+    match analyze_workflow_version(state.clone(), &user, &account_id, &workflow_version_id).await {
+        Ok(issues) if has_blocking_issues(&issues) => {
+            println!("Workflow version has {} template issues", issues.len());
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "Workflow has template references that will fail",
+                    "issues": issues
+                })),
+            )
+                .into_response();
+        }
+        Ok(_) => {}
+        Err(err) => {
+            eprintln!("Error analyzing workflow version: {:?}", err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to analyze workflow version",
+            )
+                .into_response();
+        }
+    }
+
     let client = &state.anything_client;
 
     let unpublish_json = serde_json::json!({
@@ -903,9 +939,23 @@ pub async fn publish_workflow_version(
         }
     };
 
+    //Pin the libraries this version was published against so later edits don't change it
+    let js_library_versions = match current_js_library_pins(&state, &user.jwt, &account_id).await {
+        Ok(pins) => pins,
+        Err(err) => {
+            eprintln!("Error loading JavaScript library versions: {:?}", err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to load JavaScript library versions",
+            )
+                .into_response();
+        }
+    };
+
     let update_json = serde_json::json!({
         "published": true,
         "published_at": Utc::now().to_rfc3339(),
+        "js_library_versions": js_library_versions,
     });
 
     //If called twice won't run because users are not allowed to make updates to flow_versions if published = true based on Database Permission Rules
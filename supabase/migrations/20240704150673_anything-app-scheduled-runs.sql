@@ -0,0 +1,84 @@
+CREATE TABLE IF NOT EXISTS anything.scheduled_runs
+(
+    scheduled_run_id uuid unique NOT NULL DEFAULT uuid_generate_v4() primary key,
+    -- If your model is owned by an account, you want to make sure you have an account_id column
+    -- referencing the account table. Make sure you also set permissions appropriately
+    account_id uuid not null references basejump.accounts(id),
+
+    -- ADD YOUR COLUMNS HERE
+    flow_id uuid not null references anything.flows(flow_id),
+    flow_version_id uuid references anything.flow_versions(flow_version_id), -- null means run whatever version is published at run_at
+    run_at timestamp with time zone not null,
+    payload jsonb,
+    status text not null default 'pending', -- pending, fired, canceled, failed
+    flow_session_id uuid, -- set once the run has been handed to the processor
+    fired_at timestamp with time zone,
+    error jsonb,
+
+    -- timestamps are useful for auditing
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_at timestamp with time zone,
+    created_at timestamp with time zone,
+    -- Useful for tracking who made changes to a record
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_by uuid references auth.users(id),
+    created_by uuid references auth.users(id)
+);
+
+-- The trigger engine hydrates every pending run on startup
+CREATE INDEX IF NOT EXISTS scheduled_runs_status_run_at_idx ON anything.scheduled_runs (status, run_at);
+
+-- protect the timestamps by setting created_at and updated_at to be read-only and managed by a trigger
+CREATE TRIGGER set_scheduled_runs_timestamp
+    BEFORE INSERT OR UPDATE ON anything.scheduled_runs
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_timestamps();
+
+-- protect the updated_by and created_by columns by setting them to be read-only and managed by a trigger
+CREATE TRIGGER set_scheduled_runs_user_tracking
+    BEFORE INSERT OR UPDATE ON anything.scheduled_runs
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_user_tracking();
+
+-- enable RLS on the table
+ALTER TABLE anything.scheduled_runs ENABLE ROW LEVEL SECURITY;
+
+-------------
+-- Users should be able to read records that are owned by an account they belong to
+--------------
+create policy "Account members can select" on anything.scheduled_runs
+    for select
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+----------------
+-- Users should be able to create records that are owned by an account they belong to
+----------------
+create policy "Account members can insert" on anything.scheduled_runs
+    for insert
+    to authenticated
+    with check (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+---------------
+-- Users should be able to update records that are owned by an account they belong to
+---------------
+create policy "Account members can update" on anything.scheduled_runs
+    for update
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+----------------
+-- Users should be able to delete records that are owned by an account they belong to
+----------------
+create policy "Account members can delete" on anything.scheduled_runs
+    for delete
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
@@ -0,0 +1,81 @@
+CREATE TABLE IF NOT EXISTS anything.polling_trigger_state
+(
+    polling_trigger_state_id uuid unique NOT NULL DEFAULT uuid_generate_v4() primary key,
+    -- If your model is owned by an account, you want to make sure you have an account_id column
+    -- referencing the account table. Make sure you also set permissions appropriately
+    account_id uuid not null references basejump.accounts(id),
+
+    -- ADD YOUR COLUMNS HERE
+    flow_id uuid not null references anything.flows(flow_id),
+    action_id text not null, -- the polling trigger action inside the flow definition
+    seen_ids jsonb not null default '[]'::jsonb, -- most recent item ids already started, used by id dedupe
+    high_water_mark text, -- largest cursor value already started, used by high_water_mark dedupe
+    last_polled_at timestamp with time zone,
+
+    -- timestamps are useful for auditing
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_at timestamp with time zone,
+    created_at timestamp with time zone,
+    -- Useful for tracking who made changes to a record
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_by uuid references auth.users(id),
+    created_by uuid references auth.users(id),
+
+    -- One row of dedupe state per polling trigger
+    unique (flow_id, action_id)
+);
+
+-- protect the timestamps by setting created_at and updated_at to be read-only and managed by a trigger
+CREATE TRIGGER set_polling_trigger_state_timestamp
+    BEFORE INSERT OR UPDATE ON anything.polling_trigger_state
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_timestamps();
+
+-- protect the updated_by and created_by columns by setting them to be read-only and managed by a trigger
+CREATE TRIGGER set_polling_trigger_state_user_tracking
+    BEFORE INSERT OR UPDATE ON anything.polling_trigger_state
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_user_tracking();
+
+-- enable RLS on the table
+ALTER TABLE anything.polling_trigger_state ENABLE ROW LEVEL SECURITY;
+
+-------------
+-- Users should be able to read records that are owned by an account they belong to
+--------------
+create policy "Account members can select" on anything.polling_trigger_state
+    for select
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+----------------
+-- Users should be able to create records that are owned by an account they belong to
+----------------
+create policy "Account members can insert" on anything.polling_trigger_state
+    for insert
+    to authenticated
+    with check (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+---------------
+-- Users should be able to update records that are owned by an account they belong to
+---------------
+create policy "Account members can update" on anything.polling_trigger_state
+    for update
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+----------------
+-- Users should be able to delete records that are owned by an account they belong to
+----------------
+create policy "Account members can delete" on anything.polling_trigger_state
+    for delete
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
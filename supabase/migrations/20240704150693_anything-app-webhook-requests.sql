@@ -0,0 +1,75 @@
+CREATE TABLE IF NOT EXISTS anything.webhook_requests
+(
+    webhook_request_id uuid unique NOT NULL DEFAULT uuid_generate_v4() primary key,
+    -- If your model is owned by an account, you want to make sure you have an account_id column
+    -- referencing the account table. Make sure you also set permissions appropriately
+    account_id uuid not null references basejump.accounts(id),
+
+    -- ADD YOUR COLUMNS HERE
+    flow_id uuid not null references anything.flows(flow_id),
+    flow_version_id uuid references anything.flow_versions(flow_version_id), -- version that handled the request
+    method text not null,
+    path text not null,
+    headers jsonb not null default '{}'::jsonb, -- credentials like authorization are redacted
+    query jsonb not null default '{}'::jsonb,
+    body text, -- raw request body
+    body_encoding text not null default 'utf8', -- utf8 or base64 for binary bodies
+    client_ip text,
+    status_code integer not null, -- status we answered with
+    security_result text, -- passed, failed, blocked (allowlist or rate limit) or replayed
+    flow_session_id uuid, -- set when the request started a workflow
+    rejection_reason text, -- response body when we answered with an error
+    replay_of uuid references anything.webhook_requests(webhook_request_id) on delete set null,
+
+    -- timestamps are useful for auditing
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_at timestamp with time zone,
+    created_at timestamp with time zone,
+    -- Useful for tracking who made changes to a record
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_by uuid references auth.users(id),
+    created_by uuid references auth.users(id)
+);
+
+-- Request history is read newest first per workflow and cleaned up by age
+CREATE INDEX IF NOT EXISTS webhook_requests_flow_id_created_at_idx ON anything.webhook_requests (flow_id, created_at desc);
+CREATE INDEX IF NOT EXISTS webhook_requests_created_at_idx ON anything.webhook_requests (created_at);
+
+-- protect the timestamps by setting created_at and updated_at to be read-only and managed by a trigger
+CREATE TRIGGER set_webhook_requests_timestamp
+    BEFORE INSERT OR UPDATE ON anything.webhook_requests
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_timestamps();
+
+-- protect the updated_by and created_by columns by setting them to be read-only and managed by a trigger
+CREATE TRIGGER set_webhook_requests_user_tracking
+    BEFORE INSERT OR UPDATE ON anything.webhook_requests
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_user_tracking();
+
+-- enable RLS on the table
+ALTER TABLE anything.webhook_requests ENABLE ROW LEVEL SECURITY;
+
+-------------
+-- Users should be able to read records that are owned by an account they belong to
+--------------
+create policy "Account members can select" on anything.webhook_requests
+    for select
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+----------------
+-- Requests are only written by the server with the service role so members can't insert or update them
+----------------
+
+----------------
+-- Users should be able to delete records that are owned by an account they belong to
+----------------
+create policy "Account members can delete" on anything.webhook_requests
+    for delete
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
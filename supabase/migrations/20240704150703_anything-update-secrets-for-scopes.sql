@@ -0,0 +1,48 @@
+-- Secrets can be limited to specific workflows and carry a separate value for test runs
+ALTER TABLE anything.secrets
+ADD COLUMN flow_ids uuid[], -- workflows allowed to use the secret, null or empty means every workflow
+ADD COLUMN testing_vault_secret_id uuid; -- sandbox value used when a task runs in the testing stage
+
+-- Return type changes so the function has to be dropped first
+DROP FUNCTION IF EXISTS anything.get_decrypted_secrets(uuid);
+
+CREATE OR REPLACE FUNCTION anything.get_decrypted_secrets(team_account_id uuid)
+RETURNS TABLE (
+    secret_id uuid,
+    secret_name text,
+    secret_value text,
+    secret_description text,
+    testing_secret_value text,
+    flow_ids uuid[]
+)
+LANGUAGE plpgsql
+SECURITY INVOKER
+AS $$
+BEGIN
+    IF current_setting('role', true) IS DISTINCT FROM 'service_role' THEN
+        RAISE EXCEPTION 'authentication required';
+    END IF;
+
+    RETURN QUERY
+    SELECT
+        s.secret_id,
+        s.secret_name,
+        vs.decrypted_secret AS secret_value,
+        s.secret_description,
+        tvs.decrypted_secret AS testing_secret_value,
+        s.flow_ids
+    FROM
+        anything.secrets s
+    JOIN
+        vault.decrypted_secrets vs
+    ON
+        s.vault_secret_id = vs.id
+    LEFT JOIN
+        vault.decrypted_secrets tvs
+    ON
+        s.testing_vault_secret_id = tvs.id
+    WHERE
+        s.account_id = team_account_id
+        AND s.anything_api_key = false;
+END;
+$$;
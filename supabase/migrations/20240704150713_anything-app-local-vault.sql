@@ -0,0 +1,49 @@
+-- Encrypted secret storage for self hosted installs that run without Supabase Vault (ANYTHING_VAULT_BACKEND=local)
+-- The server encrypts every value before it gets here so the database only ever sees ciphertext
+CREATE TABLE IF NOT EXISTS anything.local_vault_secrets
+(
+    id uuid unique NOT NULL primary key, -- stored as vault_secret_id on anything.secrets
+    name text unique NOT NULL,
+    description text,
+    ciphertext text NOT NULL, -- base64 AES-256-GCM ciphertext under the secret's own data key
+    nonce text NOT NULL,
+    encrypted_data_key text NOT NULL, -- data key wrapped by the master key
+    data_key_nonce text NOT NULL,
+    master_key_id text NOT NULL, -- fingerprint of the master key that wrapped the data key
+
+    updated_at timestamp with time zone,
+    created_at timestamp with time zone
+);
+
+-- Key rotation looks for rows still wrapped by an older master key
+CREATE INDEX IF NOT EXISTS local_vault_secrets_master_key_id_idx ON anything.local_vault_secrets (master_key_id);
+
+-- protect the timestamps by setting created_at and updated_at to be read-only and managed by a trigger
+CREATE TRIGGER set_local_vault_secrets_timestamp
+    BEFORE INSERT OR UPDATE ON anything.local_vault_secrets
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_timestamps();
+
+-- enable RLS without policies so only the service role can read or write ciphertext
+ALTER TABLE anything.local_vault_secrets ENABLE ROW LEVEL SECURITY;
+
+-- Batch read for the Supabase Vault backend so secrets are decrypted the same way for both backends
+CREATE OR REPLACE FUNCTION anything.read_decrypted_secrets(secret_ids uuid[])
+RETURNS TABLE (
+    id uuid,
+    decrypted_secret text
+)
+LANGUAGE plpgsql
+SECURITY INVOKER
+AS $$
+BEGIN
+    IF current_setting('role', true) IS DISTINCT FROM 'service_role' THEN
+        RAISE EXCEPTION 'authentication required';
+    END IF;
+
+    RETURN QUERY
+    SELECT s.id, s.decrypted_secret
+    FROM vault.decrypted_secrets s
+    WHERE s.id = ANY(secret_ids);
+END;
+$$;
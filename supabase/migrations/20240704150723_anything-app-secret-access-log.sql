@@ -0,0 +1,57 @@
+CREATE TABLE IF NOT EXISTS anything.secret_access_log
+(
+    secret_access_log_id uuid unique NOT NULL DEFAULT uuid_generate_v4() primary key,
+    -- If your model is owned by an account, you want to make sure you have an account_id column
+    -- referencing the account table. Make sure you also set permissions appropriately
+    account_id uuid not null references basejump.accounts(id),
+
+    -- ADD YOUR COLUMNS HERE
+    action text not null, -- read, create, update, reveal or delete
+    resource_type text not null, -- secret, api_key or account
+    resource_id text, -- secret_id or account_auth_provider_account_id
+    resource_name text, -- secret name or account slug at the time of access
+    flow_id uuid, -- set when a workflow read the value
+    flow_version_id uuid,
+    flow_session_id uuid,
+    task_id uuid,
+    actor_user_id uuid, -- set when a person made the change through the API
+
+    created_at timestamp with time zone not null default now()
+);
+
+-- Reviews page through an account's history newest first and narrow it down by resource or workflow
+CREATE INDEX IF NOT EXISTS secret_access_log_account_id_created_at_idx ON anything.secret_access_log (account_id, created_at desc);
+CREATE INDEX IF NOT EXISTS secret_access_log_resource_id_idx ON anything.secret_access_log (resource_id);
+CREATE INDEX IF NOT EXISTS secret_access_log_flow_id_idx ON anything.secret_access_log (flow_id);
+
+-- The log is append-only, not even the service role can change or remove entries
+CREATE OR REPLACE FUNCTION anything.prevent_secret_access_log_changes()
+RETURNS trigger
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    RAISE EXCEPTION 'secret_access_log is append-only';
+END;
+$$;
+
+CREATE TRIGGER secret_access_log_append_only
+    BEFORE UPDATE OR DELETE ON anything.secret_access_log
+    FOR EACH ROW
+EXECUTE PROCEDURE anything.prevent_secret_access_log_changes();
+
+-- enable RLS on the table
+ALTER TABLE anything.secret_access_log ENABLE ROW LEVEL SECURITY;
+
+-------------
+-- Users should be able to read records that are owned by an account they belong to
+--------------
+create policy "Account members can select" on anything.secret_access_log
+    for select
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+----------------
+-- Entries are only written by the server with the service role so members can't insert, update or delete them
+----------------
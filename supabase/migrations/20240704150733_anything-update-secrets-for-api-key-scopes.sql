@@ -0,0 +1,136 @@
+ALTER TABLE anything.secrets
+ADD COLUMN api_key_scopes text[], -- null means every scope, keys created before scopes keep full access
+ADD COLUMN api_key_expires_at timestamptz, -- null never expires
+ADD COLUMN api_key_allowed_ips text[]; -- CIDRs or single addresses, null or empty allows any address
+
+-- Kept out of anything.secrets so recording a use doesn't bump updated_at and updated_by
+CREATE TABLE IF NOT EXISTS anything.api_key_usage
+(
+    secret_id uuid NOT NULL primary key references anything.secrets(secret_id) ON DELETE CASCADE,
+    account_id uuid not null references basejump.accounts(id),
+    last_used_at timestamp with time zone not null default now()
+);
+
+-- enable RLS on the table
+ALTER TABLE anything.api_key_usage ENABLE ROW LEVEL SECURITY;
+
+-------------
+-- Users should be able to read records that are owned by an account they belong to
+--------------
+create policy "Account members can select" on anything.api_key_usage
+    for select
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+----------------
+-- Usage is only written by the server with the service role
+----------------
+
+-- Return types change so both functions have to be dropped first
+DROP FUNCTION IF EXISTS anything.get_decrypted_anything_api_keys(uuid);
+
+CREATE OR REPLACE FUNCTION anything.get_decrypted_anything_api_keys(team_account_id uuid)
+RETURNS TABLE (
+    secret_id uuid,
+    secret_name text,
+    secret_value text,
+    secret_description text,
+    api_key_scopes text[],
+    api_key_expires_at timestamptz,
+    api_key_allowed_ips text[],
+    api_key_last_used_at timestamptz
+)
+LANGUAGE plpgsql
+SECURITY INVOKER
+AS $$
+BEGIN
+    IF current_setting('role', true) IS DISTINCT FROM 'service_role' THEN
+        RAISE EXCEPTION 'authentication required';
+    END IF;
+
+    RETURN QUERY
+    SELECT
+        s.secret_id,
+        s.secret_name,
+        vs.decrypted_secret AS secret_value,
+        s.secret_description,
+        s.api_key_scopes,
+        s.api_key_expires_at,
+        s.api_key_allowed_ips,
+        u.last_used_at AS api_key_last_used_at
+    FROM
+        anything.secrets s
+    JOIN
+        vault.decrypted_secrets vs
+    ON
+        s.vault_secret_id = vs.id
+    LEFT JOIN
+        anything.api_key_usage u
+    ON
+        u.secret_id = s.secret_id
+    WHERE
+        s.account_id = team_account_id
+        AND s.anything_api_key = true;
+END;
+$$;
+
+DROP FUNCTION IF EXISTS anything.get_secret_by_secret_value(text);
+
+-- For getting the user account when an API key is sent in the request
+CREATE OR REPLACE FUNCTION anything.get_secret_by_secret_value(secret_value text)
+RETURNS TABLE (
+    secret_id uuid,
+    account_id uuid,
+    secret_name text,
+    vault_secret_id uuid,
+    secret_description text,
+    anything_api_key boolean,
+    updated_at timestamptz,
+    created_at timestamptz,
+    updated_by uuid,
+    created_by uuid,
+    api_key_scopes text[],
+    api_key_expires_at timestamptz,
+    api_key_allowed_ips text[],
+    api_key_last_used_at timestamptz
+)
+LANGUAGE plpgsql
+SECURITY INVOKER
+AS $$
+BEGIN
+    IF current_setting('role', true) IS DISTINCT FROM 'service_role' THEN
+        RAISE EXCEPTION 'authentication required';
+    END IF;
+
+    RETURN QUERY
+    SELECT
+        s.secret_id,
+        s.account_id,
+        s.secret_name,
+        s.vault_secret_id,
+        s.secret_description,
+        s.anything_api_key,
+        s.updated_at,
+        s.created_at,
+        s.updated_by,
+        s.created_by,
+        s.api_key_scopes,
+        s.api_key_expires_at,
+        s.api_key_allowed_ips,
+        u.last_used_at AS api_key_last_used_at
+    FROM
+        anything.secrets s
+    JOIN
+        vault.decrypted_secrets vs
+    ON
+        s.vault_secret_id = vs.id
+    LEFT JOIN
+        anything.api_key_usage u
+    ON
+        u.secret_id = s.secret_id
+    WHERE
+        vs.decrypted_secret = secret_value;
+END;
+$$;
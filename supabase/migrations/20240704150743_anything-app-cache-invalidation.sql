@@ -0,0 +1,60 @@
+-- Servers running with ANYTHING_CACHE_BUS=postgres LISTEN on this channel and drop cached
+-- account access, API keys, secrets and accounts when these rows change, including changes
+-- made outside the server. Payloads match CacheInvalidation in the server's cache_invalidation module.
+CREATE OR REPLACE FUNCTION anything.notify_cache_invalidation()
+RETURNS trigger
+LANGUAGE plpgsql
+AS $$
+DECLARE
+    changed record;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+
+    IF TG_TABLE_NAME = 'account_user' THEN
+        PERFORM pg_notify('anything_cache_invalidation', json_build_object(
+            'cache', 'account_access',
+            'user_id', changed.user_id,
+            'account_id', changed.account_id
+        )::text);
+    ELSIF TG_TABLE_NAME = 'secrets' THEN
+        IF changed.anything_api_key THEN
+            PERFORM pg_notify('anything_cache_invalidation', json_build_object(
+                'cache', 'api_key',
+                'secret_id', changed.secret_id
+            )::text);
+        ELSE
+            PERFORM pg_notify('anything_cache_invalidation', json_build_object(
+                'cache', 'secrets',
+                'account_id', changed.account_id
+            )::text);
+        END IF;
+    ELSIF TG_TABLE_NAME = 'account_auth_provider_accounts' THEN
+        PERFORM pg_notify('anything_cache_invalidation', json_build_object(
+            'cache', 'accounts',
+            'account_id', changed.account_id
+        )::text);
+    END IF;
+
+    RETURN NULL;
+END;
+$$;
+
+-- Inserts matter too, a user added to an account may have a cached denial
+CREATE TRIGGER account_user_cache_invalidation
+    AFTER INSERT OR UPDATE OR DELETE ON basejump.account_user
+    FOR EACH ROW
+EXECUTE PROCEDURE anything.notify_cache_invalidation();
+
+CREATE TRIGGER secrets_cache_invalidation
+    AFTER INSERT OR UPDATE OR DELETE ON anything.secrets
+    FOR EACH ROW
+EXECUTE PROCEDURE anything.notify_cache_invalidation();
+
+CREATE TRIGGER account_auth_provider_accounts_cache_invalidation
+    AFTER INSERT OR UPDATE OR DELETE ON anything.account_auth_provider_accounts
+    FOR EACH ROW
+EXECUTE PROCEDURE anything.notify_cache_invalidation();
@@ -0,0 +1,6 @@
+-- Per account limits for the @anything/javascript action, null uses the server default
+-- (ANYTHING_JS_TIMEOUT_MS and ANYTHING_JS_MAX_HEAP_MB). The server caps both so a bad value
+-- can't take down a worker. Set by us per plan, members can only read them.
+ALTER TABLE anything.accounts_billing
+ADD COLUMN js_timeout_ms integer CHECK (js_timeout_ms > 0),
+ADD COLUMN js_max_heap_mb integer CHECK (js_max_heap_mb > 0);
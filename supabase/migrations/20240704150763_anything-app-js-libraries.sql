@@ -0,0 +1,148 @@
+-- Named JavaScript modules an account shares across its @anything/javascript actions.
+-- Actions import them with `import { helper } from "library:<name>"`.
+CREATE TABLE IF NOT EXISTS anything.js_libraries
+(
+    js_library_id uuid unique NOT NULL DEFAULT uuid_generate_v4() primary key,
+    -- If your model is owned by an account, you want to make sure you have an account_id column
+    -- referencing the account table. Make sure you also set permissions appropriately
+    account_id uuid not null references basejump.accounts(id),
+
+    -- ADD YOUR COLUMNS HERE
+    name text not null, -- the part after "library:" in an import
+    description text,
+    latest_version integer not null default 1,
+    archived boolean not null default false, -- archived libraries stay importable at the versions workflows pinned
+
+    -- timestamps are useful for auditing
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_at timestamp with time zone,
+    created_at timestamp with time zone,
+    -- Useful for tracking who made changes to a record
+    -- Basejump has some convenience functions defined below for automatically handling these
+    updated_by uuid references auth.users(id),
+    created_by uuid references auth.users(id),
+
+    CONSTRAINT js_library_name_format CHECK (name ~ '^[a-z0-9][a-z0-9_-]{0,63}$'),
+    CONSTRAINT unique_js_library_name_per_account UNIQUE (account_id, name)
+);
+
+-- protect the timestamps by setting created_at and updated_at to be read-only and managed by a trigger
+CREATE TRIGGER set_js_libraries_timestamp
+    BEFORE INSERT OR UPDATE ON anything.js_libraries
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_timestamps();
+
+-- protect the updated_by and created_by columns by setting them to be read-only and managed by a trigger
+CREATE TRIGGER set_js_libraries_user_tracking
+    BEFORE INSERT OR UPDATE ON anything.js_libraries
+    FOR EACH ROW
+EXECUTE PROCEDURE basejump.trigger_set_user_tracking();
+
+-- enable RLS on the table
+ALTER TABLE anything.js_libraries ENABLE ROW LEVEL SECURITY;
+
+create policy "Account members can select" on anything.js_libraries
+    for select
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+create policy "Account members can insert" on anything.js_libraries
+    for insert
+    to authenticated
+    with check (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+create policy "Account members can update" on anything.js_libraries
+    for update
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+-- Libraries are archived instead of deleted so pinned versions keep working
+
+-- Every saved revision of a library. Published workflow versions pin these so they are never changed.
+CREATE TABLE IF NOT EXISTS anything.js_library_versions
+(
+    js_library_version_id uuid unique NOT NULL DEFAULT uuid_generate_v4() primary key,
+    js_library_id uuid not null references anything.js_libraries(js_library_id) ON DELETE CASCADE,
+    account_id uuid not null references basejump.accounts(id),
+
+    name text not null, -- copied from js_libraries so the runtime can load a version in one lookup
+    version integer not null,
+    code text not null,
+
+    created_at timestamp with time zone not null default now(),
+    created_by uuid references auth.users(id) default auth.uid(),
+
+    CONSTRAINT unique_js_library_version UNIQUE (js_library_id, version),
+    CONSTRAINT unique_js_library_version_name UNIQUE (account_id, name, version)
+);
+
+CREATE OR REPLACE FUNCTION anything.prevent_js_library_version_changes()
+RETURNS trigger
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    RAISE EXCEPTION 'js_library_versions can not be changed, save a new version instead';
+END;
+$$;
+
+CREATE TRIGGER js_library_versions_immutable
+    BEFORE UPDATE ON anything.js_library_versions
+    FOR EACH ROW
+EXECUTE PROCEDURE anything.prevent_js_library_version_changes();
+
+-- enable RLS on the table
+ALTER TABLE anything.js_library_versions ENABLE ROW LEVEL SECURITY;
+
+create policy "Account members can select" on anything.js_library_versions
+    for select
+    to authenticated
+    using (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+create policy "Account members can insert" on anything.js_library_versions
+    for insert
+    to authenticated
+    with check (
+    (account_id IN ( SELECT basejump.get_accounts_with_role()))
+    );
+
+-- Creates the library on its first save and appends a version on every later one.
+-- The upsert locks the library row so concurrent saves get consecutive versions.
+CREATE OR REPLACE FUNCTION anything.create_js_library_version(
+    p_account_id uuid,
+    p_name text,
+    p_code text,
+    p_description text DEFAULT NULL
+)
+RETURNS SETOF anything.js_library_versions
+LANGUAGE plpgsql
+SECURITY INVOKER
+AS $$
+DECLARE
+    library anything.js_libraries;
+BEGIN
+    INSERT INTO anything.js_libraries (account_id, name, description)
+    VALUES (p_account_id, p_name, p_description)
+    ON CONFLICT (account_id, name) DO UPDATE
+        SET latest_version = anything.js_libraries.latest_version + 1,
+            archived = false,
+            description = COALESCE(EXCLUDED.description, anything.js_libraries.description)
+    RETURNING * INTO library;
+
+    RETURN QUERY
+    INSERT INTO anything.js_library_versions (js_library_id, account_id, name, version, code)
+    VALUES (library.js_library_id, library.account_id, library.name, library.latest_version, p_code)
+    RETURNING *;
+END;
+$$;
+
+-- Library name -> version, taken from js_libraries when the workflow version is published
+ALTER TABLE anything.flow_versions
+ADD COLUMN js_library_versions jsonb;
@@ -0,0 +1,25 @@
+-- API keys are looked up by a hash of their value so the lookup works with every vault backend.
+-- The server hashes the key on create and on every request, values are only read through the vault.
+ALTER TABLE anything.secrets
+ADD COLUMN api_key_hash text; -- hex sha256 of the key, null for regular secrets
+
+CREATE UNIQUE INDEX IF NOT EXISTS secrets_api_key_hash_idx ON anything.secrets (api_key_hash) WHERE api_key_hash IS NOT NULL;
+
+-- Keys created before this migration live in Supabase Vault, the local backend ships in the same release
+DO $$
+BEGIN
+    IF to_regclass('vault.decrypted_secrets') IS NOT NULL THEN
+        UPDATE anything.secrets s
+        SET api_key_hash = encode(extensions.digest(vs.decrypted_secret, 'sha256'), 'hex')
+        FROM vault.decrypted_secrets vs
+        WHERE s.vault_secret_id = vs.id
+            AND s.anything_api_key = true
+            AND s.api_key_hash IS NULL;
+    END IF;
+END;
+$$;
+
+-- Replaced by reads through the server's vault backend
+DROP FUNCTION IF EXISTS anything.get_secret_by_secret_value(text);
+DROP FUNCTION IF EXISTS anything.get_decrypted_anything_api_keys(uuid);
+DROP FUNCTION IF EXISTS anything.get_decrypted_secrets(uuid);
@@ -0,0 +1,16 @@
+-- HMAC signatures accepted by webhook triggers, kept so a captured request can't be replayed.
+-- Shared by every server instance, the unique key is what rejects a second use.
+CREATE TABLE IF NOT EXISTS anything.webhook_signatures
+(
+    flow_id uuid not null references anything.flows(flow_id) ON DELETE CASCADE,
+    signature_hash text not null, -- hex sha256 of the accepted signature, which covers the body
+    expires_at timestamp with time zone, -- end of the timestamp tolerance, null for presets that don't sign a timestamp
+    created_at timestamp with time zone not null default now(),
+
+    primary key (flow_id, signature_hash)
+);
+
+CREATE INDEX IF NOT EXISTS webhook_signatures_expires_at_idx ON anything.webhook_signatures (expires_at) WHERE expires_at IS NOT NULL;
+
+-- Only the server reads and writes these with the service role key
+ALTER TABLE anything.webhook_signatures ENABLE ROW LEVEL SECURITY;